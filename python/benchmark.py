@@ -5,6 +5,13 @@ import argparse
 import pandas as pd
 from memory_profiler import memory_usage
 from json_flattener import JSONFlattener
+from json_flattener_rust import flatten_pandas_ready
+
+try:
+    from json_flattener_rust import flatten_pandas_ready_appended
+    HAS_APPEND_BASELINE = True
+except ImportError:
+    HAS_APPEND_BASELINE = False
 
 try:
     import polars as pl
@@ -129,6 +136,43 @@ def benchmark_rust_flattener(filepath):
     
     return results
 
+def benchmark_pandas_ready(filepath):
+    """Benchmark building the column dict for pandas in Rust, without and with dtypes
+
+    The baseline is the previous Rust column builder, which appended every
+    value to its Python list one call at a time; compare them on wide inputs,
+    where handing each column over as one list should pull ahead.
+    """
+    results = []
+    if HAS_APPEND_BASELINE:
+        start_time = time.time()
+        mem_usage, data = profile_memory(flatten_pandas_ready_appended, filepath)
+        results.append({
+            "method": "Rust per-value append columns",
+            "time_seconds": time.time() - start_time,
+            "memory_mb": mem_usage,
+            "result_type": "dict",
+            "rows": len(next(iter(data.values()), [])),
+            "columns": len(data)
+        })
+    else:
+        print("Per-value append baseline skipped: build with --features bench")
+    for with_dtypes in (False, True):
+        start_time = time.time()
+        mem_usage, result = profile_memory(flatten_pandas_ready, filepath, None, with_dtypes)
+        elapsed_time = time.time() - start_time
+
+        data = result[0] if with_dtypes else result
+        results.append({
+            "method": "Rust pandas-ready columns" + (" with dtypes" if with_dtypes else ""),
+            "time_seconds": elapsed_time,
+            "memory_mb": mem_usage,
+            "result_type": "dict",
+            "rows": len(next(iter(data.values()), [])),
+            "columns": len(data)
+        })
+    return results
+
 def run_benchmark(filepath):
     """Run all benchmarks on a file"""
     file_size = os.path.getsize(filepath)
@@ -156,7 +200,7 @@ def run_benchmark(filepath):
     
     # Run Rust implementation benchmarks
     try:
-        rust_results = benchmark_rust_flattener(filepath)
+        rust_results = benchmark_pandas_ready(filepath) + benchmark_rust_flattener(filepath)
         results.extend(rust_results)
         for result in rust_results:
             print(f"{result['method']}: {result['time_seconds']:.2f} seconds, {result['memory_mb']:.2f} MB")
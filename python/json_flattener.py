@@ -1,7 +1,7 @@
 import json
 import os
 import time
-from typing import Dict, List, Any, Optional, Union
+from typing import Dict, List, Any, Optional, Tuple, Union
 import pandas as pd
 
 try:
@@ -13,10 +13,17 @@ except ImportError:
 # Import our Rust library
 from json_flattener_rust import (
     flatten_json_str,
+    flatten_json_multi_str,
+    flatten_many_json_str,
+    flatten_changes_str,
+    unflatten_dict,
+    unflatten_file,
     flatten_json_file_py,
+    flatten_json_file_timed,
     process_large_json_file,
     flatten_pandas_ready,
     flatten_polaris_ready,
+    FlattenerEngine,
     PyFlattenOptions
 )
 
@@ -55,9 +62,29 @@ class JSONFlattener:
         
         return flatten_json_str(json_str, self.options)
     
-    def flatten_file(self, filepath: str) -> List[Dict[str, str]]:
+    def flatten_json_multi(self, json_str: str) -> List[Dict[str, str]]:
+        """Flatten a string holding a JSON array, concatenated documents or NDJSON"""
+        return flatten_json_multi_str(json_str, self.options)
+    
+    def flatten_json_many(self, json_strs: List[str]) -> List[Dict[str, str]]:
+        """Flatten a list of JSON strings, one document each, in parallel"""
+        return flatten_many_json_str(json_strs, self.options)
+    
+    def flatten_changes(self, old_json_str: str, new_json_str: str) -> Tuple[Dict[str, str], List[str]]:
+        """Flatten only what changed between two versions of a document: (changed keys, removed keys)"""
+        return flatten_changes_str(old_json_str, new_json_str, self.options)
+    
+    def unflatten(self, record: Dict[str, str]) -> Any:
+        """Rebuild the JSON document a flattened dictionary came from"""
+        return json.loads(unflatten_dict(record, self.options))
+    
+    def unflatten_file(self, filepath: Union[str, os.PathLike], output: Union[str, os.PathLike]) -> int:
+        """Rebuild the documents of an NDJSON file of flattened records into an NDJSON file"""
+        return unflatten_file(filepath, output, self.options)
+    
+    def flatten_file(self, filepath: Union[str, os.PathLike]) -> List[Dict[str, str]]:
         """Flatten a JSON file into a list of dictionaries"""
-        if filepath.endswith('.parquet'):
+        if os.fspath(filepath).endswith('.parquet'):
             # For parquet files, we read them and convert to dict
             if self.prefer_polars and HAS_POLARS:
                 df = pl.read_parquet(filepath)
@@ -68,9 +95,25 @@ class JSONFlattener:
         else:
             return flatten_json_file_py(filepath, self.options)
     
-    def flatten_large_file(self, filepath: str) -> Dict[str, str]:
+    def flatten_file_timed(
+        self, filepath: Union[str, os.PathLike], time_budget: float, skip_documents: int = 0
+    ) -> Tuple[List[Dict[str, str]], Optional[int]]:
+        """Flatten as much of a JSON file as fits in time_budget seconds
+        
+        Returns (records, skip), where skip is the skip_documents value that
+        carries on from where the budget ran out, or None once the file is done.
+        """
+        self.options.time_budget = time_budget
+        self.options.skip_documents = skip_documents
+        try:
+            return flatten_json_file_timed(filepath, self.options)
+        finally:
+            self.options.time_budget = None
+            self.options.skip_documents = 0
+    
+    def flatten_large_file(self, filepath: Union[str, os.PathLike]) -> Dict[str, str]:
         """Process a large JSON file optimized for memory efficiency"""
-        if filepath.endswith('.parquet'):
+        if os.fspath(filepath).endswith('.parquet'):
             # For parquet files, we read them and convert to dict
             if self.prefer_polars and HAS_POLARS:
                 df = pl.read_parquet(filepath)
@@ -83,36 +126,45 @@ class JSONFlattener:
         else:
             return process_large_json_file(filepath, self.options)
     
-    def flatten_to_pandas(self, filepath: str) -> pd.DataFrame:
-        """Flatten a file and convert it to a pandas DataFrame"""
-        if filepath.endswith('.parquet'):
+    def flatten_to_pandas(self, filepath: Union[str, os.PathLike],
+                          select: Optional[List[str]] = None,
+                          drop: Optional[List[str]] = None) -> pd.DataFrame:
+        """Flatten a file and convert it to a pandas DataFrame
+
+        select keeps only the columns matching one of its key patterns and drop
+        removes those matching one of its patterns, before they reach Python.
+        """
+        if os.fspath(filepath).endswith('.parquet'):
             return pd.read_parquet(filepath)
         else:
-            data = flatten_pandas_ready(filepath, self.options)
+            data = flatten_pandas_ready(filepath, self.options, select=select, drop=drop)
             return pd.DataFrame(data)
     
-    def flatten_to_polars(self, filepath: str) -> Union[pl.DataFrame, None]:
-        """Flatten a file and convert it to a polars DataFrame"""
+    def flatten_to_polars(self, filepath: Union[str, os.PathLike],
+                          select: Optional[List[str]] = None,
+                          drop: Optional[List[str]] = None) -> Union[pl.DataFrame, None]:
+        """Flatten a file and convert it to a polars DataFrame, pruning columns
+        as in flatten_to_pandas"""
         if not HAS_POLARS:
             raise ImportError("Polars is not installed. Please install it with 'pip install polars'.")
         
-        if filepath.endswith('.parquet'):
+        if os.fspath(filepath).endswith('.parquet'):
             return pl.read_parquet(filepath)
         else:
-            data = flatten_polaris_ready(filepath, self.options)
+            data = flatten_polaris_ready(filepath, self.options, select=select, drop=drop)
             return pl.DataFrame(data)
     
-    def flatten_to_dataframe(self, filepath: str) -> Union[pl.DataFrame, pd.DataFrame]:
+    def flatten_to_dataframe(self, filepath: Union[str, os.PathLike]) -> Union[pl.DataFrame, pd.DataFrame]:
         """Flatten a file and convert it to the preferred DataFrame type"""
         if self.prefer_polars and HAS_POLARS:
             return self.flatten_to_polars(filepath)
         else:
             return self.flatten_to_pandas(filepath)
     
-    def benchmark(self, filepath: str) -> Dict[str, float]:
+    def benchmark(self, filepath: Union[str, os.PathLike]) -> Dict[str, float]:
         """Benchmark flattening performance on a file"""
         file_size_mb = os.path.getsize(filepath) / (1024 * 1024)
-        is_parquet = filepath.endswith('.parquet')
+        is_parquet = os.fspath(filepath).endswith('.parquet')
         
         # Benchmark pandas conversion
         start_time = time.time()
@@ -0,0 +1,268 @@
+// src/aggregate.rs
+//! Grouping records by key columns and rolling up numeric columns
+use crate::{FlattenedJson, RecordSink, SkippedDocument};
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+
+/// Key of the record count in the records of an [`AggregateSink`]
+pub const COUNT_KEY: &str = "count";
+
+/// A rollup [`AggregateSink`] computes for every group
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Aggregation {
+    /// Records in the group, under [`COUNT_KEY`]
+    Count,
+    /// Sum of the numeric values of a key, under `<key>.sum`
+    Sum(String),
+    /// Smallest numeric value of a key, under `<key>.min`
+    Min(String),
+    /// Largest numeric value of a key, under `<key>.max`
+    Max(String),
+}
+
+impl Aggregation {
+    /// Key the rollup is written under, joined with `separator`
+    pub fn output_key(&self, separator: &str) -> String {
+        match self {
+            Aggregation::Count => COUNT_KEY.to_string(),
+            Aggregation::Sum(key) => format!("{}{}sum", key, separator),
+            Aggregation::Min(key) => format!("{}{}min", key, separator),
+            Aggregation::Max(key) => format!("{}{}max", key, separator),
+        }
+    }
+
+    fn key(&self) -> Option<&str> {
+        match self {
+            Aggregation::Count => None,
+            Aggregation::Sum(key) | Aggregation::Min(key) | Aggregation::Max(key) => Some(key),
+        }
+    }
+}
+
+impl std::str::FromStr for Aggregation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let unknown = || format!("unknown aggregation {:?} (expected count, sum:KEY, min:KEY or max:KEY)", s);
+        if s == "count" {
+            return Ok(Aggregation::Count);
+        }
+        let (function, key) = s.split_once(':').filter(|(_, key)| !key.is_empty()).ok_or_else(unknown)?;
+        match function {
+            "sum" => Ok(Aggregation::Sum(key.to_string())),
+            "min" => Ok(Aggregation::Min(key.to_string())),
+            "max" => Ok(Aggregation::Max(key.to_string())),
+            _ => Err(unknown()),
+        }
+    }
+}
+
+impl fmt::Display for Aggregation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Aggregation::Count => f.write_str("count"),
+            Aggregation::Sum(key) => write!(f, "sum:{}", key),
+            Aggregation::Min(key) => write!(f, "min:{}", key),
+            Aggregation::Max(key) => write!(f, "max:{}", key),
+        }
+    }
+}
+
+/// Running sum, min and max of the numeric values of one key in a group
+#[derive(Clone, Copy)]
+struct Rollup {
+    values: u64,
+    sum: f64,
+    min: f64,
+    max: f64,
+}
+
+impl Rollup {
+    const EMPTY: Rollup = Rollup {
+        values: 0,
+        sum: 0.0,
+        min: f64::INFINITY,
+        max: f64::NEG_INFINITY,
+    };
+
+    fn add(&mut self, value: f64) {
+        self.values += 1;
+        self.sum += value;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+}
+
+struct Group {
+    key: Vec<String>,
+    records: u64,
+    /// One per aggregation, unused for [`Aggregation::Count`]
+    rollups: Vec<Rollup>,
+}
+
+/// Sink that groups records by the values of one or more keys and writes
+/// one record per group to the inner sink: the group keys, then the
+/// [`Aggregation`]s, e.g. `{"region": "eu", "count": "12", "price.sum": "340.5"}`
+///
+/// Missing keys group as `null`. Sums, minimums and maximums only take values
+/// that parse as finite numbers and are `null` for a group without any. Groups
+/// are held until the sink is flushed, which the pipelines do once at the
+/// end, and are then written in the order they first appeared. With
+/// [`with_sorted_input`](Self::with_sorted_input) each group is written as
+/// soon as a record of the next one arrives instead, so only one is held.
+pub struct AggregateSink<S: RecordSink> {
+    inner: S,
+    group_by: Vec<String>,
+    aggregations: Vec<Aggregation>,
+    separator: String,
+    sorted: bool,
+    groups: Vec<Group>,
+    index: HashMap<Vec<String>, usize>,
+    written: u64,
+}
+
+impl<S: RecordSink> AggregateSink<S> {
+    pub fn new<K: Into<String>>(
+        inner: S,
+        group_by: impl IntoIterator<Item = K>,
+        aggregations: impl IntoIterator<Item = Aggregation>,
+    ) -> Self {
+        AggregateSink {
+            inner,
+            group_by: group_by.into_iter().map(Into::into).collect(),
+            aggregations: aggregations.into_iter().collect(),
+            separator: ".".to_string(),
+            sorted: false,
+            groups: Vec::new(),
+            index: HashMap::new(),
+            written: 0,
+        }
+    }
+
+    /// Writes each group once the next one starts, for input already sorted
+    /// (or at least clustered) by the group keys; a group that comes back
+    /// later is written again
+    pub fn with_sorted_input(mut self) -> Self {
+        self.sorted = true;
+        self
+    }
+
+    /// Separator between a key and its rollup in the output keys
+    pub fn with_separator(mut self, separator: &str) -> Self {
+        self.separator = separator.to_string();
+        self
+    }
+
+    /// Groups written to the inner sink so far
+    pub fn groups_written(&self) -> u64 {
+        self.written
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    fn write_groups(&mut self) -> io::Result<()> {
+        self.index.clear();
+        for group in std::mem::take(&mut self.groups) {
+            let mut record = FlattenedJson::default();
+            for (key, value) in self.group_by.iter().zip(group.key) {
+                record.insert(key.clone(), value);
+            }
+            for (aggregation, rollup) in self.aggregations.iter().zip(&group.rollups) {
+                let value = match aggregation {
+                    Aggregation::Count => group.records.to_string(),
+                    _ if rollup.values == 0 => "null".to_string(),
+                    Aggregation::Sum(_) => rollup.sum.to_string(),
+                    Aggregation::Min(_) => rollup.min.to_string(),
+                    Aggregation::Max(_) => rollup.max.to_string(),
+                };
+                record.insert(aggregation.output_key(&self.separator), value);
+            }
+            self.inner.write_record(record)?;
+            self.written += 1;
+        }
+        Ok(())
+    }
+}
+
+impl<S: RecordSink> RecordSink for AggregateSink<S> {
+    fn write_record(&mut self, record: FlattenedJson) -> io::Result<()> {
+        let key: Vec<String> = self
+            .group_by
+            .iter()
+            .map(|key| record.get(key).map_or("null", String::as_str).to_string())
+            .collect();
+        let position = match self.index.get(&key) {
+            Some(&position) => position,
+            None => {
+                if self.sorted {
+                    self.write_groups()?;
+                }
+                self.index.insert(key.clone(), self.groups.len());
+                self.groups.push(Group {
+                    key,
+                    records: 0,
+                    rollups: vec![Rollup::EMPTY; self.aggregations.len()],
+                });
+                self.groups.len() - 1
+            }
+        };
+        let group = &mut self.groups[position];
+        group.records += 1;
+        for (aggregation, rollup) in self.aggregations.iter().zip(&mut group.rollups) {
+            let value = aggregation.key().and_then(|key| record.get(key));
+            if let Some(number) = value.and_then(|value| value.parse::<f64>().ok()).filter(|n| n.is_finite()) {
+                rollup.add(number);
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_groups()?;
+        self.inner.flush()
+    }
+
+    fn skip_document(&mut self, skipped: SkippedDocument) -> io::Result<()> {
+        self.inner.skip_document(skipped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{flatten_reader_to_sink, FlattenOptions};
+
+    #[test]
+    fn test_groups_are_rolled_up_in_first_seen_order() {
+        let input = r#"{"region": "eu", "item": {"price": 10}}
+{"region": "us", "item": {"price": 2.5}}
+{"region": "eu", "item": {"price": "n/a"}}
+{"item": {"price": -4}}
+{"region": "eu", "item": {"price": 7}}
+"#;
+        let aggregations: Vec<Aggregation> = ["count", "sum:item.price", "min:item.price", "max:item.price"]
+            .iter()
+            .map(|aggregation| aggregation.parse().unwrap())
+            .collect();
+        let mut sink = AggregateSink::new(Vec::new(), ["region"], aggregations.clone());
+        flatten_reader_to_sink(input.as_bytes(), &mut sink, &FlattenOptions::default()).unwrap();
+        assert_eq!(sink.groups_written(), 3);
+        let groups = sink.into_inner();
+        let row = |i: usize| {
+            let keys = ["region", "count", "item.price.sum", "item.price.min", "item.price.max"];
+            keys.map(|key| groups[i][key].as_str())
+        };
+        assert_eq!(row(0), ["eu", "3", "17", "7", "10"]);
+        assert_eq!(row(1), ["us", "1", "2.5", "2.5", "2.5"]);
+        assert_eq!(row(2), ["null", "1", "-4", "-4", "-4"]);
+
+        let mut sorted = AggregateSink::new(Vec::new(), ["region"], aggregations).with_sorted_input();
+        flatten_reader_to_sink(input.as_bytes(), &mut sorted, &FlattenOptions::default()).unwrap();
+        let regions: Vec<&str> = sorted.inner.iter().map(|group| group["region"].as_str()).collect();
+        assert_eq!(regions, ["eu", "us", "eu", "null", "eu"]);
+        assert!("avg:price".parse::<Aggregation>().unwrap_err().starts_with("unknown aggregation"));
+    }
+}
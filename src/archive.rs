@@ -0,0 +1,215 @@
+// src/archive.rs
+//! Flattening every JSON member of a zip or tar archive
+use crate::{flatten_reader_to_sink, FlattenOptions, FlattenedJson, RecordSink, SkippedDocument};
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+
+/// Key holding the file name of the archive a record was read from
+pub const ARCHIVE_KEY: &str = "_archive";
+
+/// Key holding the path of the member a record was read from
+pub const MEMBER_KEY: &str = "_member";
+
+/// Archive layouts [`flatten_archive_to_sink`] can read
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Zip,
+    Tar,
+    TarGz,
+    TarZst,
+}
+
+impl ArchiveFormat {
+    /// The format named by a path's extension: `.zip`, `.tar`, `.tar.gz` or
+    /// `.tgz`, `.tar.zst` or `.tzst`
+    pub fn from_path(filepath: impl AsRef<Path>) -> Option<Self> {
+        let name = filepath.as_ref().file_name()?.to_str()?.to_ascii_lowercase();
+        if name.ends_with(".zip") {
+            Some(ArchiveFormat::Zip)
+        } else if name.ends_with(".tar") {
+            Some(ArchiveFormat::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(ArchiveFormat::TarGz)
+        } else if name.ends_with(".tar.zst") || name.ends_with(".tzst") {
+            Some(ArchiveFormat::TarZst)
+        } else {
+            None
+        }
+    }
+}
+
+/// Whether an archive member is read: `.json`, `.ndjson` and `.jsonl` files,
+/// except the resource forks macOS adds to zips (`__MACOSX/`, `._name`)
+pub fn is_json_member(name: &str) -> bool {
+    let file_name = name.rsplit('/').next().unwrap_or(name);
+    let extension = file_name.rsplit_once('.').map(|(_, extension)| extension.to_ascii_lowercase());
+    let json = matches!(extension.as_deref(), Some("json" | "ndjson" | "jsonl"));
+    json && !name.starts_with("__MACOSX/") && !file_name.starts_with("._")
+}
+
+/// Sink that adds the provenance keys to each record, leaving the final
+/// flush to the caller so CSV headers cover every member
+struct ProvenanceSink<'a, S: RecordSink> {
+    inner: &'a mut S,
+    archive: &'a str,
+    member: &'a str,
+}
+
+impl<S: RecordSink> RecordSink for ProvenanceSink<'_, S> {
+    fn write_record(&mut self, mut record: FlattenedJson) -> io::Result<()> {
+        record.insert(ARCHIVE_KEY.to_string(), self.archive.to_string());
+        record.insert(MEMBER_KEY.to_string(), self.member.to_string());
+        self.inner.write_record(record)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn skip_document(&mut self, skipped: SkippedDocument) -> io::Result<()> {
+        self.inner.skip_document(skipped)
+    }
+}
+
+/// Flattens every JSON or NDJSON member of a zip or tar archive (see
+/// [`ArchiveFormat::from_path`]) into a sink, in archive order, returning the
+/// number of records written
+///
+/// Each record gets the archive's file name under [`ARCHIVE_KEY`] and the
+/// member's path under [`MEMBER_KEY`], replacing keys of the same name from
+/// the document. Members are flattened one at a time like separate inputs,
+/// so sequence record ids start again at each member. Other members, like
+/// READMEs or directories, are skipped (see [`is_json_member`]).
+pub fn flatten_archive_to_sink<S: RecordSink>(
+    filepath: impl AsRef<Path>,
+    sink: &mut S,
+    options: &FlattenOptions,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let filepath = filepath.as_ref();
+    let Some(format) = ArchiveFormat::from_path(filepath) else {
+        return Err(format!("{} is not a .zip, .tar, .tar.gz or .tar.zst archive", filepath.display()).into());
+    };
+    let archive = filepath.file_name().unwrap_or_default().to_string_lossy().into_owned();
+    let file = File::open(filepath)?;
+
+    let mut written = 0;
+    let mut flatten_member = |member: &str, reader: &mut dyn Read| {
+        let mut sink = ProvenanceSink {
+            inner: &mut *sink,
+            archive: &archive,
+            member,
+        };
+        flatten_reader_to_sink(reader, &mut sink, options).map_err(|e| format!("{}: {}", member, e))
+    };
+    match format {
+        ArchiveFormat::Zip => {
+            let mut zip = zip::ZipArchive::new(BufReader::new(file))?;
+            for i in 0..zip.len() {
+                let mut member = zip.by_index(i)?;
+                let name = member.name().to_string();
+                if member.is_file() && is_json_member(&name) {
+                    written += flatten_member(&name, &mut member)?;
+                }
+            }
+        }
+        ArchiveFormat::Tar | ArchiveFormat::TarGz | ArchiveFormat::TarZst => {
+            let reader: Box<dyn Read> = match format {
+                ArchiveFormat::TarGz => Box::new(flate2::read::MultiGzDecoder::new(BufReader::new(file))),
+                ArchiveFormat::TarZst => Box::new(zstd::stream::read::Decoder::new(file)?),
+                _ => Box::new(BufReader::new(file)),
+            };
+            let mut tar = tar::Archive::new(reader);
+            for member in tar.entries()? {
+                let mut member = member?;
+                let name = member.path()?.to_string_lossy().into_owned();
+                if member.header().entry_type().is_file() && is_json_member(&name) {
+                    written += flatten_member(&name, &mut member)?;
+                }
+            }
+        }
+    }
+
+    sink.flush()?;
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    const MEMBERS: [(&str, &str); 4] = [
+        ("day1/a.json", r#"[{"id": 1}, {"id": 2}]"#),
+        ("day1/README.txt", "not json"),
+        ("__MACOSX/day1/._a.json", "\0\0"),
+        ("day1/b.ndjson", "{\"id\": 3}\n"),
+    ];
+
+    fn split() -> FlattenOptions {
+        FlattenOptions {
+            split_root_array: true,
+            ..Default::default()
+        }
+    }
+
+    fn provenance(records: &[FlattenedJson]) -> Vec<(&str, &str, &str)> {
+        records
+            .iter()
+            .map(|record| (record[ARCHIVE_KEY].as_str(), record[MEMBER_KEY].as_str(), record["id"].as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn test_zip_members_are_flattened_with_provenance() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("export.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&path).unwrap());
+        for (name, content) in MEMBERS {
+            zip.start_file(name, zip::write::SimpleFileOptions::default()).unwrap();
+            zip.write_all(content.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+
+        let mut records = Vec::new();
+        assert_eq!(flatten_archive_to_sink(&path, &mut records, &split()).unwrap(), 3);
+        assert_eq!(
+            provenance(&records),
+            [
+                ("export.zip", "day1/a.json", "1"),
+                ("export.zip", "day1/a.json", "2"),
+                ("export.zip", "day1/b.ndjson", "3")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tar_gz_members_are_flattened_with_provenance() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("export.tgz");
+        let gz = flate2::write::GzEncoder::new(File::create(&path).unwrap(), flate2::Compression::default());
+        let mut tar = tar::Builder::new(gz);
+        for (name, content) in MEMBERS {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            tar.append_data(&mut header, name, content.as_bytes()).unwrap();
+        }
+        tar.into_inner().unwrap().finish().unwrap();
+
+        let mut records = Vec::new();
+        flatten_archive_to_sink(&path, &mut records, &split()).unwrap();
+        let members: Vec<_> = provenance(&records).into_iter().map(|(_, member, id)| (member, id)).collect();
+        assert_eq!(members, [("day1/a.json", "1"), ("day1/a.json", "2"), ("day1/b.ndjson", "3")]);
+
+        let bad = dir.path().join("bad.tar");
+        let mut tar = tar::Builder::new(File::create(&bad).unwrap());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(5);
+        tar.append_data(&mut header, "broken.json", &b"{\"a\":"[..]).unwrap();
+        tar.finish().unwrap();
+        let error = flatten_archive_to_sink(&bad, &mut Vec::new(), &FlattenOptions::default()).unwrap_err();
+        assert!(error.to_string().starts_with("broken.json: "));
+        assert_eq!(ArchiveFormat::from_path("x.JSON"), None);
+    }
+}
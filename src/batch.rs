@@ -0,0 +1,908 @@
+// src/batch.rs
+use crate::duplicates::{note_duplicates, parse_document, DuplicateKeys};
+use crate::input::{
+    open_input_checked, read_located_documents, InputPosition, LocatedDocument, LocatedDocumentStream, LocatedLine,
+};
+#[cfg(feature = "parallel")]
+use crate::input::{LineBlocks, LineSplitter};
+#[cfg(feature = "parallel")]
+use crate::progress::{ChunkTracker, PipelineEnd};
+use crate::raw::RawJson;
+use crate::{
+    flatten_input_document, FlattenOptions, FlattenedJson, InvalidDocuments, Outcome, ParseError, RecordIdSource,
+    SkipReason, SkippedDocument,
+};
+use crate::par::*;
+#[cfg(feature = "parallel")]
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use serde_json::value::RawValue;
+use serde_json::Value;
+use std::io::Read;
+use std::path::Path;
+use std::iter::Peekable;
+#[cfg(feature = "parallel")]
+use std::sync::Arc;
+
+/// One batch of flattened records, or the error that ended the input
+pub type BatchResult = Result<Vec<FlattenedJson>, Box<dyn std::error::Error>>;
+
+/// A document that can be turned into a `Value` on a parse thread
+pub trait IntoDocument: Send {
+    fn into_document(self) -> Result<Value, serde_json::Error>;
+
+    /// Like `into_document`, also returning the paths of repeated object keys
+    /// per `policy`; documents that are already values have none
+    fn into_checked_document(self, policy: DuplicateKeys) -> Result<(Value, Vec<String>), serde_json::Error>
+    where
+        Self: Sized,
+    {
+        let _ = policy;
+        Ok((self.into_document()?, Vec::new()))
+    }
+
+    /// The document's JSON text, for `raw_values`; values are serialized
+    fn into_json_text(self) -> Result<Box<str>, serde_json::Error>
+    where
+        Self: Sized,
+    {
+        Ok(serde_json::to_string(&self.into_document()?)?.into_boxed_str())
+    }
+
+    /// Size of the serialized document, if known without serializing it
+    fn byte_len(&self) -> Option<usize> {
+        None
+    }
+
+    /// The document's JSON text, if it has one, for locating parse errors
+    fn text(&self) -> Option<&str> {
+        None
+    }
+
+    /// Where the document starts in its input, if known
+    fn start(&self) -> Option<InputPosition> {
+        None
+    }
+}
+
+impl IntoDocument for Value {
+    fn into_document(self) -> Result<Value, serde_json::Error> {
+        Ok(self)
+    }
+}
+
+impl IntoDocument for Box<RawValue> {
+    fn into_document(self) -> Result<Value, serde_json::Error> {
+        serde_json::from_str(self.get())
+    }
+
+    fn into_checked_document(self, policy: DuplicateKeys) -> Result<(Value, Vec<String>), serde_json::Error> {
+        parse_document(self.get(), policy)
+    }
+
+    fn into_json_text(self) -> Result<Box<str>, serde_json::Error> {
+        Ok(self.into())
+    }
+
+    fn byte_len(&self) -> Option<usize> {
+        Some(self.get().len())
+    }
+
+    fn text(&self) -> Option<&str> {
+        Some(self.get())
+    }
+}
+
+impl IntoDocument for Box<str> {
+    fn into_document(self) -> Result<Value, serde_json::Error> {
+        serde_json::from_str(&self)
+    }
+
+    fn into_checked_document(self, policy: DuplicateKeys) -> Result<(Value, Vec<String>), serde_json::Error> {
+        parse_document(&self, policy)
+    }
+
+    fn into_json_text(self) -> Result<Box<str>, serde_json::Error> {
+        Ok(self)
+    }
+
+    fn byte_len(&self) -> Option<usize> {
+        Some(self.len())
+    }
+
+    fn text(&self) -> Option<&str> {
+        Some(self)
+    }
+}
+
+impl IntoDocument for String {
+    fn into_document(self) -> Result<Value, serde_json::Error> {
+        serde_json::from_str(&self)
+    }
+
+    fn into_checked_document(self, policy: DuplicateKeys) -> Result<(Value, Vec<String>), serde_json::Error> {
+        parse_document(&self, policy)
+    }
+
+    fn into_json_text(self) -> Result<Box<str>, serde_json::Error> {
+        Ok(self.into_boxed_str())
+    }
+
+    fn byte_len(&self) -> Option<usize> {
+        Some(self.len())
+    }
+
+    fn text(&self) -> Option<&str> {
+        Some(self)
+    }
+}
+
+impl IntoDocument for LocatedDocument {
+    fn into_document(self) -> Result<Value, serde_json::Error> {
+        self.text.into_document()
+    }
+
+    fn into_checked_document(self, policy: DuplicateKeys) -> Result<(Value, Vec<String>), serde_json::Error> {
+        parse_document(&self.text, policy)
+    }
+
+    fn into_json_text(self) -> Result<Box<str>, serde_json::Error> {
+        Ok(self.text)
+    }
+
+    fn byte_len(&self) -> Option<usize> {
+        Some(self.text.len())
+    }
+
+    fn text(&self) -> Option<&str> {
+        Some(&self.text)
+    }
+
+    fn start(&self) -> Option<InputPosition> {
+        self.start
+    }
+}
+
+impl IntoDocument for LocatedLine<'_> {
+    fn into_document(self) -> Result<Value, serde_json::Error> {
+        serde_json::from_str(&self.text)
+    }
+
+    fn into_checked_document(self, policy: DuplicateKeys) -> Result<(Value, Vec<String>), serde_json::Error> {
+        parse_document(&self.text, policy)
+    }
+
+    fn into_json_text(self) -> Result<Box<str>, serde_json::Error> {
+        Ok(self.text.into())
+    }
+
+    fn byte_len(&self) -> Option<usize> {
+        Some(self.text.len())
+    }
+
+    fn text(&self) -> Option<&str> {
+        Some(&self.text)
+    }
+
+    fn start(&self) -> Option<InputPosition> {
+        Some(self.start)
+    }
+}
+
+/// Parses a document, locating a syntax error in its input
+fn parse_located<D: IntoDocument>(document: D, policy: DuplicateKeys) -> Result<(Value, Vec<String>), ParseError> {
+    if let Some(text) = document.text() {
+        return parse_document(text, policy).map_err(|e| ParseError::locate(&e, text, document.start()));
+    }
+    document.into_checked_document(policy).map_err(|e| ParseError::locate(&e, "", None))
+}
+
+/// A document that is not valid JSON, set aside under [`InvalidDocuments::Skip`]
+///
+/// Only sequence and UUID record ids can be given, as there is no document to
+/// take a path or hash from.
+fn invalid_document(error: &ParseError, options: &FlattenOptions, sequence: u64) -> Outcome {
+    let record_id = match options.record_id {
+        RecordIdSource::Sequence | RecordIdSource::Uuid => options.record_id.record_id(&Value::Null, sequence).ok(),
+        _ => None,
+    };
+    Outcome::Skipped(SkippedDocument {
+        document: sequence,
+        record_id: record_id.flatten(),
+        reason: SkipReason::Parse(error.clone()),
+    })
+}
+
+/// The first parse error of a chunk, unless invalid documents are skipped
+fn first_parse_error<T>(parsed: &[Result<T, ParseError>], options: &FlattenOptions) -> Result<(), ParseError> {
+    match parsed.iter().find_map(|parsed| parsed.as_ref().err()) {
+        Some(error) if options.invalid_documents == InvalidDocuments::Error => Err(error.clone()),
+        _ => Ok(()),
+    }
+}
+
+/// Thread pools for the parse and flatten stages
+///
+/// A stage runs on the current rayon pool (the global one, outside any
+/// `install`) when its thread count matches that pool's size, so the default
+/// options never spawn extra threads; `local_pools` always builds its own.
+/// Without the `parallel` feature both stages run on the calling thread.
+#[derive(Clone, Default)]
+pub(crate) struct StagePools {
+    #[cfg(feature = "parallel")]
+    parse: Option<Arc<ThreadPool>>,
+    #[cfg(feature = "parallel")]
+    flatten: Option<Arc<ThreadPool>>,
+}
+
+#[cfg(feature = "parallel")]
+impl StagePools {
+    pub(crate) fn new(options: &FlattenOptions) -> Result<Self, ThreadPoolBuildError> {
+        Ok(StagePools {
+            parse: Self::build(options.parse_thread_count(), options.local_pools)?,
+            flatten: Self::build(options.flatten_thread_count(), options.local_pools)?,
+        })
+    }
+
+    fn build(threads: usize, local: bool) -> Result<Option<Arc<ThreadPool>>, ThreadPoolBuildError> {
+        if !local && threads == rayon::current_num_threads() {
+            return Ok(None);
+        }
+        let pool = ThreadPoolBuilder::new().num_threads(threads).build()?;
+        Ok(Some(Arc::new(pool)))
+    }
+
+    /// Runs `op` on the parse pool
+    pub(crate) fn parse<R: Send>(&self, op: impl FnOnce() -> R + Send) -> R {
+        match &self.parse {
+            Some(pool) => pool.install(op),
+            None => op(),
+        }
+    }
+
+    /// Runs `op` on the flatten pool
+    pub(crate) fn flatten<R: Send>(&self, op: impl FnOnce() -> R + Send) -> R {
+        match &self.flatten {
+            Some(pool) => pool.install(op),
+            None => op(),
+        }
+    }
+}
+
+#[cfg(not(feature = "parallel"))]
+impl StagePools {
+    pub(crate) fn new(_options: &FlattenOptions) -> Result<Self, ThreadPoolBuildError> {
+        Ok(StagePools {})
+    }
+
+    /// Runs `op` on the calling thread
+    pub(crate) fn parse<R: Send>(&self, op: impl FnOnce() -> R + Send) -> R {
+        op()
+    }
+
+    /// Runs `op` on the calling thread
+    pub(crate) fn flatten<R: Send>(&self, op: impl FnOnce() -> R + Send) -> R {
+        op()
+    }
+}
+
+/// Iterator adapter that groups documents into flattened batches
+///
+/// Each batch is parsed on the parse pool and flattened on the flatten pool,
+/// preserving input order. If the underlying iterator yields an error, the
+/// documents collected so far are returned as a final batch first and the
+/// error is yielded on the next call.
+pub struct FlattenBatches<I: Iterator> {
+    documents: Peekable<I>,
+    batch_size: usize,
+    byte_budget: Option<usize>,
+    options: FlattenOptions,
+    pools: StagePools,
+    pending_error: Option<Box<dyn std::error::Error>>,
+    skip: u64,
+    next_sequence: u64,
+    filtered: u64,
+    skipped: Vec<SkippedDocument>,
+    done: bool,
+}
+
+impl<I: Iterator> FlattenBatches<I> {
+    /// Creates a batching iterator; a `batch_size` of 0 is treated as 1
+    pub fn new(documents: I, batch_size: usize, options: FlattenOptions) -> Self {
+        match StagePools::new(&options) {
+            Ok(pools) => Self::with_pools(documents, batch_size, options, pools),
+            Err(e) => {
+                let mut batches = Self::with_pools(documents, batch_size, options, StagePools::default());
+                batches.pending_error = Some(Box::new(e));
+                batches
+            }
+        }
+    }
+
+    /// Creates a batching iterator that runs on existing stage pools
+    pub(crate) fn with_pools(documents: I, batch_size: usize, options: FlattenOptions, pools: StagePools) -> Self {
+        let skip = options.skip_documents;
+        FlattenBatches {
+            documents: documents.peekable(),
+            batch_size: batch_size.max(1),
+            byte_budget: None,
+            options,
+            pools,
+            pending_error: None,
+            skip,
+            next_sequence: skip,
+            filtered: 0,
+            skipped: Vec::new(),
+            done: false,
+        }
+    }
+
+    /// Records left out of the batches so far by `filters`
+    pub fn filtered(&self) -> u64 {
+        self.filtered
+    }
+
+    /// Takes the documents a `Skip` policy left out of the batches since the
+    /// last call, in input order
+    pub fn take_skipped(&mut self) -> Vec<SkippedDocument> {
+        std::mem::take(&mut self.skipped)
+    }
+
+    /// Also limits each batch to `bytes` of serialized documents
+    ///
+    /// A document larger than the budget forms a batch on its own. Documents
+    /// whose size is unknown (already parsed values) count as zero bytes.
+    pub fn with_byte_budget(mut self, bytes: usize) -> Self {
+        self.byte_budget = Some(bytes.max(1));
+        self
+    }
+}
+
+impl<I, D, E> Iterator for FlattenBatches<I>
+where
+    I: Iterator<Item = Result<D, E>>,
+    D: IntoDocument,
+    E: Into<Box<dyn std::error::Error>>,
+{
+    type Item = BatchResult;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(e) = self.pending_error.take() {
+            self.done = true;
+            return Some(Err(e));
+        }
+        if self.done {
+            return None;
+        }
+
+        match skip_documents(&mut self.documents, std::mem::take(&mut self.skip)) {
+            Ok(true) => {}
+            Ok(false) => self.done = true,
+            Err(e) => self.pending_error = Some(e.into()),
+        }
+        if self.done || self.pending_error.is_some() {
+            return self.next();
+        }
+
+        let mut batch = Vec::with_capacity(self.batch_size.min(1024));
+        match fill_batch(&mut self.documents, self.batch_size, self.byte_budget, &mut batch) {
+            Ok(true) => {}
+            Ok(false) => self.done = true,
+            Err(e) => self.pending_error = Some(e.into()),
+        }
+
+        if batch.is_empty() {
+            return self.next();
+        }
+
+        let first_sequence = self.next_sequence;
+        self.next_sequence += batch.len() as u64;
+        let records = flatten_chunk(batch, &self.options, &self.pools, first_sequence).map(|chunk| {
+            self.filtered += chunk.filtered as u64;
+            self.skipped.extend(chunk.skipped);
+            chunk.records
+        });
+        if records.is_err() {
+            self.done = true;
+        }
+        Some(records.map_err(|e| -> Box<dyn std::error::Error> { e }))
+    }
+}
+
+/// Drops the first `count` documents, returning whether more may follow
+fn skip_documents<I, D, E>(documents: &mut I, count: u64) -> Result<bool, E>
+where
+    I: Iterator<Item = Result<D, E>>,
+{
+    for _ in 0..count {
+        match documents.next() {
+            Some(Ok(_)) => {}
+            Some(Err(e)) => return Err(e),
+            None => return Ok(false),
+        }
+    }
+    Ok(true)
+}
+
+/// Moves the next batch of documents into `batch`
+///
+/// Returns whether more documents may follow, or the error that ended the
+/// input; `batch` then holds the documents read before the error.
+fn fill_batch<I, D, E>(
+    documents: &mut Peekable<I>,
+    batch_size: usize,
+    byte_budget: Option<usize>,
+    batch: &mut Vec<D>,
+) -> Result<bool, E>
+where
+    I: Iterator<Item = Result<D, E>>,
+    D: IntoDocument,
+{
+    let mut batch_bytes = 0;
+    while batch.len() < batch_size {
+        if let (Some(budget), Some(Ok(document))) = (byte_budget, documents.peek()) {
+            let size = document.byte_len().unwrap_or(0);
+            if !batch.is_empty() && batch_bytes + size > budget {
+                break;
+            }
+        }
+        match documents.next() {
+            Some(Ok(document)) => {
+                batch_bytes += document.byte_len().unwrap_or(0);
+                batch.push(document);
+            }
+            Some(Err(e)) => return Err(e),
+            None => return Ok(false),
+        }
+    }
+    Ok(true)
+}
+
+/// Records of a chunk that pass `filters`, how many did not, and the
+/// documents a `Skip` policy left out
+pub(crate) struct FlattenedChunk {
+    pub(crate) records: Vec<FlattenedJson>,
+    pub(crate) filtered: usize,
+    pub(crate) skipped: Vec<SkippedDocument>,
+}
+
+impl FromIterator<Outcome> for FlattenedChunk {
+    fn from_iter<T: IntoIterator<Item = Outcome>>(outcomes: T) -> Self {
+        let mut chunk = FlattenedChunk {
+            records: Vec::new(),
+            filtered: 0,
+            skipped: Vec::new(),
+        };
+        for outcome in outcomes {
+            match outcome {
+                Outcome::Record(record) => chunk.records.push(record),
+                Outcome::Filtered => chunk.filtered += 1,
+                Outcome::Skipped(skipped) => chunk.skipped.push(skipped),
+            }
+        }
+        chunk
+    }
+}
+
+/// Parses a batch on the parse pool and flattens it on the flatten pool,
+/// dropping the records `filters` leave out and setting skipped documents aside
+fn flatten_chunk<D: IntoDocument>(
+    batch: Vec<D>,
+    options: &FlattenOptions,
+    pools: &StagePools,
+    first_sequence: u64,
+) -> Result<FlattenedChunk, Box<dyn std::error::Error + Send + Sync>> {
+    if options.raw_values {
+        return flatten_raw_chunk(batch, options, pools, first_sequence);
+    }
+    let values = pools.parse(|| {
+        batch
+            .into_par_iter()
+            .map(|document| parse_located(document, options.duplicate_keys))
+            .collect::<Vec<_>>()
+    });
+    first_parse_error(&values, options)?;
+
+    let outcomes = pools.flatten(|| {
+        values
+            .par_iter()
+            .enumerate()
+            .map(|(i, parsed)| {
+                let sequence = first_sequence + i as u64;
+                let (value, duplicates) = match parsed {
+                    Ok(parsed) => parsed,
+                    Err(error) => return Ok(invalid_document(error, options, sequence)),
+                };
+                let mut outcome = flatten_input_document(value, options, sequence)?;
+                if let Outcome::Record(record) = &mut outcome {
+                    note_duplicates(record, duplicates);
+                }
+                Ok(outcome.filter(options))
+            })
+            .collect::<Result<Vec<_>, crate::FlattenError>>()
+    })?;
+    Ok(outcomes.into_iter().collect())
+}
+
+/// [`flatten_chunk`] under `raw_values`, keeping the text of every document
+/// for the records that borrow from it
+fn flatten_raw_chunk<D: IntoDocument>(
+    batch: Vec<D>,
+    options: &FlattenOptions,
+    pools: &StagePools,
+    first_sequence: u64,
+) -> Result<FlattenedChunk, Box<dyn std::error::Error + Send + Sync>> {
+    let texts = batch
+        .into_iter()
+        .map(|document| Ok((document.start(), document.into_json_text()?)))
+        .collect::<Result<Vec<_>, serde_json::Error>>()?;
+    let documents = pools.parse(|| {
+        texts
+            .par_iter()
+            .map(|(start, text)| RawJson::parse(text).map_err(|e| ParseError::locate(&e, text, *start)))
+            .collect::<Vec<_>>()
+    });
+    first_parse_error(&documents, options)?;
+
+    let outcomes = pools.flatten(|| {
+        documents
+            .par_iter()
+            .enumerate()
+            .map(|(i, parsed)| match parsed {
+                Ok(document) => flatten_input_document(document, options, first_sequence + i as u64)
+                    .map(|outcome| outcome.filter(options)),
+                Err(error) => Ok(invalid_document(error, options, first_sequence + i as u64)),
+            })
+            .collect::<Result<Vec<_>, _>>()
+    })?;
+    Ok(outcomes.into_iter().collect())
+}
+
+/// What the reader thread of [`flatten_pipelined`] hands to the flatten thread
+#[cfg(feature = "parallel")]
+enum Chunk {
+    Documents(Vec<LocatedDocument>),
+    /// A block of whole lines under `ndjson`, split on the flatten thread
+    Lines(Vec<u8>),
+}
+
+/// Flattens a reader into a sink with the stages on separate scoped threads
+///
+/// A reader thread splits the input into chunks of raw documents, a flatten
+/// thread parses and flattens each chunk on the stage pools, and the calling
+/// thread writes the records to the sink, so reading overlaps with
+/// flattening and writing. The stages are connected by channels holding one
+/// chunk each. When any stage fails it drops its ends of the channels, and its
+/// neighbours stop at their next send or receive. This means a failing sink
+/// also stops the reader. A reader blocked inside `read` finishes that call
+/// first. Running out of `time_budget` stops the stages the same way, after
+/// the sink is flushed.
+///
+/// Under `ndjson` the reader thread only cuts the input into blocks of whole
+/// lines; the flatten thread splits out the lines, which stay borrowed from
+/// their block while the pools parse them.
+#[cfg(feature = "parallel")]
+pub(crate) fn flatten_pipelined<R: Read + Send, S: crate::RecordSink>(
+    reader: R,
+    sink: &mut S,
+    options: &FlattenOptions,
+    pools: &StagePools,
+) -> Result<PipelineEnd, Box<dyn std::error::Error>> {
+    options.validate()?;
+    let (batch_size, byte_budget) = match options.chunk_bytes {
+        0 => (options.chunk_size.max(1), None),
+        bytes => (usize::MAX, Some(bytes)),
+    };
+
+    std::thread::scope(|scope| {
+        let (chunk_tx, chunk_rx) = crossbeam_channel::bounded(1);
+        let (records_tx, records_rx) = crossbeam_channel::bounded(1);
+
+        scope.spawn(move || {
+            if options.ndjson {
+                for block in LineBlocks::new(reader, options) {
+                    let failed = block.is_err();
+                    if chunk_tx.send(block.map(Chunk::Lines)).is_err() || failed {
+                        return;
+                    }
+                }
+                return;
+            }
+            let mut documents = read_located_documents(reader, options).peekable();
+            match skip_documents(&mut documents, options.skip_documents) {
+                Ok(true) => {}
+                Ok(false) => return,
+                Err(e) => {
+                    let _ = chunk_tx.send(Err(e));
+                    return;
+                }
+            }
+            loop {
+                let mut chunk = Vec::with_capacity(batch_size.min(1024));
+                let end = fill_batch(&mut documents, batch_size, byte_budget, &mut chunk);
+                if !chunk.is_empty() && chunk_tx.send(Ok(Chunk::Documents(chunk))).is_err() {
+                    return;
+                }
+                match end {
+                    Ok(true) => {}
+                    Ok(false) => return,
+                    Err(e) => {
+                        let _ = chunk_tx.send(Err(e));
+                        return;
+                    }
+                }
+            }
+        });
+
+        scope.spawn(move || {
+            let mut next_sequence = options.skip_documents;
+            // Lines are only counted here, so they are skipped here too
+            let mut lines_to_skip = options.skip_documents;
+            let mut splitter = LineSplitter::new(options);
+            for chunk in chunk_rx {
+                let records = chunk.map_err(Into::into).and_then(|chunk| match chunk {
+                    Chunk::Documents(chunk) => {
+                        let first_sequence = next_sequence;
+                        next_sequence += chunk.len() as u64;
+                        flatten_chunk(chunk, options, pools, first_sequence)
+                    }
+                    Chunk::Lines(block) => {
+                        let mut lines = splitter.split(&block)?;
+                        let skipped = lines.len().min(lines_to_skip.try_into().unwrap_or(usize::MAX));
+                        lines.drain(..skipped);
+                        lines_to_skip -= skipped as u64;
+                        let first_sequence = next_sequence;
+                        next_sequence += lines.len() as u64;
+                        flatten_chunk(lines, options, pools, first_sequence)
+                    }
+                });
+                let failed = records.is_err();
+                if records_tx.send(records).is_err() || failed {
+                    return;
+                }
+            }
+        });
+
+        let mut checkpoint = None;
+        let mut chunks = ChunkTracker::new(options);
+        for chunk in records_rx {
+            let chunk = chunk.map_err(|e| -> Box<dyn std::error::Error> { e })?;
+            let count = chunk.records.len();
+            for record in chunk.records {
+                sink.write_record(record)?;
+            }
+            let skipped = chunk.skipped.len();
+            for document in chunk.skipped {
+                sink.skip_document(document)?;
+            }
+            checkpoint = chunks.chunk_done(count, chunk.filtered, skipped);
+            if checkpoint.is_some() {
+                break;
+            }
+        }
+        sink.flush()?;
+        Ok(chunks.finish(checkpoint))
+    })
+}
+
+/// Creates the batching iterator used for internal chunking
+///
+/// Chunks are bounded by `chunk_bytes` when set, otherwise by `chunk_size` records.
+pub(crate) fn chunk_reader<'a, R: Read + 'a>(
+    reader: R,
+    options: &FlattenOptions,
+) -> FlattenBatches<LocatedDocumentStream<'a>> {
+    if options.chunk_bytes > 0 {
+        flatten_reader_batched(reader, usize::MAX, options).with_byte_budget(options.chunk_bytes)
+    } else {
+        flatten_reader_batched(reader, options.chunk_size, options)
+    }
+}
+
+/// Like [`chunk_reader`], but runs on existing stage pools
+pub(crate) fn chunk_reader_with_pools<'a, R: Read + 'a>(
+    reader: R,
+    options: &FlattenOptions,
+    pools: StagePools,
+) -> FlattenBatches<LocatedDocumentStream<'a>> {
+    let documents = read_located_documents(reader, options);
+    if options.chunk_bytes > 0 {
+        FlattenBatches::with_pools(documents, usize::MAX, options.clone(), pools).with_byte_budget(options.chunk_bytes)
+    } else {
+        FlattenBatches::with_pools(documents, options.chunk_size, options.clone(), pools)
+    }
+}
+
+/// Creates a batching iterator over the documents of any reader
+///
+/// The reader thread only splits the input into raw documents (see
+/// [`read_located_documents`]); building the `Value` trees happens on the parse pool.
+pub fn flatten_reader_batched<'a, R: Read + 'a>(
+    reader: R,
+    batch_size: usize,
+    options: &FlattenOptions,
+) -> FlattenBatches<LocatedDocumentStream<'a>> {
+    FlattenBatches::new(read_located_documents(reader, options), batch_size, options.clone())
+}
+
+/// Flattens a JSON file into batches of at most `batch_size` records
+/// A path of `"-"` reads from standard input
+pub fn flatten_json_file_batched(
+    filepath: impl AsRef<Path>,
+    batch_size: usize,
+    options: &FlattenOptions,
+) -> Result<impl Iterator<Item = BatchResult>, Box<dyn std::error::Error>> {
+    options.validate()?;
+    Ok(flatten_reader_batched(open_input_checked(filepath, options)?, batch_size, options))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_local_pools_never_use_the_global_pool() {
+        let options = FlattenOptions {
+            max_concurrency: rayon::current_num_threads(),
+            ..Default::default()
+        };
+        let shared = StagePools::new(&options).unwrap();
+        assert!(shared.parse.is_none() && shared.flatten.is_none());
+
+        let local = StagePools::new(&FlattenOptions {
+            local_pools: true,
+            ..options
+        })
+        .unwrap();
+        assert!(local.parse.is_some() && local.flatten.is_some());
+        assert!(local.flatten(|| rayon::current_thread_index().is_some()));
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_ndjson_blocks_flatten_like_documents() {
+        let input = (0..50).map(|i| format!("{{\"id\": {}, \"tags\": [\"t{}\"]}}\n\n", i, i)).collect::<String>();
+        let options = FlattenOptions {
+            record_id: crate::RecordIdSource::Sequence,
+            skip_documents: 7,
+            chunk_bytes: 64,
+            ..Default::default()
+        };
+        let ndjson = FlattenOptions {
+            ndjson: true,
+            ..options.clone()
+        };
+        let pools = StagePools::new(&options).unwrap();
+        let (mut expected, mut records) = (Vec::new(), Vec::new());
+        flatten_pipelined(input.as_bytes(), &mut expected, &options, &pools).unwrap();
+        flatten_pipelined(input.as_bytes(), &mut records, &ndjson, &pools).unwrap();
+
+        assert_eq!(records.len(), 43);
+        assert_eq!(records, expected);
+        assert_eq!((records[0]["id"].as_str(), records[0]["_id"].as_str()), ("7", "7"));
+    }
+
+    #[test]
+    fn test_batches_respect_size_and_order() {
+        let input = (0..5).map(|i| format!("{{\"id\": {}}}\n", i)).collect::<String>();
+        let batches: Vec<Vec<FlattenedJson>> = flatten_reader_batched(input.as_bytes(), 2, &FlattenOptions::default())
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        assert_eq!(batches.iter().map(Vec::len).collect::<Vec<_>>(), vec![2, 2, 1]);
+        assert_eq!(batches[2][0].get("id"), Some(&"4".to_string()));
+    }
+
+    #[test]
+    fn test_sequence_ids_continue_across_batches() {
+        let input = "{\"a\": 1}\n".repeat(5);
+        let options = FlattenOptions {
+            record_id: crate::RecordIdSource::Sequence,
+            ..Default::default()
+        };
+        let ids: Vec<String> = flatten_reader_batched(input.as_bytes(), 2, &options)
+            .flat_map(Result::unwrap)
+            .map(|record| record["_id"].clone())
+            .collect();
+
+        assert_eq!(ids, vec!["0", "1", "2", "3", "4"]);
+    }
+
+    #[test]
+    fn test_batches_yield_partial_batch_before_error() {
+        let input = "{\"id\": 1}\n{\"id\": ";
+        let mut batches = flatten_reader_batched(input.as_bytes(), 10, &FlattenOptions::default());
+
+        assert_eq!(batches.next().unwrap().unwrap().len(), 1);
+        assert!(batches.next().unwrap().is_err());
+        assert!(batches.next().is_none());
+    }
+
+    #[test]
+    fn test_byte_budget_groups_small_records_and_isolates_large_ones() {
+        let large = format!("{{\"blob\": \"{}\"}}\n", "x".repeat(100));
+        let input = format!("{{\"id\": 1}}\n{{\"id\": 2}}\n{}{{\"id\": 3}}\n", large);
+        let options = FlattenOptions {
+            chunk_bytes: 20,
+            ..Default::default()
+        };
+        let sizes: Vec<usize> = chunk_reader(input.as_bytes(), &options)
+            .map(|batch| batch.unwrap().len())
+            .collect();
+
+        assert_eq!(sizes, vec![2, 1, 1]);
+    }
+
+    #[test]
+    fn test_batches_with_dedicated_stage_pools() {
+        let options = FlattenOptions {
+            parse_threads: 1,
+            flatten_threads: 3,
+            ..Default::default()
+        };
+        let values = (0..10).map(|i| Ok::<_, serde_json::Error>(serde_json::json!({ "id": i })));
+        let records: Vec<FlattenedJson> = FlattenBatches::new(values, 4, options)
+            .flat_map(Result::unwrap)
+            .collect();
+
+        assert_eq!(records.len(), 10);
+        assert_eq!(records[9].get("id"), Some(&"9".to_string()));
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_pipeline_writes_chunks_in_order_until_an_error() {
+        let input = "{\"id\": 0}\n{\"id\": 1}\n{\"id\": 2}\n{\"id\": ";
+        let options = FlattenOptions {
+            chunk_size: 2,
+            record_id: crate::RecordIdSource::Sequence,
+            ..Default::default()
+        };
+        let mut records = Vec::new();
+        let result = flatten_pipelined(input.as_bytes(), &mut records, &options, &StagePools::default());
+
+        assert!(result.is_err());
+        let ids: Vec<&str> = records.iter().map(|record| record["_id"].as_str()).collect();
+        assert_eq!(ids, vec!["0", "1", "2"]);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_pipeline_sink_error_stops_the_reader() {
+        /// An endless NDJSON stream
+        struct Endless(usize);
+
+        impl Read for Endless {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                let line = b"{\"a\": 1}\n";
+                for byte in buf.iter_mut() {
+                    *byte = line[self.0 % line.len()];
+                    self.0 += 1;
+                }
+                Ok(buf.len())
+            }
+        }
+
+        struct FailingSink(usize);
+
+        impl crate::RecordSink for FailingSink {
+            fn write_record(&mut self, _record: FlattenedJson) -> std::io::Result<()> {
+                self.0 += 1;
+                if self.0 == 3 {
+                    return Err(std::io::Error::other("disk full"));
+                }
+                Ok(())
+            }
+        }
+
+        let options = FlattenOptions {
+            chunk_size: 4,
+            ..Default::default()
+        };
+        let mut sink = FailingSink(0);
+        let err = flatten_pipelined(Endless(0), &mut sink, &options, &StagePools::default()).unwrap_err();
+
+        assert_eq!(err.to_string(), "disk full");
+        assert_eq!(sink.0, 3);
+    }
+}
@@ -0,0 +1,1562 @@
+// src/bin/jflat.rs
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use json_flattener::safe_keys::DEFAULT_MAX_KEY_BYTES;
+use json_flattener::schema::{
+    DriftPolicy, FlatSchema, SchemaBuilder, SchemaMode, UnexpectedColumns,
+};
+#[cfg(feature = "ipc")]
+use json_flattener::IpcSink;
+#[cfg(feature = "parquet")]
+use json_flattener::ParquetSink;
+#[cfg(feature = "xlsx")]
+use json_flattener::XlsxSink;
+use json_flattener::{
+    flatten_archive_to_sink, flatten_reader_to_sink, flatten_reader_to_sink_timed, index_keys,
+    open_compressed_output, open_input, open_output, partition_file_name, unflatten_reader,
+    validate_separator, AggregateSink, Aggregation, AmbiguousKeys, ArchiveFormat, ArrayAggregates,
+    ArrayLimitPolicy, ArrayPathMode, BinaryPolicy, CallbackSink, ColumnStatsSink, CsvSink,
+    DerivedColumn, DuplicateKeys, EavSink, EmptyKeys, ExpectedSchemaSink, Expr, FieldEscape,
+    FlattenOptions, FlattenedJson, GeoJsonMode, IndexKeys, InputEncoding, InvalidDocuments,
+    InvalidUtf8, KeyPlan, LeadingJunk, NdjsonSink, NonFiniteNumbers, NonObjectRecords,
+    OutputCompression, OversizedRecords, PartitionOverflow, PartitionedSink, PlanRule, PlanSink,
+    RateLimit, RecordIdSource, RecordSink, RowBinarySink, RulesSink, SafeKeySink, SafeKeys,
+    SchemaCheckSink, SharedRules, SingleKeyChains, SkippedDocument, SparseIndices, ThrottledSink,
+    ValidatingSink, ValueRule, CSV_HEADER_ROWS,
+};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Flatten nested JSON into dot-notation records
+///
+/// Reads JSON, concatenated JSON or NDJSON and writes one flattened record per
+/// input document. Use `-` for stdin/stdout to sit in Unix pipelines:
+///
+///     zcat x.json.gz | jflat --csv > x.csv
+#[derive(Parser, Debug)]
+#[command(name = "jflat", version, about, args_conflicts_with_subcommands = true)]
+struct Cli {
+    /// Input file, or `-` for standard input; every JSON member of a .zip,
+    /// .tar, .tar.gz or .tar.zst archive is read, with _archive and _member keys
+    #[arg(default_value = "-")]
+    input: String,
+
+    /// Output file, or `-` for standard output
+    #[arg(short, long, default_value = "-")]
+    output: String,
+
+    /// Write CSV instead of NDJSON
+    #[arg(long)]
+    csv: bool,
+
+    /// Write long format rows (record_id,key,value) instead of wide records
+    #[arg(long, conflicts_with = "csv")]
+    eav: bool,
+
+    /// Write ClickHouse RowBinaryWithNamesAndTypes instead of NDJSON
+    #[arg(long, conflicts_with_all = ["csv", "eav"])]
+    rowbinary: bool,
+
+    /// Write a JSON text sequence (RFC 7464, application/json-seq) instead of NDJSON
+    #[arg(long, conflicts_with_all = ["csv", "eav", "rowbinary"])]
+    json_seq: bool,
+
+    /// Reshape records to a schema saved by --save-schema (JSON, or YAML for .yaml/.yml)
+    #[arg(long, value_name = "PATH")]
+    expected_schema: Option<String>,
+
+    /// Values that don't fit the expected schema: enforce (fail) or coerce (to null)
+    #[arg(long, default_value_t = SchemaMode::Enforce, requires = "expected_schema")]
+    schema_mode: SchemaMode,
+
+    /// Keys outside the expected schema: drop, fail, keep or collect:<column>
+    #[arg(long, default_value_t = UnexpectedColumns::Drop, requires = "expected_schema")]
+    unexpected_columns: UnexpectedColumns,
+
+    /// Write the schema of the output records to this path after the run
+    #[arg(long, value_name = "PATH")]
+    save_schema: Option<String>,
+
+    /// Check values of matching keys: PATTERN=regex:RE, PATTERN=range:MIN..MAX
+    /// or PATTERN=enum:A,B; may be repeated
+    #[arg(long = "validate", value_name = "RULE")]
+    rules: Vec<ValueRule>,
+
+    /// Write records that fail validation here as NDJSON instead of to the output
+    #[arg(long, value_name = "PATH", requires = "rules")]
+    dead_letter: Option<String>,
+
+    /// Drop and mask keys by the JSON rules file at this path, e.g.
+    /// {"drop": ["debug.**"], "redact": ["**.ssn"]}; changes to the file
+    /// apply from the next record on
+    #[arg(long, value_name = "PATH")]
+    key_rules: Option<String>,
+
+    /// Keep, drop, rename or coerce keys: keep:PATTERN, drop:PATTERN,
+    /// rename:PATTERN=KEY or coerce:PATTERN=TYPE, with glob patterns or
+    /// re:REGEX; compiled once, after --key-rules; may be repeated
+    #[arg(long = "plan", value_name = "RULE")]
+    plan: Vec<PlanRule>,
+
+    /// Print the compiled --plan rules to stderr before flattening
+    #[arg(long, requires = "plan")]
+    show_plan: bool,
+
+    /// Write per-column null rates, distinct counts and distributions as JSON to this path
+    #[arg(long, value_name = "PATH")]
+    quality_report: Option<String>,
+
+    /// Keep the N records with the most keys, and their positions, in the quality report
+    #[arg(long, value_name = "N", requires = "quality_report")]
+    top_widest: Option<usize>,
+
+    /// Count records per value of this key in the quality report, e.g.
+    /// `status`; may be repeated
+    #[arg(long = "value-counts", value_name = "KEY", requires = "quality_report")]
+    value_counts: Vec<String>,
+
+    /// Collect the distinct values of columns matching this pattern in the
+    /// quality report, e.g. `items.*.unit`; may be repeated
+    #[arg(
+        long = "distinct-values",
+        value_name = "PATTERN",
+        requires = "quality_report"
+    )]
+    distinct_values: Vec<String>,
+
+    /// Distinct values kept per column before it is marked as overflowing
+    #[arg(long, value_name = "N", default_value_t = 100)]
+    max_distinct_values: usize,
+
+    /// Add a type column to the long format output
+    #[arg(long, requires = "eav")]
+    eav_types: bool,
+
+    /// Write at most this many records per second, to spare downstream systems
+    #[arg(long, value_name = "N")]
+    max_records_per_second: Option<f64>,
+
+    /// Write at most this many key and value bytes per second
+    #[arg(long, value_name = "BYTES")]
+    max_bytes_per_second: Option<f64>,
+
+    /// Stop after the chunk in progress once this many seconds have passed,
+    /// printing the --skip-documents value that carries on from there
+    #[arg(long, value_name = "SECS", value_parser = parse_seconds)]
+    time_budget: Option<Duration>,
+
+    /// Skip this many documents at the start of the input, e.g. to carry on
+    /// after --time-budget ran out
+    #[arg(long, value_name = "N", default_value_t = 0, hide_default_value = true)]
+    skip_documents: u64,
+
+    /// Write one file per value of this flattened key into the --output
+    /// directory, e.g. `event.type`
+    #[arg(long, value_name = "KEY")]
+    partition_by: Option<String>,
+
+    /// Most partition files open at once
+    #[arg(long, default_value_t = 128, requires = "partition_by")]
+    max_open_files: usize,
+
+    /// New partitions beyond --max-open-files: shared[:NAME] (one file for
+    /// all of them), evict (close the least recently written file) or error
+    #[arg(long, default_value_t = PartitionOverflow::default(), requires = "partition_by")]
+    partition_overflow: PartitionOverflow,
+
+    /// Write one record per group of these flattened keys instead of the
+    /// records, with the --aggregate rollups of each (repeatable)
+    #[arg(long, value_name = "KEY")]
+    group_by: Vec<String>,
+
+    /// Rollup per group: count, sum:KEY, min:KEY or max:KEY (repeatable;
+    /// count by default)
+    #[arg(long, value_name = "AGG", requires = "group_by")]
+    aggregate: Vec<Aggregation>,
+
+    /// Input is sorted by the --group-by keys: write each group when the
+    /// next one starts instead of holding every group until the end
+    #[arg(long, requires = "group_by")]
+    sorted_groups: bool,
+
+    /// Compress the output (inferred from a .gz/.zst output path by default)
+    #[arg(long, value_enum)]
+    compress: Option<Codec>,
+
+    /// Compression level (gzip 0-9, zstd 1-22)
+    #[arg(long)]
+    compression_level: Option<i32>,
+
+    /// Worker threads for zstd compression (0 compresses on the writing thread)
+    #[arg(long, default_value_t = 0)]
+    compression_threads: u32,
+
+    /// Write NDJSON keys in sorted order (implied by --canonical)
+    #[arg(long)]
+    sort_keys: bool,
+
+    #[command(flatten)]
+    delimited: DelimitedArgs,
+
+    #[command(flatten)]
+    safe_keys: SafeKeyArgs,
+
+    #[command(flatten)]
+    flatten: FlattenArgs,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+/// CSV and EAV output settings, shared by the main command, `convert` and `dir`
+#[derive(Args, Debug)]
+struct DelimitedArgs {
+    /// How CSV and EAV fields holding commas or line breaks are written:
+    /// quote, backslash (`\,` `\n`) or replace[:TEXT] (default a space), for
+    /// loaders that can't read quoted fields
+    #[arg(long, value_name = "ESCAPE", default_value_t = FieldEscape::Quote)]
+    field_escape: FieldEscape,
+
+    /// Records the CSV and RowBinary headers are built from, unless a schema gives them
+    #[arg(long, value_name = "N", default_value_t = CSV_HEADER_ROWS)]
+    csv_header_rows: usize,
+
+    /// Keys of later records that are not in the CSV header: fail, drop or
+    /// collect:<column> (a JSON object of them in one more column)
+    #[arg(long, value_name = "POLICY", default_value_t = UnexpectedColumns::Fail)]
+    csv_new_columns: UnexpectedColumns,
+}
+
+/// Key renaming for databases, shared by the main command, `convert` and `dir`
+#[derive(Args, Debug)]
+struct SafeKeyArgs {
+    /// Write keys as identifiers of ASCII letters, digits and `_`, at most
+    /// --max-key-bytes long, with a JSON sidecar mapping each key to its column
+    #[arg(long)]
+    safe_keys: bool,
+
+    /// Longest --safe-keys column in bytes (0 for no limit); longer ones are
+    /// cut short and end in a hash of the key
+    #[arg(long, value_name = "BYTES", default_value_t = DEFAULT_MAX_KEY_BYTES, requires = "safe_keys")]
+    max_key_bytes: usize,
+
+    /// Where --safe-keys writes the key to column mapping [default: OUTPUT.columns.json]
+    #[arg(long, value_name = "PATH", requires = "safe_keys")]
+    key_mapping: Option<String>,
+}
+
+impl SafeKeyArgs {
+    /// The renaming of --safe-keys, if set, and `schema` with its columns
+    /// renamed the same way for output sinks that take one
+    fn keys(&self, schema: Option<FlatSchema>) -> (Option<SafeKeys>, Option<FlatSchema>) {
+        if !self.safe_keys {
+            return (None, schema);
+        }
+        let mut keys = SafeKeys::new(self.max_key_bytes);
+        let schema = schema.map(|schema| {
+            schema
+                .iter()
+                .map(|(column, column_type)| (keys.column(column).to_string(), *column_type))
+                .collect()
+        });
+        (Some(keys), schema)
+    }
+
+    /// Renames keys before they reach `sink`, writing the mapping next to `output`
+    fn wrap<'a>(
+        &self,
+        sink: Box<dyn RecordSink + 'a>,
+        keys: Option<SafeKeys>,
+        output: &str,
+    ) -> Result<Box<dyn RecordSink + 'a>, String> {
+        let Some(keys) = keys else { return Ok(sink) };
+        let mapping = match (&self.key_mapping, output) {
+            (Some(path), _) => path.clone(),
+            (None, "-") => {
+                return Err("--safe-keys with standard output needs --key-mapping".into())
+            }
+            (None, output) => format!("{}.columns.json", output),
+        };
+        Ok(Box::new(
+            SafeKeySink::new(sink, keys).with_mapping_file(mapping),
+        ))
+    }
+}
+
+/// Options that control flattening, shared by every command
+#[derive(Args, Debug)]
+struct FlattenArgs {
+    /// Separator used in the flattened keys
+    #[arg(short, long, default_value = ".")]
+    separator: String,
+
+    /// Escape separator characters inside source keys with a backslash
+    #[arg(long)]
+    escape_keys: bool,
+
+    /// Maximum nested depth to process (0 means no limit)
+    #[arg(long, default_value_t = 0)]
+    max_depth: usize,
+
+    /// Leave array indices out of the flattened keys
+    #[arg(long)]
+    no_array_indices: bool,
+
+    /// Keep arrays as JSON strings instead of expanding them
+    #[arg(long)]
+    no_expand_arrays: bool,
+
+    /// Emit `<array>.#length` entries for every array
+    #[arg(long)]
+    array_metadata: bool,
+
+    /// Expand at most this many elements per array
+    #[arg(long)]
+    max_array_elements: Option<usize>,
+
+    /// What to do with longer arrays: truncate, head_tail or error
+    #[arg(long, default_value_t = ArrayLimitPolicy::Truncate)]
+    array_limit_policy: ArrayLimitPolicy,
+
+    /// Treat a record with more than this many keys as oversized
+    #[arg(long, value_name = "N", default_value_t = 0, hide_default_value = true)]
+    max_record_keys: usize,
+
+    /// Treat a record that takes longer than this many seconds to flatten as
+    /// oversized
+    #[arg(long, value_name = "SECS", value_parser = parse_seconds)]
+    record_time_limit: Option<Duration>,
+
+    /// What to do with oversized records: error, or skip to leave them out,
+    /// reporting each on standard error
+    #[arg(long, default_value_t = OversizedRecords::Error)]
+    oversized_records: OversizedRecords,
+
+    /// Documents that are scalars or arrays, e.g. NDJSON heartbeat lines:
+    /// keep, wrap[:KEY] (flatten under KEY, default `value`), skip (leave
+    /// them out, reporting each on standard error) or error
+    #[arg(long, value_name = "POLICY", default_value_t = NonObjectRecords::Keep)]
+    non_object_records: NonObjectRecords,
+
+    /// Min/max/mean/count for arrays of numbers: off, replace or alongside
+    #[arg(long, default_value_t = ArrayAggregates::Off)]
+    array_aggregates: ArrayAggregates,
+
+    /// Array handling for matching paths as PATTERN=MODE, e.g. `tags=join:,`
+    /// or `**.events=expand`; may be repeated, the first match wins
+    #[arg(long = "array-mode", value_name = "PATTERN=MODE")]
+    array_modes: Vec<ArrayPathMode>,
+
+    /// Attach an id to every record: sequence, uuid, hash or a path like `$.id`
+    #[arg(long, default_value_t = RecordIdSource::None)]
+    record_id: RecordIdSource,
+
+    /// Key that holds the record id
+    #[arg(long, default_value = "_id")]
+    record_id_key: String,
+
+    /// Write GeoJSON geometries as off, wkt, wkb (hex) or point_columns
+    #[arg(long, default_value_t = GeoJsonMode::Off)]
+    geojson: GeoJsonMode,
+
+    /// Large base64/binary strings: keep, drop, truncate:<chars> or side-files:<dir>
+    #[arg(long, default_value_t = BinaryPolicy::Keep)]
+    binary_values: BinaryPolicy,
+
+    /// Minimum length in bytes for a string to be treated as binary
+    #[arg(long)]
+    binary_min_length: Option<usize>,
+
+    /// NFC-normalize object keys
+    #[arg(long)]
+    normalize_keys: bool,
+
+    /// NFC-normalize string values
+    #[arg(long)]
+    normalize_values: bool,
+
+    /// Write leaves exactly as in the source: number formatting and string
+    /// escapes are kept
+    #[arg(long)]
+    raw_values: bool,
+
+    /// Invalid UTF-8 in the input: error, replace or skip (the document)
+    #[arg(long, default_value_t = InvalidUtf8::Error)]
+    invalid_utf8: InvalidUtf8,
+
+    /// Documents that are not valid JSON: error, or skip (leave them out,
+    /// reporting each with the error's position on standard error)
+    #[arg(long, default_value_t = InvalidDocuments::Error)]
+    invalid_documents: InvalidDocuments,
+
+    /// Input encoding: auto (BOM detection) or a label like utf-16le or latin1
+    #[arg(long, default_value_t = InputEncoding::Utf8)]
+    encoding: InputEncoding,
+
+    /// NaN/Infinity tokens in the input: error, null or string
+    #[arg(long, default_value_t = NonFiniteNumbers::Error)]
+    non_finite: NonFiniteNumbers,
+
+    /// Byte order marks and non-JSON whitespace before the first document:
+    /// skip or error
+    #[arg(long, default_value_t = LeadingJunk::Skip)]
+    leading_junk: LeadingJunk,
+
+    /// Keys repeated within a source object: last (keep the last value), error
+    /// or report (keep the last value and list the paths under _duplicate_keys)
+    #[arg(long, default_value_t = DuplicateKeys::Last)]
+    duplicate_keys: DuplicateKeys,
+
+    /// Object keys that are the empty string: keep, placeholder (write _empty_)
+    /// or error
+    #[arg(long, default_value_t = EmptyKeys::Keep)]
+    empty_keys: EmptyKeys,
+
+    /// Keys that contain the separator or look like array indices: allow,
+    /// report (list them under _ambiguous_keys) or error
+    #[arg(long, default_value_t = AmbiguousKeys::Allow)]
+    ambiguous_keys: AmbiguousKeys,
+
+    /// Chains of objects holding a single key, like envelope levels: keep,
+    /// head (only the chain's first key) or tail (only its last key)
+    #[arg(long, default_value_t = SingleKeyChains::Keep)]
+    single_key_chains: SingleKeyChains,
+
+    /// Remove this prefix from every flattened key that starts with it, e.g.
+    /// data.attributes. for an API envelope
+    #[arg(long, value_name = "PREFIX")]
+    strip_prefix: Option<String>,
+
+    /// Add _depth, _max_width, _max_array_len and _key_count to each record
+    #[arg(long)]
+    structure_metrics: bool,
+
+    /// Add a key computed from each record's values, e.g. 'price_usd = price_cents / 100' (repeatable)
+    #[arg(long = "derive", value_name = "KEY = EXPR")]
+    derived_columns: Vec<DerivedColumn>,
+
+    /// Keep only records for which EXPR is true, e.g. 'event.type == "purchase"' (repeatable, all must hold)
+    #[arg(long = "where", value_name = "EXPR")]
+    filters: Vec<Expr>,
+
+    /// Don't reserve capacity for each record from its document's leaf count
+    #[arg(long)]
+    no_presize_records: bool,
+
+    /// Treat a top-level array as one document instead of one per element
+    #[arg(long)]
+    no_split_root_array: bool,
+
+    /// Input is NDJSON: split lines on the worker threads, in blocks of --chunk-bytes
+    #[arg(long)]
+    ndjson: bool,
+
+    /// Deterministic output: canonical numbers and sorted keys
+    #[arg(long)]
+    canonical: bool,
+
+    /// Number of documents flattened per parallel chunk
+    #[arg(long)]
+    chunk_size: Option<usize>,
+
+    /// Cut chunks by serialized size (in bytes) instead of document count
+    #[arg(long, default_value_t = 0, hide_default_value = true)]
+    chunk_bytes: usize,
+
+    /// Fail on inputs larger than this many bytes instead of reading them
+    #[arg(
+        long,
+        value_name = "BYTES",
+        default_value_t = 0,
+        hide_default_value = true
+    )]
+    max_input_bytes: usize,
+
+    /// Fail on a single document larger than this many bytes instead of
+    /// holding it in memory
+    #[arg(
+        long,
+        value_name = "BYTES",
+        default_value_t = 0,
+        hide_default_value = true
+    )]
+    max_document_bytes: usize,
+
+    /// Threads used to parse documents (defaults to the number of CPUs)
+    #[arg(long, default_value_t = 0, hide_default_value = true)]
+    parse_threads: usize,
+
+    /// Threads used to flatten parsed documents (defaults to the number of CPUs)
+    #[arg(long, default_value_t = 0, hide_default_value = true)]
+    flatten_threads: usize,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Print the flat schema (column name and type) the input flattens to
+    Schema(ReportArgs),
+    /// Print per-column null rates, distinct counts and distributions
+    Profile(ReportArgs),
+    /// Print how many records contain each flattened key
+    Keys(ReportArgs),
+    /// Flatten one input format into one output format
+    Convert(ConvertArgs),
+    /// Convert every file matching a glob, several at a time
+    Dir(DirArgs),
+    /// Rebuild nested JSON documents from flattened records
+    Unflatten(UnflattenArgs),
+}
+
+#[derive(Args, Debug)]
+struct UnflattenArgs {
+    /// Flattened records as NDJSON, or `-` for standard input
+    #[arg(default_value = "-")]
+    input: String,
+
+    /// Output file, or `-` for standard output; compressed for .gz/.zst paths
+    #[arg(short, long, default_value = "-")]
+    output: String,
+
+    /// Read CSV with one column per flattened key, e.g. a spreadsheet saved
+    /// after editing flattened output; empty cells are left out
+    #[arg(long)]
+    csv: bool,
+
+    /// Rebuild objects keyed by 0, 1, ... as arrays or keep them as objects
+    #[arg(long, default_value_t = IndexKeys::Arrays)]
+    index_keys: IndexKeys,
+
+    /// Fill index keys that skip numbers with nulls, or keep an object
+    #[arg(long, default_value_t = SparseIndices::Null)]
+    sparse_indices: SparseIndices,
+
+    #[command(flatten)]
+    flatten: FlattenArgs,
+}
+
+#[derive(Args, Debug)]
+struct ConvertArgs {
+    /// Input file, or `-` for standard input
+    #[arg(default_value = "-")]
+    input: String,
+
+    /// Output file, or `-` for standard output; compressed for .gz/.zst paths
+    #[arg(short, long, default_value = "-")]
+    output: String,
+
+    #[command(flatten)]
+    formats: FormatArgs,
+}
+
+#[derive(Args, Debug)]
+struct DirArgs {
+    /// Glob of input files, e.g. 'exports/**/*.ndjson'
+    pattern: String,
+
+    /// Directory for the outputs, one per input named after it
+    #[arg(long)]
+    out_dir: PathBuf,
+
+    /// Files processed at once [default: available CPUs]
+    #[arg(short, long)]
+    jobs: Option<usize>,
+
+    /// When the exit code reports failure
+    #[arg(long, value_enum, default_value_t = FailOn::Any)]
+    fail_on: FailOn,
+
+    /// Hide the progress bars
+    #[arg(long)]
+    no_progress: bool,
+
+    #[command(flatten)]
+    formats: FormatArgs,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum FailOn {
+    /// Fail when any file fails
+    Any,
+    /// Fail only when every file fails
+    All,
+    /// Always succeed once the files are listed; failures are still reported
+    Never,
+}
+
+/// Formats and options shared by `convert` and `dir`
+#[derive(Args, Debug)]
+struct FormatArgs {
+    /// Input format
+    #[arg(long, value_enum, default_value_t = InputFormat::Json)]
+    from: InputFormat,
+
+    /// Output format
+    #[arg(long, value_enum, default_value_t = OutputFormat::Ndjson)]
+    to: OutputFormat,
+
+    /// CSV column holding the JSON documents, for --from csv
+    #[arg(long, default_value = "json")]
+    json_column: String,
+
+    /// Add a type column to eav output
+    #[arg(long)]
+    eav_types: bool,
+
+    /// Write NDJSON keys in sorted order (implied by --canonical)
+    #[arg(long)]
+    sort_keys: bool,
+
+    /// Take the columns and types of CSV, RowBinary, IPC, Parquet and xlsx
+    /// output from a schema saved by --save-schema instead of inferring them,
+    /// and reshape records to it
+    #[arg(long, value_name = "PATH")]
+    expected_schema: Option<String>,
+
+    /// Values that don't fit the expected schema: enforce (fail) or coerce (to null)
+    #[arg(long, default_value_t = SchemaMode::Enforce, requires = "expected_schema")]
+    schema_mode: SchemaMode,
+
+    #[command(flatten)]
+    delimited: DelimitedArgs,
+
+    #[command(flatten)]
+    safe_keys: SafeKeyArgs,
+
+    #[command(flatten)]
+    flatten: FlattenArgs,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum InputFormat {
+    /// A JSON document, top-level array, concatenated documents, NDJSON or
+    /// a JSON text sequence (RFC 7464)
+    Json,
+    /// One JSON document per line
+    Ndjson,
+    /// One or more YAML documents separated by `---`
+    Yaml,
+    /// CSV with one column of JSON documents (see --json-column)
+    Csv,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum OutputFormat {
+    Ndjson,
+    /// JSON text sequence (RFC 7464): each record starts with a record separator
+    JsonSeq,
+    Csv,
+    /// Long format rows (record_id,key,value)
+    Eav,
+    /// ClickHouse RowBinaryWithNamesAndTypes
+    Rowbinary,
+    /// Arrow IPC file (Feather v2) with typed columns
+    #[cfg(feature = "ipc")]
+    Ipc,
+    /// Parquet file with typed columns
+    #[cfg(feature = "parquet")]
+    Parquet,
+    /// Excel workbook with a header row
+    #[cfg(feature = "xlsx")]
+    Xlsx,
+}
+
+#[derive(Args, Debug)]
+struct ReportArgs {
+    /// Input file, or `-` for standard input
+    #[arg(default_value = "-")]
+    input: String,
+
+    /// Print JSON or an aligned table
+    #[arg(long, value_enum, default_value_t = ReportFormat::Json)]
+    format: ReportFormat,
+
+    #[command(flatten)]
+    flatten: FlattenArgs,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum ReportFormat {
+    Json,
+    Table,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Codec {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Cli {
+    fn output_compression(&self) -> OutputCompression {
+        let codec = match self.compress {
+            Some(codec) => codec,
+            None => match OutputCompression::from_path(&self.output) {
+                OutputCompression::Gzip { .. } => Codec::Gzip,
+                OutputCompression::Zstd { .. } => Codec::Zstd,
+                OutputCompression::None => Codec::None,
+            },
+        };
+        match codec {
+            Codec::None => OutputCompression::None,
+            Codec::Gzip => OutputCompression::Gzip {
+                level: self
+                    .compression_level
+                    .map_or(6, |level| level.clamp(0, 9) as u32),
+            },
+            Codec::Zstd => OutputCompression::Zstd {
+                level: self.compression_level.unwrap_or(3),
+                workers: self.compression_threads,
+            },
+        }
+    }
+}
+
+impl FlattenArgs {
+    fn options(&self) -> FlattenOptions {
+        let mut options = FlattenOptions::default();
+        options.separator = self.separator.clone();
+        options.escape_keys = self.escape_keys;
+        options.max_depth = self.max_depth;
+        options.include_array_indices = !self.no_array_indices;
+        options.expand_arrays = !self.no_expand_arrays;
+        options.array_metadata = self.array_metadata;
+        options.max_array_elements = self.max_array_elements;
+        options.array_limit_policy = self.array_limit_policy;
+        options.max_record_keys = self.max_record_keys;
+        options.record_time_limit = self.record_time_limit;
+        options.oversized_records = self.oversized_records;
+        options.non_object_records = self.non_object_records.clone();
+        options.array_aggregates = self.array_aggregates;
+        options.array_modes = self.array_modes.clone();
+        options.record_id = self.record_id.clone();
+        options.record_id_key = self.record_id_key.clone();
+        options.geojson = self.geojson;
+        options.binary_values = self.binary_values.clone();
+        options.binary_min_length = self.binary_min_length.unwrap_or(options.binary_min_length);
+        options.normalize_keys = self.normalize_keys;
+        options.normalize_values = self.normalize_values;
+        options.raw_values = self.raw_values;
+        options.invalid_utf8 = self.invalid_utf8;
+        options.invalid_documents = self.invalid_documents;
+        options.encoding = self.encoding;
+        options.leading_junk = self.leading_junk;
+        options.non_finite = self.non_finite;
+        options.duplicate_keys = self.duplicate_keys;
+        options.empty_keys = self.empty_keys;
+        options.ambiguous_keys = self.ambiguous_keys;
+        options.single_key_chains = self.single_key_chains;
+        options.strip_prefix = self.strip_prefix.clone();
+        options.structure_metrics = self.structure_metrics;
+        options.derived_columns = self.derived_columns.clone();
+        options.filters = self.filters.clone();
+        options.presize_records = !self.no_presize_records;
+        options.chunk_size = self.chunk_size.unwrap_or(options.chunk_size);
+        options.chunk_bytes = self.chunk_bytes;
+        options.max_input_bytes = self.max_input_bytes;
+        options.max_document_bytes = self.max_document_bytes;
+        options.split_root_array = !self.no_split_root_array;
+        options.ndjson = self.ndjson;
+        options.canonical = self.canonical;
+        options.parse_threads = self.parse_threads;
+        options.flatten_threads = self.flatten_threads;
+        options
+    }
+}
+
+fn run(cli: &Cli) -> Result<(), Box<dyn std::error::Error>> {
+    let mut options = cli.flatten.options();
+    options.time_budget = cli.time_budget;
+    options.skip_documents = cli.skip_documents;
+    validate_separator(&options.separator, options.escape_keys)?;
+    let expected = cli
+        .expected_schema
+        .as_deref()
+        .map(FlatSchema::load)
+        .transpose()?;
+    let format = if cli.csv {
+        OutputFormat::Csv
+    } else if cli.eav {
+        OutputFormat::Eav
+    } else if cli.rowbinary {
+        OutputFormat::Rowbinary
+    } else if cli.json_seq {
+        OutputFormat::JsonSeq
+    } else {
+        OutputFormat::Ndjson
+    };
+    let mut sink = match &cli.partition_by {
+        Some(_) if cli.safe_keys.safe_keys => {
+            return Err("--safe-keys can't be combined with --partition-by".into())
+        }
+        Some(key) => partitioned_sink(cli, format, key, expected.clone())?,
+        None => {
+            let (keys, schema) = cli.safe_keys.keys(expected.clone());
+            let output = open_compressed_output(&cli.output, cli.output_compression())?;
+            let sink = output_sink(
+                format,
+                Box::new(output),
+                &cli.flatten,
+                cli.eav_types,
+                cli.sort_keys,
+                &cli.delimited,
+                schema,
+            );
+            cli.safe_keys.wrap(sink, keys, &cli.output)?
+        }
+    };
+    let limit = RateLimit {
+        records_per_second: cli.max_records_per_second,
+        bytes_per_second: cli.max_bytes_per_second,
+        ..RateLimit::default()
+    };
+    if limit.is_limited() {
+        sink = Box::new(ThrottledSink::new(sink, limit));
+    }
+    if !cli.group_by.is_empty() {
+        let aggregations = match cli.aggregate.is_empty() {
+            true => vec![Aggregation::Count],
+            false => cli.aggregate.clone(),
+        };
+        let mut grouped = AggregateSink::new(sink, &cli.group_by, aggregations)
+            .with_separator(&options.separator);
+        if cli.sorted_groups {
+            grouped = grouped.with_sorted_input();
+        }
+        sink = Box::new(grouped);
+    }
+
+    // Optional stages wrap the output sink by reference so their results
+    // can be read once the run is done
+    let mut output_sink = sink;
+    let sink: &mut dyn RecordSink = &mut output_sink;
+    let mut stats = None;
+    let sink: &mut dyn RecordSink = match cli.quality_report {
+        Some(_) => {
+            let stats_sink = ColumnStatsSink::new(sink)
+                .with_top_widest(cli.top_widest.unwrap_or(0))
+                .with_value_counts(&cli.value_counts)
+                .with_distinct_values(&cli.distinct_values, cli.max_distinct_values)
+                .with_separator(&options.separator, options.escape_keys);
+            stats.insert(stats_sink)
+        }
+        None => sink,
+    };
+    let allow_all = DriftPolicy {
+        allow_added: true,
+        allow_removed: true,
+        allow_widening: true,
+        allow_type_changes: true,
+    };
+    let mut observed = None;
+    let sink: &mut dyn RecordSink = match cli.save_schema {
+        Some(_) => observed.insert(SchemaCheckSink::new(sink, FlatSchema::default(), allow_all)),
+        None => sink,
+    };
+    let mut conformed = None;
+    let sink: &mut dyn RecordSink = match expected {
+        Some(schema) => conformed.insert(
+            ExpectedSchemaSink::new(sink, schema)
+                .with_mode(cli.schema_mode)
+                .with_unexpected(cli.unexpected_columns.clone()),
+        ),
+        None => sink,
+    };
+
+    let mut validated = None;
+    let sink: &mut dyn RecordSink = match cli.rules.is_empty() {
+        true => sink,
+        false => {
+            let mut validating = ValidatingSink::new(sink, cli.rules.clone())
+                .with_separator(&options.separator, options.escape_keys);
+            if let Some(path) = &cli.dead_letter {
+                let dead_letter = NdjsonSink::new(open_output(path)?)
+                    .write_sorted(cli.flatten.canonical || cli.sort_keys);
+                validating = validating.with_dead_letter(Box::new(dead_letter));
+            }
+            validated.insert(validating)
+        }
+    };
+    let mut planned = None;
+    let sink: &mut dyn RecordSink = match cli.plan.is_empty() {
+        true => sink,
+        false => {
+            let plan = KeyPlan::new(cli.plan.clone(), &options)?;
+            if cli.show_plan {
+                eprint!("{}", plan);
+            }
+            planned.insert(PlanSink::new(sink, Arc::new(plan)))
+        }
+    };
+    // Rules run first, so no later stage sees dropped or masked values
+    let mut redacted = None;
+    let sink: &mut dyn RecordSink = match &cli.key_rules {
+        Some(path) => redacted.insert(RulesSink::new(
+            sink,
+            SharedRules::from_file(path, &options)?,
+        )),
+        None => sink,
+    };
+    let mut sink = SkipLog {
+        input: &cli.input,
+        sink,
+    };
+
+    if ArchiveFormat::from_path(&cli.input).is_some() {
+        flatten_archive_to_sink(&cli.input, &mut sink, &options)?;
+    } else {
+        let (_, checkpoint) =
+            flatten_reader_to_sink_timed(open_input(&cli.input)?, &mut sink, &options)?;
+        if let Some(checkpoint) = checkpoint {
+            eprintln!(
+                "jflat: time budget ran out; continue with --skip-documents {}",
+                checkpoint.documents
+            );
+        }
+    }
+    if let Some(validated) = validated.as_ref().filter(|v| v.invalid_records() > 0) {
+        eprintln!(
+            "jflat: {} records failed validation",
+            validated.invalid_records()
+        );
+        for (rule, count) in validated.violations() {
+            eprintln!("  {}: {}", rule, count);
+        }
+    }
+    if let (Some(path), Some(observed)) = (&cli.save_schema, &observed) {
+        observed.schema().save(path)?;
+    }
+    if let (Some(path), Some(stats)) = (&cli.quality_report, &stats) {
+        stats.report().save(path)?;
+    }
+    Ok(())
+}
+
+/// Parses a non-negative number of seconds
+fn parse_seconds(s: &str) -> Result<Duration, String> {
+    let secs: f64 = s.parse().map_err(|e| format!("{}", e))?;
+    Duration::try_from_secs_f64(secs).map_err(|e| e.to_string())
+}
+
+/// Creates the sink for an output format other than IPC, Parquet and xlsx,
+/// which need finishing;
+/// CSV and RowBinary take their columns from `schema` when there is one
+fn output_sink(
+    format: OutputFormat,
+    output: Box<dyn Write + Send>,
+    flatten: &FlattenArgs,
+    eav_types: bool,
+    sort_keys: bool,
+    delimited: &DelimitedArgs,
+    schema: Option<FlatSchema>,
+) -> Box<dyn RecordSink> {
+    match format {
+        OutputFormat::Ndjson | OutputFormat::JsonSeq => Box::new(
+            NdjsonSink::new(output)
+                .write_sorted(flatten.canonical || sort_keys)
+                .write_json_seq(matches!(format, OutputFormat::JsonSeq)),
+        ),
+        OutputFormat::Csv => {
+            let sink = CsvSink::new(output)
+                .with_escape(delimited.field_escape.clone())
+                .with_header_rows(delimited.csv_header_rows)
+                .with_unexpected(delimited.csv_new_columns.clone());
+            match schema {
+                Some(schema) => Box::new(sink.with_schema(schema)),
+                None => Box::new(sink),
+            }
+        }
+        OutputFormat::Eav => {
+            let mut sink = EavSink::new(output).with_escape(delimited.field_escape.clone());
+            if flatten.record_id != RecordIdSource::None {
+                sink = sink.with_id_key(&flatten.record_id_key);
+            }
+            Box::new(if eav_types { sink.with_types() } else { sink })
+        }
+        OutputFormat::Rowbinary => {
+            let sink = RowBinarySink::new(output).with_header_rows(delimited.csv_header_rows);
+            match schema {
+                Some(schema) => Box::new(sink.with_schema(schema)),
+                None => Box::new(sink),
+            }
+        }
+        #[cfg(feature = "ipc")]
+        OutputFormat::Ipc => unreachable!("IPC output is finished by convert"),
+        #[cfg(feature = "parquet")]
+        OutputFormat::Parquet => unreachable!("Parquet output is finished by convert"),
+        #[cfg(feature = "xlsx")]
+        OutputFormat::Xlsx => unreachable!("xlsx output is finished by convert"),
+    }
+}
+
+/// Creates a sink writing one file per value of `key` into the output directory
+fn partitioned_sink<'a>(
+    cli: &'a Cli,
+    format: OutputFormat,
+    key: &str,
+    schema: Option<FlatSchema>,
+) -> Result<Box<dyn RecordSink + 'a>, Box<dyn std::error::Error>> {
+    if cli.output == "-" {
+        return Err("--partition-by needs --output to name a directory".into());
+    }
+    if cli.partition_overflow == PartitionOverflow::Evict
+        && !matches!(format, OutputFormat::Ndjson | OutputFormat::JsonSeq)
+    {
+        return Err(
+            "--partition-overflow evict only works with NDJSON and JSON text sequences, other formats can't be appended to"
+                .into(),
+        );
+    }
+    let dir = PathBuf::from(&cli.output);
+    std::fs::create_dir_all(&dir)?;
+    let compression = cli.output_compression();
+    let extension = match format {
+        OutputFormat::Ndjson => "ndjson",
+        OutputFormat::JsonSeq => "json-seq",
+        OutputFormat::Csv | OutputFormat::Eav => "csv",
+        OutputFormat::Rowbinary => "rowbinary",
+        #[cfg(feature = "ipc")]
+        OutputFormat::Ipc => unreachable!("IPC output is finished by convert"),
+        #[cfg(feature = "parquet")]
+        OutputFormat::Parquet => unreachable!("Parquet output is finished by convert"),
+        #[cfg(feature = "xlsx")]
+        OutputFormat::Xlsx => unreachable!("xlsx output is finished by convert"),
+    };
+    let suffix = match compression {
+        OutputCompression::Gzip { .. } => ".gz",
+        OutputCompression::Zstd { .. } => ".zst",
+        OutputCompression::None => "",
+    };
+    // Evicted partitions are appended to; gzip and zstd streams may be concatenated
+    let open = move |partition: &str, reopen: bool| {
+        let name = format!("{}.{}{}", partition_file_name(partition), extension, suffix);
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(reopen)
+            .truncate(!reopen)
+            .open(dir.join(name))?;
+        let output = compression.wrap(BufWriter::new(file))?.finish_on_flush();
+        Ok(output_sink(
+            format,
+            Box::new(output),
+            &cli.flatten,
+            cli.eav_types,
+            cli.sort_keys,
+            &cli.delimited,
+            schema.clone(),
+        ))
+    };
+    let sink = PartitionedSink::new(key, cli.max_open_files, open)
+        .with_overflow(cli.partition_overflow.clone());
+    Ok(Box::new(sink))
+}
+
+/// Flattens the input of `schema` or `profile` and prints what was found
+fn report(command: &Command) -> Result<(), Box<dyn std::error::Error>> {
+    let args = match command {
+        Command::Schema(args) | Command::Profile(args) | Command::Keys(args) => args,
+        Command::Convert(args) => return convert(args),
+        Command::Dir(args) => return dir(args),
+        Command::Unflatten(args) => return unflatten(args),
+    };
+    let options = args.flatten.options();
+    validate_separator(&options.separator, options.escape_keys)?;
+    let input = open_input(&args.input)?;
+
+    match command {
+        Command::Schema(_) => {
+            let mut builder = SchemaBuilder::default();
+            flatten_reader_to_sink(
+                input,
+                &mut CallbackSink(|record| builder.add(&record)),
+                &options,
+            )?;
+            let schema = builder.schema();
+            match args.format {
+                ReportFormat::Json => println!("{}", schema.to_json()),
+                ReportFormat::Table => print_table(
+                    &["column", "type"],
+                    schema
+                        .iter()
+                        .map(|(column, column_type)| vec![column.clone(), column_type.to_string()])
+                        .collect(),
+                ),
+            }
+        }
+        Command::Convert(_) | Command::Dir(_) | Command::Unflatten(_) => {
+            unreachable!("handled above")
+        }
+        Command::Keys(_) => {
+            let counts = index_keys(input, &options)?;
+            match args.format {
+                ReportFormat::Json => println!("{}", serde_json::to_string_pretty(&counts)?),
+                ReportFormat::Table => print_table(
+                    &["key", "records"],
+                    counts
+                        .iter()
+                        .map(|(key, count)| vec![key.clone(), count.to_string()])
+                        .collect(),
+                ),
+            }
+        }
+        Command::Profile(_) => {
+            let mut stats = ColumnStatsSink::new(CallbackSink(|_| {}));
+            flatten_reader_to_sink(input, &mut stats, &options)?;
+            let report = stats.report();
+            match args.format {
+                ReportFormat::Json => println!("{}", report.to_json()),
+                ReportFormat::Table => {
+                    println!("{} records", report.records);
+                    let number = |n: Option<f64>| n.map_or_else(String::new, |n| n.to_string());
+                    print_table(
+                        &[
+                            "column",
+                            "type",
+                            "non_null",
+                            "null_rate",
+                            "distinct",
+                            "min",
+                            "max",
+                            "mean",
+                            "stddev",
+                        ],
+                        report
+                            .columns
+                            .iter()
+                            .map(|(column, stats)| {
+                                vec![
+                                    column.clone(),
+                                    stats
+                                        .column_type
+                                        .map_or_else(|| "null".to_string(), |t| t.to_string()),
+                                    stats.non_null.to_string(),
+                                    format!("{:.4}", stats.null_rate),
+                                    stats.distinct.to_string(),
+                                    number(stats.numeric.map(|n| n.min)),
+                                    number(stats.numeric.map(|n| n.max)),
+                                    number(stats.numeric.map(|n| n.mean)),
+                                    number(stats.numeric.map(|n| n.stddev)),
+                                ]
+                            })
+                            .collect(),
+                    );
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reads documents of any input format as NDJSON, so every format goes
+/// through the same flattening pipeline and options
+struct NdjsonReader<I: Iterator<Item = io::Result<String>>> {
+    documents: I,
+    line: Vec<u8>,
+    pos: usize,
+}
+
+impl<I: Iterator<Item = io::Result<String>>> Read for NdjsonReader<I> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.line.len() {
+            match self.documents.next().transpose()? {
+                Some(document) => {
+                    self.line = document.into_bytes();
+                    self.line.push(b'\n');
+                    self.pos = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.line.len() - self.pos);
+        buf[..n].copy_from_slice(&self.line[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+fn ndjson_reader<I>(documents: I) -> Box<dyn Read + Send>
+where
+    I: Iterator<Item = io::Result<String>> + Send + 'static,
+{
+    Box::new(NdjsonReader {
+        documents,
+        line: Vec::new(),
+        pos: 0,
+    })
+}
+
+fn invalid_data(e: impl std::fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+/// Opens an input of `convert` or `dir` as JSON text
+fn convert_input(
+    path: &str,
+    args: &FormatArgs,
+) -> Result<Box<dyn Read + Send>, Box<dyn std::error::Error>> {
+    let input = open_input(path)?;
+    Ok(match args.from {
+        InputFormat::Json | InputFormat::Ndjson => input,
+        InputFormat::Yaml => {
+            // serde_yaml reads the whole stream before yielding documents
+            let documents: Vec<_> = serde_yaml::Deserializer::from_reader(input)
+                .map(|document| {
+                    let value = serde_json::Value::deserialize(document).map_err(invalid_data)?;
+                    serde_json::to_string(&value).map_err(invalid_data)
+                })
+                .collect();
+            ndjson_reader(documents.into_iter())
+        }
+        InputFormat::Csv => {
+            let mut reader = csv::Reader::from_reader(input);
+            let column = reader
+                .headers()?
+                .iter()
+                .position(|name| name == args.json_column)
+                .ok_or_else(|| format!("CSV input has no column {:?}", args.json_column))?;
+            ndjson_reader(reader.into_records().map(move |record| {
+                let record = record.map_err(invalid_data)?;
+                Ok(record.get(column).unwrap_or("null").to_string())
+            }))
+        }
+    })
+}
+
+/// Reshapes records to the expected schema, if any, before they reach `sink`
+fn conform<'a>(
+    sink: impl RecordSink + 'a,
+    schema: Option<&FlatSchema>,
+    mode: SchemaMode,
+) -> Box<dyn RecordSink + 'a> {
+    match schema {
+        Some(schema) => Box::new(ExpectedSchemaSink::new(sink, schema.clone()).with_mode(mode)),
+        None => Box::new(sink),
+    }
+}
+
+/// Sink reporting the documents a skip policy leaves out on standard error
+struct SkipLog<'a, S> {
+    input: &'a str,
+    sink: S,
+}
+
+impl<S: RecordSink> RecordSink for SkipLog<'_, S> {
+    fn write_record(&mut self, record: FlattenedJson) -> io::Result<()> {
+        self.sink.write_record(record)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.sink.flush()
+    }
+
+    fn skip_document(&mut self, skipped: SkippedDocument) -> io::Result<()> {
+        eprintln!(
+            "jflat: {}: skipped document {}: {}",
+            self.input, skipped.document, skipped.reason
+        );
+        Ok(())
+    }
+}
+
+/// Flattens one file of any input format into any output format, returning
+/// the number of records written
+///
+/// Records go to `written_to`, usually `output` itself; the key mapping is
+/// named and the compression inferred from `output`.
+fn convert_file(
+    input: &str,
+    output: &str,
+    written_to: &str,
+    args: &FormatArgs,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let mut options = args.flatten.options();
+    validate_separator(&options.separator, options.escape_keys)?;
+    if !matches!(args.from, InputFormat::Json) {
+        // Each line or row is one document, even when it holds an array
+        options.split_root_array = false;
+    }
+    let expected = args
+        .expected_schema
+        .as_deref()
+        .map(FlatSchema::load)
+        .transpose()?;
+    let (keys, schema) = args.safe_keys.keys(expected.clone());
+    let (input_path, output_path) = (input, output);
+    let input = convert_input(input, args)?;
+    let compression = OutputCompression::from_path(output);
+    // IPC, Parquet and xlsx sinks flush the writer before their footer is
+    // written, so their stream is finished by hand instead of on flush
+    let unfinished = || compression.wrap(open_output(written_to)?);
+
+    let flatten_into =
+        |sink: &mut dyn RecordSink, input, keys| -> Result<usize, Box<dyn std::error::Error>> {
+            let renamed = args.safe_keys.wrap(Box::new(sink), keys, output_path)?;
+            let mut sink = SkipLog {
+                input: input_path,
+                sink: conform(renamed, expected.as_ref(), args.schema_mode),
+            };
+            flatten_reader_to_sink(input, &mut sink, &options)
+        };
+    match args.to {
+        #[cfg(feature = "ipc")]
+        OutputFormat::Ipc => {
+            let mut sink = match schema {
+                Some(schema) => IpcSink::new(unfinished()?).with_schema(schema),
+                None => IpcSink::new(unfinished()?),
+            };
+            let written = flatten_into(&mut sink, input, keys)?;
+            sink.finish()?.finish()?;
+            Ok(written)
+        }
+        #[cfg(feature = "parquet")]
+        OutputFormat::Parquet => {
+            let mut sink = match schema {
+                Some(schema) => ParquetSink::new(unfinished()?).with_schema(schema),
+                None => ParquetSink::new(unfinished()?),
+            };
+            let written = flatten_into(&mut sink, input, keys)?;
+            sink.finish()?.finish()?;
+            Ok(written)
+        }
+        #[cfg(feature = "xlsx")]
+        OutputFormat::Xlsx => {
+            let mut sink = match schema {
+                Some(schema) => XlsxSink::new(unfinished()?).with_schema(schema),
+                None => XlsxSink::new(unfinished()?),
+            };
+            let written = flatten_into(&mut sink, input, keys)?;
+            sink.finish()?.finish()?;
+            Ok(written)
+        }
+        _ => {
+            let mut sink = output_sink(
+                args.to,
+                Box::new(open_compressed_output(written_to, compression)?),
+                &args.flatten,
+                args.eav_types,
+                args.sort_keys,
+                &args.delimited,
+                schema,
+            );
+            flatten_into(&mut sink, input, keys)
+        }
+    }
+}
+
+fn convert(args: &ConvertArgs) -> Result<(), Box<dyn std::error::Error>> {
+    convert_file(&args.input, &args.output, &args.output, &args.formats)?;
+    Ok(())
+}
+
+/// Rebuilds nested documents from flattened NDJSON or CSV records
+fn unflatten(args: &UnflattenArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let mut options = args.flatten.options();
+    options.index_keys = args.index_keys;
+    options.sparse_indices = args.sparse_indices;
+    options.split_root_array = false;
+    let mut input = open_input(&args.input)?;
+    if args.csv {
+        let mut reader = csv::Reader::from_reader(input);
+        let headers = reader.headers()?.clone();
+        input = ndjson_reader(reader.into_records().map(move |row| {
+            let row = row.map_err(invalid_data)?;
+            let record: serde_json::Map<String, serde_json::Value> = headers
+                .iter()
+                .zip(row.iter())
+                .filter(|(_, cell)| !cell.is_empty())
+                .map(|(key, cell)| (key.to_string(), cell.into()))
+                .collect();
+            serde_json::to_string(&record).map_err(invalid_data)
+        }));
+    }
+    let output = open_compressed_output(&args.output, OutputCompression::from_path(&args.output))?;
+    unflatten_reader(input, output, &options)?;
+    Ok(())
+}
+
+/// Output path for an input of `dir`: its name without JSON/compression
+/// extensions, plus the extension of the output format
+fn dir_output(input: &Path, out_dir: &Path, format: OutputFormat) -> PathBuf {
+    let mut stem = input
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned();
+    for extension in [
+        ".gz", ".zst", ".json", ".ndjson", ".jsonl", ".yaml", ".yml", ".csv",
+    ] {
+        if let Some(trimmed) = stem.strip_suffix(extension) {
+            stem.truncate(trimmed.len());
+        }
+    }
+    let extension = match format {
+        OutputFormat::Ndjson => "ndjson",
+        OutputFormat::JsonSeq => "json-seq",
+        OutputFormat::Csv => "csv",
+        OutputFormat::Eav => "eav.csv",
+        OutputFormat::Rowbinary => "rowbinary",
+        #[cfg(feature = "ipc")]
+        OutputFormat::Ipc => "arrow",
+        #[cfg(feature = "parquet")]
+        OutputFormat::Parquet => "parquet",
+        #[cfg(feature = "xlsx")]
+        OutputFormat::Xlsx => "xlsx",
+    };
+    out_dir.join(format!("{}.{}", stem, extension))
+}
+
+/// Converts one input of `dir` under a hidden partial name next to its
+/// output, renaming it once complete so a failed conversion leaves no output
+fn dir_convert(input: &Path, output: &Path, args: &FormatArgs) -> Result<usize, Box<dyn std::error::Error>> {
+    let name = output.file_name().unwrap_or_default().to_string_lossy();
+    let partial = output.with_file_name(format!(".{}.partial", name));
+    let (output, written_to) = (output.to_string_lossy(), partial.to_string_lossy());
+    match convert_file(&input.to_string_lossy(), &output, &written_to, args) {
+        Ok(written) => {
+            std::fs::rename(&partial, &*output)?;
+            Ok(written)
+        }
+        Err(e) => {
+            let _ = std::fs::remove_file(&partial);
+            Err(e)
+        }
+    }
+}
+
+/// Converts every file matching a glob on a pool of worker threads, showing
+/// overall and per-worker progress, then summarises the failures
+fn dir(args: &DirArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let inputs = glob::glob(&args.pattern)?
+        .filter(|entry| entry.as_ref().map_or(true, |path| path.is_file()))
+        .collect::<Result<Vec<PathBuf>, _>>()?;
+    if inputs.is_empty() {
+        return Err(format!("no files match {:?}", args.pattern).into());
+    }
+    if args.formats.safe_keys.key_mapping.is_some() {
+        return Err("dir writes a key mapping next to each output; leave out --key-mapping".into());
+    }
+    let outputs: Vec<PathBuf> = inputs
+        .iter()
+        .map(|input| dir_output(input, &args.out_dir, args.formats.to))
+        .collect();
+    let mut seen = HashSet::new();
+    if let Some(clash) = outputs.iter().find(|output| !seen.insert(*output)) {
+        return Err(format!("several inputs would be written to {}", clash.display()).into());
+    }
+    std::fs::create_dir_all(&args.out_dir)?;
+
+    let jobs = args
+        .jobs
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()))
+        .clamp(1, inputs.len());
+    let progress = if args.no_progress {
+        MultiProgress::with_draw_target(ProgressDrawTarget::hidden())
+    } else {
+        MultiProgress::new()
+    };
+    let overall = progress.add(ProgressBar::new(inputs.len() as u64));
+    overall.set_style(
+        ProgressStyle::with_template("{elapsed_precise} [{bar:40}] {pos}/{len} files {msg}")?
+            .progress_chars("=> "),
+    );
+
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(Vec::with_capacity(inputs.len()));
+    std::thread::scope(|scope| {
+        for _ in 0..jobs {
+            let worker = progress.add(ProgressBar::new_spinner());
+            worker.enable_steady_tick(Duration::from_millis(120));
+            let (next, results, overall, inputs, outputs) =
+                (&next, &results, &overall, &inputs, &outputs);
+            scope.spawn(move || {
+                loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(input) = inputs.get(i) else { break };
+                    worker.set_message(input.display().to_string());
+                    let result =
+                        dir_convert(input, &outputs[i], &args.formats).map_err(|e| e.to_string());
+                    if result.is_err() {
+                        overall.set_message("(with failures)");
+                    }
+                    results.lock().unwrap().push((i, result));
+                    overall.inc(1);
+                }
+                worker.finish_and_clear();
+            });
+        }
+    });
+    overall.finish();
+
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|(i, _)| *i);
+    let records: usize = results
+        .iter()
+        .filter_map(|(_, result)| result.as_ref().ok())
+        .sum();
+    let failed: Vec<_> = results
+        .iter()
+        .filter_map(|(i, result)| Some((&inputs[*i], result.as_ref().err()?)))
+        .collect();
+    eprintln!(
+        "jflat: {} of {} files converted ({} records) into {}",
+        inputs.len() - failed.len(),
+        inputs.len(),
+        records,
+        args.out_dir.display()
+    );
+    for (input, error) in &failed {
+        eprintln!("  {}: {}", input.display(), error);
+    }
+
+    let fail = match args.fail_on {
+        FailOn::Any => !failed.is_empty(),
+        FailOn::All => failed.len() == inputs.len(),
+        FailOn::Never => false,
+    };
+    if fail {
+        return Err(format!("{} of {} files failed", failed.len(), inputs.len()).into());
+    }
+    Ok(())
+}
+
+/// Prints rows under a header with every column padded to its widest cell
+fn print_table(header: &[&str], rows: Vec<Vec<String>>) {
+    let mut widths: Vec<usize> = header.iter().map(|cell| cell.chars().count()).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let header: Vec<String> = header.iter().map(|cell| cell.to_string()).collect();
+    let rule: Vec<String> = widths.iter().map(|width| "-".repeat(*width)).collect();
+    for row in [&header, &rule].into_iter().chain(&rows) {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        println!("{}", cells.join("  ").trim_end());
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let result = match &cli.command {
+        Some(command) => report(command),
+        None => run(&cli),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("jflat: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,182 @@
+// src/binary.rs
+#[cfg(feature = "std")]
+use crate::error::FlattenError;
+#[cfg(feature = "std")]
+use crate::record_id::fnv1a;
+use alloc::format;
+use alloc::string::String;
+#[cfg(feature = "std")]
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::path::{Path, PathBuf};
+
+/// Handling of large base64 or binary-looking string leaves
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum BinaryPolicy {
+    /// Write the value unchanged
+    #[default]
+    Keep,
+    /// Leave the key out of the record
+    Drop,
+    /// Keep the first `n` characters and add a `#length` entry with the full length
+    Truncate(usize),
+    /// Write the value to a file in this directory and store the file's path instead
+    ///
+    /// Base64 values are decoded first. Files are named after a hash of the
+    /// content, so repeated blobs are written once. Requires the `std` feature.
+    #[cfg(feature = "std")]
+    SideFiles(PathBuf),
+}
+
+impl core::str::FromStr for BinaryPolicy {
+    type Err = String;
+
+    /// Parses `keep`, `drop`, `truncate:<chars>` or `side-files:<dir>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep" => Ok(BinaryPolicy::Keep),
+            "drop" => Ok(BinaryPolicy::Drop),
+            _ => {
+                if let Some(chars) = s.strip_prefix("truncate:") {
+                    let chars = chars.parse().map_err(|_| format!("invalid truncate length in {:?}", s))?;
+                    Ok(BinaryPolicy::Truncate(chars))
+                } else if let Some(dir) = s.strip_prefix("side-files:").or_else(|| s.strip_prefix("side_files:")) {
+                    side_files(dir)
+                } else {
+                    Err(format!(
+                        "unknown binary policy {:?} (expected keep, drop, truncate:<chars> or side-files:<dir>)",
+                        s
+                    ))
+                }
+            }
+        }
+    }
+}
+
+impl core::fmt::Display for BinaryPolicy {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            BinaryPolicy::Keep => f.write_str("keep"),
+            BinaryPolicy::Drop => f.write_str("drop"),
+            BinaryPolicy::Truncate(chars) => write!(f, "truncate:{}", chars),
+            #[cfg(feature = "std")]
+            BinaryPolicy::SideFiles(dir) => write!(f, "side-files:{}", dir.display()),
+        }
+    }
+}
+
+/// Returns true for strings of at least `min_len` bytes that look like
+/// base64 (optionally as a `data:` URI) or contain control characters
+pub fn looks_binary(value: &str, min_len: usize) -> bool {
+    if value.len() < min_len {
+        return false;
+    }
+    if base64_payload(value).is_some() {
+        return true;
+    }
+    let control = value
+        .chars()
+        .filter(|c| c.is_control() && !matches!(c, '\n' | '\r' | '\t'))
+        .count();
+    control * 10 > value.chars().count()
+}
+
+/// Returns the base64 body of a value that is entirely base64 or a base64 data URI
+fn base64_payload(value: &str) -> Option<&str> {
+    let body = match value.strip_prefix("data:") {
+        Some(uri) => uri.split_once(";base64,")?.1,
+        None => value,
+    };
+    let trimmed = body.trim_end_matches('=');
+    let is_base64 = body.len() - trimmed.len() <= 2
+        && trimmed
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'+' | b'/' | b'-' | b'_' | b'\n' | b'\r'));
+    // Plain words and identifiers are alphanumeric too; require the mix of
+    // cases and digits that encoded bytes practically always have
+    let mixed = trimmed.bytes().any(|b| b.is_ascii_digit())
+        && trimmed.bytes().any(|b| b.is_ascii_uppercase())
+        && trimmed.bytes().any(|b| b.is_ascii_lowercase());
+    (is_base64 && mixed).then_some(body)
+}
+
+#[cfg(feature = "std")]
+fn side_files(dir: &str) -> Result<BinaryPolicy, String> {
+    Ok(BinaryPolicy::SideFiles(PathBuf::from(dir)))
+}
+
+#[cfg(not(feature = "std"))]
+fn side_files(_dir: &str) -> Result<BinaryPolicy, String> {
+    Err("side-files requires the std feature".into())
+}
+
+/// Decodes standard or URL-safe base64, ignoring line breaks
+#[cfg(feature = "std")]
+fn decode_base64(body: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(body.len() * 3 / 4);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for b in body.bytes() {
+        let sextet = match b {
+            b'A'..=b'Z' => b - b'A',
+            b'a'..=b'z' => b - b'a' + 26,
+            b'0'..=b'9' => b - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            b'\n' | b'\r' => continue,
+            b'=' => break,
+            _ => return None,
+        };
+        buffer = (buffer << 6) | u32::from(sextet);
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+/// Writes a binary value to `dir`, returning the path of the side file
+#[cfg(feature = "std")]
+pub(crate) fn write_side_file(dir: &Path, value: &str) -> Result<String, FlattenError> {
+    let (bytes, extension) = match base64_payload(value).and_then(decode_base64) {
+        Some(decoded) => (decoded, "bin"),
+        None => (value.as_bytes().to_vec(), "txt"),
+    };
+    let path = dir.join(format!("{:016x}.{}", fnv1a(&bytes), extension));
+    let side_file_error = |e: std::io::Error| FlattenError::SideFile {
+        path: path.display().to_string(),
+        message: e.to_string(),
+    };
+    if !path.exists() {
+        std::fs::create_dir_all(dir).map_err(side_file_error)?;
+        std::fs::write(&path, &bytes).map_err(side_file_error)?;
+    }
+    Ok(path.display().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detects_base64_and_binary() {
+        let blob = "iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAADUlEQVR42mNk+M9QDwADhgGAWjR9awAAAABJRU5ErkJggg==";
+        assert!(looks_binary(blob, 16));
+        assert!(looks_binary(&format!("data:image/png;base64,{}", blob), 16));
+        assert!(!looks_binary(blob, 4096));
+        assert!(!looks_binary("a perfectly normal sentence with spaces in it", 16));
+        assert!(!looks_binary("averylongidentifierwithoutdigits", 16));
+        assert!(looks_binary("\u{1}\u{2}\u{3}\u{0}abc", 4));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_side_file_decodes_base64() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_side_file(dir.path(), "SGVsbG8sIFdvcmxkITEy").unwrap();
+        assert!(path.ends_with(".bin"));
+        assert_eq!(std::fs::read(path).unwrap(), b"Hello, World!12");
+    }
+}
@@ -0,0 +1,186 @@
+// src/canonical.rs
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use crate::value::{JsonKind, JsonLike};
+use serde_json::{Number, Value};
+
+/// Largest integer magnitude an f64 represents exactly (2^53)
+const MAX_SAFE_INTEGER: f64 = 9_007_199_254_740_992.0;
+
+/// Formats a number the same way on every run and platform
+///
+/// Integers print in plain decimal. Floats with an integral value in the
+/// exactly-representable range print without a fraction (`1.0` -> `1`,
+/// `-0.0` -> `0`); all other floats use the shortest round-trip form.
+pub fn canonical_number(n: &Number) -> String {
+    // Numbers kept exact by `arbitrary_precision` that an f64 would round keep their source text
+    #[cfg(feature = "arbitrary_precision")]
+    if !exact_in_f64(&n.to_string()) {
+        return n.to_string();
+    }
+    if n.is_f64() {
+        if let Some(f) = n.as_f64() {
+            if f.abs() < MAX_SAFE_INTEGER && f == (f as i64) as f64 {
+                return format!("{}", f as i64);
+            }
+        }
+    }
+    n.to_string()
+}
+
+/// Serializes a value with sorted object keys and canonical numbers
+pub fn canonical_json(value: &Value) -> String {
+    to_json(value, true)
+}
+
+/// Serializes any [`JsonLike`] value as compact JSON
+///
+/// Without `canonical` the output matches `serde_json`'s compact form for the
+/// same document; with it, object keys are sorted and numbers are canonical.
+pub(crate) fn to_json<J: JsonLike + ?Sized>(value: &J, canonical: bool) -> String {
+    let mut out = String::new();
+    write_json(value, canonical, &mut out);
+    out
+}
+
+fn write_json<J: JsonLike + ?Sized>(value: &J, canonical: bool, out: &mut String) {
+    match value.kind() {
+        JsonKind::Null => out.push_str("null"),
+        JsonKind::Bool => out.push_str(if value.as_bool() == Some(true) { "true" } else { "false" }),
+        JsonKind::Number => match value.as_number() {
+            Some(n) if canonical => out.push_str(&canonical_number(&n)),
+            Some(n) => out.push_str(&n.to_string()),
+            None => out.push_str("null"),
+        },
+        JsonKind::String => write_json_string(value.as_str().unwrap_or_default(), out),
+        JsonKind::Array => {
+            out.push('[');
+            for (i, item) in value.array_iter().into_iter().flatten().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_json(item, canonical, out);
+            }
+            out.push(']');
+        }
+        JsonKind::Object => {
+            let mut entries: Vec<_> = value.object_iter().into_iter().flatten().collect();
+            if canonical {
+                entries.sort_by(|a, b| a.0.cmp(b.0));
+            }
+            out.push('{');
+            for (i, (key, item)) in entries.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_json_string(key, out);
+                out.push(':');
+                write_json(item, canonical, out);
+            }
+            out.push('}');
+        }
+    }
+}
+
+/// Writes a quoted JSON string, escaping the same characters as `serde_json`
+fn write_json_string(s: &str, out: &mut String) {
+    const HEX: &[u8; 16] = b"0123456789abcdef";
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\u{8}' => out.push_str("\\b"),
+            '\u{c}' => out.push_str("\\f"),
+            c if (c as u32) < 0x20 => {
+                out.push_str("\\u00");
+                out.push(HEX[(c as usize) >> 4] as char);
+                out.push(HEX[(c as usize) & 0xf] as char);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// Splits a decimal number into its sign, significant digits and power of
+/// ten, e.g. `-12.50` into `(true, "125", -1)`; zero has no digits
+#[cfg(feature = "std")]
+pub(crate) fn decimal_parts(text: &str) -> Option<(bool, String, i64)> {
+    let (negative, unsigned) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let (mantissa, exponent) = match unsigned.find(['e', 'E']) {
+        Some(at) => (&unsigned[..at], unsigned[at + 1..].parse::<i64>().ok()?),
+        None => (unsigned, 0),
+    };
+    let (whole, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    let digits = format!("{}{}", whole, fraction);
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let leading = digits.trim_start_matches('0');
+    let significant = leading.trim_end_matches('0');
+    if significant.is_empty() {
+        return Some((false, String::new(), 0));
+    }
+    let exponent = exponent - fraction.len() as i64 + (leading.len() - significant.len()) as i64;
+    Some((negative, significant.to_string(), exponent))
+}
+
+/// Whether a decimal number reads back unchanged from the nearest f64, so a
+/// float column holds it without rounding (`0.1` does, `0.10000000000000000001`
+/// and integers beyond 2^53 that aren't multiples of their f64's spacing don't)
+#[cfg(feature = "std")]
+pub(crate) fn exact_in_f64(text: &str) -> bool {
+    match text.parse::<f64>() {
+        Ok(f) if f.is_finite() => decimal_parts(text).is_some_and(|parts| decimal_parts(&f.to_string()) == Some(parts)),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_canonical_numbers() {
+        let format = |v: Value| canonical_number(v.as_number().unwrap());
+        assert_eq!(format(json!(1.0)), "1");
+        assert_eq!(format(json!(-0.0)), "0");
+        assert_eq!(format(json!(2.5)), "2.5");
+        assert_eq!(format(json!(-7)), "-7");
+        assert_eq!(format(json!(u64::MAX)), "18446744073709551615");
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_decimal_parts_and_f64_exactness() {
+        assert_eq!(decimal_parts("-12.50"), Some((true, "125".to_string(), -1)));
+        assert_eq!(decimal_parts("0.00120e+3"), Some((false, "12".to_string(), -1)));
+        assert_eq!(decimal_parts("-0.0"), Some((false, String::new(), 0)));
+        assert_eq!(decimal_parts("1.2.3"), None);
+        assert!(exact_in_f64("1e3"));
+        assert!(exact_in_f64("0.1"));
+        assert!(!exact_in_f64("0.10000000000000000001"));
+        assert!(!exact_in_f64("1e400"));
+    }
+
+    #[test]
+    fn test_canonical_json_sorts_keys_and_normalizes_numbers() {
+        let value = json!({"b": [1.0, {"y": null, "x": true}], "a": "s"});
+        assert_eq!(canonical_json(&value), r#"{"a":"s","b":[1,{"x":true,"y":null}]}"#);
+    }
+
+    #[test]
+    fn test_to_json_matches_serde_json() {
+        let value = json!({"s": "q\"b\\s\n\u{1}\u{7f}é/", "n": [1.0, -2, 1e300], "o": {"z": null, "a": false}});
+        assert_eq!(to_json(&value, false), value.to_string());
+    }
+}
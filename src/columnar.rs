@@ -0,0 +1,188 @@
+// src/columnar.rs
+//! Records laid out as columns, for dataframes
+use crate::keys::{split_key, KeyPattern};
+use crate::FlattenedJson;
+use alloc::collections::btree_map::{self, BTreeMap};
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Records as one vector of cells per key, the shape dataframe libraries take
+///
+/// Every column has one cell per row; a cell is `None` where the row's record
+/// had no such key. Columns are kept sorted by name.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ColumnarTable {
+    rows: usize,
+    columns: BTreeMap<String, Vec<Option<String>>>,
+}
+
+impl ColumnarTable {
+    pub fn from_records(records: impl IntoIterator<Item = FlattenedJson>) -> Self {
+        let mut table = ColumnarTable::default();
+        records.into_iter().for_each(|record| table.push(record));
+        table
+    }
+
+    /// Adds a record as the last row
+    pub fn push(&mut self, record: FlattenedJson) {
+        let row = self.rows;
+        for (key, value) in record {
+            self.columns.entry(key).or_insert_with(|| vec![None; row]).push(Some(value));
+        }
+        self.rows += 1;
+        for cells in self.columns.values_mut() {
+            cells.resize(self.rows, None);
+        }
+    }
+
+    /// Appends the rows of `other`, keeping the columns of both
+    ///
+    /// Columns only one table has are filled with `None` for the other
+    /// table's rows, so merging the tables of several files or chunks gives
+    /// the table of all their records in order.
+    pub fn merge(mut self, other: ColumnarTable) -> Self {
+        let rows = self.rows;
+        for (name, mut cells) in other.columns {
+            self.columns.entry(name).or_insert_with(|| vec![None; rows]).append(&mut cells);
+        }
+        self.rows += other.rows;
+        for cells in self.columns.values_mut() {
+            cells.resize(self.rows, None);
+        }
+        self
+    }
+
+    /// Writes `fill` into every missing cell, e.g. `"null"` for a library
+    /// that can't take missing values
+    pub fn fill_missing(&mut self, fill: &str) {
+        for cell in self.columns.values_mut().flatten().filter(|cell| cell.is_none()) {
+            *cell = Some(fill.into());
+        }
+    }
+
+    /// Keeps only the columns matching one of `patterns`
+    ///
+    /// Column names are split on `.`, the default separator; for other
+    /// separators use [`retain_columns`](ColumnarTable::retain_columns).
+    pub fn select(mut self, patterns: &[KeyPattern]) -> Self {
+        self.retain_columns(|name| matches_any(patterns, name));
+        self
+    }
+
+    /// Removes the columns matching one of `patterns`, split as in [`select`](ColumnarTable::select)
+    pub fn drop(mut self, patterns: &[KeyPattern]) -> Self {
+        self.retain_columns(|name| !matches_any(patterns, name));
+        self
+    }
+
+    /// Keeps only the columns `keep` returns true for
+    pub fn retain_columns(&mut self, mut keep: impl FnMut(&str) -> bool) {
+        self.columns.retain(|name, _| keep(name));
+    }
+
+    pub fn num_rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn num_columns(&self) -> usize {
+        self.columns.len()
+    }
+
+    pub fn column_names(&self) -> impl Iterator<Item = &str> {
+        self.columns.keys().map(String::as_str)
+    }
+
+    pub fn column(&self, name: &str) -> Option<&[Option<String>]> {
+        self.columns.get(name).map(Vec::as_slice)
+    }
+
+    /// Columns with their cells, sorted by name
+    pub fn columns(&self) -> btree_map::Iter<'_, String, Vec<Option<String>>> {
+        self.columns.iter()
+    }
+
+    /// Turns the rows back into records, leaving out missing cells
+    pub fn into_records(self) -> Vec<FlattenedJson> {
+        let mut records: Vec<FlattenedJson> = (0..self.rows).map(|_| FlattenedJson::default()).collect();
+        for (name, cells) in self.columns {
+            for (record, cell) in records.iter_mut().zip(cells) {
+                if let Some(value) = cell {
+                    record.insert(name.clone(), value);
+                }
+            }
+        }
+        records
+    }
+}
+
+fn matches_any(patterns: &[KeyPattern], name: &str) -> bool {
+    let segments = split_key(name, ".", false);
+    patterns.iter().any(|pattern| pattern.matches(&segments))
+}
+
+impl IntoIterator for ColumnarTable {
+    type Item = (String, Vec<Option<String>>);
+    type IntoIter = btree_map::IntoIter<String, Vec<Option<String>>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.columns.into_iter()
+    }
+}
+
+impl FromIterator<FlattenedJson> for ColumnarTable {
+    fn from_iter<I: IntoIterator<Item = FlattenedJson>>(records: I) -> Self {
+        ColumnarTable::from_records(records)
+    }
+}
+
+#[cfg(feature = "std")]
+impl crate::RecordSink for ColumnarTable {
+    fn write_record(&mut self, record: FlattenedJson) -> std::io::Result<()> {
+        self.push(record);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    fn record(pairs: &[(&str, &str)]) -> FlattenedJson {
+        pairs.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()
+    }
+
+    fn cells<'a>(table: &'a ColumnarTable, name: &str) -> Vec<Option<&'a str>> {
+        table.column(name).unwrap().iter().map(Option::as_deref).collect()
+    }
+
+    #[test]
+    fn test_merge_keeps_rows_in_order_and_unions_columns() {
+        let first = ColumnarTable::from_records([record(&[("id", "1"), ("a", "x")]), record(&[("id", "2")])]);
+        let second: ColumnarTable = [record(&[("id", "3"), ("b", "y")])].into_iter().collect();
+        let mut table = first.merge(second).merge(ColumnarTable::default());
+
+        assert_eq!((table.num_rows(), table.num_columns()), (3, 3));
+        assert_eq!(table.column_names().collect::<Vec<_>>(), ["a", "b", "id"]);
+        assert_eq!(cells(&table, "id"), [Some("1"), Some("2"), Some("3")]);
+        assert_eq!(cells(&table, "a"), [Some("x"), None, None]);
+        assert_eq!(cells(&table, "b"), [None, None, Some("y")]);
+        assert_eq!(table.clone().into_records()[2], record(&[("id", "3"), ("b", "y")]));
+
+        table.fill_missing("null");
+        assert_eq!(cells(&table, "b"), [Some("null"), Some("null"), Some("y")]);
+    }
+
+    #[test]
+    fn test_select_and_drop_columns_by_pattern() {
+        let records = [record(&[("id", "1"), ("user.name", "a"), ("user.tags.0", "x"), ("debug.trace", "t")])];
+        let table = ColumnarTable::from_records(records);
+        let selected = table.clone().select(&[KeyPattern::new("id"), KeyPattern::new("user.**")]);
+        assert_eq!(selected.column_names().collect::<Vec<_>>(), ["id", "user.name", "user.tags.0"]);
+        let dropped = selected.drop(&[KeyPattern::new("**.tags.*")]);
+        assert_eq!(dropped.column_names().collect::<Vec<_>>(), ["id", "user.name"]);
+        assert_eq!(dropped.num_rows(), 1);
+        assert_eq!(table.drop(&[]).num_columns(), 4);
+    }
+}
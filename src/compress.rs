@@ -0,0 +1,233 @@
+// src/compress.rs
+use crate::sink::STDOUT_PATH;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// Compression applied to an output stream
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputCompression {
+    #[default]
+    None,
+    /// Gzip with a level from 0 (store) to 9 (best)
+    Gzip { level: u32 },
+    /// Zstandard with a level from 1 to 22; `workers > 0` enables multi-threaded compression
+    Zstd { level: i32, workers: u32 },
+}
+
+impl OutputCompression {
+    /// Default gzip compression (level 6)
+    pub const GZIP: OutputCompression = OutputCompression::Gzip { level: 6 };
+    /// Default single-threaded zstd compression (level 3)
+    pub const ZSTD: OutputCompression = OutputCompression::Zstd {
+        level: 3,
+        workers: 0,
+    };
+
+    /// Picks the default compression for a `.gz` or `.zst` path, and none otherwise
+    pub fn from_path(filepath: impl AsRef<Path>) -> Self {
+        match filepath.as_ref().extension().and_then(|ext| ext.to_str()) {
+            Some("gz") => Self::GZIP,
+            Some("zst") => Self::ZSTD,
+            _ => OutputCompression::None,
+        }
+    }
+
+    /// Wraps a writer so everything written to it is compressed
+    pub fn wrap<W: Write>(self, writer: W) -> io::Result<CompressedWriter<W>> {
+        let encoder = match self {
+            OutputCompression::None => Encoder::Plain(writer),
+            OutputCompression::Gzip { level } => Encoder::Gzip(flate2::write::GzEncoder::new(
+                writer,
+                flate2::Compression::new(level.min(9)),
+            )),
+            OutputCompression::Zstd { level, workers } => {
+                let mut encoder = zstd::Encoder::new(writer, level)?;
+                if workers > 0 {
+                    encoder.multithread(workers)?;
+                }
+                Encoder::Zstd(encoder)
+            }
+        };
+        Ok(CompressedWriter {
+            encoder,
+            finished: false,
+            finish_on_flush: false,
+        })
+    }
+}
+
+enum Encoder<W: Write> {
+    Plain(W),
+    Gzip(flate2::write::GzEncoder<W>),
+    Zstd(zstd::Encoder<'static, W>),
+}
+
+/// Writer compressing everything written to it, made by [`OutputCompression::wrap`]
+///
+/// [`finish`](CompressedWriter::finish) ends the compressed stream and reports
+/// whether that worked. A writer dropped unfinished still ends its stream, but
+/// any error doing so is lost.
+pub struct CompressedWriter<W: Write> {
+    encoder: Encoder<W>,
+    finished: bool,
+    finish_on_flush: bool,
+}
+
+impl<W: Write> CompressedWriter<W> {
+    /// Makes `flush` finish the stream, for writers handed to a sink that
+    /// flushes once its last record is written; writing after that fails
+    pub fn finish_on_flush(mut self) -> Self {
+        self.finish_on_flush = true;
+        self
+    }
+
+    /// Ends the compressed stream and flushes the writer underneath; later
+    /// calls do nothing
+    pub fn finish(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        match &mut self.encoder {
+            Encoder::Plain(writer) => writer.flush()?,
+            Encoder::Gzip(encoder) => {
+                encoder.try_finish()?;
+                encoder.get_mut().flush()?;
+            }
+            Encoder::Zstd(encoder) => {
+                encoder.do_finish()?;
+                encoder.get_mut().flush()?;
+            }
+        }
+        self.finished = true;
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for CompressedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.finished {
+            return Err(io::Error::other(
+                "write to a compressed stream that was already finished",
+            ));
+        }
+        match &mut self.encoder {
+            Encoder::Plain(writer) => writer.write(buf),
+            Encoder::Gzip(encoder) => encoder.write(buf),
+            Encoder::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.finish_on_flush || self.finished {
+            return self.finish();
+        }
+        match &mut self.encoder {
+            Encoder::Plain(writer) => writer.flush(),
+            Encoder::Gzip(encoder) => encoder.flush(),
+            Encoder::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+impl<W: Write> Drop for CompressedWriter<W> {
+    fn drop(&mut self) {
+        if !self.finished && !std::thread::panicking() {
+            let _ = self.finish();
+        }
+    }
+}
+
+/// Opens a buffered, compressed writer for a file, or standard output when the path is `"-"`
+///
+/// The stream is finished by the first `flush`, so errors ending it reach the
+/// sink that writes to it.
+pub fn open_compressed_output(
+    filepath: impl AsRef<Path>,
+    compression: OutputCompression,
+) -> io::Result<CompressedWriter<Box<dyn Write + Send>>> {
+    let filepath = filepath.as_ref();
+    let output: Box<dyn Write + Send> = if filepath == Path::new(STDOUT_PATH) {
+        Box::new(BufWriter::with_capacity(1 << 16, io::stdout()))
+    } else {
+        Box::new(BufWriter::with_capacity(1 << 16, File::create(filepath)?))
+    };
+    Ok(compression.wrap(output)?.finish_on_flush())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FlattenedJson, NdjsonSink, RecordSink};
+    use std::io::Read;
+
+    fn write_compressed(path: &str, compression: OutputCompression) {
+        let mut sink = NdjsonSink::new(open_compressed_output(path, compression).unwrap());
+        let mut record = FlattenedJson::default();
+        record.insert("a.b".to_string(), "1".to_string());
+        sink.write_record(record).unwrap();
+        sink.flush().unwrap();
+    }
+
+    #[test]
+    fn test_gzip_and_zstd_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let gz_path = dir.path().join("out.ndjson.gz");
+        let gz_path = gz_path.to_str().unwrap();
+        write_compressed(gz_path, OutputCompression::from_path(gz_path));
+        let mut text = String::new();
+        flate2::read::GzDecoder::new(File::open(gz_path).unwrap())
+            .read_to_string(&mut text)
+            .unwrap();
+        assert_eq!(text, "{\"a.b\":\"1\"}\n");
+
+        let zst_path = dir.path().join("out.ndjson.zst");
+        let zst_path = zst_path.to_str().unwrap();
+        write_compressed(
+            zst_path,
+            OutputCompression::Zstd {
+                level: 19,
+                workers: 2,
+            },
+        );
+        let decoded = zstd::decode_all(File::open(zst_path).unwrap()).unwrap();
+        assert_eq!(decoded, b"{\"a.b\":\"1\"}\n");
+    }
+
+    struct FailingFlush(Vec<u8>);
+
+    impl Write for FailingFlush {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Err(io::Error::other("disk full"))
+        }
+    }
+
+    #[test]
+    fn test_flush_reports_errors_finishing_the_stream() {
+        let mut output = OutputCompression::GZIP
+            .wrap(FailingFlush(Vec::new()))
+            .unwrap();
+        output.write_all(b"{}\n").unwrap();
+        assert_eq!(output.finish().unwrap_err().to_string(), "disk full");
+
+        let mut output = OutputCompression::ZSTD
+            .wrap(FailingFlush(Vec::new()))
+            .unwrap()
+            .finish_on_flush();
+        output.write_all(b"{}\n").unwrap();
+        assert!(output.flush().is_err());
+
+        let mut output = OutputCompression::GZIP
+            .wrap(Vec::new())
+            .unwrap()
+            .finish_on_flush();
+        output.write_all(b"{}\n").unwrap();
+        output.flush().unwrap();
+        assert!(output.write_all(b"{}\n").is_err());
+    }
+}
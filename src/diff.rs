@@ -0,0 +1,292 @@
+// src/diff.rs
+//! Flattening only what changed between two versions of a document, and the
+//! same changes as a JSON Patch
+use crate::flatten::{flatten_subtree, follow_chain, source_key};
+use crate::geo::{GeoJsonMode, Geometry};
+use crate::{FlattenError, FlattenOptions, FlattenedJson};
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Flattened keys that differ between two versions of a document
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FlatDiff {
+    /// Keys that are new or have a new value, with the new value
+    pub changed: BTreeMap<String, String>,
+    /// Keys of the old version that are gone, sorted
+    pub removed: Vec<String>,
+}
+
+impl FlatDiff {
+    pub fn is_empty(&self) -> bool {
+        self.changed.is_empty() && self.removed.is_empty()
+    }
+
+    /// Turns the old version's record into the new version's
+    pub fn apply(&self, record: &mut FlattenedJson) {
+        for key in &self.removed {
+            record.remove(key);
+        }
+        for (key, value) in &self.changed {
+            record.insert(key.clone(), value.clone());
+        }
+    }
+}
+
+/// Flattens the keys that changed from `old` to `new`
+///
+/// Subtrees that compare equal are skipped without being flattened, and
+/// objects are compared key by key, so a small update to a large document
+/// costs about one comparison of the two documents plus flattening the
+/// changed subtrees. Arrays, geometries and subtrees below `max_depth` are
+/// flattened whole on both sides when they differ, as their keys depend on
+/// all their elements.
+///
+/// Only the document's own keys are compared; record ids, structure metrics
+/// and the other keys added per record are not.
+pub fn flatten_changes(old: &Value, new: &Value, options: &FlattenOptions) -> Result<FlatDiff, FlattenError> {
+    let mut diff = FlatDiff::default();
+    diff_value("", old, new, options, 0, &mut diff)?;
+    // A removed source key can flatten to the same key as an added one
+    let FlatDiff { changed, removed } = &mut diff;
+    removed.retain(|key| !changed.contains_key(key));
+    removed.sort_unstable();
+    Ok(diff)
+}
+
+/// Compares two flattened records, e.g. a stored record and a freshly
+/// flattened new version of its document
+pub fn diff_records(old: &FlattenedJson, new: &FlattenedJson) -> FlatDiff {
+    let changed = new
+        .iter()
+        .filter(|(key, value)| old.get(*key) != Some(*value))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    let mut removed: Vec<String> = old.keys().filter(|key| !new.contains_key(*key)).cloned().collect();
+    removed.sort_unstable();
+    FlatDiff { changed, removed }
+}
+
+fn diff_value(
+    prefix: &str,
+    old: &Value,
+    new: &Value,
+    options: &FlattenOptions,
+    depth: usize,
+    diff: &mut FlatDiff,
+) -> Result<(), FlattenError> {
+    if old == new {
+        return Ok(());
+    }
+    match (old, new) {
+        (Value::Object(old_map), Value::Object(new_map))
+            if expands_by_key(prefix, old, options, depth) && expands_by_key(prefix, new, options, depth) =>
+        {
+            for (key, new_value) in new_map {
+                let (new_key, new_value, new_depth) = follow_chain(key, new_value, options, depth + 1);
+                let new_path = child_path(prefix, new_key, options)?;
+                let Some(old_value) = old_map.get(key) else {
+                    flatten_subtree(&new_path, new_value, &mut diff.changed, options, new_depth)?;
+                    continue;
+                };
+                let (old_key, old_value, old_depth) = follow_chain(key, old_value, options, depth + 1);
+                if (old_key, old_depth) == (new_key, new_depth) {
+                    diff_value(&new_path, old_value, new_value, options, new_depth, diff)?;
+                } else {
+                    // The single-key chain below the key got longer or shorter
+                    let (mut before, mut after) = (BTreeMap::new(), BTreeMap::new());
+                    let old_path = child_path(prefix, old_key, options)?;
+                    flatten_subtree(&old_path, old_value, &mut before, options, old_depth)?;
+                    flatten_subtree(&new_path, new_value, &mut after, options, new_depth)?;
+                    diff_flattened(before, after, diff);
+                }
+            }
+            for (key, old_value) in old_map.iter().filter(|(key, _)| !new_map.contains_key(*key)) {
+                let (old_key, old_value, old_depth) = follow_chain(key, old_value, options, depth + 1);
+                let mut gone = BTreeMap::new();
+                flatten_subtree(&child_path(prefix, old_key, options)?, old_value, &mut gone, options, old_depth)?;
+                diff.removed.extend(gone.into_keys());
+            }
+        }
+        _ => {
+            let (mut before, mut after) = (BTreeMap::new(), BTreeMap::new());
+            flatten_subtree(prefix, old, &mut before, options, depth)?;
+            flatten_subtree(prefix, new, &mut after, options, depth)?;
+            diff_flattened(before, after, diff);
+        }
+    }
+    Ok(())
+}
+
+/// Adds the differences between two flattened subtrees
+fn diff_flattened(mut before: BTreeMap<String, String>, after: BTreeMap<String, String>, diff: &mut FlatDiff) {
+    for (key, value) in after {
+        if before.remove(&key).as_ref() != Some(&value) {
+            diff.changed.insert(key, value);
+        }
+    }
+    diff.removed.extend(before.into_keys());
+}
+
+/// Whether an object is flattened one key at a time, so its keys can be
+/// compared separately
+fn expands_by_key(prefix: &str, object: &Value, options: &FlattenOptions, depth: usize) -> bool {
+    let below_max_depth = options.max_depth > 0 && depth >= options.max_depth;
+    let geometry = options.geojson != GeoJsonMode::Off && !prefix.is_empty() && Geometry::from_json(object).is_some();
+    !below_max_depth && !geometry
+}
+
+fn child_path(prefix: &str, key: &str, options: &FlattenOptions) -> Result<String, FlattenError> {
+    let key = source_key(key, prefix, options)?;
+    Ok(match prefix.is_empty() {
+        true => key.into_owned(),
+        false => format!("{}{}{}", prefix, options.separator, key),
+    })
+}
+
+/// One operation of an RFC 6902 JSON Patch; serializes as
+/// `{"op": "add", "path": "/a/0", "value": 1}`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum PatchOp {
+    Add { path: String, value: Value },
+    Remove { path: String },
+    Replace { path: String, value: Value },
+}
+
+/// The JSON Patch that turns document `a` into `b`
+///
+/// Found the same way as [`flatten_changes`]: equal subtrees are skipped and
+/// objects compared key by key. Arrays are compared element by element, with
+/// elements added or removed at the end, so inserting at the front of an
+/// array replaces every element after it. Paths are JSON Pointers (RFC 6901)
+/// into the source documents and don't depend on any flatten options.
+pub fn json_patch_from_diff(a: &Value, b: &Value) -> Vec<PatchOp> {
+    let mut ops = Vec::new();
+    patch_value(&mut String::new(), a, b, &mut ops);
+    ops
+}
+
+fn patch_value(path: &mut String, a: &Value, b: &Value, ops: &mut Vec<PatchOp>) {
+    if a == b {
+        return;
+    }
+    let parent = path.len();
+    match (a, b) {
+        (Value::Object(a_map), Value::Object(b_map)) => {
+            for (key, a_value) in a_map {
+                push_pointer_segment(path, key);
+                match b_map.get(key) {
+                    Some(b_value) => patch_value(path, a_value, b_value, ops),
+                    None => ops.push(PatchOp::Remove { path: path.clone() }),
+                }
+                path.truncate(parent);
+            }
+            for (key, b_value) in b_map.iter().filter(|(key, _)| !a_map.contains_key(*key)) {
+                push_pointer_segment(path, key);
+                ops.push(PatchOp::Add {
+                    path: path.clone(),
+                    value: b_value.clone(),
+                });
+                path.truncate(parent);
+            }
+        }
+        (Value::Array(a_items), Value::Array(b_items)) => {
+            for (i, (a_item, b_item)) in a_items.iter().zip(b_items).enumerate() {
+                path.push_str(&format!("/{}", i));
+                patch_value(path, a_item, b_item, ops);
+                path.truncate(parent);
+            }
+            // Removed from the end first, so earlier indices stay valid
+            for i in (b_items.len()..a_items.len()).rev() {
+                ops.push(PatchOp::Remove {
+                    path: format!("{}/{}", path, i),
+                });
+            }
+            for (i, b_item) in b_items.iter().enumerate().skip(a_items.len()) {
+                ops.push(PatchOp::Add {
+                    path: format!("{}/{}", path, i),
+                    value: b_item.clone(),
+                });
+            }
+        }
+        _ => ops.push(PatchOp::Replace {
+            path: path.clone(),
+            value: b.clone(),
+        }),
+    }
+}
+
+/// Appends `/key` with `~` and `/` escaped as `~0` and `~1`
+fn push_pointer_segment(path: &mut String, key: &str) {
+    path.push('/');
+    for c in key.chars() {
+        match c {
+            '~' => path.push_str("~0"),
+            '/' => path.push_str("~1"),
+            c => path.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flatten_json;
+    use alloc::string::ToString;
+    use alloc::vec;
+    use serde_json::json;
+
+    #[test]
+    fn test_changes_match_a_full_reflatten() {
+        let options = FlattenOptions::default();
+        let old = json!({"id": 1, "user": {"name": "a", "tags": ["x", "y"], "old": {"k": 1}}, "same": {"deep": [1, 2]}});
+        let new = json!({"id": 1, "user": {"name": "b", "tags": ["x"], "new": true}, "same": {"deep": [1, 2]}});
+
+        let diff = flatten_changes(&old, &new, &options).unwrap();
+        let expected: BTreeMap<_, _> =
+            [("user.name", "b"), ("user.new", "true")].iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        assert_eq!(diff.changed, expected);
+        assert_eq!(diff.removed, vec!["user.old.k", "user.tags.1"]);
+        assert_eq!(diff, diff_records(&flatten_json(&old, &options), &flatten_json(&new, &options)));
+
+        let mut record = flatten_json(&old, &options);
+        diff.apply(&mut record);
+        assert_eq!(record, flatten_json(&new, &options));
+        assert!(flatten_changes(&old, &old, &options).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_json_patch_from_diff() {
+        let a = json!({"name": "a", "tags": ["x", "y", "z"], "a/b": {"c~d": 1}, "gone": null, "n": 1});
+        let b = json!({"name": "b", "tags": ["x", "w"], "a/b": {"c~d": 2}, "n": 1, "new": [true]});
+        let ops = json_patch_from_diff(&a, &b);
+        assert_eq!(
+            serde_json::to_value(&ops).unwrap(),
+            json!([
+                {"op": "replace", "path": "/a~1b/c~0d", "value": 2},
+                {"op": "remove", "path": "/gone"},
+                {"op": "replace", "path": "/name", "value": "b"},
+                {"op": "replace", "path": "/tags/1", "value": "w"},
+                {"op": "remove", "path": "/tags/2"},
+                {"op": "add", "path": "/new", "value": [true]}
+            ])
+        );
+        assert!(json_patch_from_diff(&a, &a).is_empty());
+        assert_eq!(json_patch_from_diff(&a, &json!(1)), vec![PatchOp::Replace { path: String::new(), value: json!(1) }]);
+    }
+
+    #[test]
+    fn test_subtrees_below_max_depth_change_as_one_value() {
+        let options = FlattenOptions {
+            max_depth: 1,
+            ..Default::default()
+        };
+        let diff = flatten_changes(&json!({"a": {"b": 1, "c": 2}}), &json!({"a": {"b": 1, "c": 3}}), &options).unwrap();
+        assert_eq!(diff.changed.get("a").map(String::as_str), Some(r#"{"b":1,"c":3}"#));
+        assert!(diff.removed.is_empty());
+    }
+}
@@ -0,0 +1,238 @@
+// src/duplicates.rs
+#[cfg(feature = "std")]
+use crate::FlattenedJson;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use serde::de::{self, DeserializeSeed, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde_json::{Map, Value};
+
+/// Key added to records whose source document repeated an object key under
+/// [`DuplicateKeys::Report`], holding a JSON array of the repeated paths
+pub const DUPLICATE_KEYS_KEY: &str = "_duplicate_keys";
+
+/// Handling of keys that appear more than once in a source object
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DuplicateKeys {
+    /// Keep the last value silently, like serde_json
+    #[default]
+    Last,
+    /// Fail the document
+    Error,
+    /// Keep the last value and list the repeated paths under [`DUPLICATE_KEYS_KEY`]
+    Report,
+}
+
+impl core::str::FromStr for DuplicateKeys {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "last" => Ok(DuplicateKeys::Last),
+            "error" => Ok(DuplicateKeys::Error),
+            "report" => Ok(DuplicateKeys::Report),
+            other => Err(format!("unknown duplicate key policy {:?} (expected last, error or report)", other)),
+        }
+    }
+}
+
+impl fmt::Display for DuplicateKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DuplicateKeys::Last => "last",
+            DuplicateKeys::Error => "error",
+            DuplicateKeys::Report => "report",
+        })
+    }
+}
+
+/// Parses one document, returning it with the JSONPaths of its repeated keys
+///
+/// Under [`DuplicateKeys::Last`] this is plain `serde_json::from_str` and no
+/// paths are returned.
+pub fn parse_document(json: &str, policy: DuplicateKeys) -> Result<(Value, Vec<String>), serde_json::Error> {
+    if policy == DuplicateKeys::Last {
+        return Ok((serde_json::from_str(json)?, Vec::new()));
+    }
+    let mut deserializer = serde_json::Deserializer::from_str(json);
+    let parsed = deserialize_document(&mut deserializer, policy)?;
+    deserializer.end()?;
+    Ok(parsed)
+}
+
+/// Deserializes a document while checking for repeated keys, see [`parse_document`]
+pub fn deserialize_document<'de, D: Deserializer<'de>>(
+    deserializer: D,
+    policy: DuplicateKeys,
+) -> Result<(Value, Vec<String>), D::Error> {
+    let mut path = String::from("$");
+    let mut duplicates = Vec::new();
+    let value = DocumentSeed {
+        policy,
+        path: &mut path,
+        duplicates: &mut duplicates,
+    }
+    .deserialize(deserializer)?;
+    Ok((value, duplicates))
+}
+
+/// Lists repeated paths on a flattened record, if there are any
+#[cfg(feature = "std")]
+pub fn note_duplicates(record: &mut FlattenedJson, duplicates: &[String]) {
+    if !duplicates.is_empty() {
+        let paths = serde_json::to_string(duplicates).expect("strings always serialize");
+        record.insert(DUPLICATE_KEYS_KEY.to_string(), paths);
+    }
+}
+
+/// Key of the map serde_json deserializes an arbitrary-precision number as
+#[cfg(feature = "arbitrary_precision")]
+const NUMBER_TOKEN: &str = "$serde_json::private::Number";
+
+/// Builds a `Value` like serde_json does, tracking the path to each value
+struct DocumentSeed<'a> {
+    policy: DuplicateKeys,
+    path: &'a mut String,
+    duplicates: &'a mut Vec<String>,
+}
+
+impl DocumentSeed<'_> {
+    fn child(&mut self) -> DocumentSeed<'_> {
+        DocumentSeed {
+            policy: self.policy,
+            path: self.path,
+            duplicates: self.duplicates,
+        }
+    }
+}
+
+impl<'de> DeserializeSeed<'de> for DocumentSeed<'_> {
+    type Value = Value;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for DocumentSeed<'_> {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("any JSON value")
+    }
+
+    fn visit_bool<E>(self, value: bool) -> Result<Value, E> {
+        Ok(Value::Bool(value))
+    }
+
+    fn visit_i64<E>(self, value: i64) -> Result<Value, E> {
+        Ok(Value::from(value))
+    }
+
+    fn visit_u64<E>(self, value: u64) -> Result<Value, E> {
+        Ok(Value::from(value))
+    }
+
+    fn visit_f64<E>(self, value: f64) -> Result<Value, E> {
+        Ok(Value::from(value))
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Value, E> {
+        Ok(Value::String(value.to_string()))
+    }
+
+    fn visit_string<E>(self, value: String) -> Result<Value, E> {
+        Ok(Value::String(value))
+    }
+
+    fn visit_unit<E>(self) -> Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_none<E>(self) -> Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(mut self, mut seq: A) -> Result<Value, A::Error> {
+        let mut values = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        let parent = self.path.len();
+        loop {
+            let _ = write!(self.path, "[{}]", values.len());
+            let value = seq.next_element_seed(self.child())?;
+            self.path.truncate(parent);
+            match value {
+                Some(value) => values.push(value),
+                None => return Ok(Value::Array(values)),
+            }
+        }
+    }
+
+    fn visit_map<A: MapAccess<'de>>(mut self, mut access: A) -> Result<Value, A::Error> {
+        let mut map = Map::new();
+        let parent = self.path.len();
+        while let Some(key) = access.next_key::<String>()? {
+            // serde_json hands over arbitrary-precision numbers as a map with a single private key
+            #[cfg(feature = "arbitrary_precision")]
+            if map.is_empty() && key == NUMBER_TOKEN {
+                let number = access.next_value::<String>()?.parse().map_err(de::Error::custom)?;
+                return Ok(Value::Number(number));
+            }
+            push_path_key(self.path, &key);
+            let value = access.next_value_seed(self.child())?;
+            if map.contains_key(&key) {
+                if self.policy == DuplicateKeys::Error {
+                    return Err(de::Error::custom(format_args!("duplicate key at {}", self.path)));
+                }
+                self.duplicates.push(self.path.clone());
+            }
+            self.path.truncate(parent);
+            map.insert(key, value);
+        }
+        Ok(Value::Object(map))
+    }
+}
+
+/// Appends `.key`, or `['key']` when the key is not a plain identifier
+fn push_path_key(path: &mut String, key: &str) {
+    let plain = key.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if plain {
+        path.push('.');
+        path.push_str(key);
+    } else {
+        let _ = write!(path, "['{}']", key.replace('\\', "\\\\").replace('\'', "\\'"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+    use serde_json::json;
+
+    const DOCUMENT: &str = r#"{"a": 1, "b": [{"c": 1, "c": 2}], "a b": {"x": 0, "x": 1}, "a": 3}"#;
+
+    #[test]
+    fn test_repeated_keys_are_reported_with_their_paths() {
+        let (value, duplicates) = parse_document(DOCUMENT, DuplicateKeys::Report).unwrap();
+        assert_eq!(value, json!({"a": 3, "b": [{"c": 2}], "a b": {"x": 1}}));
+        assert_eq!(duplicates, vec!["$.b[0].c", "$['a b'].x", "$.a"]);
+
+        let (last, duplicates) = parse_document(DOCUMENT, DuplicateKeys::Last).unwrap();
+        assert_eq!(last, value);
+        assert!(duplicates.is_empty());
+    }
+
+    #[test]
+    fn test_repeated_keys_fail_under_error_policy() {
+        let err = parse_document(DOCUMENT, DuplicateKeys::Error).unwrap_err();
+        assert!(err.to_string().starts_with("duplicate key at $.b[0].c"), "{}", err);
+        assert!(parse_document(r#"{"a": 1} {"a": 2}"#, DuplicateKeys::Error).is_err());
+        assert_eq!(parse_document("[1, 2.5, null]", DuplicateKeys::Error).unwrap().0, json!([1, 2.5, null]));
+    }
+}
@@ -0,0 +1,440 @@
+// src/engine.rs
+use crate::batch::{chunk_reader_with_pools, StagePools};
+use crate::input::open_input_checked;
+#[cfg(feature = "plan")]
+use crate::plan::KeyPlan;
+use crate::progress::ChunkTracker;
+use crate::rules::SharedRules;
+use crate::{
+    flatten_input_document, try_flatten_json, ArrayAggregates, ArrayLimitPolicy, ArrayPathMode, BinaryPolicy, BudgetedSink,
+    DerivedColumn, Expr, FlattenError, FlattenOptions, FlattenedJson, GeoJsonMode, Outcome, RecordIdSource, RecordSink,
+};
+use crate::par::*;
+use serde_json::Value;
+use std::io::{self, Read};
+use std::path::Path;
+#[cfg(feature = "plan")]
+use std::sync::Arc;
+
+/// A long-lived flattener that owns its options and thread pools
+///
+/// The free functions build the parse and flatten pools on every call; a
+/// service handling many requests should create one engine and share it
+/// (it is `Send + Sync`) so the pools stay warm.
+///
+/// The crate keeps no global state: every call reads only its own options,
+/// so engines (and overrides) with different options can flatten
+/// concurrently without affecting each other. Stages whose thread count
+/// matches the global rayon pool run on it, sharing its workers with the
+/// rest of the process; set `local_pools` to give the engine its own.
+///
+/// With [`with_rules`](FlattenerEngine::with_rules), every record the engine
+/// returns or writes has the current key rules applied; they can be replaced
+/// or reloaded through [`rules`](FlattenerEngine::rules) while the engine runs.
+/// With the `plan` feature, a `KeyPlan` set with `with_plan` is compiled once
+/// and applied after the rules, on the flatten pool where records are made there.
+pub struct FlattenerEngine {
+    options: FlattenOptions,
+    pools: StagePools,
+    rules: Option<SharedRules>,
+    #[cfg(feature = "plan")]
+    plan: Option<Arc<KeyPlan>>,
+}
+
+impl FlattenerEngine {
+    /// Creates an engine, building the stage pools described by `options`
+    pub fn new(options: FlattenOptions) -> Result<Self, ThreadPoolBuildError> {
+        let pools = StagePools::new(&options)?;
+        Ok(FlattenerEngine {
+            options,
+            pools,
+            rules: None,
+            #[cfg(feature = "plan")]
+            plan: None,
+        })
+    }
+
+    /// The options every call uses
+    pub fn options(&self) -> &FlattenOptions {
+        &self.options
+    }
+
+    /// Applies `rules` to every record from now on
+    pub fn with_rules(mut self, rules: SharedRules) -> Self {
+        self.rules = Some(rules);
+        self
+    }
+
+    /// The key rules the engine applies, to replace or reload them in place
+    pub fn rules(&self) -> Option<&SharedRules> {
+        self.rules.as_ref()
+    }
+
+    /// Rereads the rules file now; returns false when the engine has no rules file
+    pub fn reload_rules(&self) -> io::Result<bool> {
+        self.rules.as_ref().map_or(Ok(false), SharedRules::reload)
+    }
+
+    /// Applies `plan` to every record from now on
+    #[cfg(feature = "plan")]
+    pub fn with_plan(mut self, plan: KeyPlan) -> Self {
+        self.plan = Some(Arc::new(plan));
+        self
+    }
+
+    /// The compiled key plan the engine applies, to inspect it
+    #[cfg(feature = "plan")]
+    pub fn plan(&self) -> Option<&Arc<KeyPlan>> {
+        self.plan.as_ref()
+    }
+
+    /// Applies the rules, then the plan
+    fn transform(&self, record: &mut FlattenedJson) {
+        if let Some(rules) = &self.rules {
+            rules.current().apply(record);
+        }
+        #[cfg(feature = "plan")]
+        if let Some(plan) = &self.plan {
+            plan.apply(record);
+        }
+    }
+
+    /// Returns an engine with `overrides` layered over this engine's options
+    ///
+    /// The new engine shares this engine's thread pools, so creating one per
+    /// request or per tenant profile is cheap.
+    pub fn with_overrides(&self, overrides: &FlattenOverrides) -> FlattenerEngine {
+        self.with_options(overrides.apply(&self.options))
+    }
+
+    /// An engine with other options, sharing this engine's pools, rules and plan
+    fn with_options(&self, options: FlattenOptions) -> FlattenerEngine {
+        FlattenerEngine {
+            options,
+            pools: self.pools.clone(),
+            rules: self.rules.clone(),
+            #[cfg(feature = "plan")]
+            plan: self.plan.clone(),
+        }
+    }
+
+    /// Flattens a single parsed document on the calling thread
+    pub fn flatten(&self, value: &Value) -> Result<FlattenedJson, FlattenError> {
+        let mut record = try_flatten_json(value, &self.options)?;
+        self.transform(&mut record);
+        Ok(record)
+    }
+
+    /// Flattens parsed documents in parallel on the flatten pool, preserving
+    /// order and leaving out the records `filters` reject and the documents a
+    /// `Skip` policy sets aside
+    pub fn flatten_values(&self, values: &[Value]) -> Result<Vec<FlattenedJson>, FlattenError> {
+        let options = &self.options;
+        options.validate_document()?;
+        let records = self.pools.flatten(|| {
+            values
+                .par_iter()
+                .enumerate()
+                .map(|(i, value)| match flatten_input_document(value, options, i as u64)?.filter(options) {
+                    Outcome::Record(mut record) => {
+                        self.transform(&mut record);
+                        Ok(Some(record))
+                    }
+                    Outcome::Filtered | Outcome::Skipped(_) => Ok(None),
+                })
+                .collect::<Result<Vec<_>, FlattenError>>()
+        })?;
+        Ok(records.into_iter().flatten().collect())
+    }
+
+    /// Flattens every document in a string (top-level array, concatenated or
+    /// NDJSON), splitting a top-level array like [`crate::flatten_json_multi_str`]
+    pub fn flatten_str(&self, json_str: &str) -> Result<Vec<FlattenedJson>, Box<dyn std::error::Error>> {
+        let engine = self.with_options(FlattenOptions {
+            split_root_array: true,
+            ..self.options.clone()
+        });
+        let mut results = Vec::new();
+        engine.flatten_reader_to_sink(json_str.as_bytes(), &mut results)?;
+        Ok(results)
+    }
+
+    /// Flattens a JSON file, honouring `memory_budget` like [`crate::flatten_json_file`]
+    pub fn flatten_file(&self, filepath: impl AsRef<Path>) -> Result<Vec<FlattenedJson>, Box<dyn std::error::Error>> {
+        let filepath = filepath.as_ref();
+        let mut sink = BudgetedSink::for_options(&self.options);
+        self.flatten_file_to_sink(filepath, &mut sink)?;
+        Ok(sink.into_records())
+    }
+
+    /// Flattens a JSON file into a sink; a path of `"-"` reads from standard input
+    pub fn flatten_file_to_sink<S: RecordSink>(
+        &self,
+        filepath: impl AsRef<Path>,
+        sink: &mut S,
+    ) -> Result<usize, Box<dyn std::error::Error>> {
+        self.options.validate()?;
+        let reader = open_input_checked(filepath, &self.options)?;
+        #[cfg(feature = "parallel")]
+        {
+            let mut sink = Transformed { engine: self, sink };
+            Ok(crate::batch::flatten_pipelined(reader, &mut sink, &self.options, &self.pools)?.written)
+        }
+        #[cfg(not(feature = "parallel"))]
+        {
+            self.flatten_reader_to_sink(reader, sink)
+        }
+    }
+
+    /// Flattens the documents of any reader into a sink, returning the record count
+    pub fn flatten_reader_to_sink<'a, R: Read + 'a, S: RecordSink>(
+        &self,
+        reader: R,
+        sink: &mut S,
+    ) -> Result<usize, Box<dyn std::error::Error>> {
+        self.options.validate()?;
+        let mut written = 0;
+        let mut chunks = ChunkTracker::new(&self.options);
+        let mut batches = chunk_reader_with_pools(reader, &self.options, self.pools.clone());
+        let mut filtered = 0;
+        while let Some(batch) = batches.next() {
+            let batch = batch?;
+            let records = batch.len();
+            for mut record in batch {
+                self.transform(&mut record);
+                sink.write_record(record)?;
+            }
+            written += records;
+            let skipped = batches.take_skipped();
+            let chunk_skipped = skipped.len();
+            for document in skipped {
+                sink.skip_document(document)?;
+            }
+            let chunk_filtered = batches.filtered() - filtered;
+            filtered = batches.filtered();
+            if chunks.chunk_done(records, chunk_filtered as usize, chunk_skipped).is_some() {
+                break;
+            }
+        }
+
+        sink.flush()?;
+        Ok(written)
+    }
+}
+
+/// Sink applying an engine's rules and plan before the caller's sink
+#[cfg(feature = "parallel")]
+struct Transformed<'e, S> {
+    engine: &'e FlattenerEngine,
+    sink: S,
+}
+
+#[cfg(feature = "parallel")]
+impl<S: RecordSink> RecordSink for Transformed<'_, S> {
+    fn write_record(&mut self, mut record: FlattenedJson) -> io::Result<()> {
+        self.engine.transform(&mut record);
+        self.sink.write_record(record)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.sink.flush()
+    }
+
+    fn skip_document(&mut self, skipped: crate::SkippedDocument) -> io::Result<()> {
+        self.sink.skip_document(skipped)
+    }
+}
+
+/// Per-call changes to an engine's options; `None` keeps the engine's value
+///
+/// Covers the options that shape each record. The rest stay the engine's:
+/// thread counts and pool settings because the pools are built once and
+/// shared, and input, size and time limits because a service sets them once
+/// for every caller.
+#[derive(Clone, Debug, Default)]
+pub struct FlattenOverrides {
+    pub separator: Option<String>,
+    pub escape_keys: Option<bool>,
+    pub max_depth: Option<usize>,
+    pub include_array_indices: Option<bool>,
+    pub expand_arrays: Option<bool>,
+    pub canonical: Option<bool>,
+    pub raw_values: Option<bool>,
+    pub array_metadata: Option<bool>,
+    pub max_array_elements: Option<Option<usize>>,
+    pub array_limit_policy: Option<ArrayLimitPolicy>,
+    pub array_aggregates: Option<ArrayAggregates>,
+    pub array_modes: Option<Vec<ArrayPathMode>>,
+    pub record_id: Option<RecordIdSource>,
+    pub record_id_key: Option<String>,
+    pub geojson: Option<GeoJsonMode>,
+    pub binary_values: Option<BinaryPolicy>,
+    pub normalize_keys: Option<bool>,
+    pub normalize_values: Option<bool>,
+    pub strip_prefix: Option<Option<String>>,
+    pub derived_columns: Option<Vec<DerivedColumn>>,
+    pub filters: Option<Vec<Expr>>,
+}
+
+impl FlattenOverrides {
+    /// Returns `base` with every set override applied
+    pub fn apply(&self, base: &FlattenOptions) -> FlattenOptions {
+        let mut options = base.clone();
+        macro_rules! apply {
+            ($($field:ident),*) => {
+                $(if let Some(value) = &self.$field {
+                    options.$field = value.clone();
+                })*
+            };
+        }
+        apply!(
+            separator,
+            escape_keys,
+            max_depth,
+            include_array_indices,
+            expand_arrays,
+            canonical,
+            raw_values,
+            array_metadata,
+            max_array_elements,
+            array_limit_policy,
+            array_aggregates,
+            array_modes,
+            record_id,
+            record_id_key,
+            geojson,
+            binary_values,
+            normalize_keys,
+            normalize_values,
+            strip_prefix,
+            derived_columns,
+            filters
+        );
+        options
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_engine_is_shared_across_threads() {
+        let engine = Arc::new(
+            FlattenerEngine::new(FlattenOptions {
+                flatten_threads: 2,
+                ..Default::default()
+            })
+            .unwrap(),
+        );
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let engine = Arc::clone(&engine);
+                std::thread::spawn(move || {
+                    let records = engine.flatten_str(&format!("[{{\"a\": {{\"b\": {}}}}}, {{\"c\": 1}}]", i)).unwrap();
+                    assert_eq!(records.len(), 2);
+                    records[0]["a.b"].clone()
+                })
+            })
+            .collect();
+        let values: Vec<String> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+
+        assert_eq!(values, vec!["0", "1", "2", "3"]);
+        let flattened = engine.flatten_values(&[json!({"x": [1]}), json!({"y": null})]).unwrap();
+        assert_eq!(flattened[0]["x.0"], "1");
+        assert_eq!(flattened[1]["y"], "null");
+    }
+
+    #[test]
+    fn test_engine_flatten_values_numbers_records_in_order() {
+        let engine = FlattenerEngine::new(FlattenOptions {
+            record_id: RecordIdSource::Sequence,
+            ..Default::default()
+        })
+        .unwrap();
+        let values: Vec<Value> = (0..50).map(|i| json!({"i": i})).collect();
+        let records = engine.flatten_values(&values).unwrap();
+
+        for (i, record) in records.iter().enumerate() {
+            assert_eq!(record["_id"], i.to_string());
+            assert_eq!(record["i"], i.to_string());
+        }
+    }
+
+    #[test]
+    fn test_concurrent_engines_keep_their_own_options() {
+        let document = json!({"a": {"b": {"c": [1, 2]}}});
+        let engines: Vec<Arc<FlattenerEngine>> = [("_", 0, false), ("/", 2, true), (".", 1, true)]
+            .into_iter()
+            .map(|(separator, max_depth, local_pools)| {
+                let options = FlattenOptions {
+                    separator: separator.to_string(),
+                    max_depth,
+                    local_pools,
+                    flatten_threads: 2,
+                    ..Default::default()
+                };
+                Arc::new(FlattenerEngine::new(options).unwrap())
+            })
+            .collect();
+        let expected: Vec<FlattenedJson> = engines.iter().map(|engine| engine.flatten(&document).unwrap()).collect();
+        assert!(expected[0].contains_key("a_b_c_1"));
+        assert_eq!(expected[1]["a/b"], r#"{"c":[1,2]}"#);
+        assert_eq!(expected[2]["a"], r#"{"b":{"c":[1,2]}}"#);
+
+        let handles: Vec<_> = (0..12)
+            .map(|i| {
+                let engine = Arc::clone(&engines[i % engines.len()]);
+                let documents = vec![document.clone(); 50];
+                std::thread::spawn(move || (i % 3, engine.flatten_values(&documents).unwrap()))
+            })
+            .collect();
+        for handle in handles {
+            let (engine, records) = handle.join().unwrap();
+            assert!(records.iter().all(|record| *record == expected[engine]));
+        }
+    }
+
+    #[test]
+    fn test_overrides_layer_over_engine_options() {
+        let engine = FlattenerEngine::new(FlattenOptions {
+            max_depth: 2,
+            ..Default::default()
+        })
+        .unwrap();
+        let tenant = engine.with_overrides(&FlattenOverrides {
+            separator: Some("__".to_string()),
+            ..Default::default()
+        });
+        let document = json!({"a": {"b": {"c": 1}}});
+
+        let flattened = tenant.flatten(&document).unwrap();
+        assert_eq!(flattened["a__b"], r#"{"c":1}"#);
+        assert_eq!(tenant.options().max_depth, 2);
+        assert!(engine.flatten(&document).unwrap().contains_key("a.b"));
+    }
+
+    #[test]
+    fn test_overrides_change_filters_and_derived_columns() {
+        let engine = FlattenerEngine::new(FlattenOptions {
+            strip_prefix: Some("data.".to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+        let tenant = engine.with_overrides(&FlattenOverrides {
+            strip_prefix: Some(None),
+            derived_columns: Some(vec!["total = data.n * 2".parse().unwrap()]),
+            filters: Some(vec!["data.n > 1".parse().unwrap()]),
+            ..Default::default()
+        });
+        let values = [json!({"data": {"n": 1}}), json!({"data": {"n": 2}})];
+
+        let records = tenant.flatten_values(&values).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0]["data.n"], "2");
+        assert_eq!(records[0]["total"], "4");
+        assert_eq!(engine.flatten_values(&values).unwrap()[0]["n"], "1");
+    }
+}
@@ -0,0 +1,172 @@
+// src/error.rs
+use crate::input::InputPosition;
+use crate::keys::KeyAmbiguity;
+use crate::options::OptionsError;
+use crate::value::JsonKind;
+use alloc::string::{String, ToString};
+use core::fmt;
+use core::time::Duration;
+
+/// Errors raised while flattening a parsed document
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FlattenError {
+    /// An array exceeded `max_array_elements` under [`ArrayLimitPolicy::Error`](crate::ArrayLimitPolicy::Error)
+    ArrayTooLong { path: String, len: usize, limit: usize },
+    /// The record id path configured in `record_id` is missing or null in a document
+    MissingRecordId(String),
+    /// A binary value could not be written to its side file
+    SideFile { path: String, message: String },
+    /// An object holds an empty key under [`EmptyKeys::Error`](crate::EmptyKeys::Error);
+    /// the path is the flattened key of that object
+    EmptyKey(String),
+    /// An object holds a key that could be mistaken for flattening notation
+    /// under [`AmbiguousKeys::Error`](crate::AmbiguousKeys::Error); the path is
+    /// the flattened key of that object
+    AmbiguousKey { path: String, key: String, reason: KeyAmbiguity },
+    /// A record grew past `max_record_keys` keys under
+    /// [`OversizedRecords::Error`](crate::OversizedRecords::Error)
+    TooManyKeys { limit: usize },
+    /// A record took longer than `record_time_limit` to flatten under
+    /// [`OversizedRecords::Error`](crate::OversizedRecords::Error)
+    RecordTimedOut { limit: Duration },
+    /// A document is a scalar or an array under
+    /// [`NonObjectRecords::Error`](crate::NonObjectRecords::Error)
+    NotAnObject(JsonKind),
+    /// The options were rejected by [`FlattenOptions::validate`](crate::FlattenOptions::validate)
+    InvalidOptions(OptionsError),
+}
+
+impl fmt::Display for FlattenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FlattenError::ArrayTooLong { path, len, limit } => write!(
+                f,
+                "array at {:?} has {} elements, more than the limit of {}",
+                path, len, limit
+            ),
+            FlattenError::MissingRecordId(path) => write!(f, "record id {} is missing or null", path),
+            FlattenError::SideFile { path, message } => {
+                write!(f, "failed to write side file {}: {}", path, message)
+            }
+            FlattenError::EmptyKey(path) if path.is_empty() => write!(f, "empty key in the root object"),
+            FlattenError::EmptyKey(path) => write!(f, "empty key in the object at {:?}", path),
+            FlattenError::AmbiguousKey { path, key, reason } if path.is_empty() => {
+                write!(f, "key {:?} in the root object {}", key, reason)
+            }
+            FlattenError::AmbiguousKey { path, key, reason } => {
+                write!(f, "key {:?} in the object at {:?} {}", key, path, reason)
+            }
+            FlattenError::TooManyKeys { limit } => write!(f, "record has more than {} keys", limit),
+            FlattenError::RecordTimedOut { limit } => {
+                write!(f, "record took longer than {:?} to flatten", limit)
+            }
+            FlattenError::NotAnObject(kind) => write!(f, "document is {}, not an object", kind),
+            FlattenError::InvalidOptions(e) => write!(f, "invalid options: {}", e),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for FlattenError {}
+
+/// Bytes of document text kept on each side of a parse error in [`ParseError::excerpt`]
+pub const EXCERPT_CONTEXT: usize = 24;
+
+/// A document that is not valid JSON, with where the parser gave up
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// What the parser found, e.g. ``expected `,` or `}` ``; without a
+    /// position it ends with the line and column within the document
+    pub message: String,
+    /// Where in the input the error is, when the document's start is known
+    pub position: Option<InputPosition>,
+    /// The document text around the error
+    pub excerpt: String,
+}
+
+impl ParseError {
+    /// Locates a serde_json error in the text of a document starting at `start`
+    pub fn locate(error: &serde_json::Error, text: &str, start: Option<InputPosition>) -> Self {
+        let line = error.line().saturating_sub(1);
+        let line_start: usize = text.split_inclusive('\n').take(line).map(str::len).sum();
+        let index = char_floor(text, line_start + error.column().saturating_sub(1));
+        let from = char_floor(text, index.saturating_sub(EXCERPT_CONTEXT));
+        let excerpt = &text[from..char_floor(text, index + EXCERPT_CONTEXT)];
+        let position = start.map(|start| InputPosition {
+            offset: start.offset + index as u64,
+            line: start.line + line as u64,
+        });
+        let mut message = error.to_string();
+        if let (Some(_), Some(at)) = (position, message.rfind(" at line ")) {
+            message.truncate(at);
+        }
+        ParseError {
+            message,
+            position,
+            excerpt: excerpt.to_string(),
+        }
+    }
+}
+
+/// The char boundary at or before `index`, within `text`
+fn char_floor(text: &str, index: usize) -> usize {
+    let mut index = index.min(text.len());
+    while !text.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.position {
+            Some(at) => write!(f, "invalid JSON at line {}, byte {}: {}", at.line, at.offset, self.message)?,
+            None => write!(f, "invalid JSON: {}", self.message)?,
+        }
+        match self.excerpt.is_empty() {
+            true => Ok(()),
+            false => write!(f, " near {:?}", self.excerpt),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ParseError {}
+
+/// Why a document was left out under a `Skip` policy
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SkipReason {
+    /// Flattening failed under [`OversizedRecords::Skip`](crate::OversizedRecords::Skip)
+    /// or [`NonObjectRecords::Skip`](crate::NonObjectRecords::Skip)
+    Flatten(FlattenError),
+    /// The document is not valid JSON, under [`InvalidDocuments::Skip`](crate::InvalidDocuments::Skip)
+    Parse(ParseError),
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkipReason::Flatten(e) => e.fmt(f),
+            SkipReason::Parse(e) => e.fmt(f),
+        }
+    }
+}
+
+/// A document a `Skip` policy left out of an input's records
+///
+/// Pipelines hand these to [`RecordSink::skip_document`](crate::RecordSink::skip_document)
+/// instead of writing a record in their place.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkippedDocument {
+    /// Position of the document in the input, counting `skip_documents`
+    pub document: u64,
+    /// The record id the document would have had, when one can be given
+    pub record_id: Option<String>,
+    pub reason: SkipReason,
+}
+
+impl From<OptionsError> for FlattenError {
+    fn from(e: OptionsError) -> Self {
+        FlattenError::InvalidOptions(e)
+    }
+}
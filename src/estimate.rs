@@ -0,0 +1,246 @@
+// src/estimate.rs
+use crate::value::{JsonKind, JsonLike};
+#[cfg(feature = "std")]
+use crate::FlattenedJson;
+use alloc::string::String;
+#[cfg(feature = "std")]
+use alloc::string::ToString;
+use core::fmt::{self, Write};
+
+/// Bytes every entry adds on top of its key and value: two `String` headers
+/// and a hash table slot
+pub(crate) const ENTRY_OVERHEAD: usize = 2 * core::mem::size_of::<String>() + core::mem::size_of::<u64>();
+
+/// Bytes of an empty record: the hash table's pointer, mask and two counts,
+/// and the two keys of its `RandomState` (the size of `FlattenedJson`, which
+/// a `no_std` build doesn't have)
+const RECORD_BYTES: usize = 4 * core::mem::size_of::<usize>() + 2 * core::mem::size_of::<u64>();
+
+/// Size of the record a document flattens to, computed without flattening it
+///
+/// The figures assume the default options: every array is expanded and keys
+/// are joined with a one-byte separator.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FlatEstimate {
+    /// Number of scalar leaves, which is the number of entries in the record
+    pub leaf_count: usize,
+    /// Number of segments in the longest flattened key (0 for a scalar document)
+    pub max_depth: usize,
+    /// Rough in-memory size of the record, on the same scale as
+    /// [`estimated_record_bytes`](crate::spill::estimated_record_bytes)
+    pub est_bytes: usize,
+}
+
+/// Estimates the size of a document's flattened record
+///
+/// Useful to pre-allocate sinks or to reject oversized documents before
+/// paying for flattening them:
+///
+/// ```
+/// use json_flattener::estimate_flattened_size;
+/// use serde_json::json;
+///
+/// let estimate = estimate_flattened_size(&json!({"user": {"name": "Ann", "tags": ["a", "b"]}}));
+/// assert_eq!(estimate.leaf_count, 3);
+/// assert_eq!(estimate.max_depth, 3);
+/// ```
+pub fn estimate_flattened_size<J: JsonLike + ?Sized>(value: &J) -> FlatEstimate {
+    let mut estimate = FlatEstimate {
+        est_bytes: RECORD_BYTES,
+        ..FlatEstimate::default()
+    };
+    for_each_child(value, 0, &mut |child, key_len| visit(child, 1, key_len, &mut estimate));
+    estimate
+}
+
+/// Number of scalar leaves in a document, which is the number of entries it
+/// flattens to with the default options
+///
+/// Cheaper than [`estimate_flattened_size`] when only the count is needed;
+/// records are pre-sized with it when `presize_records` is set.
+pub fn leaf_count<J: JsonLike + ?Sized>(value: &J) -> usize {
+    fn count<J: JsonLike + ?Sized>(value: &J) -> usize {
+        match value.kind() {
+            JsonKind::Array => value.array_iter().into_iter().flatten().map(count).sum(),
+            JsonKind::Object => value.object_iter().into_iter().flatten().map(|(_, v)| count(v)).sum(),
+            _ => 1,
+        }
+    }
+    match value.kind() {
+        JsonKind::Array | JsonKind::Object => count(value),
+        _ => 0,
+    }
+}
+
+fn visit<J: JsonLike + ?Sized>(value: &J, depth: usize, key_len: usize, estimate: &mut FlatEstimate) {
+    match value.kind() {
+        JsonKind::Array | JsonKind::Object => {
+            for_each_child(value, key_len, &mut |child, child_key_len| {
+                visit(child, depth + 1, child_key_len, estimate)
+            });
+        }
+        _ => {
+            estimate.max_depth = estimate.max_depth.max(depth);
+            estimate.leaf_count += 1;
+            estimate.est_bytes += key_len + scalar_len(value) + ENTRY_OVERHEAD;
+        }
+    }
+}
+
+/// Key of a record's [`StructureMetrics::depth`] under `FlattenOptions::structure_metrics`
+pub const DEPTH_KEY: &str = "_depth";
+/// Key of a record's [`StructureMetrics::max_object_width`]
+pub const MAX_WIDTH_KEY: &str = "_max_width";
+/// Key of a record's [`StructureMetrics::max_array_len`]
+pub const MAX_ARRAY_LEN_KEY: &str = "_max_array_len";
+/// Key of a record's [`StructureMetrics::key_count`]
+pub const KEY_COUNT_KEY: &str = "_key_count";
+
+/// Shape of a source document, for spotting records that are deeper or wider
+/// than a feed normally sends
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StructureMetrics {
+    /// Levels of nested containers (0 for a scalar document, 1 for a flat object)
+    pub depth: usize,
+    /// Most keys in any one object
+    pub max_object_width: usize,
+    /// Most elements in any one array
+    pub max_array_len: usize,
+    /// Keys across all objects
+    pub key_count: usize,
+}
+
+impl StructureMetrics {
+    /// Adds the metrics to a record under [`DEPTH_KEY`], [`MAX_WIDTH_KEY`],
+    /// [`MAX_ARRAY_LEN_KEY`] and [`KEY_COUNT_KEY`]
+    #[cfg(feature = "std")]
+    pub fn insert_into(&self, record: &mut FlattenedJson) {
+        record.insert(DEPTH_KEY.to_string(), self.depth.to_string());
+        record.insert(MAX_WIDTH_KEY.to_string(), self.max_object_width.to_string());
+        record.insert(MAX_ARRAY_LEN_KEY.to_string(), self.max_array_len.to_string());
+        record.insert(KEY_COUNT_KEY.to_string(), self.key_count.to_string());
+    }
+}
+
+/// Measures the depth and width of a document
+///
+/// Records get these figures as metadata keys when
+/// `FlattenOptions::structure_metrics` is set; calling this directly keeps
+/// them out of the records.
+pub fn structure_metrics<J: JsonLike + ?Sized>(value: &J) -> StructureMetrics {
+    fn measure<J: JsonLike + ?Sized>(value: &J, depth: usize, metrics: &mut StructureMetrics) {
+        if let Some(elements) = value.array_iter() {
+            metrics.depth = metrics.depth.max(depth);
+            metrics.max_array_len = metrics.max_array_len.max(elements.len());
+            elements.for_each(|element| measure(element, depth + 1, metrics));
+        } else if let Some(entries) = value.object_iter() {
+            metrics.depth = metrics.depth.max(depth);
+            let mut width = 0;
+            for (_, child) in entries {
+                width += 1;
+                measure(child, depth + 1, metrics);
+            }
+            metrics.max_object_width = metrics.max_object_width.max(width);
+            metrics.key_count += width;
+        }
+    }
+
+    let mut metrics = StructureMetrics::default();
+    measure(value, 1, &mut metrics);
+    metrics
+}
+
+/// Calls `f` with every child of a container and the length of its key
+fn for_each_child<J: JsonLike + ?Sized>(value: &J, key_len: usize, f: &mut dyn FnMut(&J, usize)) {
+    let separator_len = usize::from(key_len > 0);
+    if let Some(elements) = value.array_iter() {
+        for (i, element) in elements.enumerate() {
+            f(element, key_len + separator_len + display_len(i));
+        }
+    } else if let Some(entries) = value.object_iter() {
+        for (key, child) in entries {
+            f(child, key_len + separator_len + key.len());
+        }
+    }
+}
+
+/// Length of a scalar as written to a record
+fn scalar_len<J: JsonLike + ?Sized>(value: &J) -> usize {
+    match value.kind() {
+        JsonKind::String => value.as_str().map_or(0, str::len),
+        JsonKind::Number => value.as_number().map_or(0, display_len),
+        JsonKind::Bool if value.as_bool() == Some(true) => 4,
+        JsonKind::Bool => 5,
+        _ => 4,
+    }
+}
+
+/// Length of a value's `Display` output, without allocating it
+fn display_len(value: impl fmt::Display) -> usize {
+    struct Counter(usize);
+
+    impl Write for Counter {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            self.0 += s.len();
+            Ok(())
+        }
+    }
+
+    let mut counter = Counter(0);
+    let _ = write!(counter, "{}", value);
+    counter.0
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::spill::estimated_record_bytes;
+    use crate::{flatten_json, FlattenOptions};
+    use serde_json::json;
+
+    #[test]
+    fn test_estimate_matches_flattened_record() {
+        let value = json!({
+            "id": 12345,
+            "user": {"name": "Ann", "active": true, "score": 9.75},
+            "items": [{"sku": "a-1", "qty": 2}, {"sku": "b-22", "qty": null}]
+        });
+        let record = flatten_json(&value, &FlattenOptions::default());
+        let estimate = estimate_flattened_size(&value);
+
+        assert_eq!(estimate.leaf_count, record.len());
+        assert_eq!(leaf_count(&value), record.len());
+        assert_eq!(estimate.max_depth, 3);
+        assert_eq!(estimate.est_bytes, estimated_record_bytes(&record));
+    }
+
+    #[test]
+    fn test_structure_metrics() {
+        let value = json!({"a": {"b": [1, 2, 3, {"c": []}]}, "d": 1, "e": {}});
+        let metrics = structure_metrics(&value);
+        assert_eq!(
+            metrics,
+            StructureMetrics {
+                depth: 5,
+                max_object_width: 3,
+                max_array_len: 4,
+                key_count: 5
+            }
+        );
+        assert_eq!(structure_metrics(&json!(null)), StructureMetrics::default());
+
+        let options = FlattenOptions {
+            structure_metrics: true,
+            ..Default::default()
+        };
+        let record = flatten_json(&value, &options);
+        assert_eq!(record[DEPTH_KEY], "5");
+        assert_eq!(record[KEY_COUNT_KEY], "5");
+    }
+
+    #[test]
+    fn test_estimate_of_scalar_document() {
+        assert_eq!(estimate_flattened_size(&json!("text")).leaf_count, 0);
+        assert_eq!(leaf_count(&json!(1)), 0);
+    }
+}
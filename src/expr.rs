@@ -0,0 +1,600 @@
+// src/expr.rs
+//! A small expression language over flattened values, for derived columns
+//! and record filters
+#[cfg(feature = "std")]
+use crate::FlattenedJson;
+use alloc::borrow::Cow;
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use core::cmp::Ordering;
+use core::fmt;
+
+/// A value an [`Expr`] works with
+///
+/// Flattened values are read as text, or as `Null` when missing or `null`;
+/// text that parses as a number counts as one in arithmetic.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Datum<'a> {
+    Null,
+    Bool(bool),
+    Number(f64),
+    Text(Cow<'a, str>),
+}
+
+impl Datum<'_> {
+    pub fn is_null(&self) -> bool {
+        matches!(self, Datum::Null)
+    }
+
+    /// The datum as a finite number, if it is or holds one
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            Datum::Number(n) => Some(*n),
+            Datum::Text(text) => text.parse::<f64>().ok(),
+            _ => None,
+        }
+        .filter(|n| n.is_finite())
+    }
+
+    /// Whether the datum counts as true in a filter or `&&`, `||` and `!`:
+    /// anything but null, `false`, zero, `""` and the text `false`
+    pub fn is_true(&self) -> bool {
+        match self {
+            Datum::Null => false,
+            Datum::Bool(b) => *b,
+            Datum::Number(n) => *n != 0.0 && !n.is_nan(),
+            Datum::Text(text) => !text.is_empty() && text != "false",
+        }
+    }
+
+    /// The datum as a flattened value: `null`, `true`, a number without a
+    /// trailing `.0`, or the text
+    pub fn to_text(&self) -> Cow<'_, str> {
+        match self {
+            Datum::Null => Cow::Borrowed("null"),
+            Datum::Bool(b) => Cow::Borrowed(if *b { "true" } else { "false" }),
+            Datum::Number(n) if n.is_finite() => Cow::Owned(n.to_string()),
+            Datum::Number(_) => Cow::Borrowed("null"),
+            Datum::Text(text) => Cow::Borrowed(text),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum BinaryOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Function {
+    Concat,
+    Coalesce,
+    Lower,
+    Upper,
+    Trim,
+    Length,
+    Abs,
+}
+
+impl Function {
+    fn named(name: &str) -> Option<Self> {
+        Some(match name {
+            "concat" => Function::Concat,
+            "coalesce" => Function::Coalesce,
+            "lower" => Function::Lower,
+            "upper" => Function::Upper,
+            "trim" => Function::Trim,
+            "length" => Function::Length,
+            "abs" => Function::Abs,
+            _ => return None,
+        })
+    }
+
+    /// Whether the function takes any number of arguments instead of one
+    fn variadic(self) -> bool {
+        matches!(self, Function::Concat | Function::Coalesce)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Node {
+    Literal(Datum<'static>),
+    Key(String),
+    Negate(Box<Node>),
+    Not(Box<Node>),
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+    Binary(BinaryOp, Box<Node>, Box<Node>),
+    Call(Function, Vec<Node>),
+}
+
+#[cfg(feature = "std")]
+impl Node {
+    fn evaluate<'a>(&self, record: &'a FlattenedJson) -> Datum<'a> {
+        match self {
+            Node::Literal(datum) => datum.clone(),
+            Node::Key(key) => match record.get(key.as_str()) {
+                Some(value) if value != "null" => Datum::Text(Cow::Borrowed(value)),
+                _ => Datum::Null,
+            },
+            Node::Negate(operand) => operand.evaluate(record).as_number().map_or(Datum::Null, |n| Datum::Number(-n)),
+            Node::Not(operand) => Datum::Bool(!operand.evaluate(record).is_true()),
+            Node::And(left, right) => Datum::Bool(left.evaluate(record).is_true() && right.evaluate(record).is_true()),
+            Node::Or(left, right) => Datum::Bool(left.evaluate(record).is_true() || right.evaluate(record).is_true()),
+            Node::Binary(op, left, right) if op.compares() => {
+                let ordering = compare(&left.evaluate(record), &right.evaluate(record));
+                Datum::Bool(match op {
+                    BinaryOp::Equal => ordering == Some(Ordering::Equal),
+                    BinaryOp::NotEqual => ordering != Some(Ordering::Equal),
+                    BinaryOp::Less => ordering == Some(Ordering::Less),
+                    BinaryOp::LessOrEqual => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+                    BinaryOp::Greater => ordering == Some(Ordering::Greater),
+                    _ => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+                })
+            }
+            Node::Binary(op, left, right) => {
+                let (Some(a), Some(b)) = (left.evaluate(record).as_number(), right.evaluate(record).as_number()) else {
+                    return Datum::Null;
+                };
+                match op {
+                    BinaryOp::Add => Datum::Number(a + b),
+                    BinaryOp::Subtract => Datum::Number(a - b),
+                    BinaryOp::Multiply => Datum::Number(a * b),
+                    BinaryOp::Divide | BinaryOp::Remainder if b == 0.0 => Datum::Null,
+                    BinaryOp::Divide => Datum::Number(a / b),
+                    _ => Datum::Number(a % b),
+                }
+            }
+            Node::Call(function, arguments) => call(*function, arguments, record),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl BinaryOp {
+    fn compares(self) -> bool {
+        !matches!(
+            self,
+            BinaryOp::Add | BinaryOp::Subtract | BinaryOp::Multiply | BinaryOp::Divide | BinaryOp::Remainder
+        )
+    }
+}
+
+/// Orders two values as numbers when both are, otherwise as text; null only
+/// equals null and is not ordered against anything else
+#[cfg(feature = "std")]
+fn compare(a: &Datum, b: &Datum) -> Option<Ordering> {
+    match (a, b) {
+        (Datum::Null, Datum::Null) => Some(Ordering::Equal),
+        (Datum::Null, _) | (_, Datum::Null) => None,
+        _ => match (a.as_number(), b.as_number()) {
+            (Some(a), Some(b)) => a.partial_cmp(&b),
+            _ => Some(a.to_text().cmp(&b.to_text())),
+        },
+    }
+}
+
+#[cfg(feature = "std")]
+fn call<'a>(function: Function, arguments: &[Node], record: &'a FlattenedJson) -> Datum<'a> {
+    let mut values = arguments.iter().map(|argument| argument.evaluate(record));
+    match function {
+        Function::Concat => {
+            let mut text = String::new();
+            for value in values.filter(|value| !value.is_null()) {
+                text.push_str(&value.to_text());
+            }
+            Datum::Text(Cow::Owned(text))
+        }
+        Function::Coalesce => values.find(|value| !value.is_null()).unwrap_or(Datum::Null),
+        _ => {
+            let value = values.next().unwrap_or(Datum::Null);
+            if value.is_null() {
+                return Datum::Null;
+            }
+            let text = || value.to_text();
+            match function {
+                Function::Lower => Datum::Text(Cow::Owned(text().to_lowercase())),
+                Function::Upper => Datum::Text(Cow::Owned(text().to_uppercase())),
+                Function::Trim => Datum::Text(Cow::Owned(text().trim().to_string())),
+                Function::Length => Datum::Number(text().chars().count() as f64),
+                _ => value.as_number().map_or(Datum::Null, |n| Datum::Number(if n < 0.0 { -n } else { n })),
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(f64),
+    Text(String),
+    Name(String),
+    /// A key in backquotes
+    Key(String),
+    Symbol(&'static str),
+}
+
+/// Operators and punctuation, two-character ones first
+const SYMBOLS: [&str; 17] = [
+    "==", "!=", "<=", ">=", "&&", "||", "<", ">", "!", "+", "-", "*", "/", "%", "(", ")", ",",
+];
+
+fn tokenize(source: &str) -> Result<Vec<(usize, Token)>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+    while let Some(&(at, c)) = chars.peek() {
+        let token = match c {
+            _ if c.is_whitespace() => {
+                chars.next();
+                continue;
+            }
+            '0'..='9' => {
+                let mut end = at;
+                let mut previous = c;
+                while let Some(&(i, c)) = chars.peek() {
+                    let sign = matches!(c, '+' | '-') && matches!(previous, 'e' | 'E');
+                    if !(c.is_ascii_digit() || matches!(c, '.' | 'e' | 'E') || sign) {
+                        break;
+                    }
+                    previous = c;
+                    end = i + 1;
+                    chars.next();
+                }
+                let number = &source[at..end];
+                Token::Number(number.parse().map_err(|_| format!("invalid number {:?} at byte {}", number, at))?)
+            }
+            '"' => {
+                chars.next();
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, 'n')) => text.push('\n'),
+                            Some((_, 't')) => text.push('\t'),
+                            Some((_, c)) => text.push(c),
+                            None => return Err(format!("unterminated string at byte {}", at)),
+                        },
+                        Some((_, c)) => text.push(c),
+                        None => return Err(format!("unterminated string at byte {}", at)),
+                    }
+                }
+                Token::Text(text)
+            }
+            '`' => {
+                chars.next();
+                let key: String = chars.by_ref().map(|(_, c)| c).take_while(|&c| c != '`').collect();
+                if !source[at + 1..].contains('`') {
+                    return Err(format!("unterminated `key` at byte {}", at));
+                }
+                Token::Key(key)
+            }
+            _ if c.is_alphabetic() || c == '_' => {
+                let mut name = String::new();
+                while let Some(&(_, c)) = chars.peek().filter(|(_, c)| c.is_alphanumeric() || matches!(c, '_' | '.')) {
+                    name.push(c);
+                    chars.next();
+                }
+                Token::Name(name)
+            }
+            _ => {
+                let symbol = SYMBOLS
+                    .into_iter()
+                    .find(|symbol| source[at..].starts_with(symbol))
+                    .ok_or_else(|| format!("unexpected {:?} at byte {}", c, at))?;
+                chars.nth(symbol.len() - 1);
+                Token::Symbol(symbol)
+            }
+        };
+        tokens.push((at, token));
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent over the tokens, one method per precedence level
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    next: usize,
+    len: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next).map(|(_, token)| token)
+    }
+
+    fn eat(&mut self, symbol: &'static str) -> bool {
+        let found = self.peek() == Some(&Token::Symbol(symbol));
+        self.next += found as usize;
+        found
+    }
+
+    fn error(&self, expected: &str) -> String {
+        match self.tokens.get(self.next) {
+            Some((at, token)) => format!("expected {} at byte {}, found {:?}", expected, at, token),
+            None => format!("expected {} at byte {}, found the end", expected, self.len),
+        }
+    }
+
+    fn or(&mut self) -> Result<Node, String> {
+        let mut node = self.and()?;
+        while self.eat("||") {
+            node = Node::Or(Box::new(node), Box::new(self.and()?));
+        }
+        Ok(node)
+    }
+
+    fn and(&mut self) -> Result<Node, String> {
+        let mut node = self.comparison()?;
+        while self.eat("&&") {
+            node = Node::And(Box::new(node), Box::new(self.comparison()?));
+        }
+        Ok(node)
+    }
+
+    /// Comparisons don't chain: `a < b < c` is an error
+    fn comparison(&mut self) -> Result<Node, String> {
+        let node = self.additive()?;
+        let op = match () {
+            _ if self.eat("==") => BinaryOp::Equal,
+            _ if self.eat("!=") => BinaryOp::NotEqual,
+            _ if self.eat("<=") => BinaryOp::LessOrEqual,
+            _ if self.eat(">=") => BinaryOp::GreaterOrEqual,
+            _ if self.eat("<") => BinaryOp::Less,
+            _ if self.eat(">") => BinaryOp::Greater,
+            _ => return Ok(node),
+        };
+        Ok(Node::Binary(op, Box::new(node), Box::new(self.additive()?)))
+    }
+
+    fn additive(&mut self) -> Result<Node, String> {
+        let mut node = self.multiplicative()?;
+        loop {
+            let op = match () {
+                _ if self.eat("+") => BinaryOp::Add,
+                _ if self.eat("-") => BinaryOp::Subtract,
+                _ => return Ok(node),
+            };
+            node = Node::Binary(op, Box::new(node), Box::new(self.multiplicative()?));
+        }
+    }
+
+    fn multiplicative(&mut self) -> Result<Node, String> {
+        let mut node = self.unary()?;
+        loop {
+            let op = match () {
+                _ if self.eat("*") => BinaryOp::Multiply,
+                _ if self.eat("/") => BinaryOp::Divide,
+                _ if self.eat("%") => BinaryOp::Remainder,
+                _ => return Ok(node),
+            };
+            node = Node::Binary(op, Box::new(node), Box::new(self.unary()?));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        match () {
+            _ if self.eat("-") => Ok(Node::Negate(Box::new(self.unary()?))),
+            _ if self.eat("!") => Ok(Node::Not(Box::new(self.unary()?))),
+            _ => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Node, String> {
+        let token = self.peek().cloned().ok_or_else(|| self.error("a value"))?;
+        self.next += 1;
+        match token {
+            Token::Number(n) => Ok(Node::Literal(Datum::Number(n))),
+            Token::Text(text) => Ok(Node::Literal(Datum::Text(Cow::Owned(text)))),
+            Token::Key(key) => Ok(Node::Key(key)),
+            Token::Symbol("(") => {
+                let node = self.or()?;
+                match self.eat(")") {
+                    true => Ok(node),
+                    false => Err(self.error("`)`")),
+                }
+            }
+            Token::Name(name) if self.eat("(") => {
+                let function = Function::named(&name).ok_or_else(|| {
+                    let expected = "concat, coalesce, lower, upper, trim, length or abs";
+                    format!("unknown function {:?} (expected {})", name, expected)
+                })?;
+                let mut arguments = Vec::new();
+                while !self.eat(")") {
+                    if !arguments.is_empty() && !self.eat(",") {
+                        return Err(self.error("`,` or `)`"));
+                    }
+                    arguments.push(self.or()?);
+                }
+                match (function.variadic(), arguments.len()) {
+                    (true, 0) => Err(format!("{} takes at least one argument", name)),
+                    (false, n) if n != 1 => Err(format!("{} takes one argument, not {}", name, n)),
+                    _ => Ok(Node::Call(function, arguments)),
+                }
+            }
+            Token::Name(name) => Ok(match name.as_str() {
+                "null" => Node::Literal(Datum::Null),
+                "true" => Node::Literal(Datum::Bool(true)),
+                "false" => Node::Literal(Datum::Bool(false)),
+                _ => Node::Key(name),
+            }),
+            Token::Symbol(_) => {
+                self.next -= 1;
+                Err(self.error("a value"))
+            }
+        }
+    }
+}
+
+/// An expression over the values of a flattened record
+///
+/// Keys are written bare when they hold only letters, digits, `_` and `.`
+/// (`user.name`, `items.0.price`), otherwise in backquotes (`` `tags.#length` ``).
+/// There are number and `"string"` literals, `null`, `true` and `false`,
+/// `+ - * / %` on numbers, parentheses, and the functions `concat` (of any
+/// number of values, leaving out nulls), `coalesce` (the first value that is
+/// not null), `lower`, `upper`, `trim`, `length` and `abs`. Arithmetic on
+/// nulls or non-numbers, and division by zero, gives null.
+///
+/// Comparisons (`== != < <= > >=`) are numeric when both sides are numbers
+/// and textual otherwise; null equals only null and is neither less nor
+/// greater than anything. `&&`, `||` and `!` go by [`Datum::is_true`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Expr {
+    source: String,
+    node: Node,
+}
+
+impl Expr {
+    #[cfg(feature = "std")]
+    pub fn evaluate<'a>(&self, record: &'a FlattenedJson) -> Datum<'a> {
+        self.node.evaluate(record)
+    }
+
+    /// Whether the expression is true for a record, as a filter
+    #[cfg(feature = "std")]
+    pub fn matches(&self, record: &FlattenedJson) -> bool {
+        self.node.evaluate(record).is_true()
+    }
+
+    /// The expression as it was written
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+}
+
+impl core::str::FromStr for Expr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |e: String| format!("invalid expression {:?}: {}", s, e);
+        let mut parser = Parser {
+            tokens: tokenize(s).map_err(invalid)?,
+            next: 0,
+            len: s.len(),
+        };
+        let node = parser.or().map_err(invalid)?;
+        if parser.peek().is_some() {
+            return Err(invalid(parser.error("an operator")));
+        }
+        Ok(Expr {
+            source: s.trim().to_string(),
+            node,
+        })
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+/// A key added to every record, holding the value of an [`Expr`] over the
+/// record's other values
+///
+/// Parsed from `<key> = <expr>`, e.g. `full_name = concat(first, " ", last)`
+/// or `price_usd = price_cents / 100`; the key may be in backquotes. Derived
+/// columns are computed in order as documents are flattened, so each sees
+/// the ones before it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DerivedColumn {
+    pub key: String,
+    pub expr: Expr,
+}
+
+impl DerivedColumn {
+    /// Computes the column for a record and inserts it
+    #[cfg(feature = "std")]
+    pub fn derive(&self, record: &mut FlattenedJson) {
+        let value = self.expr.evaluate(record).to_text().into_owned();
+        record.insert(self.key.clone(), value);
+    }
+}
+
+impl core::str::FromStr for DerivedColumn {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (key, expr) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected <key> = <expression>, got {:?}", s))?;
+        let key = key.trim();
+        let key = key.strip_prefix('`').and_then(|key| key.strip_suffix('`')).unwrap_or(key);
+        if key.is_empty() {
+            return Err(format!("derived column without a key in {:?}", s));
+        }
+        Ok(DerivedColumn {
+            key: key.to_string(),
+            expr: expr.parse()?,
+        })
+    }
+}
+
+impl fmt::Display for DerivedColumn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} = {}", self.key, self.expr)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::{flatten_json, FlattenOptions};
+    use alloc::vec;
+    use serde_json::json;
+
+    #[test]
+    fn test_derived_columns_are_computed_while_flattening() {
+        let options = FlattenOptions {
+            derived_columns: vec![
+                "full_name = concat(user.first, \" \", user.last)".parse().unwrap(),
+                "price_usd = price_cents / 100".parse().unwrap(),
+                "`tag count` = coalesce(`tags.#length`, 0) * 2 + -1".parse().unwrap(),
+                "shout = upper(trim(full_name))".parse().unwrap(),
+                "ratio = price_cents / missing".parse().unwrap(),
+            ],
+            ..Default::default()
+        };
+        let record = flatten_json(&json!({"user": {"first": "Ada", "last": null}, "price_cents": 1250}), &options);
+        assert_eq!(record["full_name"], "Ada ");
+        assert_eq!(record["price_usd"], "12.5");
+        assert_eq!(record["tag count"], "-1");
+        assert_eq!(record["shout"], "ADA");
+        assert_eq!(record["ratio"], "null");
+
+        let column: DerivedColumn = "total = (a + b) % 4".parse().unwrap();
+        assert_eq!(column.to_string(), "total = (a + b) % 4");
+        assert!("x = concat(".parse::<DerivedColumn>().unwrap_err().contains("found the end"));
+        assert!("x = round(a)".parse::<DerivedColumn>().unwrap_err().contains("unknown function"));
+        assert!("x = a b".parse::<DerivedColumn>().is_err());
+    }
+
+    #[test]
+    fn test_filters_compare_numbers_text_and_null() {
+        let document = json!({"event": {"type": "purchase"}, "amount": "12", "paid": true});
+        let record = flatten_json(&document, &Default::default());
+        let matches = |filter: &str| filter.parse::<Expr>().unwrap().matches(&record);
+        assert!(matches("event.type == \"purchase\" && amount > 9"));
+        assert!(matches("amount >= 12.0 && paid && !missing"));
+        assert!(matches("missing == null || amount < 0"));
+        assert!(!matches("missing != null"));
+        assert!(!matches("missing < 1 || missing >= 1"));
+        assert!(matches("lower(event.type) > \"pay\""));
+        assert!(!matches("paid == false"));
+        assert!("a < b < c".parse::<Expr>().is_err());
+        assert!("a = b".parse::<Expr>().is_err());
+    }
+}
@@ -0,0 +1,610 @@
+// src/flatten.rs
+//! The flattening traversal
+//!
+//! Only `core` and `alloc` are used here, so this module builds without the
+//! `std` feature and can run inside runtimes that have an allocator but no
+//! operating system. Reading files and streams and parallel processing live
+//! in the std-only modules.
+use crate::binary::{looks_binary, BinaryPolicy};
+use crate::canonical::{canonical_number, to_json};
+use crate::error::FlattenError;
+use crate::geo::{self, GeoJsonMode, Geometry};
+use crate::keys::{
+    escape_key, key_ambiguity, normalize_nfc, AmbiguousKeys, EmptyKeys, KeyAmbiguity, SingleKeyChains, EMPTY_KEY_PLACEHOLDER,
+    ESCAPE_CHAR,
+};
+#[cfg(feature = "std")]
+use crate::keys::AMBIGUOUS_KEYS_KEY;
+use crate::{ArrayAggregates, ArrayLimitPolicy, ArrayMode, FlattenOptions, ARRAY_METADATA_MARKER};
+use alloc::borrow::Cow;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use crate::value::{JsonKind, JsonLike};
+use serde_json::Number;
+
+/// Map that receives flattened `key -> value` entries
+///
+/// Implemented for `BTreeMap` (sorted keys, `alloc` only), `Vec` of pairs
+/// (document order) and, with the `std` feature, `HashMap`.
+pub trait FlatMap {
+    /// Inserts an entry, replacing any earlier value for the same key
+    fn insert(&mut self, key: String, value: String);
+
+    /// Called after each object member and array element; an error stops
+    /// the traversal
+    fn check(&mut self) -> Result<(), FlattenError> {
+        Ok(())
+    }
+}
+
+impl FlatMap for BTreeMap<String, String> {
+    fn insert(&mut self, key: String, value: String) {
+        BTreeMap::insert(self, key, value);
+    }
+}
+
+impl FlatMap for Vec<(String, String)> {
+    fn insert(&mut self, key: String, value: String) {
+        self.push((key, value));
+    }
+}
+
+#[cfg(feature = "std")]
+impl<S: std::hash::BuildHasher> FlatMap for std::collections::HashMap<String, String, S> {
+    fn insert(&mut self, key: String, value: String) {
+        std::collections::HashMap::insert(self, key, value);
+    }
+}
+
+/// Flattens a parsed JSON document into any [`FlatMap`]
+///
+/// This is the whole traversal without the record id, which depends on the
+/// document's position in its input. The document can be a
+/// `serde_json::Value` or any other [`JsonLike`] representation.
+pub fn flatten_into<J: JsonLike + ?Sized, M: FlatMap + ?Sized>(
+    value: &J,
+    options: &FlattenOptions,
+    result: &mut M,
+) -> Result<(), FlattenError> {
+    options.validate()?;
+    flatten_subtree("", value, result, options, 0)
+}
+
+/// Flattens a value under `prefix` like [`flatten_value`], removing
+/// `strip_prefix` from the keys
+pub(crate) fn flatten_subtree<J: JsonLike + ?Sized, M: FlatMap + ?Sized>(
+    prefix: &str,
+    value: &J,
+    result: &mut M,
+    options: &FlattenOptions,
+    depth: usize,
+) -> Result<(), FlattenError> {
+    match options.strip_prefix.as_deref() {
+        Some(strip) if !strip.is_empty() => {
+            flatten_value(prefix, value, &mut StripPrefix { prefix: strip, inner: result }, options, depth)
+        }
+        _ => flatten_value(prefix, value, result, options, depth),
+    }
+}
+
+/// Removes a prefix from keys as they are inserted, leaving keys that are
+/// the prefix itself or don't start with it unchanged
+struct StripPrefix<'a, M: ?Sized> {
+    prefix: &'a str,
+    inner: &'a mut M,
+}
+
+impl<M: FlatMap + ?Sized> FlatMap for StripPrefix<'_, M> {
+    fn insert(&mut self, mut key: String, value: String) {
+        if key.len() > self.prefix.len() && key.starts_with(self.prefix) {
+            key.drain(..self.prefix.len());
+        }
+        self.inner.insert(key, value);
+    }
+
+    fn check(&mut self) -> Result<(), FlattenError> {
+        self.inner.check()
+    }
+}
+
+/// Stops a record's traversal once it holds more than `max_record_keys`
+/// entries or has run longer than `record_time_limit`
+#[cfg(feature = "std")]
+pub(crate) struct RecordLimits<'a, M: ?Sized> {
+    inner: &'a mut M,
+    entries: usize,
+    max_entries: usize,
+    deadline: Option<(std::time::Instant, core::time::Duration)>,
+    checks: u32,
+}
+
+#[cfg(feature = "std")]
+impl<'a, M: FlatMap + ?Sized> RecordLimits<'a, M> {
+    pub(crate) fn new(inner: &'a mut M, options: &FlattenOptions) -> Self {
+        RecordLimits {
+            inner,
+            entries: 0,
+            max_entries: options.max_record_keys,
+            deadline: options.record_time_limit.map(|limit| (std::time::Instant::now() + limit, limit)),
+            checks: 0,
+        }
+    }
+
+    /// Checks the limits, clock included, once more after the traversal
+    pub(crate) fn finish(mut self) -> Result<(), FlattenError> {
+        self.checks = CLOCK_EVERY - 1;
+        self.check()
+    }
+}
+
+/// Checks between readings of the clock, which costs more than a check
+#[cfg(feature = "std")]
+const CLOCK_EVERY: u32 = 64;
+
+#[cfg(feature = "std")]
+impl<M: FlatMap + ?Sized> FlatMap for RecordLimits<'_, M> {
+    fn insert(&mut self, key: String, value: String) {
+        self.entries += 1;
+        self.inner.insert(key, value);
+    }
+
+    fn check(&mut self) -> Result<(), FlattenError> {
+        if self.max_entries > 0 && self.entries > self.max_entries {
+            return Err(FlattenError::TooManyKeys { limit: self.max_entries });
+        }
+        self.checks = self.checks.wrapping_add(1);
+        if let Some((deadline, limit)) = self.deadline.filter(|_| self.checks.is_multiple_of(CLOCK_EVERY)) {
+            if std::time::Instant::now() >= deadline {
+                return Err(FlattenError::RecordTimedOut { limit });
+            }
+        }
+        self.inner.check()
+    }
+}
+
+/// Flattens a JSON value recursively
+pub(crate) fn flatten_value<J: JsonLike + ?Sized, M: FlatMap + ?Sized>(
+    prefix: &str,
+    value: &J,
+    result: &mut M,
+    options: &FlattenOptions,
+    depth: usize,
+) -> Result<(), FlattenError> {
+    // Check if we've exceeded the maximum depth
+    if options.max_depth > 0 && depth >= options.max_depth {
+        // Store the whole subtree as a JSON string
+        result.insert(prefix.to_string(), stringify_value(value, options));
+        return Ok(());
+    }
+
+    match value.kind() {
+        JsonKind::Object if options.geojson != GeoJsonMode::Off && !prefix.is_empty() => {
+            match Geometry::from_json(value) {
+                Some(geometry) => insert_geometry(prefix, &geometry, result, options),
+                None => flatten_object(prefix, value, result, options, depth)?,
+            }
+        }
+        JsonKind::Object => {
+            flatten_object(prefix, value, result, options, depth)?;
+        }
+        JsonKind::Array => {
+            flatten_array(prefix, value, result, options, depth)?;
+        }
+        _ if prefix.is_empty() => {}
+        JsonKind::String => {
+            let s = value.as_str().unwrap_or_default();
+            if options.binary_values != BinaryPolicy::Keep && looks_binary(s, options.binary_min_length) {
+                insert_binary(prefix, s, result, options)?;
+            } else if let Some(raw) = raw_leaf(value, options) {
+                result.insert(prefix.to_string(), raw.to_string());
+            } else if options.normalize_values {
+                result.insert(prefix.to_string(), normalize_nfc(s).into_owned());
+            } else {
+                result.insert(prefix.to_string(), s.to_string());
+            }
+        }
+        JsonKind::Number | JsonKind::Bool | JsonKind::Null => {
+            result.insert(prefix.to_string(), render_scalar(value, options));
+        }
+    }
+    Ok(())
+}
+
+/// Flattens a JSON object
+fn flatten_object<J: JsonLike + ?Sized, M: FlatMap + ?Sized>(
+    prefix: &str,
+    obj: &J,
+    result: &mut M,
+    options: &FlattenOptions,
+    depth: usize,
+) -> Result<(), FlattenError> {
+    for (key, value) in obj.object_iter().into_iter().flatten() {
+        let (key, value, depth) = follow_chain(key, value, options, depth + 1);
+        let key = source_key(key, prefix, options)?;
+        let new_prefix = if prefix.is_empty() {
+            key.into_owned()
+        } else {
+            format!("{}{}{}", prefix, options.separator, key)
+        };
+        flatten_value(&new_prefix, value, result, options, depth)?;
+        result.check()?;
+    }
+    Ok(())
+}
+
+/// Follows a chain of single-key objects below `key` under `single_key_chains`
+///
+/// Returns the key the end of the chain is written under, with the value at
+/// the end and the depth it is flattened at; `depth` is the depth of `value`.
+/// The chain stops at an object that would be stored whole under `max_depth`.
+pub(crate) fn follow_chain<'a, J: JsonLike + ?Sized>(
+    key: &'a str,
+    value: &'a J,
+    options: &FlattenOptions,
+    depth: usize,
+) -> (&'a str, &'a J, usize) {
+    if options.single_key_chains == SingleKeyChains::Keep {
+        return (key, value, depth);
+    }
+    let (mut key_out, mut value, mut depth) = (key, value, depth);
+    loop {
+        let expanded = options.max_depth == 0 || depth < options.max_depth;
+        let mut entries = match value.object_iter() {
+            Some(entries) if expanded => entries,
+            _ => break,
+        };
+        let (Some((inner_key, inner)), None) = (entries.next(), entries.next()) else {
+            break;
+        };
+        if options.single_key_chains == SingleKeyChains::Tail {
+            key_out = inner_key;
+        }
+        value = inner;
+        depth += 1;
+    }
+    (key_out, value, depth)
+}
+
+/// Returns a source object key as it appears in flattened keys, `prefix`
+/// being the key of the object holding it
+pub(crate) fn source_key<'a>(
+    key: &'a str,
+    prefix: &str,
+    options: &FlattenOptions,
+) -> Result<Cow<'a, str>, FlattenError> {
+    if key.is_empty() {
+        match options.empty_keys {
+            EmptyKeys::Keep => {}
+            EmptyKeys::Placeholder => return Ok(Cow::Borrowed(EMPTY_KEY_PLACEHOLDER)),
+            EmptyKeys::Error => return Err(FlattenError::EmptyKey(prefix.to_string())),
+        }
+    } else if key == EMPTY_KEY_PLACEHOLDER && options.escape_keys && options.empty_keys == EmptyKeys::Placeholder {
+        return Ok(Cow::Owned(format!("{}{}", ESCAPE_CHAR, key)));
+    }
+    if options.ambiguous_keys == AmbiguousKeys::Error {
+        if let Some(reason) = ambiguity(key, options) {
+            return Err(FlattenError::AmbiguousKey {
+                path: prefix.to_string(),
+                key: key.to_string(),
+                reason,
+            });
+        }
+    }
+    let key = if options.normalize_keys {
+        normalize_nfc(key)
+    } else {
+        Cow::Borrowed(key)
+    };
+    if options.escape_keys {
+        if let Cow::Owned(escaped) = escape_key(&key, &options.separator) {
+            return Ok(Cow::Owned(escaped));
+        }
+    }
+    Ok(key)
+}
+
+fn ambiguity(key: &str, options: &FlattenOptions) -> Option<KeyAmbiguity> {
+    key_ambiguity(key, &options.separator, options.escape_keys, options.include_array_indices)
+}
+
+/// Lists the flattened keys of a document whose source keys contain the
+/// separator or look like array indices, see [`AmbiguousKeys`]
+///
+/// A pre-scan for documents about to be flattened with `options`; keys below
+/// `max_depth`, which end up inside stringified subtrees, are left out.
+pub fn find_ambiguous_keys<J: JsonLike + ?Sized>(document: &J, options: &FlattenOptions) -> Vec<String> {
+    let mut found = Vec::new();
+    scan_ambiguous_keys("", document, options, 0, &mut found);
+    if let Some(strip) = options.strip_prefix.as_deref().filter(|strip| !strip.is_empty()) {
+        for key in &mut found {
+            if key.len() > strip.len() && key.starts_with(strip) {
+                key.drain(..strip.len());
+            }
+        }
+    }
+    found
+}
+
+fn scan_ambiguous_keys<J: JsonLike + ?Sized>(
+    prefix: &str,
+    value: &J,
+    options: &FlattenOptions,
+    depth: usize,
+    found: &mut Vec<String>,
+) {
+    if options.max_depth > 0 && depth >= options.max_depth {
+        return;
+    }
+    if let Some(entries) = value.object_iter() {
+        for (key, child) in entries {
+            let (key, child, depth) = follow_chain(key, child, options, depth + 1);
+            let segment = source_key(key, prefix, options).unwrap_or(Cow::Borrowed(key));
+            let path = child_key(prefix, &segment, options);
+            if ambiguity(key, options).is_some() {
+                found.push(path.clone());
+            }
+            scan_ambiguous_keys(&path, child, options, depth, found);
+        }
+    } else if let Some(items) = value.array_iter() {
+        for (i, item) in items.enumerate() {
+            let path = match options.include_array_indices {
+                true => format!("{}{}{}", prefix, options.separator, i),
+                false => prefix.to_string(),
+            };
+            scan_ambiguous_keys(&path, item, options, depth + 1, found);
+        }
+    }
+}
+
+/// Lists ambiguous keys on a record under [`AmbiguousKeys::Report`]
+#[cfg(feature = "std")]
+pub(crate) fn note_ambiguous_keys<J: JsonLike + ?Sized, M: FlatMap + ?Sized>(
+    document: &J,
+    options: &FlattenOptions,
+    result: &mut M,
+) {
+    if options.ambiguous_keys != AmbiguousKeys::Report {
+        return;
+    }
+    let found = find_ambiguous_keys(document, options);
+    if !found.is_empty() {
+        let keys = serde_json::to_string(&found).expect("strings always serialize");
+        result.insert(AMBIGUOUS_KEYS_KEY.to_string(), keys);
+    }
+}
+
+/// Appends a named segment to a prefix
+pub(crate) fn child_key(prefix: &str, name: &str, options: &FlattenOptions) -> String {
+    if prefix.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}{}{}", prefix, options.separator, name)
+    }
+}
+
+/// Builds the key of a metadata entry such as `items.#length`
+///
+/// Metadata segments start with [`ARRAY_METADATA_MARKER`]; source keys that start
+/// with the same character can collide with them.
+fn metadata_key(prefix: &str, name: &str, options: &FlattenOptions) -> String {
+    child_key(prefix, &format!("{}{}", ARRAY_METADATA_MARKER, name), options)
+}
+
+/// Inserts `min`, `max`, `mean` and `count` for an array of numbers
+///
+/// Returns false, inserting nothing, if any element is not a number.
+fn insert_aggregates<J: JsonLike + ?Sized, M: FlatMap + ?Sized>(
+    prefix: &str,
+    array: &J,
+    result: &mut M,
+    options: &FlattenOptions,
+) -> bool {
+    let numbers = match elements(array).map(J::as_number).collect::<Option<Vec<_>>>() {
+        Some(numbers) => numbers,
+        None => return false,
+    };
+    result.insert(child_key(prefix, "count", options), numbers.len().to_string());
+
+    let mut min: Option<(&Number, f64)> = None;
+    let mut max: Option<(&Number, f64)> = None;
+    let mut sum = 0.0;
+    for (number, float) in numbers.iter().filter_map(|n| Some(n).zip(n.as_f64())) {
+        sum += float;
+        if min.is_none_or(|(_, m)| float < m) {
+            min = Some((number, float));
+        }
+        if max.is_none_or(|(_, m)| float > m) {
+            max = Some((number, float));
+        }
+    }
+
+    let render = |n: &Number| if options.canonical { canonical_number(n) } else { n.to_string() };
+    if let (Some((min, _)), Some((max, _))) = (min, max) {
+        result.insert(child_key(prefix, "min", options), render(min));
+        result.insert(child_key(prefix, "max", options), render(max));
+        result.insert(child_key(prefix, "mean", options), (sum / numbers.len() as f64).to_string());
+    }
+    true
+}
+
+/// The elements of an array node
+fn elements<J: JsonLike + ?Sized>(array: &J) -> impl Iterator<Item = &J> {
+    array.array_iter().into_iter().flatten()
+}
+
+/// The elements of an expanded array that are flattened, with their
+/// indices, once `limit` (an exceeded `max_array_elements`) is applied
+pub(crate) fn kept_elements<'j, J: JsonLike + ?Sized>(
+    prefix: &str,
+    array: &'j J,
+    limit: Option<usize>,
+    options: &FlattenOptions,
+) -> Result<impl Iterator<Item = (usize, &'j J)>, FlattenError> {
+    let len = array.array_iter().map_or(0, |items| items.len());
+    // Elements in `head_end..tail_start` are skipped
+    let (head_end, tail_start) = match (limit, options.array_limit_policy) {
+        (None, _) => (len, len),
+        (Some(limit), ArrayLimitPolicy::Truncate) => (limit, len),
+        (Some(limit), ArrayLimitPolicy::HeadTail) => (limit - limit / 2, len - limit / 2),
+        (Some(limit), ArrayLimitPolicy::Error) => {
+            return Err(FlattenError::ArrayTooLong {
+                path: prefix.to_string(),
+                len,
+                limit,
+            })
+        }
+    };
+    Ok(elements(array).enumerate().filter(move |(i, _)| *i < head_end || *i >= tail_start))
+}
+
+/// The key of array element `i`, which is the array's own key without
+/// `include_array_indices`
+pub(crate) fn element_key(prefix: &str, i: usize, options: &FlattenOptions) -> String {
+    if options.include_array_indices {
+        format!("{}{}{}", prefix, options.separator, i)
+    } else {
+        prefix.to_string()
+    }
+}
+
+/// Flattens a JSON array
+fn flatten_array<J: JsonLike + ?Sized, M: FlatMap + ?Sized>(
+    prefix: &str,
+    array: &J,
+    result: &mut M,
+    options: &FlattenOptions,
+    depth: usize,
+) -> Result<(), FlattenError> {
+    let len = array.array_iter().map_or(0, |items| items.len());
+    if options.array_aggregates != ArrayAggregates::Off
+        && insert_aggregates(prefix, array, result, options)
+        && options.array_aggregates == ArrayAggregates::Replace
+    {
+        if options.array_metadata {
+            result.insert(metadata_key(prefix, "length", options), len.to_string());
+        }
+        return Ok(());
+    }
+
+    let mode = options.array_mode(prefix);
+    let limit = options
+        .max_array_elements
+        .filter(|&limit| mode == ArrayMode::Expand && len > limit);
+    if options.array_metadata || limit.is_some() {
+        result.insert(metadata_key(prefix, "length", options), len.to_string());
+    }
+
+    if let ArrayMode::Join(delimiter) = &mode {
+        let joined = elements(array)
+            .map(|value| match value.kind() {
+                JsonKind::String => raw_leaf(value, options).or(value.as_str()).unwrap_or_default().to_string(),
+                JsonKind::Number => render_scalar(value, options),
+                _ => stringify_value(value, options),
+            })
+            .collect::<Vec<_>>()
+            .join(delimiter);
+        result.insert(prefix.to_string(), joined);
+    } else if mode == ArrayMode::Expand {
+        for (i, value) in kept_elements(prefix, array, limit, options)? {
+            flatten_value(&element_key(prefix, i, options), value, result, options, depth + 1)?;
+            result.check()?;
+        }
+    } else {
+        // Store the array as a JSON string
+        result.insert(prefix.to_string(), stringify_value(array, options));
+    }
+    Ok(())
+}
+
+/// Writes a binary-looking string according to `options.binary_values`
+fn insert_binary<M: FlatMap + ?Sized>(
+    prefix: &str,
+    value: &str,
+    result: &mut M,
+    options: &FlattenOptions,
+) -> Result<(), FlattenError> {
+    match &options.binary_values {
+        BinaryPolicy::Keep => {
+            result.insert(prefix.to_string(), value.to_string());
+        }
+        BinaryPolicy::Drop => {}
+        BinaryPolicy::Truncate(chars) => {
+            result.insert(prefix.to_string(), value.chars().take(*chars).collect());
+            result.insert(metadata_key(prefix, "length", options), value.len().to_string());
+        }
+        #[cfg(feature = "std")]
+        BinaryPolicy::SideFiles(dir) => {
+            result.insert(prefix.to_string(), crate::binary::write_side_file(dir, value)?);
+        }
+    }
+    Ok(())
+}
+
+/// Writes a recognized GeoJSON geometry according to `options.geojson`
+fn insert_geometry<M: FlatMap + ?Sized>(prefix: &str, geometry: &Geometry, result: &mut M, options: &FlattenOptions) {
+    match (options.geojson, geometry) {
+        (GeoJsonMode::PointColumns, Geometry::Point(Some(position))) => {
+            for (name, coordinate) in ["lon", "lat", "alt"].iter().zip(position) {
+                result.insert(child_key(prefix, name, options), coordinate.to_string());
+            }
+        }
+        (GeoJsonMode::WkbHex, _) => {
+            result.insert(prefix.to_string(), geo::hex(&geometry.to_wkb()));
+        }
+        _ => {
+            result.insert(prefix.to_string(), geometry.to_wkt());
+        }
+    }
+}
+
+/// Source text of a leaf under `raw_values`, without the quotes of a string
+fn raw_leaf<'j, J: JsonLike + ?Sized>(value: &'j J, options: &FlattenOptions) -> Option<&'j str> {
+    let text = value.source_text().filter(|_| options.raw_values)?;
+    match value.kind() {
+        JsonKind::String => text.get(1..text.len() - 1),
+        _ => Some(text),
+    }
+}
+
+/// Renders a number, boolean or null leaf
+fn render_scalar<J: JsonLike + ?Sized>(value: &J, options: &FlattenOptions) -> String {
+    if let Some(raw) = raw_leaf(value, options) {
+        return raw.to_string();
+    }
+    match value.as_number() {
+        Some(n) if options.canonical => canonical_number(&n),
+        Some(n) => n.to_string(),
+        None => to_json(value, false),
+    }
+}
+
+/// Serializes a subtree that is stored as a single JSON string value
+fn stringify_value<J: JsonLike + ?Sized>(value: &J, options: &FlattenOptions) -> String {
+    match value.source_text().filter(|_| options.raw_values) {
+        Some(text) => text.to_string(),
+        None => to_json(value, options.canonical),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_flatten_into_alloc_maps() {
+        let value = json!({"b": {"c": [1, 2]}, "a": true});
+        let options = FlattenOptions::default();
+
+        let mut sorted = BTreeMap::new();
+        flatten_into(&value, &options, &mut sorted).unwrap();
+        assert_eq!(sorted.keys().collect::<Vec<_>>(), ["a", "b.c.0", "b.c.1"]);
+
+        let mut pairs = Vec::new();
+        flatten_into(&value, &options, &mut pairs).unwrap();
+        assert_eq!(pairs.len(), 3);
+        assert!(pairs.contains(&("b.c.1".to_string(), "2".to_string())));
+    }
+}
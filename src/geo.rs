@@ -0,0 +1,309 @@
+// src/geo.rs
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use crate::value::{JsonKind, JsonLike};
+use serde_json::{Map, Value};
+
+/// How GeoJSON geometry objects are written when `FlattenOptions::geojson` is enabled
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GeoJsonMode {
+    /// Geometries are flattened like any other object
+    #[default]
+    Off,
+    /// Well-known text, e.g. `POINT (30 10)`
+    Wkt,
+    /// Little-endian well-known binary as lowercase hex
+    WkbHex,
+    /// `lon`/`lat` (and `alt`) entries for Points, WKT for every other geometry
+    PointColumns,
+}
+
+impl core::str::FromStr for GeoJsonMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(GeoJsonMode::Off),
+            "wkt" => Ok(GeoJsonMode::Wkt),
+            "wkb" => Ok(GeoJsonMode::WkbHex),
+            "point-columns" | "point_columns" => Ok(GeoJsonMode::PointColumns),
+            other => Err(format!(
+                "unknown GeoJSON mode {:?} (expected off, wkt, wkb or point_columns)",
+                other
+            )),
+        }
+    }
+}
+
+impl core::fmt::Display for GeoJsonMode {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            GeoJsonMode::Off => "off",
+            GeoJsonMode::Wkt => "wkt",
+            GeoJsonMode::WkbHex => "wkb",
+            GeoJsonMode::PointColumns => "point_columns",
+        })
+    }
+}
+
+/// A parsed GeoJSON geometry
+#[derive(Debug, Clone, PartialEq)]
+pub enum Geometry {
+    Point(Option<Vec<f64>>),
+    LineString(Vec<Vec<f64>>),
+    Polygon(Vec<Vec<Vec<f64>>>),
+    MultiPoint(Vec<Vec<f64>>),
+    MultiLineString(Vec<Vec<Vec<f64>>>),
+    MultiPolygon(Vec<Vec<Vec<Vec<f64>>>>),
+    GeometryCollection(Vec<Geometry>),
+}
+
+impl Geometry {
+    /// Parses a GeoJSON geometry object; returns `None` for anything else,
+    /// including malformed geometries, which are then flattened normally
+    pub fn from_object(object: &Map<String, Value>) -> Option<Geometry> {
+        Self::from_members(
+            object.get("type")?.as_str()?,
+            object.get("coordinates"),
+            object.get("geometries"),
+        )
+    }
+
+    /// Parses a GeoJSON geometry object in any [`JsonLike`] representation
+    pub fn from_json<J: JsonLike + ?Sized>(value: &J) -> Option<Geometry> {
+        Self::from_members(value.get("type")?.as_str()?, value.get("coordinates"), value.get("geometries"))
+    }
+
+    fn from_members<J: JsonLike + ?Sized>(
+        kind: &str,
+        coordinates: Option<&J>,
+        geometries: Option<&J>,
+    ) -> Option<Geometry> {
+        match kind {
+            "Point" => match coordinates?.array_iter()?.len() {
+                0 => Some(Geometry::Point(None)),
+                _ => Some(Geometry::Point(Some(position(coordinates?)?))),
+            },
+            "LineString" => Some(Geometry::LineString(positions(coordinates?)?)),
+            "Polygon" => Some(Geometry::Polygon(nested(coordinates?, positions)?)),
+            "MultiPoint" => Some(Geometry::MultiPoint(positions(coordinates?)?)),
+            "MultiLineString" => Some(Geometry::MultiLineString(nested(coordinates?, positions)?)),
+            "MultiPolygon" => Some(Geometry::MultiPolygon(nested(coordinates?, |rings| {
+                nested(rings, positions)
+            })?)),
+            "GeometryCollection" => Some(Geometry::GeometryCollection(nested(geometries?, |g| {
+                if g.kind() == JsonKind::Object {
+                    Geometry::from_json(g)
+                } else {
+                    None
+                }
+            })?)),
+            _ => None,
+        }
+    }
+
+    /// Well-known text representation
+    pub fn to_wkt(&self) -> String {
+        let mut wkt = String::from(self.wkt_tag());
+        if self.has_z() {
+            wkt.push_str(" Z");
+        }
+        if self.is_empty() {
+            wkt.push_str(" EMPTY");
+            return wkt;
+        }
+        wkt.push(' ');
+        match self {
+            Geometry::Point(Some(p)) => wkt.push_str(&format!("({})", wkt_position(p))),
+            Geometry::Point(None) => {}
+            Geometry::LineString(line) => wkt.push_str(&wkt_line(line)),
+            Geometry::Polygon(rings) => wkt.push_str(&wkt_list(rings, |r| wkt_line(r))),
+            Geometry::MultiPoint(points) => wkt.push_str(&wkt_list(points, |p| format!("({})", wkt_position(p)))),
+            Geometry::MultiLineString(lines) => wkt.push_str(&wkt_list(lines, |l| wkt_line(l))),
+            Geometry::MultiPolygon(polygons) => {
+                wkt.push_str(&wkt_list(polygons, |rings| wkt_list(rings, |r| wkt_line(r))))
+            }
+            Geometry::GeometryCollection(geometries) => wkt.push_str(&wkt_list(geometries, Geometry::to_wkt)),
+        }
+        wkt
+    }
+
+    /// Little-endian well-known binary (ISO flavour, Z types add 1000)
+    pub fn to_wkb(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.write_wkb(&mut out);
+        out
+    }
+
+    fn write_wkb(&self, out: &mut Vec<u8>) {
+        let z = self.has_z();
+        let dims = if z { 3 } else { 2 };
+        out.push(1);
+        out.extend_from_slice(&(self.wkb_type() + if z { 1000 } else { 0 }).to_le_bytes());
+
+        let write_position = |out: &mut Vec<u8>, p: &[f64]| {
+            for i in 0..dims {
+                out.extend_from_slice(&p.get(i).copied().unwrap_or(0.0).to_le_bytes());
+            }
+        };
+        let write_line = |out: &mut Vec<u8>, line: &[Vec<f64>]| {
+            out.extend_from_slice(&(line.len() as u32).to_le_bytes());
+            for p in line {
+                write_position(out, p);
+            }
+        };
+        let write_polygon = |out: &mut Vec<u8>, rings: &[Vec<Vec<f64>>]| {
+            out.extend_from_slice(&(rings.len() as u32).to_le_bytes());
+            for ring in rings {
+                write_line(out, ring);
+            }
+        };
+
+        match self {
+            // An empty point is encoded with NaN coordinates by convention
+            Geometry::Point(p) => write_position(out, p.as_deref().unwrap_or(&[f64::NAN, f64::NAN, f64::NAN])),
+            Geometry::LineString(line) => write_line(out, line),
+            Geometry::Polygon(rings) => write_polygon(out, rings),
+            Geometry::MultiPoint(points) => {
+                let parts: Vec<Geometry> = points.iter().map(|p| Geometry::Point(Some(p.clone()))).collect();
+                write_parts(out, &parts);
+            }
+            Geometry::MultiLineString(lines) => {
+                let parts: Vec<Geometry> = lines.iter().map(|l| Geometry::LineString(l.clone())).collect();
+                write_parts(out, &parts);
+            }
+            Geometry::MultiPolygon(polygons) => {
+                let parts: Vec<Geometry> = polygons.iter().map(|p| Geometry::Polygon(p.clone())).collect();
+                write_parts(out, &parts);
+            }
+            Geometry::GeometryCollection(geometries) => write_parts(out, geometries),
+        }
+    }
+
+    fn wkt_tag(&self) -> &'static str {
+        match self {
+            Geometry::Point(_) => "POINT",
+            Geometry::LineString(_) => "LINESTRING",
+            Geometry::Polygon(_) => "POLYGON",
+            Geometry::MultiPoint(_) => "MULTIPOINT",
+            Geometry::MultiLineString(_) => "MULTILINESTRING",
+            Geometry::MultiPolygon(_) => "MULTIPOLYGON",
+            Geometry::GeometryCollection(_) => "GEOMETRYCOLLECTION",
+        }
+    }
+
+    fn wkb_type(&self) -> u32 {
+        match self {
+            Geometry::Point(_) => 1,
+            Geometry::LineString(_) => 2,
+            Geometry::Polygon(_) => 3,
+            Geometry::MultiPoint(_) => 4,
+            Geometry::MultiLineString(_) => 5,
+            Geometry::MultiPolygon(_) => 6,
+            Geometry::GeometryCollection(_) => 7,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        match self {
+            Geometry::Point(p) => p.is_none(),
+            Geometry::LineString(v) | Geometry::MultiPoint(v) => v.is_empty(),
+            Geometry::Polygon(v) | Geometry::MultiLineString(v) => v.is_empty(),
+            Geometry::MultiPolygon(v) => v.is_empty(),
+            Geometry::GeometryCollection(v) => v.is_empty(),
+        }
+    }
+
+    /// True if the first position carries a third (altitude) coordinate
+    fn has_z(&self) -> bool {
+        let first = match self {
+            Geometry::Point(p) => p.as_ref(),
+            Geometry::LineString(v) | Geometry::MultiPoint(v) => v.first(),
+            Geometry::Polygon(v) | Geometry::MultiLineString(v) => v.first().and_then(|l| l.first()),
+            Geometry::MultiPolygon(v) => v.first().and_then(|p| p.first()).and_then(|l| l.first()),
+            Geometry::GeometryCollection(_) => None,
+        };
+        first.is_some_and(|p| p.len() > 2)
+    }
+}
+
+fn write_parts(out: &mut Vec<u8>, parts: &[Geometry]) {
+    out.extend_from_slice(&(parts.len() as u32).to_le_bytes());
+    for part in parts {
+        part.write_wkb(out);
+    }
+}
+
+fn wkt_position(p: &[f64]) -> String {
+    p.iter().map(f64::to_string).collect::<Vec<_>>().join(" ")
+}
+
+fn wkt_line(line: &[Vec<f64>]) -> String {
+    wkt_list(line, |p| wkt_position(p))
+}
+
+fn wkt_list<T>(items: &[T], render: impl Fn(&T) -> String) -> String {
+    format!("({})", items.iter().map(render).collect::<Vec<_>>().join(", "))
+}
+
+/// Parses a position: an array of at least two numbers
+fn position<J: JsonLike + ?Sized>(value: &J) -> Option<Vec<f64>> {
+    let array = value.array_iter()?;
+    if array.len() < 2 {
+        return None;
+    }
+    array.map(|n| n.as_number()?.as_f64()).collect()
+}
+
+fn positions<J: JsonLike + ?Sized>(value: &J) -> Option<Vec<Vec<f64>>> {
+    nested(value, position)
+}
+
+fn nested<J: JsonLike + ?Sized, T>(value: &J, parse: impl Fn(&J) -> Option<T>) -> Option<Vec<T>> {
+    value.array_iter()?.map(parse).collect()
+}
+
+/// Lowercase hex encoding used for WKB output
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn geometry(value: Value) -> Geometry {
+        Geometry::from_object(value.as_object().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_geometry_to_wkt() {
+        assert_eq!(geometry(json!({"type": "Point", "coordinates": [30, 10.5]})).to_wkt(), "POINT (30 10.5)");
+        assert_eq!(
+            geometry(json!({"type": "Polygon", "coordinates": [[[0, 0], [1, 0], [1, 1], [0, 0]]]})).to_wkt(),
+            "POLYGON ((0 0, 1 0, 1 1, 0 0))"
+        );
+        assert_eq!(
+            geometry(json!({"type": "MultiPoint", "coordinates": [[1, 2, 3], [4, 5, 6]]})).to_wkt(),
+            "MULTIPOINT Z ((1 2 3), (4 5 6))"
+        );
+        assert_eq!(
+            geometry(json!({"type": "GeometryCollection", "geometries": [
+                {"type": "Point", "coordinates": [1, 2]},
+                {"type": "LineString", "coordinates": []}
+            ]}))
+            .to_wkt(),
+            "GEOMETRYCOLLECTION (POINT (1 2), LINESTRING EMPTY)"
+        );
+        assert!(Geometry::from_object(json!({"type": "Point", "coordinates": ["x"]}).as_object().unwrap()).is_none());
+        assert!(Geometry::from_object(json!({"type": "Feature"}).as_object().unwrap()).is_none());
+    }
+
+    #[test]
+    fn test_point_to_wkb() {
+        let wkb = hex(&geometry(json!({"type": "Point", "coordinates": [1, 2]})).to_wkb());
+        assert_eq!(wkb, "0101000000000000000000f03f0000000000000040");
+    }
+}
@@ -0,0 +1,90 @@
+// src/hash.rs
+use core::hash::{BuildHasherDefault, Hasher};
+
+/// Builds [`FxHasher`]s, for the crate's internal maps and for records
+/// flattened with [`flatten_into`](crate::flatten_into) into a
+/// `HashMap<String, String, FxBuildHasher>`
+pub type FxBuildHasher = BuildHasherDefault<FxHasher>;
+
+const SEED: u64 = 0x51_7c_c1_b7_27_22_0a_95;
+
+/// The rotate-multiply hash used inside rustc ("FxHash")
+///
+/// Records typically have 10 to 100 short keys, where SipHash's setup and
+/// finalization dominate the cost of an insert. FxHash is not resistant to
+/// deliberately colliding keys; the worst case is one slow record. The
+/// public [`FlattenedJson`](crate::FlattenedJson) keeps std's `RandomState`,
+/// so callers that name it as a plain `HashMap<String, String>` still compile.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FxHasher {
+    hash: u64,
+}
+
+impl FxHasher {
+    #[inline]
+    fn add_to_hash(&mut self, word: u64) {
+        self.hash = (self.hash.rotate_left(5) ^ word).wrapping_mul(SEED);
+    }
+}
+
+impl Hasher for FxHasher {
+    #[inline]
+    fn write(&mut self, bytes: &[u8]) {
+        let mut chunks = bytes.chunks_exact(8);
+        for chunk in &mut chunks {
+            let mut word = [0u8; 8];
+            word.copy_from_slice(chunk);
+            self.add_to_hash(u64::from_le_bytes(word));
+        }
+        let mut rest = chunks.remainder();
+        if rest.len() >= 4 {
+            let mut word = [0u8; 4];
+            word.copy_from_slice(&rest[..4]);
+            self.add_to_hash(u64::from(u32::from_le_bytes(word)));
+            rest = &rest[4..];
+        }
+        for &byte in rest {
+            self.add_to_hash(u64::from(byte));
+        }
+    }
+
+    #[inline]
+    fn write_u8(&mut self, i: u8) {
+        self.add_to_hash(u64::from(i));
+    }
+
+    #[inline]
+    fn write_u64(&mut self, i: u64) {
+        self.add_to_hash(i);
+    }
+
+    #[inline]
+    fn write_usize(&mut self, i: usize) {
+        self.add_to_hash(i as u64);
+    }
+
+    #[inline]
+    fn finish(&self) -> u64 {
+        self.hash
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::hash::BuildHasher;
+
+    fn hash(key: &str) -> u64 {
+        FxBuildHasher::default().hash_one(key)
+    }
+
+    #[test]
+    fn test_fx_hash_is_deterministic_and_spreads_keys() {
+        assert_eq!(hash("user.name"), hash("user.name"));
+        let keys = ["a", "b", "a.b", "b.a", "items.0", "items.1", "items.10", "user.address.city"];
+        let mut hashes: Vec<_> = keys.iter().map(|key| hash(key)).collect();
+        hashes.sort_unstable();
+        hashes.dedup();
+        assert_eq!(hashes.len(), keys.len());
+    }
+}
@@ -0,0 +1,1342 @@
+// src/input.rs
+#[cfg(feature = "std")]
+use crate::nonfinite::{NonFiniteNumbers, NonFiniteReader};
+#[cfg(feature = "std")]
+use crate::FlattenOptions;
+use alloc::borrow::Cow;
+use alloc::format;
+use alloc::string::String;
+use encoding_rs::Encoding;
+#[cfg(feature = "std")]
+use encoding_rs_io::DecodeReaderBytesBuilder;
+#[cfg(feature = "std")]
+use serde_json::value::RawValue;
+#[cfg(feature = "std")]
+use std::fs::File;
+#[cfg(feature = "std")]
+use std::io::{self, BufRead, BufReader, Read};
+#[cfg(feature = "std")]
+use std::path::Path;
+
+/// Path that selects standard input instead of a file
+#[cfg(feature = "std")]
+pub const STDIN_PATH: &str = "-";
+
+/// ASCII record separator, which starts every text of a JSON text sequence
+/// (RFC 7464, `application/json-seq`)
+pub const RECORD_SEPARATOR: u8 = 0x1e;
+
+/// Bytes read per block of lines under `ndjson` when `chunk_bytes` is 0
+pub const NDJSON_BLOCK_BYTES: usize = 4 << 20;
+
+/// Raw text of one top-level document, or the error that ended the input
+#[cfg(feature = "std")]
+pub type DocumentResult = io::Result<Box<str>>;
+
+/// Stream of raw documents produced by [`read_documents`]
+#[cfg(feature = "std")]
+pub type DocumentStream<'a> = Box<dyn Iterator<Item = DocumentResult> + 'a>;
+
+/// A place in the input: bytes from its start, and the 1-based line
+///
+/// Input is counted after decoding to UTF-8 and dropping any byte order mark,
+/// so for UTF-8 files without one these are positions in the file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+pub struct InputPosition {
+    pub offset: u64,
+    pub line: u64,
+}
+
+impl Default for InputPosition {
+    fn default() -> Self {
+        InputPosition { offset: 0, line: 1 }
+    }
+}
+
+/// Raw text of one document with where it starts in the input
+#[cfg(feature = "std")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LocatedDocument {
+    pub text: Box<str>,
+    /// Unknown for input read by serde_json's own stream
+    pub start: Option<InputPosition>,
+}
+
+/// Stream of located documents produced by [`read_located_documents`]
+#[cfg(feature = "std")]
+pub type LocatedDocumentStream<'a> = Box<dyn Iterator<Item = io::Result<LocatedDocument>> + 'a>;
+
+/// Handling of byte sequences that are not valid UTF-8 in reader and file input
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InvalidUtf8 {
+    /// Fail with an `InvalidData` error
+    #[default]
+    Error,
+    /// Replace invalid sequences with U+FFFD
+    Replace,
+    /// Drop every document that contains an invalid sequence
+    SkipRecord,
+}
+
+impl core::str::FromStr for InvalidUtf8 {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(InvalidUtf8::Error),
+            "replace" => Ok(InvalidUtf8::Replace),
+            "skip" => Ok(InvalidUtf8::SkipRecord),
+            other => Err(format!(
+                "unknown invalid UTF-8 policy {:?} (expected error, replace or skip)",
+                other
+            )),
+        }
+    }
+}
+
+impl core::fmt::Display for InvalidUtf8 {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            InvalidUtf8::Error => "error",
+            InvalidUtf8::Replace => "replace",
+            InvalidUtf8::SkipRecord => "skip",
+        })
+    }
+}
+
+/// Handling of documents in reader and file input that are not valid JSON
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InvalidDocuments {
+    /// Fail with the parse error
+    #[default]
+    Error,
+    /// Leave the document out, reporting it as a
+    /// [`SkippedDocument`](crate::SkippedDocument) with the
+    /// [`ParseError`](crate::ParseError), and carry on with the next one
+    Skip,
+}
+
+impl core::str::FromStr for InvalidDocuments {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(InvalidDocuments::Error),
+            "skip" => Ok(InvalidDocuments::Skip),
+            other => Err(format!("unknown invalid document policy {:?} (expected error or skip)", other)),
+        }
+    }
+}
+
+impl core::fmt::Display for InvalidDocuments {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            InvalidDocuments::Error => "error",
+            InvalidDocuments::Skip => "skip",
+        })
+    }
+}
+
+/// Character encoding of reader and file input
+///
+/// Input in any other encoding than UTF-8 is transcoded to UTF-8 while it is
+/// read; malformed sequences become U+FFFD.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InputEncoding {
+    /// UTF-8, read without transcoding
+    #[default]
+    Utf8,
+    /// Detect UTF-8 and UTF-16LE/BE from a byte order mark, otherwise UTF-8
+    Auto,
+    /// A fixed encoding; a byte order mark still takes precedence
+    Fixed(&'static Encoding),
+}
+
+impl core::str::FromStr for InputEncoding {
+    type Err = String;
+
+    /// Parses `auto` or any WHATWG encoding label, e.g. `utf-16le` or `latin1`
+    /// (which, as in browsers, decodes as windows-1252)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("auto") {
+            return Ok(InputEncoding::Auto);
+        }
+        match Encoding::for_label(s.as_bytes()) {
+            Some(encoding) if encoding == encoding_rs::UTF_8 => Ok(InputEncoding::Utf8),
+            Some(encoding) => Ok(InputEncoding::Fixed(encoding)),
+            None => Err(format!("unknown encoding {:?}", s)),
+        }
+    }
+}
+
+impl core::fmt::Display for InputEncoding {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            InputEncoding::Utf8 => f.write_str("utf-8"),
+            InputEncoding::Auto => f.write_str("auto"),
+            InputEncoding::Fixed(encoding) => f.write_str(&encoding.name().to_ascii_lowercase()),
+        }
+    }
+}
+
+/// Handling of byte order marks and other whitespace JSON doesn't allow
+/// before the first document, as written by some Windows tools
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LeadingJunk {
+    /// Drop it, keeping any JSON whitespace around it
+    #[default]
+    Skip,
+    /// Fail with a [`LeadingJunkError`]
+    Error,
+}
+
+impl core::str::FromStr for LeadingJunk {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(LeadingJunk::Skip),
+            "error" => Ok(LeadingJunk::Error),
+            other => Err(format!("unknown leading junk policy {:?} (expected skip or error)", other)),
+        }
+    }
+}
+
+impl core::fmt::Display for LeadingJunk {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            LeadingJunk::Skip => "skip",
+            LeadingJunk::Error => "error",
+        })
+    }
+}
+
+/// The first junk character found under [`LeadingJunk::Error`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LeadingJunkError(pub char);
+
+impl core::fmt::Display for LeadingJunkError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self.0 {
+            '\u{feff}' => f.write_str("input starts with a byte order mark"),
+            c => write!(f, "input starts with {:?}, which is not JSON whitespace", c),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for LeadingJunkError {}
+
+/// A size limit of the options that the input exceeded, carried in an
+/// `InvalidData` I/O error
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SizeLimitError {
+    /// The input is larger than `max_input_bytes`; `size` is known for files
+    Input { size: Option<u64>, limit: usize },
+    /// Document number `index`, counting from 0, is larger than `max_document_bytes`
+    Document { index: usize, limit: usize },
+}
+
+impl core::fmt::Display for SizeLimitError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            SizeLimitError::Input { size: Some(size), limit } => {
+                write!(f, "input is {} bytes, more than the limit of {} (max_input_bytes)", size, limit)
+            }
+            SizeLimitError::Input { size: None, limit } => {
+                write!(f, "input is larger than the limit of {} bytes (max_input_bytes)", limit)
+            }
+            SizeLimitError::Document { index, limit } => write!(
+                f,
+                "document {} is larger than the limit of {} bytes (max_document_bytes)",
+                index, limit
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for SizeLimitError {}
+
+#[cfg(feature = "std")]
+fn size_limit_error(error: SizeLimitError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+/// Reader that fails with a [`SizeLimitError`] once more than `limit` bytes
+/// have been read
+#[cfg(feature = "std")]
+struct LimitedReader<R> {
+    inner: R,
+    remaining: u64,
+    error: SizeLimitError,
+}
+
+#[cfg(feature = "std")]
+impl<R: Read> LimitedReader<R> {
+    fn new(inner: R, limit: usize, error: SizeLimitError) -> Self {
+        LimitedReader {
+            inner,
+            remaining: limit as u64,
+            error,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: Read> Read for LimitedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.remaining = self.remaining.checked_sub(n as u64).ok_or_else(|| size_limit_error(self.error))?;
+        Ok(n)
+    }
+}
+
+/// Caps the documents a scanner collects at `max_document_bytes`
+#[cfg(feature = "std")]
+#[derive(Clone, Copy)]
+struct DocumentLimit {
+    limit: usize,
+    /// Index of the document being collected
+    index: usize,
+}
+
+#[cfg(feature = "std")]
+impl DocumentLimit {
+    fn new(options: &FlattenOptions) -> Self {
+        DocumentLimit {
+            limit: options.max_document_bytes,
+            index: 0,
+        }
+    }
+
+    /// Appends `bytes` to the current document, failing if it gets too large
+    fn extend(&self, document: &mut Vec<u8>, bytes: &[u8]) -> io::Result<()> {
+        self.check(document.len() + bytes.len())?;
+        document.extend_from_slice(bytes);
+        Ok(())
+    }
+
+    /// Fails if the current document is `len` bytes and too large
+    fn check(&self, len: usize) -> io::Result<()> {
+        if self.limit > 0 && len > self.limit {
+            return Err(size_limit_error(SizeLimitError::Document {
+                index: self.index,
+                limit: self.limit,
+            }));
+        }
+        Ok(())
+    }
+
+    fn next_document(&mut self) {
+        self.index += 1;
+    }
+}
+
+fn is_json_whitespace(c: char) -> bool {
+    matches!(c, ' ' | '\t' | '\n' | '\r')
+}
+
+fn is_junk(c: char) -> bool {
+    c == '\u{feff}' || (c.is_whitespace() && !is_json_whitespace(c))
+}
+
+/// Drops byte order marks and non-JSON whitespace before the first document
+/// of a string, or reports them under [`LeadingJunk::Error`]
+pub fn skip_leading_junk(text: &str, policy: LeadingJunk) -> Result<Cow<'_, str>, LeadingJunkError> {
+    let start = text.find(|c: char| !is_json_whitespace(c) && !is_junk(c)).unwrap_or(text.len());
+    let leading = &text[..start];
+    match leading.chars().find(|&c| is_junk(c)) {
+        None => Ok(Cow::Borrowed(text)),
+        Some(c) if policy == LeadingJunk::Error => Err(LeadingJunkError(c)),
+        Some(_) => {
+            // JSON whitespace is kept so line numbers in parse errors still match
+            let mut kept: String = leading.chars().filter(|&c| is_json_whitespace(c)).collect();
+            kept.push_str(&text[start..]);
+            Ok(Cow::Owned(kept))
+        }
+    }
+}
+
+/// Reader that applies [`skip_leading_junk`] to the start of its input on the
+/// first read
+#[cfg(feature = "std")]
+struct LeadingJunkReader<R> {
+    inner: BufReader<R>,
+    policy: LeadingJunk,
+    /// JSON whitespace read while looking for junk, still to be served
+    kept: Option<Vec<u8>>,
+    pos: usize,
+}
+
+#[cfg(feature = "std")]
+impl<R: Read> LeadingJunkReader<R> {
+    fn scan(&mut self) -> io::Result<Vec<u8>> {
+        let mut kept = Vec::new();
+        loop {
+            let buf = self.inner.fill_buf()?;
+            let text = match core::str::from_utf8(buf) {
+                Ok(text) => text,
+                Err(e) => core::str::from_utf8(&buf[..e.valid_up_to()]).expect("valid up to here"),
+            };
+            let mut scanned = 0;
+            let mut done = text.is_empty();
+            for c in text.chars() {
+                if is_junk(c) {
+                    if self.policy == LeadingJunk::Error {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, LeadingJunkError(c)));
+                    }
+                } else if is_json_whitespace(c) {
+                    kept.push(c as u8);
+                } else {
+                    done = true;
+                    break;
+                }
+                scanned += c.len_utf8();
+            }
+            // A character cut off at the end of the buffer ends the scan too
+            done |= scanned < buf.len() && scanned == text.len();
+            self.inner.consume(scanned);
+            if done {
+                return Ok(kept);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: Read> Read for LeadingJunkReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.kept.is_none() {
+            self.kept = Some(self.scan()?);
+        }
+        let kept = self.kept.as_deref().unwrap_or_default();
+        if self.pos < kept.len() {
+            let n = buf.len().min(kept.len() - self.pos);
+            buf[..n].copy_from_slice(&kept[self.pos..self.pos + n]);
+            self.pos += n;
+            return Ok(n);
+        }
+        self.inner.read(buf)
+    }
+}
+
+/// Wraps a reader so it yields UTF-8 according to `encoding`
+#[cfg(feature = "std")]
+pub fn decode_input<'a, R: Read + 'a>(reader: R, encoding: InputEncoding) -> Box<dyn Read + 'a> {
+    match encoding {
+        InputEncoding::Utf8 => Box::new(reader),
+        InputEncoding::Auto => Box::new(DecodeReaderBytesBuilder::new().build(reader)),
+        InputEncoding::Fixed(encoding) => Box::new(
+            DecodeReaderBytesBuilder::new()
+                .encoding(Some(encoding))
+                .bom_override(true)
+                .build(reader),
+        ),
+    }
+}
+
+/// Decodes a reader per `options.encoding`, handles junk before the first
+/// document per `options.leading_junk`, rewrites non-finite number tokens
+/// per `options.non_finite` and stops at `options.max_input_bytes`
+#[cfg(feature = "std")]
+pub(crate) fn prepare_input<'a, R: Read + 'a>(reader: R, options: &FlattenOptions) -> Box<dyn Read + 'a> {
+    let reader: Box<dyn Read + 'a> = match options.max_input_bytes {
+        0 => Box::new(reader),
+        limit => Box::new(LimitedReader::new(reader, limit, SizeLimitError::Input { size: None, limit })),
+    };
+    let reader: Box<dyn Read + 'a> = Box::new(LeadingJunkReader {
+        inner: BufReader::new(decode_input(reader, options.encoding)),
+        policy: options.leading_junk,
+        kept: None,
+        pos: 0,
+    });
+    match options.non_finite {
+        NonFiniteNumbers::Error => reader,
+        policy => Box::new(NonFiniteReader::new(reader, policy)),
+    }
+}
+
+/// Opens a file for reading, or standard input when the path is `"-"`
+#[cfg(feature = "std")]
+pub fn open_input(filepath: impl AsRef<Path>) -> io::Result<Box<dyn Read + Send>> {
+    let filepath = filepath.as_ref();
+    if filepath == Path::new(STDIN_PATH) {
+        Ok(Box::new(io::stdin()))
+    } else {
+        Ok(Box::new(File::open(filepath)?))
+    }
+}
+
+/// Stops a reader whose whole input is one document at
+/// `options.max_document_bytes`
+#[cfg(feature = "std")]
+pub(crate) fn limit_document<'a>(reader: Box<dyn Read + 'a>, options: &FlattenOptions) -> Box<dyn Read + 'a> {
+    match options.max_document_bytes {
+        0 => reader,
+        limit => Box::new(LimitedReader::new(reader, limit, SizeLimitError::Document { index: 0, limit })),
+    }
+}
+
+/// Opens a file like [`open_input`], first failing with a [`SizeLimitError`]
+/// if it is larger than `options.max_input_bytes`
+#[cfg(feature = "std")]
+pub(crate) fn open_input_checked(
+    filepath: impl AsRef<Path>,
+    options: &FlattenOptions,
+) -> io::Result<Box<dyn Read + Send>> {
+    let filepath = filepath.as_ref();
+    let limit = options.max_input_bytes;
+    if limit > 0 && filepath != Path::new(STDIN_PATH) {
+        let size = std::fs::metadata(filepath)?.len();
+        if size > limit as u64 {
+            return Err(size_limit_error(SizeLimitError::Input { size: Some(size), limit }));
+        }
+    }
+    open_input(filepath)
+}
+
+/// Splits any reader into the raw text of its documents
+///
+/// Input that is a single top-level array yields one document per element when
+/// `split_root_array` is set, and a JSON text sequence (RFC 7464, detected by
+/// its leading record separator) one document per text. Anything else (one
+/// document, concatenated documents or NDJSON) yields one document per
+/// top-level value.
+///
+/// Input is decoded according to `encoding`, and invalid UTF-8 is handled
+/// per document according to `invalid_utf8`.
+#[cfg(feature = "std")]
+pub fn read_documents<'a, R: Read + 'a>(reader: R, options: &FlattenOptions) -> DocumentStream<'a> {
+    Box::new(read_located_documents(reader, options).map(|document| Ok(document?.text)))
+}
+
+/// Splits a reader into documents like [`read_documents`], with where each
+/// document starts
+///
+/// Under [`InvalidDocuments::Skip`] documents
+/// are always split on raw bytes, so one that is not valid JSON doesn't end
+/// the input and every start is known.
+#[cfg(feature = "std")]
+pub fn read_located_documents<'a, R: Read + 'a>(reader: R, options: &FlattenOptions) -> LocatedDocumentStream<'a> {
+    if options.ndjson {
+        return Box::new(LineDocuments::new(reader, options));
+    }
+    let mut reader = Tracked::new(BufReader::new(prepare_input(reader, options)));
+    match first_non_whitespace(&mut reader) {
+        Ok(Some(b'[')) if options.split_root_array => {
+            return Box::new(ArrayElements::new(reader, options))
+        }
+        Ok(Some(RECORD_SEPARATOR)) => return Box::new(JsonSeqDocuments::new(reader, options)),
+        Ok(_) => {}
+        Err(e) => return Box::new(std::iter::once(Err(e))),
+    }
+    if options.invalid_utf8 != InvalidUtf8::Error
+        || options.max_document_bytes > 0
+        || options.invalid_documents == InvalidDocuments::Skip
+    {
+        // serde_json rejects invalid UTF-8 and syntax errors for the whole
+        // stream and reads a document whatever its size, so documents are
+        // split on raw bytes and decoded one at a time instead
+        return Box::new(ByteDocuments::new(reader, options));
+    }
+
+    Box::new(serde_json::Deserializer::from_reader(reader).into_iter::<Box<RawValue>>().map(|document| {
+        Ok(LocatedDocument {
+            text: Box::<str>::from(document?),
+            start: None,
+        })
+    }))
+}
+
+/// Reads NDJSON input in blocks of whole lines
+///
+/// Only the last newline of each block is looked for here; [`LineSplitter`]
+/// splits the lines out wherever the block is processed. A line longer than a
+/// block is read whole, unless it is over `max_document_bytes`: the block is
+/// then cut short for the splitter to reject.
+#[cfg(feature = "std")]
+pub(crate) struct LineBlocks<'a> {
+    reader: Box<dyn Read + 'a>,
+    block_bytes: usize,
+    max_line_bytes: usize,
+    /// Start of the line that ended the previous block
+    carry: Vec<u8>,
+}
+
+#[cfg(feature = "std")]
+impl<'a> LineBlocks<'a> {
+    pub(crate) fn new<R: Read + 'a>(reader: R, options: &FlattenOptions) -> Self {
+        LineBlocks {
+            reader: prepare_input(reader, options),
+            block_bytes: match options.chunk_bytes {
+                0 => NDJSON_BLOCK_BYTES,
+                bytes => bytes,
+            },
+            max_line_bytes: options.max_document_bytes,
+            carry: Vec::new(),
+        }
+    }
+
+    fn read_block(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut block = std::mem::take(&mut self.carry);
+        loop {
+            let searched = block.len();
+            let read = (&mut self.reader).take(self.block_bytes as u64).read_to_end(&mut block)?;
+            if read < self.block_bytes {
+                return Ok((!block.is_empty()).then_some(block));
+            }
+            if let Some(i) = block[searched..].iter().rposition(|&b| b == b'\n') {
+                self.carry = block.split_off(searched + i + 1);
+                return Ok(Some(block));
+            }
+            if self.max_line_bytes > 0 && block.len() > self.max_line_bytes {
+                return Ok(Some(block));
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Iterator for LineBlocks<'_> {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_block().transpose()
+    }
+}
+
+/// One NDJSON line borrowed from its block
+#[cfg(feature = "std")]
+pub(crate) struct LocatedLine<'b> {
+    pub(crate) text: Cow<'b, str>,
+    pub(crate) start: InputPosition,
+}
+
+/// Splits blocks of whole lines, in input order, into their non-blank lines
+#[cfg(feature = "std")]
+pub(crate) struct LineSplitter {
+    position: InputPosition,
+    invalid_utf8: InvalidUtf8,
+    limit: DocumentLimit,
+}
+
+#[cfg(feature = "std")]
+impl LineSplitter {
+    pub(crate) fn new(options: &FlattenOptions) -> Self {
+        LineSplitter {
+            position: InputPosition::default(),
+            invalid_utf8: options.invalid_utf8,
+            limit: DocumentLimit::new(options),
+        }
+    }
+
+    /// The lines of the next block, decoded per `invalid_utf8`
+    pub(crate) fn split<'b>(&mut self, block: &'b [u8]) -> io::Result<Vec<LocatedLine<'b>>> {
+        let mut lines = Vec::new();
+        for line in block.split_inclusive(|&b| b == b'\n') {
+            let start = self.position;
+            self.position.offset += line.len() as u64;
+            self.position.line += line.ends_with(b"\n") as u64;
+            if line.iter().all(u8::is_ascii_whitespace) {
+                continue;
+            }
+            let line = line.strip_suffix(b"\n").unwrap_or(line);
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            self.limit.check(line.len())?;
+            self.limit.next_document();
+            let text = match std::str::from_utf8(line) {
+                Ok(text) => Cow::Borrowed(text),
+                Err(e) => match self.invalid_utf8 {
+                    InvalidUtf8::Error => return Err(invalid_data(&e.to_string())),
+                    InvalidUtf8::Replace => String::from_utf8_lossy(line),
+                    InvalidUtf8::SkipRecord => continue,
+                },
+            };
+            lines.push(LocatedLine { text, start });
+        }
+        Ok(lines)
+    }
+}
+
+/// The lines of NDJSON input as owned documents, for the paths that take
+/// one document at a time
+#[cfg(feature = "std")]
+struct LineDocuments<'a> {
+    blocks: LineBlocks<'a>,
+    splitter: LineSplitter,
+    pending: std::vec::IntoIter<LocatedDocument>,
+    done: bool,
+}
+
+#[cfg(feature = "std")]
+impl<'a> LineDocuments<'a> {
+    fn new<R: Read + 'a>(reader: R, options: &FlattenOptions) -> Self {
+        LineDocuments {
+            blocks: LineBlocks::new(reader, options),
+            splitter: LineSplitter::new(options),
+            pending: Vec::new().into_iter(),
+            done: false,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Iterator for LineDocuments<'_> {
+    type Item = io::Result<LocatedDocument>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(document) = self.pending.next() {
+                return Some(Ok(document));
+            }
+            if self.done {
+                return None;
+            }
+            let lines = self.blocks.next()?.and_then(|block| {
+                let lines = self.splitter.split(&block)?;
+                Ok(lines.into_iter().map(|line| LocatedDocument {
+                    text: line.text.into(),
+                    start: Some(line.start),
+                }).collect::<Vec<_>>())
+            });
+            match lines {
+                Ok(lines) => self.pending = lines.into_iter(),
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+/// Reader that keeps the position of the next byte to be consumed
+#[cfg(feature = "std")]
+struct Tracked<R> {
+    inner: R,
+    position: InputPosition,
+}
+
+#[cfg(feature = "std")]
+impl<R: BufRead> Tracked<R> {
+    fn new(inner: R) -> Self {
+        Tracked {
+            inner,
+            position: InputPosition::default(),
+        }
+    }
+
+}
+
+#[cfg(feature = "std")]
+fn advance(position: &mut InputPosition, bytes: &[u8]) {
+    position.offset += bytes.len() as u64;
+    position.line += bytes.iter().filter(|&&b| b == b'\n').count() as u64;
+}
+
+#[cfg(feature = "std")]
+impl<R: BufRead> Read for Tracked<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        advance(&mut self.position, &buf[..read]);
+        Ok(read)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: BufRead> BufRead for Tracked<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        // Consuming only follows a successful `fill_buf`, so this returns the
+        // buffered bytes without reading
+        if let Ok(buf) = self.inner.fill_buf() {
+            advance(&mut self.position, &buf[..amt.min(buf.len())]);
+        }
+        self.inner.consume(amt);
+    }
+}
+
+/// Peeks at the first byte that is not JSON whitespace without consuming it
+#[cfg(feature = "std")]
+fn first_non_whitespace<R: BufRead>(reader: &mut R) -> io::Result<Option<u8>> {
+    loop {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            return Ok(None);
+        }
+        match buf.iter().position(|b| !b.is_ascii_whitespace()) {
+            Some(i) => {
+                let byte = buf[i];
+                reader.consume(i);
+                return Ok(Some(byte));
+            }
+            None => {
+                let len = buf.len();
+                reader.consume(len);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Decodes the raw bytes of one document, returning `None` if it is skipped
+#[cfg(feature = "std")]
+fn decode_document(bytes: Vec<u8>, invalid_utf8: InvalidUtf8) -> io::Result<Option<String>> {
+    match String::from_utf8(bytes) {
+        Ok(text) => Ok(Some(text)),
+        Err(e) => match invalid_utf8 {
+            InvalidUtf8::Error => Err(invalid_data(&e.to_string())),
+            InvalidUtf8::Replace => Ok(Some(String::from_utf8_lossy(e.as_bytes()).into_owned())),
+            InvalidUtf8::SkipRecord => Ok(None),
+        },
+    }
+}
+
+/// Streams the elements of a top-level array as raw text
+///
+/// Only tracks nesting and string boundaries to find element ends; the
+/// elements themselves are validated when they are parsed.
+#[cfg(feature = "std")]
+struct ArrayElements<R> {
+    reader: Tracked<R>,
+    invalid_utf8: InvalidUtf8,
+    limit: DocumentLimit,
+    started: bool,
+    closed: bool,
+    done: bool,
+}
+
+#[cfg(feature = "std")]
+impl<R: BufRead> ArrayElements<R> {
+    fn new(reader: Tracked<R>, options: &FlattenOptions) -> Self {
+        ArrayElements {
+            reader,
+            invalid_utf8: options.invalid_utf8,
+            limit: DocumentLimit::new(options),
+            started: false,
+            closed: false,
+            done: false,
+        }
+    }
+
+    fn read_element(&mut self) -> io::Result<Option<LocatedDocument>> {
+        while let Some((start, bytes)) = self.scan_element()? {
+            self.limit.next_document();
+            if let Some(element) = decode_document(bytes, self.invalid_utf8)? {
+                let element = element.trim();
+                if element.is_empty() {
+                    return Err(invalid_data("empty element in top-level array"));
+                }
+                return Ok(Some(LocatedDocument {
+                    text: element.into(),
+                    start: Some(start),
+                }));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Reads the raw bytes of the next element, with where it starts
+    fn scan_element(&mut self) -> io::Result<Option<(InputPosition, Vec<u8>)>> {
+        if !self.started {
+            self.started = true;
+            // The opening bracket was found by `first_non_whitespace`
+            self.reader.consume(1);
+            if first_non_whitespace(&mut self.reader)? == Some(b']') {
+                self.reader.consume(1);
+                self.closed = true;
+            }
+        }
+        if self.closed {
+            return match first_non_whitespace(&mut self.reader)? {
+                None => Ok(None),
+                Some(_) => Err(invalid_data("trailing characters after top-level array")),
+            };
+        }
+
+        first_non_whitespace(&mut self.reader)?;
+        let start = self.reader.position;
+        let mut element = Vec::new();
+        let mut depth = 0usize;
+        let mut in_string = false;
+        let mut escaped = false;
+        loop {
+            let buf = self.reader.fill_buf()?;
+            if buf.is_empty() {
+                return Err(invalid_data("unexpected end of input inside top-level array"));
+            }
+
+            let mut end = None;
+            for (i, &b) in buf.iter().enumerate() {
+                if in_string {
+                    if escaped {
+                        escaped = false;
+                    } else if b == b'\\' {
+                        escaped = true;
+                    } else if b == b'"' {
+                        in_string = false;
+                    }
+                    continue;
+                }
+                match b {
+                    b'"' => in_string = true,
+                    b'{' | b'[' => depth += 1,
+                    b'}' | b']' if depth > 0 => depth -= 1,
+                    b']' => {
+                        self.closed = true;
+                        end = Some(i);
+                        break;
+                    }
+                    b',' if depth == 0 => {
+                        end = Some(i);
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+
+            match end {
+                Some(i) => {
+                    self.limit.extend(&mut element, &buf[..i])?;
+                    self.reader.consume(i + 1);
+                    break;
+                }
+                None => {
+                    let len = buf.len();
+                    self.limit.extend(&mut element, buf)?;
+                    self.reader.consume(len);
+                }
+            }
+        }
+
+        Ok(Some((start, element)))
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: BufRead> Iterator for ArrayElements<R> {
+    type Item = io::Result<LocatedDocument>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.read_element() {
+            Ok(Some(element)) => Some(Ok(element)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// Streams the texts of a JSON text sequence as raw text
+///
+/// JSON can't hold an unescaped record separator, so texts are split on it
+/// without looking inside them. Empty texts are skipped; a truncated text is
+/// reported when it fails to parse.
+#[cfg(feature = "std")]
+struct JsonSeqDocuments<R> {
+    reader: Tracked<R>,
+    invalid_utf8: InvalidUtf8,
+    limit: DocumentLimit,
+}
+
+#[cfg(feature = "std")]
+impl<R: BufRead> JsonSeqDocuments<R> {
+    fn new(reader: Tracked<R>, options: &FlattenOptions) -> Self {
+        JsonSeqDocuments {
+            reader,
+            invalid_utf8: options.invalid_utf8,
+            limit: DocumentLimit::new(options),
+        }
+    }
+
+    fn read_text(&mut self) -> io::Result<Option<LocatedDocument>> {
+        let mut bytes = Vec::new();
+        loop {
+            bytes.clear();
+            first_non_whitespace(&mut self.reader)?;
+            let start = self.reader.position;
+            if !self.scan_text(&mut bytes)? {
+                return Ok(None);
+            }
+            let text = bytes.trim_ascii();
+            if text.is_empty() {
+                continue;
+            }
+            self.limit.next_document();
+            if let Some(text) = decode_document(text.to_vec(), self.invalid_utf8)? {
+                return Ok(Some(LocatedDocument {
+                    text: text.into(),
+                    start: Some(start),
+                }));
+            }
+        }
+    }
+
+    /// Reads the bytes up to the next record separator, returning whether
+    /// there were any left
+    fn scan_text(&mut self, text: &mut Vec<u8>) -> io::Result<bool> {
+        let mut read = false;
+        loop {
+            let buf = self.reader.fill_buf()?;
+            if buf.is_empty() {
+                return Ok(read);
+            }
+            read = true;
+            match buf.iter().position(|&b| b == RECORD_SEPARATOR) {
+                Some(i) => {
+                    self.limit.extend(text, &buf[..i])?;
+                    self.reader.consume(i + 1);
+                    return Ok(true);
+                }
+                None => {
+                    let len = buf.len();
+                    self.limit.extend(text, buf)?;
+                    self.reader.consume(len);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: BufRead> Iterator for JsonSeqDocuments<R> {
+    type Item = io::Result<LocatedDocument>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_text().transpose()
+    }
+}
+
+/// Streams concatenated documents or NDJSON as raw bytes
+///
+/// Like [`ArrayElements`], only nesting and string boundaries are tracked;
+/// each document is validated when it is parsed.
+#[cfg(feature = "std")]
+struct ByteDocuments<R> {
+    reader: Tracked<R>,
+    invalid_utf8: InvalidUtf8,
+    limit: DocumentLimit,
+    /// Ends a document cut short by the end of the input there, for the
+    /// parser to reject, instead of failing
+    lenient: bool,
+    done: bool,
+}
+
+#[cfg(feature = "std")]
+impl<R: BufRead> ByteDocuments<R> {
+    fn new(reader: Tracked<R>, options: &FlattenOptions) -> Self {
+        ByteDocuments {
+            reader,
+            invalid_utf8: options.invalid_utf8,
+            limit: DocumentLimit::new(options),
+            lenient: options.invalid_documents == InvalidDocuments::Skip,
+            done: false,
+        }
+    }
+
+    fn read_document(&mut self) -> io::Result<Option<LocatedDocument>> {
+        while let Some((start, bytes)) = self.scan_document()? {
+            self.limit.next_document();
+            if let Some(document) = decode_document(bytes, self.invalid_utf8)? {
+                return Ok(Some(LocatedDocument {
+                    text: document.into(),
+                    start: Some(start),
+                }));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Reads the raw bytes of the next top-level value, with where it starts
+    fn scan_document(&mut self) -> io::Result<Option<(InputPosition, Vec<u8>)>> {
+        let first = match first_non_whitespace(&mut self.reader)? {
+            Some(byte) => byte,
+            None => return Ok(None),
+        };
+        let start = self.reader.position;
+        // Numbers and literals end at whitespace or the start of the next value
+        let scalar = !matches!(first, b'{' | b'[' | b'"');
+
+        let mut document = Vec::new();
+        let mut depth = 0usize;
+        let mut in_string = false;
+        let mut escaped = false;
+        loop {
+            let buf = self.reader.fill_buf()?;
+            if buf.is_empty() {
+                if scalar || self.lenient {
+                    return Ok(Some((start, document)));
+                }
+                return Err(invalid_data("unexpected end of input inside document"));
+            }
+
+            let mut end = None;
+            for (i, &b) in buf.iter().enumerate() {
+                if in_string {
+                    if escaped {
+                        escaped = false;
+                    } else if b == b'\\' {
+                        escaped = true;
+                    } else if b == b'"' {
+                        in_string = false;
+                        if depth == 0 {
+                            end = Some(i + 1);
+                            break;
+                        }
+                    }
+                    continue;
+                }
+                if scalar {
+                    if b.is_ascii_whitespace() || matches!(b, b'{' | b'[' | b'"') {
+                        end = Some(i);
+                        break;
+                    }
+                    continue;
+                }
+                match b {
+                    b'"' => in_string = true,
+                    b'{' | b'[' => depth += 1,
+                    b'}' | b']' => {
+                        depth = depth.saturating_sub(1);
+                        if depth == 0 {
+                            end = Some(i + 1);
+                            break;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            match end {
+                Some(i) => {
+                    self.limit.extend(&mut document, &buf[..i])?;
+                    self.reader.consume(i);
+                    return Ok(Some((start, document)));
+                }
+                None => {
+                    let len = buf.len();
+                    self.limit.extend(&mut document, buf)?;
+                    self.reader.consume(len);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: BufRead> Iterator for ByteDocuments<R> {
+    type Item = io::Result<LocatedDocument>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.read_document() {
+            Ok(Some(document)) => Some(Ok(document)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    fn documents(input: &str) -> Vec<String> {
+        let options = FlattenOptions {
+            split_root_array: true,
+            ..Default::default()
+        };
+        read_documents(input.as_bytes(), &options)
+            .map(|document| document.unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_root_array_is_split_into_elements() {
+        let input = r#" [ {"a": "x,]"}, [1, [2]], 3 , "s\"]" ] "#;
+        assert_eq!(
+            documents(input),
+            vec![r#"{"a": "x,]"}"#, "[1, [2]]", "3", r#""s\"]""#]
+        );
+        assert!(documents("[]").is_empty());
+    }
+
+    #[test]
+    fn test_concatenated_and_ndjson_documents() {
+        assert_eq!(documents("{\"a\":1}{\"a\":2}\n{\"a\":3}\n"), vec!["{\"a\":1}", "{\"a\":2}", "{\"a\":3}"]);
+    }
+
+    #[test]
+    fn test_json_text_sequences_are_split_on_record_separators() {
+        let input = "\n\x1e{\"a\": \"x y\"}\n\x1e\x1e [1, 2]\n\x1e3\n\x1e";
+        assert_eq!(documents(input), vec!["{\"a\": \"x y\"}", "[1, 2]", "3"]);
+
+        let input: &[u8] = b"\x1e{\"a\": \"\xff\"}\n\x1e{\"a\": 2}\n";
+        let options = FlattenOptions {
+            invalid_utf8: InvalidUtf8::SkipRecord,
+            ..Default::default()
+        };
+        let texts: Vec<_> = read_documents(input, &options).map(Result::unwrap).collect();
+        assert_eq!(texts.iter().map(|d| d.as_ref()).collect::<Vec<_>>(), vec!["{\"a\": 2}"]);
+        assert!(read_documents(input, &FlattenOptions::default()).any(|text| text.is_err()));
+    }
+
+    #[test]
+    fn test_size_limits_stop_large_inputs_and_documents() {
+        let limited = |max_input_bytes, max_document_bytes| FlattenOptions {
+            max_input_bytes,
+            max_document_bytes,
+            split_root_array: true,
+            ..Default::default()
+        };
+        let size_error = |input: &str, options: &FlattenOptions| {
+            let error = read_documents(input.as_bytes(), options).find_map(Result::err).unwrap();
+            *error.get_ref().unwrap().downcast_ref::<SizeLimitError>().unwrap()
+        };
+
+        let ndjson = "{\"a\": 1}\n{\"a\": \"long value\"}\n{\"a\": 3}\n";
+        assert_eq!(read_documents(ndjson.as_bytes(), &limited(0, 20)).count(), 3);
+        assert_eq!(size_error(ndjson, &limited(0, 12)), SizeLimitError::Document { index: 1, limit: 12 });
+        let array = "[1, [2, 3, 4, 5], 6]";
+        assert_eq!(size_error(array, &limited(0, 8)), SizeLimitError::Document { index: 1, limit: 8 });
+        let sequence = "\x1e1\n\x1e\"long text\"\n";
+        assert_eq!(size_error(sequence, &limited(0, 5)), SizeLimitError::Document { index: 1, limit: 5 });
+
+        assert_eq!(size_error(ndjson, &limited(16, 0)), SizeLimitError::Input { size: None, limit: 16 });
+        assert_eq!(read_documents(ndjson.as_bytes(), &limited(ndjson.len(), 0)).count(), 3);
+    }
+
+    #[test]
+    fn test_invalid_utf8_policies() {
+        let input: &[u8] = b"{\"a\": \"ok\"}\n{\"a\": \"bad \xff\"}\n{\"a\": 3} 4 \"s\"";
+        let read = |invalid_utf8, split_root_array| {
+            let options = FlattenOptions {
+                invalid_utf8,
+                split_root_array,
+                ..Default::default()
+            };
+            read_documents(input, &options).collect::<io::Result<Vec<_>>>()
+        };
+
+        assert!(read(InvalidUtf8::Error, true).is_err());
+        let replaced = read(InvalidUtf8::Replace, true).unwrap();
+        assert_eq!(replaced[1].as_ref(), "{\"a\": \"bad \u{fffd}\"}");
+        assert_eq!(replaced.len(), 5);
+        let skipped = read(InvalidUtf8::SkipRecord, true).unwrap();
+        assert_eq!(
+            skipped.iter().map(|d| d.as_ref()).collect::<Vec<_>>(),
+            vec!["{\"a\": \"ok\"}", "{\"a\": 3}", "4", "\"s\""]
+        );
+
+        let array: &[u8] = b"[1, \"\xfe\", 3]";
+        let options = FlattenOptions {
+            invalid_utf8: InvalidUtf8::SkipRecord,
+            split_root_array: true,
+            ..Default::default()
+        };
+        let elements: Vec<_> = read_documents(array, &options).map(Result::unwrap).collect();
+        assert_eq!(elements.iter().map(|d| d.as_ref()).collect::<Vec<_>>(), vec!["1", "3"]);
+    }
+
+    #[test]
+    fn test_input_encodings() {
+        let utf16le: Vec<u8> = [0xFF, 0xFE]
+            .into_iter()
+            .chain("{\"n\": \"\u{e9}\"}".encode_utf16().flat_map(u16::to_le_bytes))
+            .collect();
+        let auto = FlattenOptions {
+            encoding: InputEncoding::Auto,
+            ..Default::default()
+        };
+        let documents: Vec<_> = read_documents(&utf16le[..], &auto).map(Result::unwrap).collect();
+        assert_eq!(documents[0].as_ref(), "{\"n\": \"\u{e9}\"}");
+
+        let latin1 = FlattenOptions {
+            encoding: "latin1".parse().unwrap(),
+            ..Default::default()
+        };
+        let documents: Vec<_> = read_documents(&b"{\"n\": \"caf\xe9\"}"[..], &latin1)
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(documents[0].as_ref(), "{\"n\": \"caf\u{e9}\"}");
+        assert!("klingon".parse::<InputEncoding>().is_err());
+    }
+
+    #[test]
+    fn test_byte_order_mark_and_junk_whitespace_are_skipped() {
+        let input = "\u{feff}\n\u{a0}{\"a\": 1}\n{\"a\": 2}";
+        assert_eq!(documents(input), vec!["{\"a\": 1}", "{\"a\": 2}"]);
+        assert_eq!(documents("\u{feff}[1, 2]"), vec!["1", "2"]);
+        assert_eq!(skip_leading_junk(input, LeadingJunk::Skip).unwrap(), "\n{\"a\": 1}\n{\"a\": 2}");
+        assert!(matches!(skip_leading_junk(" {}", LeadingJunk::Error), Ok(Cow::Borrowed(" {}"))));
+
+        let strict = FlattenOptions {
+            leading_junk: LeadingJunk::Error,
+            ..Default::default()
+        };
+        let err = read_documents(input.as_bytes(), &strict).next().unwrap().unwrap_err();
+        assert_eq!(err.to_string(), "input starts with a byte order mark");
+        assert_eq!(
+            skip_leading_junk("\u{c}{}", LeadingJunk::Error).unwrap_err().to_string(),
+            "input starts with '\\u{c}', which is not JSON whitespace"
+        );
+    }
+
+    #[test]
+    fn test_malformed_root_array_is_an_error() {
+        let results: Vec<_> = read_documents("[1, 2".as_bytes(), &FlattenOptions::default()).collect();
+        assert!(results.last().unwrap().is_err());
+        let results: Vec<_> = read_documents("[1] x".as_bytes(), &FlattenOptions::default()).collect();
+        assert!(results.last().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_ndjson_lines_are_split_across_blocks_with_positions() {
+        let input = "{\"a\": 1}\r\n\n  \n{\"a\": \"a long line\"}\n{\"a\": 3}";
+        let options = FlattenOptions {
+            ndjson: true,
+            chunk_bytes: 4,
+            ..Default::default()
+        };
+        let documents: Vec<LocatedDocument> = read_located_documents(input.as_bytes(), &options)
+            .collect::<io::Result<_>>()
+            .unwrap();
+        let texts: Vec<&str> = documents.iter().map(|document| document.text.as_ref()).collect();
+        assert_eq!(texts, ["{\"a\": 1}", "{\"a\": \"a long line\"}", "{\"a\": 3}"]);
+        let starts: Vec<(u64, u64)> =
+            documents.iter().map(|document| document.start.map(|s| (s.offset, s.line)).unwrap()).collect();
+        assert_eq!(starts, [(0, 1), (14, 4), (35, 5)]);
+
+        let limited = FlattenOptions {
+            max_document_bytes: 12,
+            ..options
+        };
+        let results: Vec<_> = read_located_documents(input.as_bytes(), &limited).collect();
+        let err = results.last().unwrap().as_ref().unwrap_err();
+        assert!(err.to_string().starts_with("document 1 is larger than the limit of 12 bytes"));
+    }
+}
@@ -0,0 +1,276 @@
+// src/ipc.rs
+use crate::canonical::decimal_parts;
+use crate::schema::{infer_schema, is_null, ColumnType, FlatSchema};
+use crate::{flatten_json_file_to_sink, FlattenOptions, FlattenedJson, RecordSink};
+use arrow_array::{ArrayRef, BooleanArray, Decimal128Array, Float64Array, Int64Array, RecordBatch, StringArray};
+use arrow_ipc::writer::FileWriter;
+use arrow_schema::{ArrowError, DataType, Field, Schema, SchemaRef};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Sink that writes records to an Arrow IPC file (Feather v2)
+///
+/// Rows are buffered until the first flush, which fixes the schema: every
+/// column seen so far, sorted by name, typed by [`infer_schema`], or the
+/// schema given to [`with_schema`](IpcSink::with_schema). Decimal
+/// columns become `Decimal128(38, s)` with the largest scale among those rows,
+/// or exact strings when a value needs more than 38 digits. Later flushes
+/// write further record batches with that schema; keys that were not part of it
+/// are dropped, and values that don't fit a column's type become nulls. The
+/// file is only readable once [`finish`](IpcSink::finish) has written its footer.
+pub struct IpcSink<W: Write> {
+    writer: Option<W>,
+    file: Option<FileWriter<W>>,
+    /// Schema to write instead of one inferred from the first rows
+    schema: Option<FlatSchema>,
+    rows: Vec<FlattenedJson>,
+}
+
+impl<W: Write> IpcSink<W> {
+    pub fn new(writer: W) -> Self {
+        IpcSink {
+            writer: Some(writer),
+            file: None,
+            schema: None,
+            rows: Vec::new(),
+        }
+    }
+
+    /// Types the file by a schema saved from an earlier run instead of
+    /// inferring one, so every run of a stable feed gets the same columns and
+    /// types; decimal scales are still sized to the first rows
+    pub fn with_schema(mut self, schema: FlatSchema) -> Self {
+        self.schema = Some(schema);
+        self
+    }
+
+    /// Flushes buffered rows, writes the file footer and returns the underlying writer
+    pub fn finish(mut self) -> io::Result<W> {
+        RecordSink::flush(&mut self)?;
+        let mut file = self.file.take().ok_or_else(|| io::Error::other("IPC file was not started"))?;
+        file.finish().map_err(io::Error::other)?;
+        file.into_inner().map_err(io::Error::other)
+    }
+
+    fn write_batch(&mut self) -> Result<(), ArrowError> {
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => {
+                let schema = self.schema.take().unwrap_or_else(|| infer_schema(&self.rows));
+                let writer = self.writer.take().expect("writer is taken when the file is started");
+                self.file.insert(FileWriter::try_new(writer, &arrow_schema(&schema, &self.rows))?)
+            }
+        };
+        if self.rows.is_empty() {
+            return Ok(());
+        }
+
+        let rows = std::mem::take(&mut self.rows);
+        file.write(&record_batch(file.schema().clone(), &rows)?)
+    }
+}
+
+impl<W: Write> RecordSink for IpcSink<W> {
+    fn write_record(&mut self, record: FlattenedJson) -> io::Result<()> {
+        self.rows.push(record);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_batch().map_err(io::Error::other)?;
+        match &mut self.file {
+            Some(file) => file.flush().map_err(io::Error::other),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Most digits a `Decimal128` holds
+const DECIMAL_PRECISION: u8 = 38;
+
+/// Arrow schema for inferred column types, sizing decimal columns to `rows`;
+/// every column is nullable
+pub(crate) fn arrow_schema(columns: &FlatSchema, rows: &[FlattenedJson]) -> Schema {
+    let fields: Vec<Field> = columns
+        .iter()
+        .map(|(name, column_type)| {
+            let data_type = match column_type {
+                ColumnType::Bool => DataType::Boolean,
+                ColumnType::Int => DataType::Int64,
+                ColumnType::Float => DataType::Float64,
+                ColumnType::Decimal => decimal_type(rows, name),
+                ColumnType::Text => DataType::Utf8,
+            };
+            Field::new(name, data_type, true)
+        })
+        .collect();
+    Schema::new(fields)
+}
+
+/// `Decimal128` with the largest scale of a column's values, or `Utf8` when
+/// a value doesn't fit in 38 digits at that scale
+fn decimal_type(rows: &[FlattenedJson], name: &str) -> DataType {
+    let values: Vec<&str> = column_values(rows, name).flatten().collect();
+    let scale = values
+        .iter()
+        .filter_map(|value| decimal_parts(value))
+        .map(|(_, _, exponent)| (-exponent).max(0))
+        .max()
+        .unwrap_or(0);
+    match i8::try_from(scale) {
+        Ok(scale) if values.iter().all(|value| decimal_value(value, scale).is_some()) => {
+            DataType::Decimal128(DECIMAL_PRECISION, scale)
+        }
+        _ => DataType::Utf8,
+    }
+}
+
+/// A decimal's digits as an integer at `scale`, if it has no more fraction
+/// digits than that and fits the precision
+fn decimal_value(value: &str, scale: i8) -> Option<i128> {
+    let (negative, digits, exponent) = decimal_parts(value)?;
+    let shift = u32::try_from(exponent + i64::from(scale)).ok()?;
+    let n = match digits.is_empty() {
+        true => 0,
+        false => digits.parse::<i128>().ok()?.checked_mul(10i128.checked_pow(shift)?)?,
+    };
+    if n >= 10i128.pow(u32::from(DECIMAL_PRECISION)) {
+        return None;
+    }
+    Some(if negative { -n } else { n })
+}
+
+/// A column's values; nulls and missing keys are `None`
+fn column_values<'a>(rows: &'a [FlattenedJson], name: &'a str) -> impl Iterator<Item = Option<&'a str>> + 'a {
+    rows.iter()
+        .map(move |row| row.get(name).map(String::as_str))
+        .map(|value| value.filter(|_| !is_null(value)))
+}
+
+/// Builds the array of one column; nulls, missing keys and values that don't
+/// fit the column's type become Arrow nulls
+fn column_array(rows: &[FlattenedJson], name: &str, data_type: &DataType) -> Result<ArrayRef, ArrowError> {
+    let values = column_values(rows, name);
+    Ok(match data_type {
+        DataType::Boolean => Arc::new(values.map(|value| value.and_then(|v| v.parse().ok())).collect::<BooleanArray>()),
+        DataType::Int64 => Arc::new(values.map(|value| value.and_then(|v| v.parse().ok())).collect::<Int64Array>()),
+        DataType::Float64 => Arc::new(values.map(|value| value.and_then(|v| v.parse().ok())).collect::<Float64Array>()),
+        DataType::Decimal128(precision, scale) => Arc::new(
+            values
+                .map(|value| value.and_then(|v| decimal_value(v, *scale)))
+                .collect::<Decimal128Array>()
+                .with_precision_and_scale(*precision, *scale)?,
+        ),
+        _ => Arc::new(values.collect::<StringArray>()),
+    })
+}
+
+/// One record batch of `rows` with the columns of `schema`
+pub(crate) fn record_batch(schema: SchemaRef, rows: &[FlattenedJson]) -> Result<RecordBatch, ArrowError> {
+    let columns = schema
+        .fields()
+        .iter()
+        .map(|field| column_array(rows, field.name(), field.data_type()))
+        .collect::<Result<_, _>>()?;
+    RecordBatch::try_new(schema, columns)
+}
+
+/// Flattens a JSON file into an Arrow IPC file (Feather v2) with inferred
+/// column types, returning the number of records written
+///
+/// A path of `"-"` reads from standard input. Every record is held in memory
+/// until the schema is known.
+pub fn flatten_to_ipc(
+    input: impl AsRef<Path>,
+    output: impl AsRef<Path>,
+    options: &FlattenOptions,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let mut sink = IpcSink::new(BufWriter::new(File::create(output)?));
+    let written = flatten_json_file_to_sink(input, &mut sink, options)?;
+    sink.finish()?.flush()?;
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_array::Array;
+    use arrow_ipc::reader::FileReader;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_ipc_sink_writes_typed_columns() {
+        let options = FlattenOptions::default();
+        let mut sink = IpcSink::new(Vec::new());
+        for value in [
+            serde_json::json!({"id": 1, "user": {"name": "Ann", "score": 9.5}, "ok": true}),
+            serde_json::json!({"id": 2, "user": {"name": null}, "ok": false}),
+        ] {
+            sink.write_record(crate::flatten_json(&value, &options)).unwrap();
+        }
+        let bytes = sink.finish().unwrap();
+
+        let reader = FileReader::try_new(Cursor::new(bytes), None).unwrap();
+        let schema = reader.schema();
+        let types: Vec<(&str, &DataType)> = schema.fields().iter().map(|f| (f.name().as_str(), f.data_type())).collect();
+        assert_eq!(
+            types,
+            vec![
+                ("id", &DataType::Int64),
+                ("ok", &DataType::Boolean),
+                ("user.name", &DataType::Utf8),
+                ("user.score", &DataType::Float64),
+            ]
+        );
+
+        let batches: Vec<RecordBatch> = reader.collect::<Result<_, _>>().unwrap();
+        assert_eq!(batches.len(), 1);
+        let names = batches[0].column(2).as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!(names.value(0), "Ann");
+        assert!(names.is_null(1));
+        assert!(batches[0].column(3).is_null(1));
+    }
+
+    #[test]
+    fn test_decimal_columns_keep_every_digit() {
+        let mut sink = IpcSink::new(Vec::new());
+        for (amount, huge) in [
+            ("12345678901234567890.125", "123456789012345678901234567890123456789.5"),
+            ("-0.5", "2"),
+            ("null", "3"),
+        ] {
+            let record = [("amount", amount), ("huge", huge)].map(|(k, v)| (k.to_string(), v.to_string()));
+            sink.write_record(record.into_iter().collect()).unwrap();
+        }
+        let reader = FileReader::try_new(Cursor::new(sink.finish().unwrap()), None).unwrap();
+        let schema = reader.schema();
+        assert_eq!(schema.field(0).data_type(), &DataType::Decimal128(38, 3));
+        assert_eq!(schema.field(1).data_type(), &DataType::Utf8);
+
+        let batches: Vec<RecordBatch> = reader.collect::<Result<_, _>>().unwrap();
+        let amounts = batches[0].column(0).as_any().downcast_ref::<Decimal128Array>().unwrap();
+        assert_eq!(amounts.value_as_string(0), "12345678901234567890.125");
+        assert_eq!(amounts.value_as_string(1), "-0.500");
+        assert!(amounts.is_null(2));
+        let huge = batches[0].column(1).as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!(huge.value(0), "123456789012345678901234567890123456789.5");
+    }
+
+    #[test]
+    fn test_schema_hint_replaces_inference() {
+        let schema: FlatSchema =
+            [("id".to_string(), ColumnType::Float), ("note".to_string(), ColumnType::Text)].into_iter().collect();
+        let mut sink = IpcSink::new(Vec::new()).with_schema(schema);
+        let record: FlattenedJson = [("id".to_string(), "3".to_string())].into_iter().collect();
+        sink.write_record(record).unwrap();
+
+        let reader = FileReader::try_new(Cursor::new(sink.finish().unwrap()), None).unwrap();
+        let schema = reader.schema();
+        let types: Vec<(&str, &DataType)> = schema.fields().iter().map(|f| (f.name().as_str(), f.data_type())).collect();
+        assert_eq!(types, vec![("id", &DataType::Float64), ("note", &DataType::Utf8)]);
+        let batches: Vec<RecordBatch> = reader.collect::<Result<_, _>>().unwrap();
+        assert!(batches[0].column(1).is_null(0));
+    }
+}
@@ -0,0 +1,477 @@
+// src/keys.rs
+use alloc::borrow::Cow;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use alloc::vec;
+use core::fmt;
+use unicode_normalization::{is_nfc_quick, IsNormalized, UnicodeNormalization};
+
+/// Character used to escape separator characters inside source keys
+pub const ESCAPE_CHAR: char = '\\';
+
+/// Problems with a configured key separator
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SeparatorError {
+    /// The separator is the empty string
+    Empty,
+    /// The separator contains the escape character, so escaped keys can't be split
+    ContainsEscapeChar,
+    /// A proper prefix of the separator equals a proper suffix (e.g. `"__"`),
+    /// so keys starting or ending with those characters produce ambiguous
+    /// paths unless `escape_keys` is enabled
+    SelfOverlapping(String),
+}
+
+impl fmt::Display for SeparatorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SeparatorError::Empty => write!(f, "separator must not be empty"),
+            SeparatorError::ContainsEscapeChar => {
+                write!(f, "separator must not contain the escape character '{}'", ESCAPE_CHAR)
+            }
+            SeparatorError::SelfOverlapping(sep) => write!(
+                f,
+                "separator {:?} overlaps itself, so keys that start or end with its characters \
+                 are ambiguous; enable escape_keys",
+                sep
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for SeparatorError {}
+
+/// Checks that a separator can produce unambiguous keys
+///
+/// With `escape_keys` every separator character inside source keys is
+/// escaped, which makes any non-empty separator without the escape character
+/// unambiguous. Without escaping, self-overlapping separators are rejected.
+pub fn validate_separator(separator: &str, escape_keys: bool) -> Result<(), SeparatorError> {
+    if separator.is_empty() {
+        return Err(SeparatorError::Empty);
+    }
+    if escape_keys {
+        if separator.contains(ESCAPE_CHAR) {
+            return Err(SeparatorError::ContainsEscapeChar);
+        }
+    } else if is_self_overlapping(separator) {
+        return Err(SeparatorError::SelfOverlapping(separator.to_string()));
+    }
+    Ok(())
+}
+
+/// Returns true if some proper prefix of `separator` is also a suffix of it
+fn is_self_overlapping(separator: &str) -> bool {
+    separator
+        .char_indices()
+        .skip(1)
+        .any(|(i, _)| separator.ends_with(&separator[..i]))
+}
+
+/// Escapes a source key so it can be joined with the separator unambiguously
+///
+/// Every character that occurs in the separator, and the escape character
+/// itself, is prefixed with the escape character.
+pub fn escape_key<'a>(key: &'a str, separator: &str) -> Cow<'a, str> {
+    let needs_escape = |c: char| c == ESCAPE_CHAR || separator.contains(c);
+    if !key.chars().any(needs_escape) {
+        return Cow::Borrowed(key);
+    }
+
+    let mut escaped = String::with_capacity(key.len() + 4);
+    for c in key.chars() {
+        if needs_escape(c) {
+            escaped.push(ESCAPE_CHAR);
+        }
+        escaped.push(c);
+    }
+    Cow::Owned(escaped)
+}
+
+/// Segment written in place of an empty source key under [`EmptyKeys::Placeholder`]
+pub const EMPTY_KEY_PLACEHOLDER: &str = "_empty_";
+
+/// Handling of object keys that are the empty string
+///
+/// An empty key leaves nothing between two separators (`a..b`), or a leading
+/// or trailing separator, which is easy to mistake for a separator inside a key.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EmptyKeys {
+    /// Write the empty segment as it is
+    #[default]
+    Keep,
+    /// Write [`EMPTY_KEY_PLACEHOLDER`] instead; with `escape_keys` a source
+    /// key that is literally the placeholder is escaped, so the two stay apart
+    Placeholder,
+    /// Fail the document
+    Error,
+}
+
+impl core::str::FromStr for EmptyKeys {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep" => Ok(EmptyKeys::Keep),
+            "placeholder" => Ok(EmptyKeys::Placeholder),
+            "error" => Ok(EmptyKeys::Error),
+            other => Err(format!("unknown empty key policy {:?} (expected keep, placeholder or error)", other)),
+        }
+    }
+}
+
+impl fmt::Display for EmptyKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            EmptyKeys::Keep => "keep",
+            EmptyKeys::Placeholder => "placeholder",
+            EmptyKeys::Error => "error",
+        })
+    }
+}
+
+/// Key added to records under [`AmbiguousKeys::Report`], holding a JSON array
+/// of the flattened keys whose source keys are ambiguous
+pub const AMBIGUOUS_KEYS_KEY: &str = "_ambiguous_keys";
+
+/// Why a source key can be mistaken for flattening notation
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyAmbiguity {
+    /// The key contains the separator and `escape_keys` is off, so `{"a.b": 1}`
+    /// and `{"a": {"b": 1}}` both flatten to `a.b`
+    ContainsSeparator,
+    /// The key is a number while array indices are in keys, so `{"0": 1}` and
+    /// `[1]` both flatten to `0`
+    NumericIndex,
+}
+
+impl fmt::Display for KeyAmbiguity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            KeyAmbiguity::ContainsSeparator => "contains the separator",
+            KeyAmbiguity::NumericIndex => "looks like an array index",
+        })
+    }
+}
+
+/// Handling of source keys that contain the separator or look like array indices
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AmbiguousKeys {
+    /// Flatten them like any other key
+    #[default]
+    Allow,
+    /// Flatten them and list their flattened keys under [`AMBIGUOUS_KEYS_KEY`]
+    Report,
+    /// Fail the document
+    Error,
+}
+
+impl core::str::FromStr for AmbiguousKeys {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allow" => Ok(AmbiguousKeys::Allow),
+            "report" => Ok(AmbiguousKeys::Report),
+            "error" => Ok(AmbiguousKeys::Error),
+            other => Err(format!("unknown ambiguous key policy {:?} (expected allow, report or error)", other)),
+        }
+    }
+}
+
+impl fmt::Display for AmbiguousKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AmbiguousKeys::Allow => "allow",
+            AmbiguousKeys::Report => "report",
+            AmbiguousKeys::Error => "error",
+        })
+    }
+}
+
+/// Handling of chains of objects that hold a single key, such as the levels
+/// of an envelope: `{"envelope": {"payload": {"user": {"id": 1, "name": "a"}}}}`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SingleKeyChains {
+    /// One segment per level: `envelope.payload.user.id`
+    #[default]
+    Keep,
+    /// Only the chain's first key: `envelope.id`
+    Head,
+    /// Only the chain's last key: `user.id`
+    Tail,
+}
+
+impl core::str::FromStr for SingleKeyChains {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep" => Ok(SingleKeyChains::Keep),
+            "head" => Ok(SingleKeyChains::Head),
+            "tail" => Ok(SingleKeyChains::Tail),
+            other => Err(format!("unknown single key chain policy {:?} (expected keep, head or tail)", other)),
+        }
+    }
+}
+
+impl fmt::Display for SingleKeyChains {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SingleKeyChains::Keep => "keep",
+            SingleKeyChains::Head => "head",
+            SingleKeyChains::Tail => "tail",
+        })
+    }
+}
+
+/// Returns why a source key would be ambiguous once flattened, if it would be
+///
+/// Escaping removes the separator ambiguity but not the numeric one, since
+/// digits are never escaped.
+pub fn key_ambiguity(key: &str, separator: &str, escape_keys: bool, array_indices: bool) -> Option<KeyAmbiguity> {
+    if !escape_keys && !separator.is_empty() && key.contains(separator) {
+        Some(KeyAmbiguity::ContainsSeparator)
+    } else if array_indices && !key.is_empty() && key.bytes().all(|b| b.is_ascii_digit()) {
+        Some(KeyAmbiguity::NumericIndex)
+    } else {
+        None
+    }
+}
+
+/// Returns the longest prefix of whole segments shared by all keys, ending
+/// with the separator, e.g. `data.attributes.` for `data.attributes.id` and
+/// `data.attributes.name`; empty when the keys share no segment
+///
+/// Detect it on a sample of records and pass it as `strip_prefix`, so the
+/// same prefix is removed from every document.
+pub fn common_key_prefix<'a>(keys: impl IntoIterator<Item = &'a str>, separator: &str) -> String {
+    let mut keys = keys.into_iter();
+    let Some(first) = keys.next() else {
+        return String::new();
+    };
+    let mut common = first;
+    for key in keys {
+        let shared = common.char_indices().zip(key.chars()).find(|((_, a), b)| a != b);
+        if let Some(((end, _), _)) = shared {
+            common = &common[..end];
+        } else if key.len() < common.len() {
+            common = key;
+        }
+    }
+    // A key equal to the prefix would leave nothing, so cut before its last segment
+    match common.rfind(separator) {
+        Some(end) if !separator.is_empty() => common[..end + separator.len()].to_string(),
+        _ => String::new(),
+    }
+}
+
+/// Returns the NFC (canonical composition) form of a string, borrowing when
+/// it is already normalized
+pub fn normalize_nfc(text: &str) -> Cow<'_, str> {
+    if is_nfc_quick(text.chars()) == IsNormalized::Yes {
+        Cow::Borrowed(text)
+    } else {
+        Cow::Owned(text.nfc().collect())
+    }
+}
+
+/// Splits a flattened key into its path segments
+///
+/// With `escaped` set, escape sequences are honoured and removed, reversing
+/// [`escape_key`]. Otherwise the key is split on every separator occurrence.
+pub fn split_key(key: &str, separator: &str, escaped: bool) -> Vec<String> {
+    split_segments(key, separator, escaped).into_iter().map(|(segment, _)| segment).collect()
+}
+
+/// Splits a flattened key into the source keys it was built from
+///
+/// Like [`split_key`], except that under [`EmptyKeys::Placeholder`] each
+/// [`EMPTY_KEY_PLACEHOLDER`] segment is turned back into an empty key. Only
+/// with `escaped` can a source key that was literally the placeholder be
+/// told apart from it.
+pub fn split_source_key(key: &str, separator: &str, escaped: bool, empty_keys: EmptyKeys) -> Vec<String> {
+    split_segments(key, separator, escaped)
+        .into_iter()
+        .map(|(segment, had_escapes)| match empty_keys {
+            EmptyKeys::Placeholder if !had_escapes && segment == EMPTY_KEY_PLACEHOLDER => String::new(),
+            _ => segment,
+        })
+        .collect()
+}
+
+/// Splits a key into segments, noting for each whether it held escapes
+fn split_segments(key: &str, separator: &str, escaped: bool) -> Vec<(String, bool)> {
+    if separator.is_empty() {
+        return vec![(key.to_string(), false)];
+    }
+    if !escaped {
+        return key.split(separator).map(|segment| (segment.to_string(), false)).collect();
+    }
+
+    let mut segments = Vec::new();
+    let mut current = String::new();
+    let mut had_escapes = false;
+    let mut rest = key;
+    while let Some(c) = rest.chars().next() {
+        if c == ESCAPE_CHAR {
+            had_escapes = true;
+            rest = &rest[c.len_utf8()..];
+            if let Some(next) = rest.chars().next() {
+                current.push(next);
+                rest = &rest[next.len_utf8()..];
+            }
+        } else if rest.starts_with(separator) {
+            segments.push((core::mem::take(&mut current), core::mem::take(&mut had_escapes)));
+            rest = &rest[separator.len()..];
+        } else {
+            current.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    segments.push((current, had_escapes));
+    segments
+}
+
+/// A glob over the segments of a flattened key
+///
+/// Patterns are written with `.` between segments (or a custom separator via
+/// [`KeyPattern::with_separator`]). A `*` segment matches exactly one segment,
+/// including array indices, and `**` matches any number of segments.
+/// For example `tags`, `items.*.tags` and `**.tags`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyPattern {
+    source: String,
+    segments: Vec<PatternSegment>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum PatternSegment {
+    Literal(String),
+    Any,
+    AnyDepth,
+}
+
+impl KeyPattern {
+    /// Parses a pattern whose segments are separated by `.`
+    pub fn new(pattern: &str) -> Self {
+        Self::with_separator(pattern, ".")
+    }
+
+    /// Parses a pattern whose segments are separated by `separator`;
+    /// separator characters inside a segment can be escaped with `\`
+    pub fn with_separator(pattern: &str, separator: &str) -> Self {
+        let segments = split_key(pattern, separator, true)
+            .into_iter()
+            .map(|segment| match segment.as_str() {
+                "*" => PatternSegment::Any,
+                "**" => PatternSegment::AnyDepth,
+                _ => PatternSegment::Literal(segment),
+            })
+            .collect();
+        KeyPattern {
+            source: pattern.to_string(),
+            segments,
+        }
+    }
+
+    /// The pattern as it was written
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    /// Returns true if the pattern matches a key given as its segments
+    pub fn matches<S: AsRef<str>>(&self, segments: &[S]) -> bool {
+        fn matches_from<S: AsRef<str>>(pattern: &[PatternSegment], segments: &[S]) -> bool {
+            match pattern.split_first() {
+                None => segments.is_empty(),
+                Some((PatternSegment::AnyDepth, rest)) => {
+                    (0..=segments.len()).any(|skip| matches_from(rest, &segments[skip..]))
+                }
+                Some((segment, rest)) => match segments.split_first() {
+                    Some((first, remaining)) => {
+                        let segment_matches = match segment {
+                            PatternSegment::Literal(literal) => literal == first.as_ref(),
+                            _ => true,
+                        };
+                        segment_matches && matches_from(rest, remaining)
+                    }
+                    None => false,
+                },
+            }
+        }
+        matches_from(&self.segments, segments)
+    }
+}
+
+impl fmt::Display for KeyPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_separator() {
+        assert_eq!(validate_separator("", false), Err(SeparatorError::Empty));
+        assert!(validate_separator(".", false).is_ok());
+        assert!(validate_separator("->", false).is_ok());
+        assert!(matches!(validate_separator("__", false), Err(SeparatorError::SelfOverlapping(_))));
+        assert!(matches!(validate_separator("::", false), Err(SeparatorError::SelfOverlapping(_))));
+        assert!(validate_separator("__", true).is_ok());
+        assert_eq!(validate_separator("\\", true), Err(SeparatorError::ContainsEscapeChar));
+    }
+
+    #[test]
+    fn test_escape_and_split_round_trip() {
+        for separator in [".", "__", "::", "-", "/"] {
+            let keys = ["a_", "_b", "x__y", "c::d", "e.f", "g-h", "back\\slash", "plain"];
+            let joined = keys
+                .iter()
+                .map(|key| escape_key(key, separator))
+                .collect::<Vec<_>>()
+                .join(separator);
+
+            assert_eq!(split_key(&joined, separator, true), keys, "separator {:?}", separator);
+        }
+    }
+
+    #[test]
+    fn test_normalize_nfc() {
+        assert!(matches!(normalize_nfc("plain"), Cow::Borrowed("plain")));
+        assert_eq!(normalize_nfc("cafe\u{301}"), "caf\u{e9}");
+    }
+
+    #[test]
+    fn test_key_pattern_globs() {
+        assert!(KeyPattern::new("tags").matches(&["tags"]));
+        assert!(!KeyPattern::new("tags").matches(&["user", "tags"]));
+        assert!(KeyPattern::new("items.*.tags").matches(&["items", "3", "tags"]));
+        assert!(KeyPattern::new("**.tags").matches(&["tags"]));
+        assert!(KeyPattern::new("**.tags").matches(&["a", "b", "tags"]));
+        assert!(!KeyPattern::new("**.tags").matches(&["tags", "a"]));
+        assert!(KeyPattern::with_separator("a__*", "__").matches(&["a", "b"]));
+    }
+
+    #[test]
+    fn test_placeholder_segments_split_back_to_empty_keys() {
+        let placeholder = EmptyKeys::Placeholder;
+        assert_eq!(split_source_key("a._empty_.b", ".", true, placeholder), ["a", "", "b"]);
+        assert_eq!(split_source_key("a.\\_empty_", ".", true, placeholder), ["a", "_empty_"]);
+        assert_eq!(split_source_key("a._empty_", ".", false, placeholder), ["a", ""]);
+        assert_eq!(split_source_key("a._empty_", ".", true, EmptyKeys::Keep), ["a", "_empty_"]);
+        assert_eq!("placeholder".parse::<EmptyKeys>(), Ok(placeholder));
+        assert!("drop".parse::<EmptyKeys>().unwrap_err().contains("keep, placeholder or error"));
+    }
+
+    #[test]
+    fn test_escape_leaves_clean_keys_borrowed() {
+        assert!(matches!(escape_key("name", "__"), Cow::Borrowed("name")));
+        assert_eq!(escape_key("first_name", "__"), "first\\_name");
+    }
+}
@@ -1,20 +1,265 @@
 // src/lib.rs
-use serde_json::{Value, Map};
-use std::collections::HashMap;
-use rayon::prelude::*;
-use std::sync::{Arc, Mutex};
-use std::io::{BufReader};
-use std::fs::File;
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 
-pub type FlattenedJson = HashMap<String, String>;
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::time::Duration;
+#[cfg(feature = "std")]
+use flatten::note_ambiguous_keys;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "std")]
+use serde_json::Value;
+
+#[cfg(feature = "std")]
+pub mod aggregate;
+#[cfg(feature = "archive")]
+pub mod archive;
+#[cfg(feature = "std")]
+pub mod batch;
+pub mod binary;
+pub mod canonical;
+#[cfg(feature = "std")]
+pub mod columnar;
+#[cfg(feature = "compression")]
+pub mod compress;
+#[cfg(feature = "std")]
+pub mod diff;
+pub mod duplicates;
+#[cfg(feature = "std")]
+pub mod engine;
+pub mod error;
+pub mod estimate;
+pub mod expr;
+pub mod flatten;
+pub mod geo;
+pub mod hash;
+pub mod input;
+#[cfg(feature = "ipc")]
+pub mod ipc;
+pub mod keys;
+#[cfg(feature = "std")]
+pub mod merge;
+pub mod nonfinite;
+pub mod options;
+mod par;
+#[cfg(feature = "parquet")]
+pub mod parquet_sink;
+#[cfg(feature = "std")]
+pub mod partition;
+#[cfg(feature = "std")]
+pub mod pivot;
+#[cfg(feature = "plan")]
+pub mod plan;
+#[cfg(feature = "std")]
+pub mod pool;
+#[cfg(feature = "postgres")]
+pub mod pg;
+#[cfg(feature = "std")]
+pub mod proto;
+#[cfg(feature = "std")]
+pub mod progress;
+#[cfg(feature = "python")]
+mod python;
+pub mod raw;
+#[cfg(feature = "std")]
+pub mod record;
+pub mod record_id;
+#[cfg(feature = "redis")]
+pub mod redis_sink;
+#[cfg(feature = "std")]
+pub mod schema;
+#[cfg(feature = "std")]
+pub mod retry;
+#[cfg(feature = "std")]
+pub mod rules;
+#[cfg(feature = "std")]
+pub mod run;
+pub mod safe_keys;
+#[cfg(feature = "std")]
+pub mod sink;
+#[cfg(feature = "std")]
+pub mod spill;
+#[cfg(feature = "std")]
+pub mod stats;
+#[cfg(feature = "validation")]
+pub mod validate;
+#[cfg(feature = "std")]
+mod stream;
+#[cfg(feature = "testing")]
+pub mod testing;
+#[cfg(feature = "std")]
+pub mod throttle;
+pub mod unflatten;
+pub mod value;
+pub mod visit;
+#[cfg(feature = "watch")]
+pub mod watch;
+#[cfg(feature = "xlsx")]
+pub mod xlsx;
+
+#[cfg(feature = "std")]
+pub use aggregate::{AggregateSink, Aggregation};
+#[cfg(feature = "archive")]
+pub use archive::{flatten_archive_to_sink, ArchiveFormat};
+#[cfg(feature = "std")]
+pub use batch::{flatten_json_file_batched, flatten_reader_batched, BatchResult, FlattenBatches};
+pub use binary::{looks_binary, BinaryPolicy};
+#[cfg(feature = "compression")]
+pub use compress::{open_compressed_output, CompressedWriter, OutputCompression};
+pub use canonical::{canonical_json, canonical_number};
+#[cfg(feature = "std")]
+pub use columnar::ColumnarTable;
+#[cfg(feature = "std")]
+pub use crossbeam_channel;
+#[cfg(feature = "std")]
+pub use engine::{FlattenOverrides, FlattenerEngine};
+#[cfg(feature = "std")]
+pub use diff::{diff_records, flatten_changes, json_patch_from_diff, FlatDiff, PatchOp};
+pub use duplicates::{parse_document, DuplicateKeys};
+pub use error::{FlattenError, ParseError, SkipReason, SkippedDocument};
+pub use estimate::{
+    estimate_flattened_size, leaf_count, structure_metrics, FlatEstimate, StructureMetrics, DEPTH_KEY, KEY_COUNT_KEY,
+    MAX_ARRAY_LEN_KEY, MAX_WIDTH_KEY,
+};
+pub use expr::{Datum, DerivedColumn, Expr};
+pub use flatten::{find_ambiguous_keys, flatten_into, FlatMap};
+pub use geo::{GeoJsonMode, Geometry};
+pub use hash::{FxBuildHasher, FxHasher};
+pub use par::ThreadPoolBuildError;
+#[cfg(feature = "std")]
+pub use pivot::pivot_array;
+#[cfg(feature = "std")]
+pub use progress::{Checkpoint, ChunkHook, ChunkProgress};
+#[cfg(feature = "postgres")]
+pub use pg::PostgresSink;
+pub use keys::{
+    common_key_prefix, escape_key, key_ambiguity, normalize_nfc, split_key, split_source_key, validate_separator,
+    AmbiguousKeys, EmptyKeys, KeyAmbiguity, KeyPattern, SeparatorError, SingleKeyChains, AMBIGUOUS_KEYS_KEY,
+    EMPTY_KEY_PLACEHOLDER,
+};
+pub use input::{
+    skip_leading_junk, InputEncoding, InputPosition, InvalidDocuments, InvalidUtf8, LeadingJunk, LeadingJunkError,
+    SizeLimitError, NDJSON_BLOCK_BYTES, RECORD_SEPARATOR,
+};
+#[cfg(feature = "std")]
+pub use merge::{merge_flattened, MergeConflict, MergeStrategy};
+pub use nonfinite::{scrub_non_finite, NonFiniteNumbers};
+#[cfg(feature = "std")]
+pub use unflatten::unflatten;
+pub use unflatten::{IndexKeys, SparseIndices, MAX_FILLED_NULLS};
+pub use options::{OptionsError, OptionsWarning, OPTIONS_VERSION};
+#[cfg(feature = "ipc")]
+pub use ipc::{flatten_to_ipc, IpcSink};
+#[cfg(feature = "parquet")]
+pub use parquet_sink::{flatten_to_parquet, ParquetSink};
+#[cfg(feature = "xlsx")]
+pub use xlsx::{flatten_to_xlsx, XlsxSink};
+#[cfg(feature = "std")]
+pub use input::{
+    decode_input, open_input, read_documents, read_located_documents, DocumentResult, DocumentStream, LocatedDocument,
+    LocatedDocumentStream, STDIN_PATH,
+};
+pub use raw::RawJson;
+#[cfg(feature = "std")]
+pub use record::FlattenedRecord;
+pub use record_id::{JsonPath, RecordIdSource};
+#[cfg(feature = "redis")]
+pub use redis_sink::{RedisLayout, RedisSink};
+#[cfg(feature = "std")]
+pub use partition::{partition_file_name, PartitionOverflow, PartitionedSink};
+#[cfg(feature = "plan")]
+pub use plan::{KeyAction, KeyPlan, PlanError, PlanRule, PlanSink, PlanStats};
+#[cfg(feature = "std")]
+pub use retry::{ResumableReader, RetryPolicy};
+#[cfg(feature = "std")]
+pub use rules::{KeyRules, RulesSink, SharedRules};
+#[cfg(feature = "std")]
+pub use run::{flatten_json_file_run, flatten_reader_run, FlattenRun, RecordError, RunStats};
+#[cfg(feature = "std")]
+pub use safe_keys::SafeKeySink;
+pub use safe_keys::SafeKeys;
+pub use value::{JsonKind, JsonLike};
+pub use visit::{walk, JsonVisitor};
+#[cfg(feature = "std")]
+pub use sink::{
+    open_output, CallbackSink, ChannelSink, CsvSink, EavSink, ExpectedSchemaSink, FieldEscape, NdjsonSink, RecordSink,
+    RowBinarySink, SchemaCheckSink, CSV_HEADER_ROWS,
+};
+#[cfg(feature = "std")]
+pub use stats::{index_keys, ColumnStatsSink, DistinctValues, QualityReport, ValueCounts, WideRecord};
+#[cfg(feature = "std")]
+pub use throttle::{record_payload_bytes, RateLimit, RateLimiter, ThrottledSink};
+#[cfg(feature = "validation")]
+pub use validate::{ValidatingSink, ValueRule};
+#[cfg(feature = "std")]
+pub use pool::{PoolStats, PooledRecord, PooledSink, StringPool};
+#[cfg(feature = "std")]
+pub use spill::{BudgetedSink, SpillSink, SpilledRecords};
+#[cfg(feature = "std")]
+pub use stream::{
+    flatten_json_file, flatten_json_file_pooled, flatten_json_file_spilled, flatten_json_file_timed,
+    flatten_json_file_to_sink, flatten_json_multi_str, flatten_json_streaming, flatten_json_streaming_resumable,
+    flatten_reader, flatten_reader_to_sink, flatten_reader_to_sink_timed, flatten_to_channel, flatten_values,
+    process_large_json_object, try_flatten_values, unflatten_file, unflatten_reader,
+};
+
+/// A flattened record
+///
+/// Only built with the `std` feature, so turning the feature on never changes
+/// a type a `no_std` build sees; without it, flatten into any [`FlatMap`]
+/// with [`flatten_into`], e.g. a `BTreeMap<String, String>`.
+#[cfg(feature = "std")]
+pub type FlattenedJson = std::collections::HashMap<String, String>;
+
+/// A record's pairs sorted by key, so output doesn't depend on hash order
+#[cfg(feature = "std")]
+pub fn to_sorted_vec(record: &FlattenedJson) -> Vec<(String, String)> {
+    let mut pairs: Vec<(String, String)> = record.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+    pairs.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+    pairs
+}
+
+/// Like [`to_sorted_vec`], without copying the pairs
+#[cfg(feature = "std")]
+pub fn into_sorted_vec(record: FlattenedJson) -> Vec<(String, String)> {
+    let mut pairs: Vec<(String, String)> = record.into_iter().collect();
+    pairs.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+    pairs
+}
+
+/// First character of metadata key segments, e.g. `items.#length`
+pub const ARRAY_METADATA_MARKER: char = '#';
 
 /// Options for controlling the flattening process
-#[derive(Clone, Debug)]
+///
+/// Build options from `FlattenOptions::default()`; fields are added in minor
+/// releases. Stored options are best written with
+/// [`to_json_value`](FlattenOptions::to_json_value) and read back with
+/// [`from_json_value`](FlattenOptions::from_json_value), which fills in new
+/// fields with their defaults and reports what it could not carry over.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+#[non_exhaustive]
 pub struct FlattenOptions {
-    /// Separator used in the flattened keys
+    /// Separator used in the flattened keys (may be several characters, e.g. `"__"`)
     pub separator: String,
+    /// Escape separator characters (and `\`) inside source keys with `\`
+    /// so flattened keys can always be split back unambiguously
+    pub escape_keys: bool,
     /// Maximum concurrency for parallel processing
+    /// Used for any stage whose own thread count is 0
     pub max_concurrency: usize,
+    /// Threads used to parse documents in the file/stream paths (0 uses `max_concurrency`)
+    pub parse_threads: usize,
+    /// Threads used to flatten parsed documents (0 uses `max_concurrency`)
+    pub flatten_threads: usize,
+    /// Build dedicated parse and flatten pools even when a stage's thread
+    /// count matches the global rayon pool, so concurrent callers never
+    /// share workers
+    pub local_pools: bool,
     /// Maximum nested depth to process (0 means no limit)
     pub max_depth: usize,
     /// Whether to include array indices in keys
@@ -23,253 +268,673 @@ pub struct FlattenOptions {
     pub expand_arrays: bool,
     /// Chunk size for processing large JSON files
     pub chunk_size: usize,
+    /// Byte budget per chunk; when non-zero, chunks are cut by serialized size
+    /// instead of `chunk_size` records (0 disables)
+    pub chunk_bytes: usize,
+    /// Soft limit on the estimated size of collected results (0 means no limit)
+    pub memory_budget: usize,
+    /// What `flatten_json_file_spilled` does past `memory_budget`; the
+    /// functions returning a `Vec` of records always fail there
+    #[serde(with = "options::as_str")]
+    pub over_budget: OverBudget,
+    /// Largest input, in bytes, the file, reader and streaming paths read
+    /// before failing with a [`SizeLimitError`] (0 means no limit); files are
+    /// checked before they are opened
+    pub max_input_bytes: usize,
+    /// Largest single document, in bytes, before failing with a
+    /// [`SizeLimitError`] instead of holding it in memory (0 means no limit);
+    /// checked while documents are split from the input, and against the
+    /// whole input in [`process_large_json_object`]
+    pub max_document_bytes: usize,
+    /// Time the file and reader pipelines may run before they stop cleanly
+    /// after the chunk in progress, flushing the sink; the `_timed` entry
+    /// points return a [`Checkpoint`] to carry on from (`None` means no limit)
+    pub time_budget: Option<Duration>,
+    /// Documents at the start of the input to skip without flattening, to
+    /// carry on from a [`Checkpoint`]; sequence record ids still count them
+    pub skip_documents: u64,
+    /// Treat the elements of a top-level array as separate records in the
+    /// file and reader paths, instead of flattening the array as one record;
+    /// [`flatten_json_multi_str`] always splits
+    pub split_root_array: bool,
+    /// Read the input as NDJSON, one document per non-blank line. The reader
+    /// thread only finds the last newline of each block of `chunk_bytes`
+    /// bytes ([`NDJSON_BLOCK_BYTES`] when 0); the lines are split, parsed and
+    /// flattened on the worker threads, so such blocks are the chunks of the
+    /// pipelined paths instead of `chunk_size` records
+    pub ndjson: bool,
+    /// Produce byte-identical output for identical input: canonical number
+    /// formatting, sorted keys in stringified subtrees and in sink output
+    pub canonical: bool,
+    /// Write leaves as their exact source text: numbers as written (`1.50`,
+    /// `1E+3`), strings with their escapes, and subtrees stored as strings
+    /// byte for byte. Takes precedence over `canonical` and `normalize_values`
+    /// for leaves. Documents that arrive already parsed are serialized first,
+    /// and `duplicate_keys` is not checked
+    pub raw_values: bool,
+    /// Emit `<array>.#length` metadata for every array, including empty ones
+    pub array_metadata: bool,
+    /// Maximum number of elements expanded per array (`None` means no limit)
+    ///
+    /// Arrays over the limit always get a `#length` entry with their true length.
+    pub max_array_elements: Option<usize>,
+    /// What to do with arrays longer than `max_array_elements`
+    #[serde(with = "options::as_str")]
+    pub array_limit_policy: ArrayLimitPolicy,
+    /// Most keys a single record may flatten to (0 means no limit)
+    pub max_record_keys: usize,
+    /// Longest a single record may take to flatten (`None` means no limit);
+    /// checked between object members and array elements, with the `std` feature
+    pub record_time_limit: Option<Duration>,
+    /// What to do with a record over `max_record_keys` or `record_time_limit`
+    #[serde(with = "options::as_str")]
+    pub oversized_records: OversizedRecords,
+    /// What to do with documents that are scalars or arrays, e.g. NDJSON
+    /// heartbeat lines holding a bare string
+    #[serde(with = "options::as_str")]
+    pub non_object_records: NonObjectRecords,
+    /// Emit `min`/`max`/`mean`/`count` entries for arrays of numbers
+    #[serde(with = "options::as_str")]
+    pub array_aggregates: ArrayAggregates,
+    /// Per-path array handling; the first matching pattern wins and arrays
+    /// matching none use the global mode from `expand_arrays`
+    #[serde(with = "options::as_str_vec")]
+    pub array_modes: Vec<ArrayPathMode>,
+    /// Identifier attached to every flattened record under `record_id_key`
+    #[serde(with = "options::as_str")]
+    pub record_id: RecordIdSource,
+    /// Key that holds the record id (default `_id`)
+    pub record_id_key: String,
+    /// Write GeoJSON geometry objects as single values instead of expanding
+    /// their coordinate arrays
+    #[serde(with = "options::as_str")]
+    pub geojson: GeoJsonMode,
+    /// What to do with base64 or binary-looking string values of at least
+    /// `binary_min_length` bytes
+    #[serde(with = "options::as_str")]
+    pub binary_values: BinaryPolicy,
+    /// Minimum length in bytes for a string to be treated as binary
+    pub binary_min_length: usize,
+    /// NFC-normalize object keys, so keys differing only in normalization
+    /// form map to the same column
+    pub normalize_keys: bool,
+    /// NFC-normalize string values
+    pub normalize_values: bool,
+    /// Handling of invalid UTF-8 in reader and file input
+    #[serde(with = "options::as_str")]
+    pub invalid_utf8: InvalidUtf8,
+    /// Handling of documents in reader and file input that are not valid JSON
+    #[serde(with = "options::as_str")]
+    pub invalid_documents: InvalidDocuments,
+    /// Character encoding of reader and file input
+    #[serde(with = "options::as_str")]
+    pub encoding: InputEncoding,
+    /// Handling of byte order marks and non-JSON whitespace before the first
+    /// document of reader, file and string input
+    #[serde(with = "options::as_str")]
+    pub leading_junk: LeadingJunk,
+    /// Handling of `NaN`, `Infinity` and `-Infinity` tokens in the input
+    #[serde(with = "options::as_str")]
+    pub non_finite: NonFiniteNumbers,
+    /// Handling of keys repeated within a source object
+    #[serde(with = "options::as_str")]
+    pub duplicate_keys: DuplicateKeys,
+    /// Handling of object keys that are the empty string
+    #[serde(with = "options::as_str")]
+    pub empty_keys: EmptyKeys,
+    /// Handling of object keys that contain the separator or look like array
+    /// indices, which flatten to the same keys as nested objects or arrays
+    #[serde(with = "options::as_str")]
+    pub ambiguous_keys: AmbiguousKeys,
+    /// Write chains of objects holding a single key, like envelope levels,
+    /// under one segment instead of one per level
+    #[serde(with = "options::as_str")]
+    pub single_key_chains: SingleKeyChains,
+    /// Flattened key prefix removed from every key that starts with it, e.g.
+    /// `data.attributes.` for an envelope; see [`common_key_prefix`]
+    pub strip_prefix: Option<String>,
+    /// Whether [`unflatten`] rebuilds objects keyed by `0`, `1`, ... as arrays
+    #[serde(with = "options::as_str")]
+    pub index_keys: IndexKeys,
+    /// How [`unflatten`] rebuilds index keys that skip numbers
+    #[serde(with = "options::as_str")]
+    pub sparse_indices: SparseIndices,
+    /// Add the document's [`StructureMetrics`] to each record under
+    /// [`DEPTH_KEY`], [`MAX_WIDTH_KEY`], [`MAX_ARRAY_LEN_KEY`] and [`KEY_COUNT_KEY`]
+    pub structure_metrics: bool,
+    /// Keys computed from each record's values as it is flattened, in order,
+    /// e.g. `price_usd = price_cents / 100`
+    #[serde(with = "options::as_str_vec")]
+    pub derived_columns: Vec<DerivedColumn>,
+    /// Keep only the records for which every expression is true, e.g.
+    /// `event.type == "purchase"`, checked on the worker threads after
+    /// `derived_columns` in the file, reader and multi-document paths.
+    /// Records of skipped documents are always kept
+    #[serde(with = "options::as_str_vec")]
+    pub filters: Vec<Expr>,
+    /// Reserve room for every leaf of a document before flattening it, so
+    /// records are not rehashed while they grow
+    pub presize_records: bool,
+    /// Called after each chunk of records reaches the sink in the file and
+    /// reader pipelines
+    #[cfg(feature = "std")]
+    #[serde(skip)]
+    pub chunk_hook: Option<ChunkHook>,
+}
+
+/// How an array is written to the flattened record
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ArrayMode {
+    /// One entry per element
+    Expand,
+    /// The whole array as a JSON string
+    Stringify,
+    /// Elements joined with a delimiter; nested values are written as JSON
+    Join(String),
+}
+
+impl core::str::FromStr for ArrayMode {
+    type Err = String;
+
+    /// Parses `expand`, `stringify`, `join` (with `,`) or `join:<delimiter>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "expand" => Ok(ArrayMode::Expand),
+            "stringify" => Ok(ArrayMode::Stringify),
+            "join" => Ok(ArrayMode::Join(",".to_string())),
+            _ => match s.strip_prefix("join:") {
+                Some(delimiter) => Ok(ArrayMode::Join(delimiter.to_string())),
+                None => Err(format!(
+                    "unknown array mode {:?} (expected expand, stringify, join or join:<delimiter>)",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+impl core::fmt::Display for ArrayMode {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ArrayMode::Expand => f.write_str("expand"),
+            ArrayMode::Stringify => f.write_str("stringify"),
+            ArrayMode::Join(delimiter) => write!(f, "join:{}", delimiter),
+        }
+    }
+}
+
+/// An array mode that applies to the arrays whose path matches a pattern
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ArrayPathMode {
+    pub pattern: KeyPattern,
+    pub mode: ArrayMode,
+}
+
+impl ArrayPathMode {
+    pub fn new(pattern: &str, mode: ArrayMode) -> Self {
+        ArrayPathMode {
+            pattern: KeyPattern::new(pattern),
+            mode,
+        }
+    }
+}
+
+impl core::fmt::Display for ArrayPathMode {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}={}", self.pattern, self.mode)
+    }
+}
+
+impl core::str::FromStr for ArrayPathMode {
+    type Err = String;
+
+    /// Parses `<pattern>=<mode>`, e.g. `tags=join:|` or `**.events=expand`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (pattern, mode) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected <pattern>=<mode>, got {:?}", s))?;
+        Ok(ArrayPathMode::new(pattern, mode.parse()?))
+    }
+}
+
+/// Handling of arrays longer than [`FlattenOptions::max_array_elements`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ArrayLimitPolicy {
+    /// Expand only the first `max_array_elements` elements
+    #[default]
+    Truncate,
+    /// Expand the first and last elements, half of the limit each; indices are kept
+    HeadTail,
+    /// Fail with [`FlattenError::ArrayTooLong`]
+    Error,
+}
+
+/// Handling of records over [`FlattenOptions::max_record_keys`] or
+/// [`FlattenOptions::record_time_limit`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OversizedRecords {
+    /// Fail with [`FlattenError::TooManyKeys`] or [`FlattenError::RecordTimedOut`]
+    #[default]
+    Error,
+    /// Leave the document out of an input's records and report it as a
+    /// [`SkippedDocument`], so one document can't stall or fail the run;
+    /// flattening a single document still fails
+    Skip,
+}
+
+impl core::str::FromStr for OversizedRecords {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(OversizedRecords::Error),
+            "skip" => Ok(OversizedRecords::Skip),
+            other => Err(format!("unknown oversized record policy {:?} (expected error or skip)", other)),
+        }
+    }
+}
+
+impl core::fmt::Display for OversizedRecords {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            OversizedRecords::Error => "error",
+            OversizedRecords::Skip => "skip",
+        })
+    }
+}
+
+/// Handling of results past [`FlattenOptions::memory_budget`] in
+/// `flatten_json_file_spilled`
+///
+/// The functions returning a `Vec` of records fail with an `OutOfMemory` error
+/// past the budget whatever this says, since records spilled to disk would
+/// only have to be read back into memory.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OverBudget {
+    /// Move the records to a temp file and hand them out from there
+    #[default]
+    Spill,
+    /// Fail with an `OutOfMemory` error, like the functions returning a `Vec`
+    Error,
+}
+
+impl core::str::FromStr for OverBudget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "spill" => Ok(OverBudget::Spill),
+            "error" => Ok(OverBudget::Error),
+            other => Err(format!("unknown over budget policy {:?} (expected spill or error)", other)),
+        }
+    }
+}
+
+impl core::fmt::Display for OverBudget {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            OverBudget::Spill => "spill",
+            OverBudget::Error => "error",
+        })
+    }
+}
+
+/// Key [`NonObjectRecords::Wrap`] writes a document under by default
+pub const WRAP_KEY: &str = "value";
+
+/// Handling of documents that are scalars or arrays rather than objects
+///
+/// Documents here are what records are made from: NDJSON lines, concatenated
+/// documents and the elements of a root array split by `split_root_array`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum NonObjectRecords {
+    /// Flatten them as they are: a scalar gives a record without leaves, and
+    /// an array keys starting with the separator, like `.0`
+    #[default]
+    Keep,
+    /// Flatten them as the value of this key, e.g. `value` or `value.0`
+    Wrap(String),
+    /// Leave the document out and report it, as for [`OversizedRecords::Skip`]
+    Skip,
+    /// Fail with [`FlattenError::NotAnObject`]
+    Error,
+}
+
+impl core::str::FromStr for NonObjectRecords {
+    type Err = String;
+
+    /// Parses `keep`, `wrap` (under [`WRAP_KEY`]), `wrap:KEY`, `skip` or `error`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep" => Ok(NonObjectRecords::Keep),
+            "wrap" => Ok(NonObjectRecords::Wrap(WRAP_KEY.to_string())),
+            "skip" => Ok(NonObjectRecords::Skip),
+            "error" => Ok(NonObjectRecords::Error),
+            _ => match s.strip_prefix("wrap:") {
+                Some(key) if !key.is_empty() => Ok(NonObjectRecords::Wrap(key.to_string())),
+                _ => Err(format!("unknown non-object record policy {:?} (expected keep, wrap[:KEY], skip or error)", s)),
+            },
+        }
+    }
+}
+
+impl core::fmt::Display for NonObjectRecords {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            NonObjectRecords::Keep => f.write_str("keep"),
+            NonObjectRecords::Wrap(key) if key == WRAP_KEY => f.write_str("wrap"),
+            NonObjectRecords::Wrap(key) => write!(f, "wrap:{}", key),
+            NonObjectRecords::Skip => f.write_str("skip"),
+            NonObjectRecords::Error => f.write_str("error"),
+        }
+    }
+}
+
+/// Aggregates for arrays whose elements are all numbers
+///
+/// Aggregates cover every element, even when `max_array_elements` limits the
+/// expansion. Empty arrays get only a `count` of 0.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ArrayAggregates {
+    /// Expand numeric arrays like any other array
+    #[default]
+    Off,
+    /// Emit `scores.min`, `scores.max`, `scores.mean` and `scores.count` instead of the elements
+    Replace,
+    /// Emit the aggregates in addition to the expanded elements
+    Alongside,
+}
+
+impl core::str::FromStr for ArrayAggregates {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(ArrayAggregates::Off),
+            "replace" => Ok(ArrayAggregates::Replace),
+            "alongside" => Ok(ArrayAggregates::Alongside),
+            other => Err(format!(
+                "unknown array aggregates mode {:?} (expected off, replace or alongside)",
+                other
+            )),
+        }
+    }
+}
+
+impl core::fmt::Display for ArrayAggregates {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            ArrayAggregates::Off => "off",
+            ArrayAggregates::Replace => "replace",
+            ArrayAggregates::Alongside => "alongside",
+        })
+    }
+}
+
+impl core::str::FromStr for ArrayLimitPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "truncate" => Ok(ArrayLimitPolicy::Truncate),
+            "head_tail" | "head-tail" => Ok(ArrayLimitPolicy::HeadTail),
+            "error" => Ok(ArrayLimitPolicy::Error),
+            other => Err(format!(
+                "unknown array limit policy {:?} (expected truncate, head_tail or error)",
+                other
+            )),
+        }
+    }
+}
+
+impl core::fmt::Display for ArrayLimitPolicy {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            ArrayLimitPolicy::Truncate => "truncate",
+            ArrayLimitPolicy::HeadTail => "head_tail",
+            ArrayLimitPolicy::Error => "error",
+        })
+    }
 }
 
 impl Default for FlattenOptions {
     fn default() -> Self {
         FlattenOptions {
             separator: ".".to_string(),
-            max_concurrency: num_cpus::get(),
+            escape_keys: false,
+            max_concurrency: par::available_threads(),
+            parse_threads: 0,
+            flatten_threads: 0,
+            local_pools: false,
             max_depth: 0,
             include_array_indices: true,
             expand_arrays: true,
             chunk_size: 10000,
+            chunk_bytes: 0,
+            memory_budget: 0,
+            over_budget: OverBudget::Spill,
+            max_input_bytes: 0,
+            max_document_bytes: 0,
+            time_budget: None,
+            skip_documents: 0,
+            split_root_array: false,
+            ndjson: false,
+            canonical: false,
+            array_metadata: false,
+            max_array_elements: None,
+            array_limit_policy: ArrayLimitPolicy::Truncate,
+            max_record_keys: 0,
+            record_time_limit: None,
+            oversized_records: OversizedRecords::Error,
+            non_object_records: NonObjectRecords::Keep,
+            array_aggregates: ArrayAggregates::Off,
+            array_modes: Vec::new(),
+            record_id: RecordIdSource::None,
+            record_id_key: "_id".to_string(),
+            geojson: GeoJsonMode::Off,
+            binary_values: BinaryPolicy::Keep,
+            binary_min_length: 1024,
+            normalize_keys: false,
+            normalize_values: false,
+            raw_values: false,
+            invalid_utf8: InvalidUtf8::Error,
+            invalid_documents: InvalidDocuments::Error,
+            encoding: InputEncoding::Utf8,
+            leading_junk: LeadingJunk::Skip,
+            non_finite: NonFiniteNumbers::Error,
+            duplicate_keys: DuplicateKeys::Last,
+            empty_keys: EmptyKeys::Keep,
+            ambiguous_keys: AmbiguousKeys::Allow,
+            single_key_chains: SingleKeyChains::Keep,
+            strip_prefix: None,
+            index_keys: IndexKeys::Arrays,
+            sparse_indices: SparseIndices::Null,
+            structure_metrics: false,
+            derived_columns: Vec::new(),
+            filters: Vec::new(),
+            presize_records: true,
+            #[cfg(feature = "std")]
+            chunk_hook: None,
         }
     }
 }
 
-/// Flattens a JSON value into a HashMap with dot-notation keys
-pub fn flatten_json(value: &Value, options: &FlattenOptions) -> FlattenedJson {
-    let mut result = HashMap::new();
-    flatten_value("", value, &mut result, options, 0);
-    result
-}
+impl FlattenOptions {
+    /// Number of threads used for the parse stage
+    pub fn parse_thread_count(&self) -> usize {
+        Self::resolve_threads(self.parse_threads, self.max_concurrency)
+    }
 
-/// Flattens a JSON value recursively
-fn flatten_value(
-    prefix: &str,
-    value: &Value,
-    result: &mut FlattenedJson,
-    options: &FlattenOptions,
-    depth: usize,
-) {
-    // Check if we've exceeded the maximum depth
-    if options.max_depth > 0 && depth >= options.max_depth {
-        // Store the whole subtree as a JSON string
-        result.insert(prefix.to_string(), value.to_string());
-        return;
-    }
-
-    match value {
-        Value::Object(map) => {
-            flatten_object(prefix, map, result, options, depth);
-        }
-        Value::Array(array) => {
-            flatten_array(prefix, array, result, options, depth);
-        }
-        Value::String(s) => {
-            if !prefix.is_empty() {
-                result.insert(prefix.to_string(), s.clone());
-            }
-        }
-        Value::Number(n) => {
-            if !prefix.is_empty() {
-                result.insert(prefix.to_string(), n.to_string());
+    /// Number of threads used for the flatten stage
+    pub fn flatten_thread_count(&self) -> usize {
+        Self::resolve_threads(self.flatten_threads, self.max_concurrency)
+    }
+
+    /// Array mode for the array at a flattened key
+    pub fn array_mode(&self, path: &str) -> ArrayMode {
+        if !self.array_modes.is_empty() {
+            let segments = if path.is_empty() {
+                Vec::new()
+            } else {
+                split_key(path, &self.separator, self.escape_keys)
+            };
+            if let Some(path_mode) = self.array_modes.iter().find(|m| m.pattern.matches(&segments)) {
+                return path_mode.mode.clone();
             }
         }
-        Value::Bool(b) => {
-            if !prefix.is_empty() {
-                result.insert(prefix.to_string(), b.to_string());
-            }
+
+        if self.expand_arrays {
+            ArrayMode::Expand
+        } else {
+            ArrayMode::Stringify
         }
-        Value::Null => {
-            if !prefix.is_empty() {
-                result.insert(prefix.to_string(), "null".to_string());
-            }
+    }
+
+    fn resolve_threads(threads: usize, fallback: usize) -> usize {
+        match (threads, fallback) {
+            (0, 0) => par::available_threads(),
+            (0, fallback) => fallback,
+            (threads, _) => threads,
         }
     }
 }
 
-/// Flattens a JSON object
-fn flatten_object(
-    prefix: &str,
-    obj: &Map<String, Value>,
-    result: &mut FlattenedJson,
-    options: &FlattenOptions,
-    depth: usize,
-) {
-    for (key, value) in obj {
-        let new_prefix = if prefix.is_empty() {
-            key.clone()
-        } else {
-            format!("{}{}{}", prefix, options.separator, key)
-        };
-        flatten_value(&new_prefix, value, result, options, depth + 1);
-    }
+/// Flattens a JSON value into a HashMap with dot-notation keys
+///
+/// Never panics with the default policies. Only options checked by
+/// [`FlattenOptions::validate`] that affect a single document are checked, so
+/// settings such as `chunk_size` or `max_concurrency` are ignored here.
+///
+/// # Panics
+///
+/// Panics wherever [`try_flatten_json`] returns an error, which takes one of
+/// the opt-in error policies or limits:
+/// - `max_array_elements` or `oversized_records` set without what they need
+/// - an array exceeds `max_array_elements` under [`ArrayLimitPolicy::Error`]
+/// - an object holds an empty key under [`EmptyKeys::Error`], or a key
+///   mistakable for flattening notation under [`AmbiguousKeys::Error`]
+/// - the record passes `max_record_keys` or `record_time_limit` under
+///   [`OversizedRecords::Error`]
+/// - the document is a scalar or an array under [`NonObjectRecords::Error`]
+/// - the `record_id` path is missing or null, or a binary value's side file
+///   can't be written
+#[cfg(feature = "std")]
+pub fn flatten_json(value: &Value, options: &FlattenOptions) -> FlattenedJson {
+    try_flatten_json(value, options).unwrap_or_else(|e| panic!("{}", e))
 }
 
-/// Flattens a JSON array
-fn flatten_array(
-    prefix: &str,
-    array: &[Value],
-    result: &mut FlattenedJson,
+/// Flattens a JSON value, returning an error instead of panicking when a
+/// limit configured with an error policy is exceeded
+#[cfg(feature = "std")]
+pub fn try_flatten_json(value: &Value, options: &FlattenOptions) -> Result<FlattenedJson, FlattenError> {
+    options.validate_document()?;
+    flatten_document(value, options, 0)
+}
+
+/// Flattens the document at position `sequence` of an input and attaches its record id
+#[cfg(feature = "std")]
+pub(crate) fn flatten_document<J: JsonLike + ?Sized>(
+    value: &J,
     options: &FlattenOptions,
-    depth: usize,
-) {
-    if options.expand_arrays {
-        for (i, value) in array.iter().enumerate() {
-            let new_prefix = if options.include_array_indices {
-                format!("{}{}{}", prefix, options.separator, i)
-            } else {
-                prefix.to_string()
-            };
-            flatten_value(&new_prefix, value, result, options, depth + 1);
-        }
+    sequence: u64,
+) -> Result<FlattenedJson, FlattenError> {
+    let kind = value.kind();
+    let prefix = match &options.non_object_records {
+        _ if kind == JsonKind::Object => "",
+        NonObjectRecords::Keep => "",
+        NonObjectRecords::Wrap(key) => key,
+        NonObjectRecords::Skip | NonObjectRecords::Error => return Err(FlattenError::NotAnObject(kind)),
+    };
+    let mut result = new_record(value, options);
+    let flattened = if options.max_record_keys > 0 || options.record_time_limit.is_some() {
+        let mut limited = flatten::RecordLimits::new(&mut result, options);
+        flatten::flatten_subtree(prefix, value, &mut limited, options, 0).and_then(|()| limited.finish())
     } else {
-        // Store the array as a JSON string
-        result.insert(prefix.to_string(), serde_json::to_string(array).unwrap_or_default());
+        flatten::flatten_subtree(prefix, value, &mut result, options, 0)
+    };
+    flattened?;
+    if let Some(id) = options.record_id.record_id(value, sequence)? {
+        result.insert(options.record_id_key.clone(), id);
+    }
+    if options.structure_metrics {
+        structure_metrics(value).insert_into(&mut result);
     }
+    note_ambiguous_keys(value, options, &mut result);
+    for column in &options.derived_columns {
+        column.derive(&mut result);
+    }
+    Ok(result)
 }
 
-/// Flattens a JSON file in a streaming fashion
-/// This is optimized for memory usage with very large files
-pub fn flatten_json_file(
-    filepath: &str,
-    options: &FlattenOptions,
-) -> Result<Vec<FlattenedJson>, Box<dyn std::error::Error>> {
-    let file = File::open(filepath)?;
-    let reader = BufReader::new(file);
-    
-    // Use a streaming JSON parser for memory efficiency
-    let stream = serde_json::Deserializer::from_reader(reader).into_iter::<Value>();
-    
-    // For array-root JSONs, process elements individually
-    let results = Arc::new(Mutex::new(Vec::new()));
-    let chunk_size = options.chunk_size;
-    
-    // Process the stream in chunks to limit memory usage
-    let mut chunk = Vec::with_capacity(chunk_size);
-    
-    for item in stream {
-        match item {
-            Ok(value) => {
-                chunk.push(value);
-                
-                if chunk.len() >= chunk_size {
-                    process_chunk(&chunk, &results, options);
-                    chunk.clear();
-                }
-            }
-            Err(e) => {
-                return Err(Box::new(e));
+/// What became of one document of an input
+#[cfg(feature = "std")]
+pub(crate) enum Outcome {
+    Record(FlattenedJson),
+    /// Left out by `filters`
+    Filtered,
+    Skipped(SkippedDocument),
+}
+
+#[cfg(feature = "std")]
+impl Outcome {
+    /// Turns a record that fails one of `filters` into [`Outcome::Filtered`]
+    pub(crate) fn filter(self, options: &FlattenOptions) -> Outcome {
+        match self {
+            Outcome::Record(record) if !options.filters.iter().all(|filter| filter.matches(&record)) => {
+                Outcome::Filtered
             }
+            outcome => outcome,
         }
     }
-    
-    // Process any remaining items
-    if !chunk.is_empty() {
-        process_chunk(&chunk, &results, options);
-    }
-    
-    // Return the accumulated results
-    let results = Arc::try_unwrap(results)
-        .expect("There should be no more references to the results")
-        .into_inner()?;
-    
-    Ok(results)
 }
 
-/// Process a chunk of JSON values in parallel
-fn process_chunk(
-    chunk: &[Value],
-    results: &Arc<Mutex<Vec<FlattenedJson>>>,
-    options: &FlattenOptions,
-) {
-    // Use Rayon for parallel processing
-    let parallel_results: Vec<FlattenedJson> = chunk
-        .par_iter()
-        .map(|value| flatten_json(value, options))
-        .collect();
-    
-    // Add the results to the shared collection
-    let mut results_guard = results.lock().unwrap();
-    results_guard.extend(parallel_results);
-}
-
-/// Processes a single large JSON object by iterating through its top-level keys
-/// This is useful for very large objects that might not fit in memory
-// Process a large JSON object by iterating through its top-level keys
-pub fn process_large_json_object(
-    filepath: &str,
+/// Flattens the document at position `sequence` of an input, setting it
+/// aside when a `Skip` policy covers its error
+#[cfg(feature = "std")]
+pub(crate) fn flatten_input_document<J: JsonLike + ?Sized>(
+    value: &J,
     options: &FlattenOptions,
-) -> Result<FlattenedJson, Box<dyn std::error::Error>> {
-    let file = File::open(filepath)?;
-    let reader = BufReader::new(file);
-    
-    // Parse the outer structure of the JSON to get top-level keys
-    let json: Value = serde_json::from_reader(reader)?;
-    
-    if let Value::Object(map) = json {
-        // Process each top-level key in parallel
-        let flattened = Arc::new(Mutex::new(HashMap::new()));
-        
-        // Convert map entries to a Vec which can be processed in parallel
-        let entries: Vec<_> = map.into_iter().collect();
-        
-        // Now we can use par_iter on the Vec
-        entries.par_iter().for_each(|(key, value)| {
-            let mut partial_result = HashMap::new();
-            flatten_value(key, value, &mut partial_result, options, 0);
-            
-            // Merge the partial results
-            let mut flattened_guard = flattened.lock().unwrap();
-            flattened_guard.extend(partial_result);
-        });
-        
-        let result = Arc::try_unwrap(flattened)
-            .expect("There should be no more references to the flattened map")
-            .into_inner()?;
-        
-        Ok(result)
-    } else {
-        // If the top-level is not an object, just flatten it directly
-        Ok(flatten_json(&json, options))
+    sequence: u64,
+) -> Result<Outcome, FlattenError> {
+    match flatten_document(value, options, sequence) {
+        Ok(record) => Ok(Outcome::Record(record)),
+        Err(e) if skips(&e, options) => Ok(Outcome::Skipped(SkippedDocument {
+            document: sequence,
+            record_id: options.record_id.record_id(value, sequence).ok().flatten(),
+            reason: SkipReason::Flatten(e),
+        })),
+        Err(e) => Err(e),
     }
 }
 
-/// A more memory efficient version for extremely large files
-/// This uses a streaming approach and processes the JSON file line by line
-pub fn flatten_json_streaming(
-    filepath: &str,
-    callback: impl Fn(FlattenedJson) + Send + Sync,
-    options: &FlattenOptions,
-) -> Result<(), Box<dyn std::error::Error>> {
-    use std::io::{BufRead};
-    
-    let file = File::open(filepath)?;
-    let reader = BufReader::new(file);
-    
-    // Process the file line by line
-    for line in reader.lines() {
-        let line = line?;
-        if line.trim().is_empty() {
-            continue;
+/// Whether a `Skip` policy covers a flattening error
+#[cfg(feature = "std")]
+fn skips(error: &FlattenError, options: &FlattenOptions) -> bool {
+    match error {
+        FlattenError::TooManyKeys { .. } | FlattenError::RecordTimedOut { .. } => {
+            options.oversized_records == OversizedRecords::Skip
         }
-        
-        // Parse the JSON line
-        let json: Value = serde_json::from_str(&line)?;
-        
-        // Flatten the JSON
-        let flattened = flatten_json(&json, options);
-        
-        // Call the callback with the flattened JSON
-        callback(flattened);
+        FlattenError::NotAnObject(_) => options.non_object_records == NonObjectRecords::Skip,
+        _ => false,
+    }
+}
+
+/// Creates the record for a document, pre-sized for its leaves and record id
+/// (at most `max_record_keys` of them)
+#[cfg(feature = "std")]
+fn new_record<J: JsonLike + ?Sized>(value: &J, options: &FlattenOptions) -> FlattenedJson {
+    if options.presize_records {
+        let leaves = match options.max_record_keys {
+            0 => leaf_count(value),
+            limit => leaf_count(value).min(limit),
+        };
+        FlattenedJson::with_capacity(leaves + 1)
+    } else {
+        FlattenedJson::default()
     }
-    
-    Ok(())
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "std"))]
 mod tests {
     use super::*;
     use serde_json::json;
@@ -294,6 +959,16 @@ mod tests {
         assert_eq!(flattened.get("address.city"), Some(&"New York".to_string()));
     }
 
+    #[test]
+    fn test_records_are_std_hash_maps_and_fx_maps_are_opt_in() {
+        let json = json!({"a": {"b": 1}, "c": [true]});
+        let record: std::collections::HashMap<String, String> = flatten_json(&json, &FlattenOptions::default());
+
+        let mut fx: std::collections::HashMap<String, String, FxBuildHasher> = Default::default();
+        flatten_into(&json, &FlattenOptions::default(), &mut fx).unwrap();
+        assert_eq!(fx.into_iter().collect::<FlattenedJson>(), record);
+    }
+
     #[test]
     fn test_flatten_array() {
         let json = json!({
@@ -329,6 +1004,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::field_reassign_with_default)]
     fn test_custom_separator() {
         let json = json!({
             "user": {
@@ -347,6 +1023,403 @@ mod tests {
     }
 
     #[test]
+    fn test_canonical_mode() {
+        let json = json!({"price": 10.0, "nested": {"b": 2.0, "a": -0.0}});
+        let options = FlattenOptions {
+            canonical: true,
+            max_depth: 1,
+            ..Default::default()
+        };
+        let flattened = flatten_json(&json, &options);
+
+        assert_eq!(flattened.get("price"), Some(&"10".to_string()));
+        assert_eq!(flattened.get("nested"), Some(&r#"{"a":0,"b":2}"#.to_string()));
+    }
+
+    #[test]
+    fn test_multi_char_separator_with_escaping() {
+        let json = json!({"a_": {"b": 1}, "a": {"_b": 2}});
+        let ambiguous = FlattenOptions {
+            separator: "__".to_string(),
+            ..Default::default()
+        };
+        // Without escaping both paths collapse to the same key
+        assert_eq!(flatten_json(&json, &ambiguous).len(), 1);
+
+        let options = FlattenOptions {
+            escape_keys: true,
+            ..ambiguous
+        };
+        let flattened = flatten_json(&json, &options);
+        assert_eq!(flattened.get("a\\___b"), Some(&"1".to_string()));
+        assert_eq!(flattened.get("a__\\_b"), Some(&"2".to_string()));
+        assert_eq!(split_key("a__\\_b", "__", true), vec!["a", "_b"]);
+    }
+
+    #[test]
+    fn test_empty_key_policies() {
+        let json = json!({"": 1, "a": {"": 2, "_empty_": 3}});
+        let kept = flatten_json(&json, &FlattenOptions::default());
+        assert_eq!(kept.get("a."), Some(&"2".to_string()));
+
+        let placeholder = FlattenOptions {
+            empty_keys: EmptyKeys::Placeholder,
+            escape_keys: true,
+            ..Default::default()
+        };
+        let flattened = flatten_json(&json, &placeholder);
+        assert_eq!(flattened.get("_empty_"), Some(&"1".to_string()));
+        assert_eq!(flattened.get("a._empty_"), Some(&"2".to_string()));
+        assert_eq!(flattened.get("a.\\_empty_"), Some(&"3".to_string()));
+        for (key, expected) in [("a._empty_", ["a", ""]), ("a.\\_empty_", ["a", "_empty_"])] {
+            assert_eq!(split_source_key(key, ".", true, EmptyKeys::Placeholder), expected);
+        }
+
+        let error = FlattenOptions {
+            empty_keys: EmptyKeys::Error,
+            ..Default::default()
+        };
+        assert_eq!(try_flatten_json(&json, &error), Err(FlattenError::EmptyKey(String::new())));
+        let nested = json!({"a": {"b": {"": 1}}});
+        assert_eq!(try_flatten_json(&nested, &error).unwrap_err().to_string(), "empty key in the object at \"a.b\"");
+    }
+
+    #[test]
+    fn test_ambiguous_key_policies() {
+        let json = json!({"a.b": 1, "a": {"b": 2}, "list": [{"0": 3}], "n": {"42": 4}});
+        assert_eq!(find_ambiguous_keys(&json, &FlattenOptions::default()), vec!["a.b", "list.0.0", "n.42"]);
+        let escaped = FlattenOptions {
+            escape_keys: true,
+            ..Default::default()
+        };
+        assert_eq!(find_ambiguous_keys(&json, &escaped), vec!["list.0.0", "n.42"]);
+
+        let report = FlattenOptions {
+            ambiguous_keys: AmbiguousKeys::Report,
+            include_array_indices: false,
+            ..Default::default()
+        };
+        let flattened = flatten_json(&json, &report);
+        assert_eq!(flattened.get(AMBIGUOUS_KEYS_KEY), Some(&r#"["a.b"]"#.to_string()));
+
+        let error = FlattenOptions {
+            ambiguous_keys: AmbiguousKeys::Error,
+            ..Default::default()
+        };
+        let err = try_flatten_json(&json!({"n": {"42": 4}}), &error).unwrap_err();
+        assert_eq!(err.to_string(), "key \"42\" in the object at \"n\" looks like an array index");
+        assert!(try_flatten_json(&json!({"a": {"b": [1]}}), &error).is_ok());
+    }
+
+    #[test]
+    fn test_single_key_chains() {
+        let json = json!({"id": 1, "envelope": {"payload": {"user": {"name": "a", "tags": [{"v": {"x": 2}}]}}}});
+        let head = FlattenOptions {
+            single_key_chains: SingleKeyChains::Head,
+            ..Default::default()
+        };
+        let flattened = flatten_json(&json, &head);
+        assert_eq!(flattened.get("envelope.name"), Some(&"a".to_string()));
+        assert_eq!(flattened.get("envelope.tags.0.v"), Some(&"2".to_string()));
+        assert_eq!(flattened.get("id"), Some(&"1".to_string()));
+
+        let tail = FlattenOptions {
+            single_key_chains: SingleKeyChains::Tail,
+            ..Default::default()
+        };
+        let flattened = flatten_json(&json, &tail);
+        assert_eq!(flattened.get("user.name"), Some(&"a".to_string()));
+        assert_eq!(flattened.get("user.tags.0.x"), Some(&"2".to_string()));
+
+        let shallow = FlattenOptions {
+            max_depth: 2,
+            ..tail.clone()
+        };
+        let stored = r#"{"user":{"name":"a","tags":[{"v":{"x":2}}]}}"#;
+        assert_eq!(flatten_json(&json, &shallow).get("payload"), Some(&stored.to_string()));
+
+        let longer = json!({"id": 1, "envelope": {"payload": {"user": {"name": "a"}}}});
+        let diff = flatten_changes(&json, &longer, &tail).unwrap();
+        assert_eq!(diff.changed.get("name"), Some(&"a".to_string()));
+        assert_eq!(diff.removed, vec!["user.name", "user.tags.0.x"]);
+    }
+
+    #[cfg(feature = "arbitrary_precision")]
+    #[test]
+    fn test_arbitrary_precision_numbers_stay_exact() {
+        let json: Value = serde_json::from_str(
+            r#"{"amount": 170141183460469231731687303715884105727.000001, "rate": 0.1000000000000000000001, "n": 1.0}"#,
+        )
+        .unwrap();
+        for canonical in [false, true] {
+            let options = FlattenOptions {
+                canonical,
+                ..Default::default()
+            };
+            let flattened = flatten_json(&json, &options);
+            assert_eq!(flattened["amount"], "170141183460469231731687303715884105727.000001");
+            assert_eq!(flattened["rate"], "0.1000000000000000000001");
+        }
+        let checked = duplicates::parse_document("[1.50, 12345678901234567890123]", DuplicateKeys::Report).unwrap();
+        assert_eq!(checked.0.to_string(), "[1.50,12345678901234567890123]");
+    }
+
+    #[test]
+    fn test_strip_prefix() {
+        let json = json!({"data": {"attributes": {"id": 1, "name": "a", "tags": ["x"]}, "type": "user"}});
+        let full = flatten_json(&json, &FlattenOptions::default());
+        let prefix = common_key_prefix(full.keys().filter(|key| key.contains("attributes")).map(String::as_str), ".");
+        assert_eq!(prefix, "data.attributes.");
+        assert_eq!(common_key_prefix(full.keys().map(String::as_str), "."), "data.");
+        assert_eq!(common_key_prefix(["a.b"], "."), "a.");
+        assert_eq!(common_key_prefix(["ab.c", "ac.d"], "."), "");
+
+        let options = FlattenOptions {
+            strip_prefix: Some(prefix),
+            ..Default::default()
+        };
+        let flattened = flatten_json(&json, &options);
+        assert_eq!(flattened.get("id"), Some(&"1".to_string()));
+        assert_eq!(flattened.get("tags.0"), Some(&"x".to_string()));
+        assert_eq!(flattened.get("data.type"), Some(&"user".to_string()));
+        assert!(!flattened.keys().any(|key| key.starts_with("data.attributes")));
+    }
+
+    #[test]
+    fn test_oversized_records_fail_or_are_skipped() {
+        let wide = json!({"id": 7, "fields": (0..1000).map(|i| i.to_string()).collect::<Vec<_>>()});
+        let limited = FlattenOptions {
+            max_record_keys: 100,
+            ..Default::default()
+        };
+        assert_eq!(try_flatten_json(&wide, &limited), Err(FlattenError::TooManyKeys { limit: 100 }));
+        assert_eq!(try_flatten_json(&json!({"a": 1}), &limited).unwrap().len(), 1);
+
+        let skipping = FlattenOptions {
+            oversized_records: OversizedRecords::Skip,
+            record_id: "$.id".parse().unwrap(),
+            ..limited
+        };
+        assert_eq!(try_flatten_json(&wide, &skipping), Err(FlattenError::TooManyKeys { limit: 100 }));
+        #[cfg(feature = "std")]
+        {
+            let Ok(Outcome::Skipped(skipped)) = flatten_input_document(&wide, &skipping, 4) else {
+                panic!("the oversized document was not skipped");
+            };
+            assert_eq!(skipped.document, 4);
+            assert_eq!(skipped.reason.to_string(), "record has more than 100 keys");
+            assert_eq!(skipped.record_id.as_deref(), Some("7"));
+        }
+
+        let timed = FlattenOptions {
+            record_time_limit: Some(core::time::Duration::ZERO),
+            ..Default::default()
+        };
+        let error = try_flatten_json(&wide, &timed).unwrap_err();
+        assert_eq!(error, FlattenError::RecordTimedOut { limit: core::time::Duration::ZERO });
+        let no_limit = FlattenOptions {
+            oversized_records: OversizedRecords::Skip,
+            ..Default::default()
+        };
+        assert!(no_limit.validate().is_err());
+    }
+
+    #[test]
+    fn test_non_object_documents_are_kept_wrapped_skipped_or_rejected() {
+        let policy = |policy: &str| FlattenOptions {
+            non_object_records: policy.parse().unwrap(),
+            ..Default::default()
+        };
+        assert!(try_flatten_json(&json!("heartbeat"), &policy("keep")).unwrap().is_empty());
+        assert_eq!(try_flatten_json(&json!([1, 2]), &policy("keep")).unwrap()[".1"], "2");
+
+        let wrapped = try_flatten_json(&json!("heartbeat"), &policy("wrap")).unwrap();
+        assert_eq!(wrapped["value"], "heartbeat");
+        let wrapped = try_flatten_json(&json!([1, {"a": true}]), &policy("wrap:payload")).unwrap();
+        assert_eq!(wrapped["payload.1.a"], "true");
+        assert_eq!(try_flatten_json(&json!({"a": 1}), &policy("wrap")).unwrap()["a"], "1");
+
+        assert_eq!(try_flatten_json(&json!(3), &policy("skip")), Err(FlattenError::NotAnObject(JsonKind::Number)));
+        #[cfg(feature = "std")]
+        {
+            let Ok(Outcome::Skipped(skipped)) = flatten_input_document(&json!(3), &policy("skip"), 0) else {
+                panic!("the number was not skipped");
+            };
+            assert_eq!(skipped.reason, SkipReason::Flatten(FlattenError::NotAnObject(JsonKind::Number)));
+        }
+        assert_eq!(try_flatten_json(&json!(null), &policy("error")), Err(FlattenError::NotAnObject(JsonKind::Null)));
+
+        assert_eq!(policy("wrap:payload").non_object_records.to_string(), "wrap:payload");
+        assert!("wrap:".parse::<NonObjectRecords>().is_err());
+    }
+
+    #[test]
+    fn test_array_metadata() {
+        let json = json!({"items": [1, 2, 3], "tags": [], "nested": {"list": [{"a": 1}]}});
+        let options = FlattenOptions {
+            array_metadata: true,
+            ..Default::default()
+        };
+        let flattened = flatten_json(&json, &options);
+
+        assert_eq!(flattened.get("items.#length"), Some(&"3".to_string()));
+        assert_eq!(flattened.get("items.2"), Some(&"3".to_string()));
+        assert_eq!(flattened.get("tags.#length"), Some(&"0".to_string()));
+        assert_eq!(flattened.get("nested.list.#length"), Some(&"1".to_string()));
+        assert!(!flatten_json(&json, &FlattenOptions::default()).contains_key("items.#length"));
+    }
+
+    #[test]
+    fn test_max_array_elements_policies() {
+        let json = json!({"values": [0, 1, 2, 3, 4, 5], "short": [1]});
+        let truncate = FlattenOptions {
+            max_array_elements: Some(4),
+            ..Default::default()
+        };
+        let flattened = flatten_json(&json, &truncate);
+        assert_eq!(flattened.get("values.#length"), Some(&"6".to_string()));
+        assert!(flattened.contains_key("values.3") && !flattened.contains_key("values.4"));
+        assert!(!flattened.contains_key("short.#length"));
+
+        let head_tail = FlattenOptions {
+            array_limit_policy: ArrayLimitPolicy::HeadTail,
+            ..truncate.clone()
+        };
+        let mut kept: Vec<String> = flatten_json(&json, &head_tail)
+            .into_keys()
+            .filter(|k| k.starts_with("values.") && !k.ends_with("#length"))
+            .collect();
+        kept.sort();
+        assert_eq!(kept, vec!["values.0", "values.1", "values.4", "values.5"]);
+
+        let error = FlattenOptions {
+            array_limit_policy: ArrayLimitPolicy::Error,
+            ..truncate
+        };
+        assert_eq!(
+            try_flatten_json(&json, &error),
+            Err(FlattenError::ArrayTooLong {
+                path: "values".to_string(),
+                len: 6,
+                limit: 4
+            })
+        );
+    }
+
+    #[test]
+    fn test_numeric_array_aggregates() {
+        let json = json!({"scores": [3, 1.5, 7, 4.5], "tags": ["a", 1], "empty": []});
+        let replace = FlattenOptions {
+            array_aggregates: ArrayAggregates::Replace,
+            ..Default::default()
+        };
+        let flattened = flatten_json(&json, &replace);
+        assert_eq!(flattened.get("scores.min"), Some(&"1.5".to_string()));
+        assert_eq!(flattened.get("scores.max"), Some(&"7".to_string()));
+        assert_eq!(flattened.get("scores.mean"), Some(&"4".to_string()));
+        assert_eq!(flattened.get("scores.count"), Some(&"4".to_string()));
+        assert_eq!(flattened.get("empty.count"), Some(&"0".to_string()));
+        assert!(!flattened.contains_key("scores.0"));
+        // Mixed arrays are expanded as usual
+        assert!(flattened.contains_key("tags.1") && !flattened.contains_key("tags.count"));
+
+        let alongside = FlattenOptions {
+            array_aggregates: ArrayAggregates::Alongside,
+            ..Default::default()
+        };
+        let flattened = flatten_json(&json, &alongside);
+        assert!(flattened.contains_key("scores.0") && flattened.contains_key("scores.mean"));
+    }
+
+    #[test]
+    fn test_per_path_array_modes() {
+        let json = json!({
+            "tags": ["a", "b"],
+            "events": [{"type": "click"}],
+            "user": {"tags": ["x"], "scores": [1, 2]}
+        });
+        let options = FlattenOptions {
+            expand_arrays: false,
+            array_modes: vec![
+                "tags=join".parse().unwrap(),
+                ArrayPathMode::new("events", ArrayMode::Expand),
+                "**.tags=join:|".parse().unwrap(),
+            ],
+            ..Default::default()
+        };
+        let flattened = flatten_json(&json, &options);
+
+        assert_eq!(flattened.get("tags"), Some(&"a,b".to_string()));
+        assert_eq!(flattened.get("events.0.type"), Some(&"click".to_string()));
+        assert_eq!(flattened.get("user.tags"), Some(&"x".to_string()));
+        assert_eq!(flattened.get("user.scores"), Some(&"[1,2]".to_string()));
+        assert!("tags=explode".parse::<ArrayPathMode>().is_err());
+    }
+
+    #[test]
+    fn test_geojson_geometries() {
+        let json = json!({
+            "type": "Feature",
+            "geometry": {"type": "LineString", "coordinates": [[0, 0], [1.5, 2]]},
+            "properties": {"site": {"type": "Point", "coordinates": [-71.06, 42.36]}}
+        });
+        let options = FlattenOptions {
+            geojson: GeoJsonMode::Wkt,
+            ..Default::default()
+        };
+        let flattened = flatten_json(&json, &options);
+        assert_eq!(flattened.get("geometry"), Some(&"LINESTRING (0 0, 1.5 2)".to_string()));
+        assert_eq!(flattened.get("properties.site"), Some(&"POINT (-71.06 42.36)".to_string()));
+        assert_eq!(flattened.get("type"), Some(&"Feature".to_string()));
+
+        let columns = FlattenOptions {
+            geojson: GeoJsonMode::PointColumns,
+            ..Default::default()
+        };
+        let flattened = flatten_json(&json, &columns);
+        assert_eq!(flattened.get("properties.site.lon"), Some(&"-71.06".to_string()));
+        assert_eq!(flattened.get("properties.site.lat"), Some(&"42.36".to_string()));
+        assert_eq!(flattened.get("geometry"), Some(&"LINESTRING (0 0, 1.5 2)".to_string()));
+    }
+
+    #[test]
+    fn test_binary_value_policies() {
+        let blob = "iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAADUlEQVR42mNk+M9QDwADhgGAWjR9awAAAABJRU5ErkJggg==";
+        let json = json!({"file": {"name": "pixel.png", "data": blob}});
+        let truncate = FlattenOptions {
+            binary_values: BinaryPolicy::Truncate(8),
+            binary_min_length: 32,
+            ..Default::default()
+        };
+        let flattened = flatten_json(&json, &truncate);
+        assert_eq!(flattened.get("file.data"), Some(&"iVBORw0K".to_string()));
+        assert_eq!(flattened.get("file.data.#length"), Some(&blob.len().to_string()));
+        assert_eq!(flattened.get("file.name"), Some(&"pixel.png".to_string()));
+
+        let drop = FlattenOptions {
+            binary_values: BinaryPolicy::Drop,
+            ..truncate
+        };
+        assert!(!flatten_json(&json, &drop).contains_key("file.data"));
+    }
+
+    #[test]
+    fn test_unicode_normalization() {
+        // "café" with a precomposed and a decomposed "é"
+        let json = json!({"caf\u{e9}": 1, "cafe\u{301}": 2, "name": "Jose\u{301}"});
+        let options = FlattenOptions {
+            normalize_keys: true,
+            normalize_values: true,
+            ..Default::default()
+        };
+        let flattened = flatten_json(&json, &options);
+        assert_eq!(flattened.len(), 2);
+        assert!(flattened.contains_key("caf\u{e9}"));
+        assert_eq!(flattened.get("name"), Some(&"Jos\u{e9}".to_string()));
+    }
+
+    #[test]
+    #[allow(clippy::field_reassign_with_default, clippy::unnecessary_get_then_check)]
     fn test_max_depth() {
         let json = json!({
             "user": {
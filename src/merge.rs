@@ -0,0 +1,135 @@
+// src/merge.rs
+//! Combining flattened records about the same entity
+use crate::FlattenedJson;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+/// What [`merge_flattened`] does with a key whose records hold different values
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MergeStrategy {
+    /// Keep the value of the first record holding the key
+    FirstWins,
+    /// Keep the value of the last record holding the key
+    #[default]
+    LastWins,
+    /// Write a JSON array of the distinct values, in record order
+    Collect,
+    /// Fail with a [`MergeConflict`]
+    Error,
+}
+
+impl core::str::FromStr for MergeStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "first" => Ok(MergeStrategy::FirstWins),
+            "last" => Ok(MergeStrategy::LastWins),
+            "collect" => Ok(MergeStrategy::Collect),
+            "error" => Ok(MergeStrategy::Error),
+            other => Err(format!("unknown merge strategy {:?} (expected first, last, collect or error)", other)),
+        }
+    }
+}
+
+impl fmt::Display for MergeStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            MergeStrategy::FirstWins => "first",
+            MergeStrategy::LastWins => "last",
+            MergeStrategy::Collect => "collect",
+            MergeStrategy::Error => "error",
+        })
+    }
+}
+
+/// Two records hold different values for a key under [`MergeStrategy::Error`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MergeConflict {
+    pub key: String,
+    /// The key's value in the first record holding it
+    pub first: String,
+    /// The first value that differs from it
+    pub other: String,
+}
+
+impl fmt::Display for MergeConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "records disagree on {:?}: {:?} and {:?}", self.key, self.first, self.other)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for MergeConflict {}
+
+/// Merges records, e.g. partial documents about one entity, into one record
+///
+/// Keys held by a single record, or with the same value in every record
+/// holding them, are copied as they are; only keys whose values differ are
+/// resolved by `strategy`.
+pub fn merge_flattened(records: &[FlattenedJson], strategy: MergeStrategy) -> Result<FlattenedJson, MergeConflict> {
+    // Distinct values per key, in the order the records hold them
+    let mut values: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for record in records {
+        for (key, value) in record {
+            let seen = values.entry(key.as_str()).or_default();
+            if !seen.contains(&value.as_str()) {
+                seen.push(value);
+            }
+        }
+    }
+
+    let mut merged = FlattenedJson::default();
+    for (key, seen) in values {
+        let value = match (strategy, seen.as_slice()) {
+            (_, [only]) => only.to_string(),
+            (MergeStrategy::FirstWins, [first, ..]) => first.to_string(),
+            (MergeStrategy::LastWins, [.., last]) => last.to_string(),
+            (MergeStrategy::Collect, _) => serde_json::to_string(&seen).expect("strings always serialize"),
+            (MergeStrategy::Error, [first, other, ..]) => {
+                return Err(MergeConflict {
+                    key: key.to_string(),
+                    first: first.to_string(),
+                    other: other.to_string(),
+                })
+            }
+            (_, []) => unreachable!("every key has a value"),
+        };
+        merged.insert(key.to_string(), value);
+    }
+    Ok(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flatten_json;
+    use serde_json::json;
+
+    #[test]
+    fn test_merge_strategies() {
+        let options = Default::default();
+        let records = [
+            flatten_json(&json!({"id": 7, "name": "Ada", "address": {"city": "Paris"}}), &options),
+            flatten_json(&json!({"id": 7, "address": {"city": "Lyon", "zip": "69001"}}), &options),
+            flatten_json(&json!({"id": 7, "address": {"city": "Nice"}}), &options),
+        ];
+
+        let last = merge_flattened(&records, MergeStrategy::LastWins).unwrap();
+        assert_eq!(last["address.city"], "Nice");
+        assert_eq!(last["address.zip"], "69001");
+        assert_eq!(last["id"], "7");
+        assert_eq!(last["name"], "Ada");
+        assert_eq!(merge_flattened(&records, MergeStrategy::FirstWins).unwrap()["address.city"], "Paris");
+        let collected = merge_flattened(&records, MergeStrategy::Collect).unwrap();
+        assert_eq!(collected["address.city"], r#"["Paris","Lyon","Nice"]"#);
+        assert_eq!(collected["id"], "7");
+
+        let err = merge_flattened(&records, MergeStrategy::Error).unwrap_err();
+        assert_eq!(err.to_string(), r#"records disagree on "address.city": "Paris" and "Lyon""#);
+        assert!(merge_flattened(&records[..0], MergeStrategy::Error).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,218 @@
+// src/nonfinite.rs
+use alloc::borrow::Cow;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::io::{self, Read};
+
+/// Handling of the `NaN`, `Infinity` and `-Infinity` tokens that some
+/// producers emit as numbers but strict JSON rejects
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NonFiniteNumbers {
+    /// Leave them in, so parsing fails like for any invalid JSON
+    #[default]
+    Error,
+    /// Read them as `null`
+    Null,
+    /// Read them as the strings `"NaN"`, `"Infinity"` and `"-Infinity"`
+    String,
+}
+
+impl core::str::FromStr for NonFiniteNumbers {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(NonFiniteNumbers::Error),
+            "null" => Ok(NonFiniteNumbers::Null),
+            "string" => Ok(NonFiniteNumbers::String),
+            other => Err(format!(
+                "unknown non-finite number policy {:?} (expected error, null or string)",
+                other
+            )),
+        }
+    }
+}
+
+impl core::fmt::Display for NonFiniteNumbers {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            NonFiniteNumbers::Error => "error",
+            NonFiniteNumbers::Null => "null",
+            NonFiniteNumbers::String => "string",
+        })
+    }
+}
+
+const TOKENS: [&[u8]; 3] = [b"NaN", b"Infinity", b"-Infinity"];
+
+/// Rewrites non-finite tokens outside of strings, one byte at a time, so
+/// tokens split across reads are still found
+#[derive(Debug)]
+struct Scrubber {
+    policy: NonFiniteNumbers,
+    in_string: bool,
+    escaped: bool,
+    /// Bytes of a token being matched
+    pending: Vec<u8>,
+}
+
+impl Scrubber {
+    fn new(policy: NonFiniteNumbers) -> Self {
+        Scrubber {
+            policy,
+            in_string: false,
+            escaped: false,
+            pending: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, byte: u8, out: &mut Vec<u8>) {
+        if self.in_string {
+            match byte {
+                _ if self.escaped => self.escaped = false,
+                b'\\' => self.escaped = true,
+                b'"' => self.in_string = false,
+                _ => {}
+            }
+            out.push(byte);
+            return;
+        }
+
+        self.pending.push(byte);
+        match TOKENS.iter().find(|token| token.starts_with(&self.pending)) {
+            Some(token) if token.len() == self.pending.len() => {
+                match self.policy {
+                    NonFiniteNumbers::Null => out.extend_from_slice(b"null"),
+                    _ => {
+                        out.push(b'"');
+                        out.extend_from_slice(token);
+                        out.push(b'"');
+                    }
+                }
+                self.pending.clear();
+            }
+            Some(_) => {}
+            None => {
+                // Not a token after all: pass the bytes through, and look at
+                // the last one again in case it starts a token or a string
+                self.pending.pop();
+                out.append(&mut self.pending);
+                if TOKENS.iter().any(|token| token[0] == byte) {
+                    self.pending.push(byte);
+                } else {
+                    self.in_string = byte == b'"';
+                    out.push(byte);
+                }
+            }
+        }
+    }
+
+    /// Passes through a partial token left at the end of the input
+    fn finish(&mut self, out: &mut Vec<u8>) {
+        out.append(&mut self.pending);
+    }
+}
+
+/// Rewrites the non-finite tokens of a JSON text according to `policy`
+pub fn scrub_non_finite(json: &str, policy: NonFiniteNumbers) -> Cow<'_, str> {
+    if policy == NonFiniteNumbers::Error || !(json.contains("NaN") || json.contains("Infinity")) {
+        return Cow::Borrowed(json);
+    }
+    let mut scrubber = Scrubber::new(policy);
+    let mut out = Vec::with_capacity(json.len() + 16);
+    for &byte in json.as_bytes() {
+        scrubber.push(byte, &mut out);
+    }
+    scrubber.finish(&mut out);
+    Cow::Owned(String::from_utf8(out).expect("only ASCII bytes are replaced"))
+}
+
+/// Reader that rewrites non-finite tokens on the fly, see [`scrub_non_finite`]
+#[cfg(feature = "std")]
+pub struct NonFiniteReader<R: Read> {
+    reader: R,
+    scrubber: Scrubber,
+    buf: Vec<u8>,
+    out: Vec<u8>,
+    pos: usize,
+    done: bool,
+}
+
+#[cfg(feature = "std")]
+impl<R: Read> NonFiniteReader<R> {
+    pub fn new(reader: R, policy: NonFiniteNumbers) -> Self {
+        NonFiniteReader {
+            reader,
+            scrubber: Scrubber::new(policy),
+            buf: vec![0; 1 << 16],
+            out: Vec::new(),
+            pos: 0,
+            done: false,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: Read> Read for NonFiniteReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.out.len() && !self.done {
+            self.out.clear();
+            self.pos = 0;
+            let n = self.reader.read(&mut self.buf)?;
+            if n == 0 {
+                self.done = true;
+                self.scrubber.finish(&mut self.out);
+            }
+            for &byte in &self.buf[..n] {
+                self.scrubber.push(byte, &mut self.out);
+            }
+        }
+        let n = buf.len().min(self.out.len() - self.pos);
+        buf[..n].copy_from_slice(&self.out[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokens_outside_strings_are_replaced() {
+        let json = r#"{"a": NaN, "b": [Infinity,-Infinity, -1.5], "c": "NaN \" Infinity", "d": null}"#;
+        assert_eq!(
+            scrub_non_finite(json, NonFiniteNumbers::Null),
+            r#"{"a": null, "b": [null,null, -1.5], "c": "NaN \" Infinity", "d": null}"#
+        );
+        assert_eq!(
+            scrub_non_finite(json, NonFiniteNumbers::String),
+            r#"{"a": "NaN", "b": ["Infinity","-Infinity", -1.5], "c": "NaN \" Infinity", "d": null}"#
+        );
+        assert_eq!(scrub_non_finite(json, NonFiniteNumbers::Error), json);
+        assert_eq!(scrub_non_finite("[-N\"x\"]", NonFiniteNumbers::Null), "[-N\"x\"]");
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_reader_finds_tokens_split_across_reads() {
+        /// Yields one byte per read
+        struct Trickle<'a>(&'a [u8]);
+        impl Read for Trickle<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                let n = self.0.len().min(buf.len()).min(1);
+                buf[..n].copy_from_slice(&self.0[..n]);
+                self.0 = &self.0[n..];
+                Ok(n)
+            }
+        }
+
+        let mut out = String::new();
+        NonFiniteReader::new(Trickle(b"[NaN,\"-Infinity\",-Infinity,-Inf"), NonFiniteNumbers::Null)
+            .read_to_string(&mut out)
+            .unwrap();
+        assert_eq!(out, "[null,\"-Infinity\",null,-Inf");
+    }
+}
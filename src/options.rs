@@ -0,0 +1,394 @@
+// src/options.rs
+//! Versioned storage and validation of [`FlattenOptions`]
+use crate::keys::{SeparatorError, ESCAPE_CHAR};
+use crate::{ArrayLimitPolicy, ArrayMode, FlattenOptions, OversizedRecords, RecordIdSource};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+use serde_json::{Map, Value};
+
+/// Version of the stored options layout written by this release
+///
+/// Bumped whenever options are added, so a loader can tell a config that
+/// predates an option from one that leaves it out on purpose.
+pub const OPTIONS_VERSION: u32 = 3;
+
+/// Key holding [`OPTIONS_VERSION`] in stored options
+pub const VERSION_KEY: &str = "version";
+
+/// Options each [`OPTIONS_VERSION`] added, oldest first
+///
+/// A new option goes in a new entry for the bumped version; earlier entries
+/// never change, since stored options of that version are read against them.
+const ADDED_OPTIONS: &[(u32, &[&str])] = &[
+    (
+        1,
+        &[
+            "separator", "escape_keys", "max_concurrency", "parse_threads", "flatten_threads", "max_depth",
+            "include_array_indices", "expand_arrays", "chunk_size", "chunk_bytes", "memory_budget",
+            "split_root_array", "canonical", "raw_values", "array_metadata", "max_array_elements",
+            "array_limit_policy", "array_aggregates", "array_modes", "record_id", "record_id_key", "geojson",
+            "binary_values", "binary_min_length", "normalize_keys", "normalize_values", "invalid_utf8", "encoding",
+            "leading_junk", "non_finite", "duplicate_keys", "empty_keys", "structure_metrics", "presize_records",
+        ],
+    ),
+    (
+        2,
+        &[
+            "local_pools", "max_input_bytes", "max_document_bytes", "time_budget", "skip_documents", "ndjson",
+            "max_record_keys", "record_time_limit", "oversized_records", "non_object_records", "invalid_documents",
+            "ambiguous_keys", "single_key_chains", "strip_prefix", "index_keys", "sparse_indices",
+            "derived_columns", "filters",
+        ],
+    ),
+    (3, &["over_budget"]),
+];
+
+/// Something [`FlattenOptions::from_json_value`] could not carry over as written
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OptionsWarning {
+    /// The options were written by a newer release
+    NewerVersion(u32),
+    /// An option this release doesn't know, ignored
+    UnknownOption(String),
+    /// An option added after the options were written, set to its default
+    DefaultedOption(String),
+}
+
+impl fmt::Display for OptionsWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OptionsWarning::NewerVersion(version) => write!(
+                f,
+                "options have version {}, newer than the supported version {}",
+                version, OPTIONS_VERSION
+            ),
+            OptionsWarning::UnknownOption(name) => write!(f, "unknown option {:?} ignored", name),
+            OptionsWarning::DefaultedOption(name) => write!(f, "option {:?} not set, using the default", name),
+        }
+    }
+}
+
+/// A setting [`FlattenOptions::validate`] rejects because it can't work as configured
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OptionsError {
+    /// `separator` is empty, or contains the escape character under `escape_keys`
+    Separator(SeparatorError),
+    /// `chunk_size` is 0 while chunks are counted in records (`chunk_bytes` is 0)
+    ZeroChunkSize,
+    /// `max_concurrency` is 0
+    ZeroConcurrency,
+    /// `record_id` is set but `record_id_key` is empty
+    EmptyRecordIdKey,
+    /// `max_array_elements` is set but no array is expanded
+    ArrayLimitWithoutExpansion,
+    /// `array_limit_policy` is changed from its default but `max_array_elements` is unset
+    ArrayLimitPolicyWithoutLimit(ArrayLimitPolicy),
+    /// `oversized_records` skips records but neither `max_record_keys` nor
+    /// `record_time_limit` is set
+    OversizedPolicyWithoutLimit,
+}
+
+impl fmt::Display for OptionsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OptionsError::Separator(e) => write!(f, "{}", e),
+            OptionsError::ZeroChunkSize => {
+                write!(f, "chunk_size must be at least 1 (or set chunk_bytes to cut chunks by size)")
+            }
+            OptionsError::ZeroConcurrency => write!(f, "max_concurrency must be at least 1"),
+            OptionsError::EmptyRecordIdKey => write!(f, "record_id_key must not be empty when record_id is set"),
+            OptionsError::ArrayLimitWithoutExpansion => write!(
+                f,
+                "max_array_elements has no effect: expand_arrays is off and no array_modes entry expands"
+            ),
+            OptionsError::ArrayLimitPolicyWithoutLimit(policy) => {
+                write!(f, "array_limit_policy {} has no effect without max_array_elements", policy)
+            }
+            OptionsError::OversizedPolicyWithoutLimit => write!(
+                f,
+                "oversized_records skip has no effect without max_record_keys or record_time_limit"
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for OptionsError {}
+
+impl FlattenOptions {
+    /// Checks for settings that can't work, so they fail up front instead of
+    /// producing confusing output
+    ///
+    /// Every file, reader and value entry point calls this before flattening.
+    /// Self-overlapping separators like `"__"` are allowed here; use
+    /// [`validate_separator`](crate::validate_separator) to reject those too.
+    pub fn validate(&self) -> Result<(), OptionsError> {
+        if self.separator.is_empty() {
+            return Err(OptionsError::Separator(SeparatorError::Empty));
+        }
+        if self.chunk_size == 0 && self.chunk_bytes == 0 {
+            return Err(OptionsError::ZeroChunkSize);
+        }
+        if self.max_concurrency == 0 {
+            return Err(OptionsError::ZeroConcurrency);
+        }
+        self.validate_document()
+    }
+
+    /// Like [`validate`](FlattenOptions::validate), checking only the settings
+    /// used to flatten documents that are already parsed
+    ///
+    /// Chunking, concurrency and an empty separator (which joins segments
+    /// directly) are left alone, so `flatten_json` and `flatten_values` keep
+    /// accepting every option value they always have; only the policies and
+    /// limits that can't work together fail here.
+    pub(crate) fn validate_document(&self) -> Result<(), OptionsError> {
+        if self.escape_keys && self.separator.contains(ESCAPE_CHAR) {
+            return Err(OptionsError::Separator(SeparatorError::ContainsEscapeChar));
+        }
+        if self.record_id != RecordIdSource::None && self.record_id_key.is_empty() {
+            return Err(OptionsError::EmptyRecordIdKey);
+        }
+        match self.max_array_elements {
+            Some(_) if !self.expand_arrays && !self.array_modes.iter().any(|m| m.mode == ArrayMode::Expand) => {
+                return Err(OptionsError::ArrayLimitWithoutExpansion)
+            }
+            None if self.array_limit_policy != ArrayLimitPolicy::default() => {
+                return Err(OptionsError::ArrayLimitPolicyWithoutLimit(self.array_limit_policy))
+            }
+            _ => {}
+        }
+        let record_limit = self.max_record_keys > 0 || self.record_time_limit.is_some();
+        if self.oversized_records != OversizedRecords::default() && !record_limit {
+            return Err(OptionsError::OversizedPolicyWithoutLimit);
+        }
+        Ok(())
+    }
+
+    /// The options as a JSON object tagged with [`OPTIONS_VERSION`]
+    ///
+    /// Policies are written in their command-line form, e.g. `"join:|"`; the
+    /// chunk hook is left out.
+    pub fn to_json_value(&self) -> Value {
+        let mut value = serde_json::to_value(self).expect("options always serialize");
+        if let Value::Object(map) = &mut value {
+            map.insert(VERSION_KEY.to_string(), Value::from(OPTIONS_VERSION));
+        }
+        value
+    }
+
+    /// Reads options stored by this or another release
+    ///
+    /// Options missing from the object get their defaults, and unknown ones
+    /// are ignored. Both are reported as warnings, except for options that
+    /// already existed in the object's version, which were left out on
+    /// purpose. An object without a version counts as older than version 1.
+    pub fn from_json_value(value: Value) -> Result<(FlattenOptions, Vec<OptionsWarning>), serde_json::Error> {
+        let mut map: Map<String, Value> = serde_json::from_value(value)?;
+        let version = match map.remove(VERSION_KEY) {
+            Some(version) => serde_json::from_value(version)?,
+            None => 0,
+        };
+        let Value::Object(known) = serde_json::to_value(FlattenOptions::default())? else {
+            unreachable!("options serialize to an object")
+        };
+
+        let mut warnings = Vec::new();
+        if version > OPTIONS_VERSION {
+            warnings.push(OptionsWarning::NewerVersion(version));
+        }
+        let unknown = map.keys().filter(|name| !known.contains_key(*name));
+        warnings.extend(unknown.map(|name| OptionsWarning::UnknownOption(name.clone())));
+        let added = ADDED_OPTIONS.iter().filter(|(added_in, _)| *added_in > version);
+        let missing = added.flat_map(|(_, names)| names.iter()).filter(|name| !map.contains_key(**name));
+        warnings.extend(missing.map(|name| OptionsWarning::DefaultedOption(name.to_string())));
+        Ok((serde_json::from_value(Value::Object(map))?, warnings))
+    }
+
+    /// Like [`from_json_value`](FlattenOptions::from_json_value), from JSON text
+    pub fn from_json_str(json: &str) -> Result<(FlattenOptions, Vec<OptionsWarning>), serde_json::Error> {
+        FlattenOptions::from_json_value(serde_json::from_str(json)?)
+    }
+}
+
+/// Serializes a policy as its `Display` text and parses it back with `FromStr`
+pub(crate) mod as_str {
+    use alloc::string::{String, ToString};
+    use core::fmt::Display;
+    use core::str::FromStr;
+    use serde::de::Error as _;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<T: Display, S: Serializer>(value: &T, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&value.to_string())
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: FromStr<Err = String>,
+        D: Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?.parse().map_err(D::Error::custom)
+    }
+}
+
+/// [`as_str`] for each element of a list
+pub(crate) mod as_str_vec {
+    use alloc::string::{String, ToString};
+    use alloc::vec::Vec;
+    use core::fmt::Display;
+    use core::str::FromStr;
+    use serde::de::Error as _;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<T: Display, S: Serializer>(values: &[T], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(values.iter().map(|value| value.to_string()))
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<Vec<T>, D::Error>
+    where
+        T: FromStr<Err = String>,
+        D: Deserializer<'de>,
+    {
+        Vec::<String>::deserialize(deserializer)?
+            .iter()
+            .map(|value| value.parse().map_err(D::Error::custom))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ArrayPathMode, BinaryPolicy, DuplicateKeys};
+    use alloc::vec;
+    use serde_json::json;
+
+    #[test]
+    fn test_options_round_trip_and_old_configs_load_with_warnings() {
+        let options = FlattenOptions {
+            separator: "__".to_string(),
+            max_array_elements: Some(3),
+            array_modes: vec!["tags=join:|".parse::<ArrayPathMode>().unwrap()],
+            binary_values: BinaryPolicy::Truncate(8),
+            duplicate_keys: DuplicateKeys::Report,
+            ..Default::default()
+        };
+
+        let stored = options.to_json_value();
+        assert_eq!(stored[VERSION_KEY], OPTIONS_VERSION);
+        assert_eq!(stored["array_modes"], json!(["tags=join:|"]));
+        let (loaded, warnings) = FlattenOptions::from_json_value(stored).unwrap();
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        assert_eq!(loaded.to_json_value(), options.to_json_value());
+
+        let (loaded, warnings) =
+            FlattenOptions::from_json_str(r#"{"separator": "/", "retired": 1, "duplicate_keys": "error"}"#).unwrap();
+        assert_eq!(loaded.separator, "/");
+        assert_eq!(loaded.duplicate_keys, DuplicateKeys::Error);
+        assert_eq!(loaded.chunk_size, FlattenOptions::default().chunk_size);
+        assert!(warnings.contains(&OptionsWarning::UnknownOption("retired".to_string())));
+        assert!(warnings.contains(&OptionsWarning::DefaultedOption("chunk_size".to_string())));
+        assert!(!warnings.contains(&OptionsWarning::DefaultedOption("separator".to_string())));
+
+        let (_, warnings) = FlattenOptions::from_json_str(r#"{"version": 99, "separator": "."}"#).unwrap();
+        assert_eq!(warnings, vec![OptionsWarning::NewerVersion(99)]);
+        assert!(FlattenOptions::from_json_str(r#"{"duplicate_keys": "sometimes"}"#).is_err());
+    }
+
+    #[test]
+    fn test_every_option_is_listed_under_the_version_that_added_it() {
+        let Value::Object(mut stored) = FlattenOptions::default().to_json_value() else {
+            unreachable!("options serialize to an object")
+        };
+        stored.remove(VERSION_KEY);
+        let mut listed: Vec<&str> = ADDED_OPTIONS.iter().flat_map(|(_, names)| names.iter().copied()).collect();
+        listed.sort_unstable();
+        let mut fields: Vec<&str> = stored.keys().map(String::as_str).collect();
+        fields.sort_unstable();
+        assert_eq!(listed, fields, "a new option needs an ADDED_OPTIONS entry and an OPTIONS_VERSION bump");
+        assert_eq!(ADDED_OPTIONS.last().map(|(version, _)| *version), Some(OPTIONS_VERSION));
+
+        let (_, warnings) = FlattenOptions::from_json_str(r#"{"version": 1, "separator": "."}"#).unwrap();
+        assert!(warnings.contains(&OptionsWarning::DefaultedOption("filters".to_string())));
+        assert!(warnings.contains(&OptionsWarning::DefaultedOption("non_object_records".to_string())));
+        assert!(!warnings.contains(&OptionsWarning::DefaultedOption("chunk_size".to_string())));
+    }
+
+    #[test]
+    fn test_validate_rejects_settings_that_cannot_work() {
+        let invalid = |options: FlattenOptions| options.validate().unwrap_err();
+        assert!(FlattenOptions::default().validate().is_ok());
+        assert_eq!(
+            invalid(FlattenOptions {
+                separator: String::new(),
+                ..Default::default()
+            }),
+            OptionsError::Separator(SeparatorError::Empty)
+        );
+        assert_eq!(
+            invalid(FlattenOptions {
+                chunk_size: 0,
+                ..Default::default()
+            }),
+            OptionsError::ZeroChunkSize
+        );
+        let by_bytes = FlattenOptions {
+            chunk_size: 0,
+            chunk_bytes: 1 << 20,
+            ..Default::default()
+        };
+        assert!(by_bytes.validate().is_ok());
+        assert_eq!(
+            invalid(FlattenOptions {
+                max_concurrency: 0,
+                ..Default::default()
+            }),
+            OptionsError::ZeroConcurrency
+        );
+        assert_eq!(
+            invalid(FlattenOptions {
+                expand_arrays: false,
+                max_array_elements: Some(10),
+                ..Default::default()
+            }),
+            OptionsError::ArrayLimitWithoutExpansion
+        );
+        let expanded_path = FlattenOptions {
+            expand_arrays: false,
+            max_array_elements: Some(10),
+            array_modes: vec!["items=expand".parse::<ArrayPathMode>().unwrap()],
+            ..Default::default()
+        };
+        assert!(expanded_path.validate().is_ok());
+        let error = invalid(FlattenOptions {
+            array_limit_policy: ArrayLimitPolicy::Error,
+            ..Default::default()
+        });
+        assert_eq!(error.to_string(), "array_limit_policy error has no effect without max_array_elements");
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_entry_points_check_the_options_they_use() {
+        // Input entry points fail before flattening anything; single documents
+        // only check what flattening them uses
+        let zero = FlattenOptions {
+            chunk_size: 0,
+            max_concurrency: 0,
+            separator: String::new(),
+            ..Default::default()
+        };
+        assert!(crate::flatten_json_multi_str("{}", &zero).is_err());
+        let flat = crate::try_flatten_json(&json!({"a": {"b": 1}}), &zero).unwrap();
+        assert_eq!(flat.get("ab").map(String::as_str), Some("1"));
+        let unlimited = FlattenOptions {
+            array_limit_policy: ArrayLimitPolicy::Error,
+            ..zero
+        };
+        let err = crate::try_flatten_json(&json!({"a": 1}), &unlimited).unwrap_err();
+        let expected = OptionsError::ArrayLimitPolicyWithoutLimit(ArrayLimitPolicy::Error);
+        assert_eq!(err, crate::FlattenError::InvalidOptions(expected));
+    }
+}
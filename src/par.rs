@@ -0,0 +1,53 @@
+// src/par.rs
+//! Parallel iteration backed by rayon with the `parallel` feature, and by
+//! plain sequential iterators without it, so call sites read the same either way
+#[cfg(feature = "parallel")]
+pub(crate) use rayon::prelude::*;
+#[cfg(feature = "parallel")]
+pub use rayon::ThreadPoolBuildError;
+
+/// Error building the stage thread pools; without the `parallel` feature no
+/// pools are built, so this can never occur
+#[cfg(not(feature = "parallel"))]
+pub type ThreadPoolBuildError = core::convert::Infallible;
+
+#[cfg(all(feature = "std", not(feature = "parallel")))]
+pub(crate) use sequential::*;
+
+#[cfg(all(feature = "std", not(feature = "parallel")))]
+mod sequential {
+    use alloc::vec::Vec;
+
+    /// Sequential stand-in for rayon's `IntoParallelIterator`
+    pub(crate) trait IntoParallelIterator: IntoIterator + Sized {
+        fn into_par_iter(self) -> Self::IntoIter {
+            self.into_iter()
+        }
+    }
+
+    impl<T> IntoParallelIterator for Vec<T> {}
+
+    /// Sequential stand-in for rayon's `IntoParallelRefIterator` on slices
+    pub(crate) trait ParallelSlice<T> {
+        fn par_iter(&self) -> core::slice::Iter<'_, T>;
+    }
+
+    impl<T> ParallelSlice<T> for [T] {
+        fn par_iter(&self) -> core::slice::Iter<'_, T> {
+            self.iter()
+        }
+    }
+}
+
+/// Default number of worker threads: the CPU count with the `parallel`
+/// feature, otherwise 1
+pub(crate) fn available_threads() -> usize {
+    #[cfg(feature = "parallel")]
+    {
+        num_cpus::get()
+    }
+    #[cfg(not(feature = "parallel"))]
+    {
+        1
+    }
+}
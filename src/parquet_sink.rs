@@ -0,0 +1,168 @@
+// src/parquet_sink.rs
+use crate::ipc::{arrow_schema, record_batch};
+use crate::schema::{infer_schema, FlatSchema};
+use crate::{flatten_json_file_to_sink, FlattenOptions, FlattenedJson, RecordSink};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression, ZstdLevel};
+use parquet::errors::ParquetError;
+use parquet::file::properties::WriterProperties;
+use arrow_schema::SchemaRef;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Sink that writes records to a zstd-compressed Parquet file
+///
+/// Columns are fixed the same way as for [`IpcSink`](crate::IpcSink): rows
+/// are buffered until the first flush, whose columns and inferred types (or
+/// the schema given to [`with_schema`](ParquetSink::with_schema)) hold for
+/// the rest of the file. Later keys are dropped and values that don't fit a
+/// column's type become nulls. Rows are written in row groups of up to a
+/// million; the file is only readable once [`finish`](ParquetSink::finish)
+/// has written its footer.
+pub struct ParquetSink<W: Write + Send> {
+    writer: Option<W>,
+    file: Option<(ArrowWriter<W>, SchemaRef)>,
+    /// Schema to write instead of one inferred from the first rows
+    schema: Option<FlatSchema>,
+    rows: Vec<FlattenedJson>,
+}
+
+impl<W: Write + Send> ParquetSink<W> {
+    pub fn new(writer: W) -> Self {
+        ParquetSink {
+            writer: Some(writer),
+            file: None,
+            schema: None,
+            rows: Vec::new(),
+        }
+    }
+
+    /// Types the file by a schema saved from an earlier run instead of
+    /// inferring one; decimal scales are still sized to the first rows
+    pub fn with_schema(mut self, schema: FlatSchema) -> Self {
+        self.schema = Some(schema);
+        self
+    }
+
+    /// Flushes buffered rows, writes the file footer and returns the underlying writer
+    pub fn finish(mut self) -> io::Result<W> {
+        RecordSink::flush(&mut self)?;
+        let (file, _) = self.file.take().ok_or_else(|| io::Error::other("Parquet file was not started"))?;
+        file.into_inner().map_err(io::Error::other)
+    }
+
+    fn write_batch(&mut self) -> Result<(), ParquetError> {
+        let (file, schema) = match &mut self.file {
+            Some((file, schema)) => (file, schema),
+            None => {
+                let schema = self.schema.take().unwrap_or_else(|| infer_schema(&self.rows));
+                let writer = self.writer.take().expect("writer is taken when the file is started");
+                let properties =
+                    WriterProperties::builder().set_compression(Compression::ZSTD(ZstdLevel::default())).build();
+                let schema = Arc::new(arrow_schema(&schema, &self.rows));
+                let file = ArrowWriter::try_new(writer, schema.clone(), Some(properties))?;
+                let (file, schema) = self.file.insert((file, schema));
+                (file, schema)
+            }
+        };
+        if self.rows.is_empty() {
+            return Ok(());
+        }
+
+        let rows = std::mem::take(&mut self.rows);
+        file.write(&record_batch(schema.clone(), &rows)?)
+    }
+}
+
+impl<W: Write + Send> RecordSink for ParquetSink<W> {
+    fn write_record(&mut self, record: FlattenedJson) -> io::Result<()> {
+        self.rows.push(record);
+        Ok(())
+    }
+
+    /// Hands buffered rows to the current row group, which is written out
+    /// once it is full or the file is finished
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_batch().map_err(io::Error::other)
+    }
+}
+
+/// Flattens a JSON file into a Parquet file with inferred column types,
+/// returning the number of records written
+///
+/// A path of `"-"` reads from standard input. Every record is held in memory
+/// until the schema is known.
+pub fn flatten_to_parquet(
+    input: impl AsRef<Path>,
+    output: impl AsRef<Path>,
+    options: &FlattenOptions,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let mut sink = ParquetSink::new(BufWriter::new(File::create(output)?));
+    let written = flatten_json_file_to_sink(input, &mut sink, options)?;
+    sink.finish()?.flush()?;
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::ColumnType;
+    use arrow_array::{Array, Decimal128Array, RecordBatch, StringArray};
+    use arrow_schema::DataType;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use std::io::{Seek, SeekFrom};
+
+    fn read(mut file: File) -> Vec<RecordBatch> {
+        file.seek(SeekFrom::Start(0)).unwrap();
+        let reader = ParquetRecordBatchReaderBuilder::try_new(file).unwrap().build().unwrap();
+        reader.collect::<Result<_, _>>().unwrap()
+    }
+
+    #[test]
+    fn test_parquet_sink_writes_typed_columns() {
+        let options = FlattenOptions::default();
+        let mut sink = ParquetSink::new(tempfile::tempfile().unwrap());
+        for value in [
+            serde_json::json!({"id": 1, "user": {"name": "Ann", "score": 9.5}, "ok": true}),
+            serde_json::json!({"id": 2, "user": {"name": null, "score": null}, "ok": false}),
+        ] {
+            sink.write_record(crate::flatten_json(&value, &options)).unwrap();
+        }
+        sink.flush().unwrap();
+        sink.write_record(crate::flatten_json(&serde_json::json!({"id": 3, "extra": 1}), &options)).unwrap();
+        let batches = read(sink.finish().unwrap());
+
+        let schema = batches[0].schema();
+        let types: Vec<(&str, &DataType)> = schema.fields().iter().map(|f| (f.name().as_str(), f.data_type())).collect();
+        assert_eq!(
+            types,
+            vec![
+                ("id", &DataType::Int64),
+                ("ok", &DataType::Boolean),
+                ("user.name", &DataType::Utf8),
+                ("user.score", &DataType::Float64),
+            ]
+        );
+        assert_eq!(batches.iter().map(RecordBatch::num_rows).sum::<usize>(), 3);
+        let names = batches[0].column(2).as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!(names.value(0), "Ann");
+        assert!(names.is_null(1));
+    }
+
+    #[test]
+    fn test_schema_hint_keeps_decimals_exact() {
+        let schema: FlatSchema = [("amount".to_string(), ColumnType::Decimal)].into_iter().collect();
+        let mut sink = ParquetSink::new(tempfile::tempfile().unwrap()).with_schema(schema);
+        for amount in ["12345678901234567890.125", "-0.5"] {
+            sink.write_record([("amount".to_string(), amount.to_string())].into_iter().collect()).unwrap();
+        }
+        let batches = read(sink.finish().unwrap());
+
+        let amounts = batches[0].column(0).as_any().downcast_ref::<Decimal128Array>().unwrap();
+        assert_eq!(amounts.data_type(), &DataType::Decimal128(38, 3));
+        assert_eq!(amounts.value_as_string(0), "12345678901234567890.125");
+        assert_eq!(amounts.value_as_string(1), "-0.500");
+    }
+}
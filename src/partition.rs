@@ -0,0 +1,274 @@
+// src/partition.rs
+//! Splitting output into one sink per value of a key
+use crate::schema::is_null;
+use crate::{FlattenedJson, RecordSink};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::io;
+
+/// Partition of records whose key is missing, null or empty
+pub const MISSING_PARTITION: &str = "_missing";
+
+/// Default partition taking the records of partitions beyond the cap under
+/// [`PartitionOverflow::Shared`]
+pub const OVERFLOW_PARTITION: &str = "_overflow";
+
+/// What [`PartitionedSink`] does with a new partition once `max_open` sinks are open
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PartitionOverflow {
+    /// Send its records to one shared partition, which takes one of the
+    /// `max_open` slots
+    Shared(String),
+    /// Flush and close the least recently written sink, opening it again
+    /// (to append) when its partition comes back
+    Evict,
+    /// Fail the run
+    Error,
+}
+
+impl Default for PartitionOverflow {
+    fn default() -> Self {
+        PartitionOverflow::Shared(OVERFLOW_PARTITION.to_string())
+    }
+}
+
+impl std::str::FromStr for PartitionOverflow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "shared" => Ok(PartitionOverflow::default()),
+            "evict" => Ok(PartitionOverflow::Evict),
+            "error" => Ok(PartitionOverflow::Error),
+            _ => match s.strip_prefix("shared:") {
+                Some(name) if !name.is_empty() => Ok(PartitionOverflow::Shared(name.to_string())),
+                _ => Err(format!("unknown partition overflow {:?} (expected shared[:NAME], evict or error)", s)),
+            },
+        }
+    }
+}
+
+impl fmt::Display for PartitionOverflow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PartitionOverflow::Shared(name) if name == OVERFLOW_PARTITION => f.write_str("shared"),
+            PartitionOverflow::Shared(name) => write!(f, "shared:{}", name),
+            PartitionOverflow::Evict => f.write_str("evict"),
+            PartitionOverflow::Error => f.write_str("error"),
+        }
+    }
+}
+
+/// Sink that writes records to one sink per value of a flattened key, e.g.
+/// one CSV file per `event.type`
+///
+/// Sinks are created by `open(partition, reopen)` the first time a partition
+/// is written; `reopen` is set when an evicted partition comes back, so the
+/// sink should append to what was written before. At most `max_open` sinks
+/// are open at once, and new partitions beyond that are handled per
+/// [`PartitionOverflow`]. Use [`partition_file_name`] to turn partitions into
+/// file names.
+pub struct PartitionedSink<S: RecordSink, F> {
+    key: String,
+    open: F,
+    max_open: usize,
+    overflow: PartitionOverflow,
+    /// Open sinks with the tick they were last written at
+    sinks: HashMap<String, (S, u64)>,
+    written: BTreeSet<String>,
+    overflow_records: u64,
+    tick: u64,
+}
+
+impl<S: RecordSink, F: FnMut(&str, bool) -> io::Result<S>> PartitionedSink<S, F> {
+    /// Partitions by `key`, keeping at most `max_open` sinks open (at least one)
+    pub fn new(key: &str, max_open: usize, open: F) -> Self {
+        PartitionedSink {
+            key: key.to_string(),
+            open,
+            max_open: max_open.max(1),
+            overflow: PartitionOverflow::default(),
+            sinks: HashMap::new(),
+            written: BTreeSet::new(),
+            overflow_records: 0,
+            tick: 0,
+        }
+    }
+
+    pub fn with_overflow(mut self, overflow: PartitionOverflow) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    /// Partitions written so far, sorted, including the shared overflow partition
+    pub fn partitions(&self) -> impl Iterator<Item = &str> {
+        self.written.iter().map(String::as_str)
+    }
+
+    /// Records sent to the shared partition instead of their own
+    pub fn overflow_records(&self) -> u64 {
+        self.overflow_records
+    }
+
+    /// Flushes every open sink and returns them with their partitions, sorted
+    pub fn into_sinks(mut self) -> io::Result<Vec<(String, S)>> {
+        RecordSink::flush(&mut self)?;
+        let mut sinks: Vec<(String, S)> = self.sinks.into_iter().map(|(name, (sink, _))| (name, sink)).collect();
+        sinks.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        Ok(sinks)
+    }
+
+    /// The partition a record is written to, before overflow
+    fn partition_of(&self, record: &FlattenedJson) -> String {
+        let value = record.get(&self.key).map(String::as_str);
+        match value {
+            Some(value) if !is_null(Some(value)) && !value.is_empty() => value.to_string(),
+            _ => MISSING_PARTITION.to_string(),
+        }
+    }
+
+    /// The open sink for a partition, opening it (or overflowing) if needed
+    fn sink_for(&mut self, partition: String) -> io::Result<&mut S> {
+        let mut partition = partition;
+        if !self.sinks.contains_key(&partition) {
+            match &self.overflow {
+                // One slot stays free for the shared partition
+                PartitionOverflow::Shared(shared) if partition != *shared => {
+                    let own = self.sinks.keys().filter(|name| *name != shared).count();
+                    if own + 1 >= self.max_open {
+                        partition = shared.clone();
+                        self.overflow_records += 1;
+                    }
+                }
+                PartitionOverflow::Evict if self.sinks.len() >= self.max_open => {
+                    let oldest = self.sinks.iter().min_by_key(|(_, (_, tick))| *tick).map(|(name, _)| name.clone());
+                    if let Some((mut sink, _)) = oldest.and_then(|name| self.sinks.remove(&name)) {
+                        sink.flush()?;
+                    }
+                }
+                PartitionOverflow::Error if self.sinks.len() >= self.max_open => {
+                    return Err(io::Error::other(format!(
+                        "partition {:?} would exceed the limit of {} open partitions",
+                        partition, self.max_open
+                    )));
+                }
+                _ => {}
+            }
+        }
+
+        self.tick += 1;
+        let tick = self.tick;
+        if !self.sinks.contains_key(&partition) {
+            let sink = (self.open)(&partition, self.written.contains(&partition))?;
+            self.written.insert(partition.clone());
+            self.sinks.insert(partition.clone(), (sink, tick));
+        }
+        let (sink, last_used) = self.sinks.get_mut(&partition).expect("the sink was just opened");
+        *last_used = tick;
+        Ok(sink)
+    }
+}
+
+impl<S: RecordSink, F: FnMut(&str, bool) -> io::Result<S>> RecordSink for PartitionedSink<S, F> {
+    fn write_record(&mut self, record: FlattenedJson) -> io::Result<()> {
+        let partition = self.partition_of(&record);
+        self.sink_for(partition)?.write_record(record)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.sinks.values_mut().try_for_each(|(sink, _)| sink.flush())
+    }
+}
+
+/// A file name for a partition: ASCII letters, digits, `-`, `_` and `.` are
+/// kept and every other byte is written as `%XX`, so distinct partitions get
+/// distinct names that are safe on every platform
+///
+/// A leading `.` is escaped too, so no partition becomes `..` or a hidden file.
+pub fn partition_file_name(partition: &str) -> String {
+    let mut name = String::with_capacity(partition.len());
+    for (i, byte) in partition.bytes().enumerate() {
+        match byte {
+            b'.' if i == 0 => name.push_str("%2E"),
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' | b'.' => name.push(byte as char),
+            _ => name.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(event: Option<&str>, id: usize) -> FlattenedJson {
+        let mut record: FlattenedJson = [("id".to_string(), id.to_string())].into_iter().collect();
+        if let Some(event) = event {
+            record.insert("event.type".to_string(), event.to_string());
+        }
+        record
+    }
+
+    fn ids(records: &[FlattenedJson]) -> Vec<&str> {
+        records.iter().map(|record| record["id"].as_str()).collect()
+    }
+
+    #[test]
+    fn test_records_are_split_by_key() {
+        let mut sink = PartitionedSink::new("event.type", 8, |_: &str, _| Ok(Vec::new()));
+        for (i, event) in [Some("click"), Some("view"), None, Some("click"), Some("null")].into_iter().enumerate() {
+            sink.write_record(record(event, i)).unwrap();
+        }
+        assert_eq!(sink.partitions().collect::<Vec<_>>(), ["_missing", "click", "view"]);
+
+        let sinks = sink.into_sinks().unwrap();
+        let split: Vec<(&str, Vec<&str>)> = sinks.iter().map(|(name, records)| (name.as_str(), ids(records))).collect();
+        assert_eq!(split, [("_missing", vec!["2", "4"]), ("click", vec!["0", "3"]), ("view", vec!["1"])]);
+    }
+
+    #[test]
+    fn test_overflow_strategies() {
+        let events = ["a", "b", "c", "a", "d"];
+
+        let mut shared = PartitionedSink::new("event.type", 3, |_: &str, _| Ok(Vec::new()));
+        for (i, event) in events.into_iter().enumerate() {
+            shared.write_record(record(Some(event), i)).unwrap();
+        }
+        assert_eq!(shared.overflow_records(), 2);
+        let sinks = shared.into_sinks().unwrap();
+        let split: Vec<(&str, Vec<&str>)> = sinks.iter().map(|(name, records)| (name.as_str(), ids(records))).collect();
+        assert_eq!(split, [("_overflow", vec!["2", "4"]), ("a", vec!["0", "3"]), ("b", vec!["1"])]);
+
+        let mut opened = Vec::new();
+        let mut evicting = PartitionedSink::new("event.type", 2, |name: &str, reopen| {
+            opened.push((name.to_string(), reopen));
+            Ok(Vec::new())
+        })
+        .with_overflow(PartitionOverflow::Evict);
+        for (i, event) in events.into_iter().enumerate() {
+            evicting.write_record(record(Some(event), i)).unwrap();
+        }
+        drop(evicting);
+        let opened: Vec<(&str, bool)> = opened.iter().map(|(name, reopen)| (name.as_str(), *reopen)).collect();
+        assert_eq!(opened, [("a", false), ("b", false), ("c", false), ("a", true), ("d", false)]);
+
+        let mut strict =
+            PartitionedSink::new("event.type", 2, |_: &str, _| Ok(Vec::new())).with_overflow(PartitionOverflow::Error);
+        strict.write_record(record(Some("a"), 0)).unwrap();
+        strict.write_record(record(Some("b"), 1)).unwrap();
+        assert!(strict.write_record(record(Some("c"), 2)).is_err());
+        assert!(strict.write_record(record(Some("a"), 3)).is_ok());
+    }
+
+    #[test]
+    fn test_partition_file_names_are_safe_and_distinct() {
+        assert_eq!(partition_file_name("click-2024_01.v2"), "click-2024_01.v2");
+        assert_eq!(partition_file_name("a/b c"), "a%2Fb%20c");
+        assert_eq!(partition_file_name(".."), "%2E.");
+        assert_eq!(partition_file_name("é"), "%C3%A9");
+        assert_ne!(partition_file_name("a/b"), partition_file_name("a_b"));
+        assert_eq!("shared:rest".parse::<PartitionOverflow>(), Ok(PartitionOverflow::Shared("rest".to_string())));
+        assert_eq!(PartitionOverflow::default().to_string(), "shared");
+        assert!("spill".parse::<PartitionOverflow>().is_err());
+    }
+}
@@ -0,0 +1,102 @@
+// src/pivot.rs
+//! Turning the elements of a flattened array into records of their own
+use crate::{FlattenOptions, FlattenedJson, ARRAY_METADATA_MARKER};
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Splits a record into one record per element of the array at `array_key`
+///
+/// `items.0.price` and `items.1.price` become `items.price` in the first and
+/// second record, and every key outside the array is copied to each of them;
+/// an element that is a scalar (`tags.0`) is written under the array's key.
+/// With `array_metadata` set, each record also gets the element's position
+/// under `<array_key>.#index`. A record without elements in the array comes
+/// back unchanged, as the only record.
+///
+/// `array_key` is the array's flattened key, escaped like the record's keys
+/// when `escape_keys` is set.
+pub fn pivot_array(record: &FlattenedJson, array_key: &str, options: &FlattenOptions) -> Vec<FlattenedJson> {
+    let separator = options.separator.as_str();
+    let element_prefix = format!("{}{}", array_key, separator);
+    let mut shared = FlattenedJson::default();
+    let mut elements: BTreeMap<usize, Vec<(String, String)>> = BTreeMap::new();
+    for (key, value) in record {
+        match key.strip_prefix(&element_prefix).and_then(|rest| split_index(rest, separator)) {
+            Some((index, tail)) => {
+                let key = match tail {
+                    Some(tail) => format!("{}{}", element_prefix, tail),
+                    None => array_key.to_string(),
+                };
+                elements.entry(index).or_default().push((key, value.clone()));
+            }
+            None => {
+                shared.insert(key.clone(), value.clone());
+            }
+        }
+    }
+    if elements.is_empty() {
+        return vec![shared];
+    }
+
+    let index_key = format!("{}{}index", element_prefix, ARRAY_METADATA_MARKER);
+    elements
+        .into_iter()
+        .map(|(index, entries)| {
+            let mut row = shared.clone();
+            if options.array_metadata {
+                row.insert(index_key.clone(), index.to_string());
+            }
+            row.extend(entries);
+            row
+        })
+        .collect()
+}
+
+/// Splits `3.price` into the index 3 and the rest of the key
+fn split_index<'a>(rest: &'a str, separator: &str) -> Option<(usize, Option<&'a str>)> {
+    let (index, tail) = match rest.split_once(separator) {
+        Some((index, tail)) => (index, Some(tail)),
+        None => (rest, None),
+    };
+    if index.is_empty() || !index.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some((index.parse().ok()?, tail))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flatten_json;
+    use serde_json::json;
+
+    #[test]
+    fn test_array_elements_become_records() {
+        let json = json!({"order": 5, "items": [{"sku": "a", "price": 2}, {"sku": "b", "tags": ["x"]}], "tags": [1]});
+        let options = FlattenOptions {
+            array_metadata: true,
+            ..Default::default()
+        };
+        let rows = pivot_array(&flatten_json(&json, &options), "items", &options);
+
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0]["items.sku"], "a");
+        assert_eq!(rows[0]["items.price"], "2");
+        assert_eq!(rows[0]["items.#index"], "0");
+        assert_eq!(rows[1]["items.tags.0"], "x");
+        assert!(!rows[1].contains_key("items.price"));
+        for row in &rows {
+            assert_eq!(row["order"], "5");
+            assert_eq!(row["tags.0"], "1");
+            assert_eq!(row["items.#length"], "2");
+        }
+
+        let scalars = pivot_array(&flatten_json(&json!({"id": 1, "tags": ["x", "y"]}), &options), "tags", &options);
+        assert_eq!(scalars.iter().map(|row| row["tags"].as_str()).collect::<Vec<_>>(), ["x", "y"]);
+        let none = flatten_json(&json!({"id": 1}), &options);
+        assert_eq!(pivot_array(&none, "items", &options), vec![none.clone()]);
+    }
+}
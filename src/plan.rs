@@ -0,0 +1,466 @@
+// src/plan.rs
+//! Keeping, dropping, renaming and coercing keys by pattern, compiled once
+use crate::keys::{escape_key, split_key};
+use crate::schema::{is_null, ColumnType};
+use crate::{FlattenOptions, FlattenedJson, RecordSink, SkippedDocument};
+use regex::{Regex, RegexSet};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+
+/// Prefix of a [`PlanRule`] pattern that is a regex over the whole key
+pub const REGEX_PREFIX: &str = "re:";
+
+/// What a [`PlanRule`] does to the keys it matches
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum KeyAction {
+    /// Once a plan has a keep rule, keys no keep rule matches are dropped
+    Keep,
+    Drop,
+    /// Moves the value to another key. Regex patterns can use their groups
+    /// (`${1}`); after a glob ending in `**`, a target ending in `**` keeps the
+    /// segments it matched, so `user.**` to `u.**` renames `user.a.b` to `u.a.b`
+    Rename(String),
+    /// Converts the value to the type, writing `null` when it can't be
+    Coerce(ColumnType),
+}
+
+/// A pattern and what to do with the keys matching it
+///
+/// Parsed from `keep:<pattern>`, `drop:<pattern>`, `rename:<pattern>=<key>`
+/// or `coerce:<pattern>=<type>`. Patterns are [`KeyPattern`](crate::KeyPattern) globs, or regexes
+/// over the whole key when they start with [`REGEX_PREFIX`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PlanRule {
+    pub pattern: String,
+    pub action: KeyAction,
+}
+
+impl PlanRule {
+    pub fn keep(pattern: &str) -> Self {
+        Self::new(pattern, KeyAction::Keep)
+    }
+
+    pub fn drop(pattern: &str) -> Self {
+        Self::new(pattern, KeyAction::Drop)
+    }
+
+    pub fn rename(pattern: &str, target: &str) -> Self {
+        Self::new(pattern, KeyAction::Rename(target.to_string()))
+    }
+
+    pub fn coerce(pattern: &str, column_type: ColumnType) -> Self {
+        Self::new(pattern, KeyAction::Coerce(column_type))
+    }
+
+    fn new(pattern: &str, action: KeyAction) -> Self {
+        PlanRule {
+            pattern: pattern.to_string(),
+            action,
+        }
+    }
+}
+
+impl core::str::FromStr for PlanRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, rule) = s
+            .split_once(':')
+            .ok_or_else(|| format!("expected <action>:<pattern>, got {:?}", s))?;
+        let argument = || {
+            rule.rsplit_once('=')
+                .ok_or_else(|| format!("expected {}:<pattern>=<argument>, got {:?}", kind, s))
+        };
+        match kind {
+            "keep" => Ok(Self::keep(rule)),
+            "drop" => Ok(Self::drop(rule)),
+            "rename" => argument().map(|(pattern, target)| Self::rename(pattern, target)),
+            "coerce" => {
+                let (pattern, column_type) = argument()?;
+                Ok(Self::coerce(pattern, column_type.parse()?))
+            }
+            _ => Err(format!("unknown action {:?} in {:?} (expected keep, drop, rename or coerce)", kind, s)),
+        }
+    }
+}
+
+impl fmt::Display for PlanRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.action {
+            KeyAction::Keep => write!(f, "keep:{}", self.pattern),
+            KeyAction::Drop => write!(f, "drop:{}", self.pattern),
+            KeyAction::Rename(target) => write!(f, "rename:{}={}", self.pattern, target),
+            KeyAction::Coerce(column_type) => write!(f, "coerce:{}={}", self.pattern, column_type),
+        }
+    }
+}
+
+/// A [`PlanRule`] that can't be compiled
+#[derive(Debug)]
+pub enum PlanError {
+    Regex { rule: String, error: regex::Error },
+    /// A rename target ending in `**` after a pattern that doesn't end in
+    /// its only `**`
+    RenameTail(String),
+}
+
+impl fmt::Display for PlanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlanError::Regex { rule, error } => write!(f, "invalid regex in {:?}: {}", rule, error),
+            PlanError::RenameTail(rule) => write!(
+                f,
+                "{:?} keeps the segments of a ** its pattern doesn't end in, or has more than one of",
+                rule
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PlanError {}
+
+/// A trie over the segments of glob patterns; patterns sharing leading
+/// segments share nodes, so one walk of a key finds every glob it matches
+#[derive(Debug, Default)]
+struct Node {
+    literal: BTreeMap<String, Node>,
+    any: Option<Box<Node>>,
+    any_depth: Option<Box<Node>>,
+    /// Rules whose pattern ends here
+    rules: Vec<usize>,
+}
+
+impl Node {
+    fn insert(&mut self, pattern: &str, separator: &str, rule: usize) {
+        let mut node = self;
+        for segment in split_key(pattern, separator, true) {
+            node = match segment.as_str() {
+                "*" => node.any.get_or_insert_with(Default::default),
+                "**" => node.any_depth.get_or_insert_with(Default::default),
+                _ => node.literal.entry(segment).or_default(),
+            };
+        }
+        node.rules.push(rule);
+    }
+
+    fn collect(&self, segments: &[String], matched: &mut Vec<usize>) {
+        if segments.is_empty() {
+            matched.extend(&self.rules);
+        }
+        if let Some(deep) = &self.any_depth {
+            (0..=segments.len()).for_each(|skip| deep.collect(&segments[skip..], matched));
+        }
+        if let Some((first, rest)) = segments.split_first() {
+            if let Some(child) = self.literal.get(first) {
+                child.collect(rest, matched);
+            }
+            if let Some(any) = &self.any {
+                any.collect(rest, matched);
+            }
+        }
+    }
+
+    /// Nodes below and including this one, and the deepest path's length
+    fn size(&self) -> (usize, usize) {
+        let children = self.literal.values().chain(self.any.as_deref()).chain(self.any_depth.as_deref());
+        children.map(Node::size).fold((1, 0), |(nodes, depth), (n, d)| (nodes + n, depth.max(d + 1)))
+    }
+
+    fn write_tree(&self, f: &mut fmt::Formatter<'_>, rules: &[PlanRule], indent: usize) -> fmt::Result {
+        let children = self.literal.iter().map(|(segment, node)| (segment.as_str(), node));
+        let wildcards = [("*", self.any.as_deref()), ("**", self.any_depth.as_deref())];
+        for (segment, node) in children.chain(wildcards.into_iter().filter_map(|(s, node)| Some((s, node?)))) {
+            write!(f, "{:indent$}{}", "", segment, indent = indent)?;
+            for &rule in &node.rules {
+                write!(f, " -> #{} {}", rule, rules[rule])?;
+            }
+            writeln!(f)?;
+            node.write_tree(f, rules, indent + 2)?;
+        }
+        Ok(())
+    }
+}
+
+/// Counts describing a compiled [`KeyPlan`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct PlanStats {
+    pub rules: usize,
+    /// Rules with glob patterns, matched through the trie
+    pub glob_rules: usize,
+    /// Rules with regex patterns, matched together as one regex set
+    pub regex_rules: usize,
+    /// Trie nodes, not counting the root
+    pub trie_nodes: usize,
+    pub trie_depth: usize,
+}
+
+/// [`PlanRule`]s compiled once, into a prefix trie for the glob patterns and
+/// a [`RegexSet`] for the regex ones
+///
+/// Build one per engine or run and share it (it is `Send + Sync`, and
+/// [`PlanSink`] and the engine hold it in an `Arc`); applying it never
+/// compiles anything. For each key, drop beats keep, and the first matching
+/// rename and coercion in rule order apply. The plan can be inspected through
+/// [`stats`](KeyPlan::stats), [`explain`](KeyPlan::explain) and `Display`.
+#[derive(Debug)]
+pub struct KeyPlan {
+    rules: Vec<PlanRule>,
+    trie: Node,
+    regexes: RegexSet,
+    /// Rule of each pattern in `regexes`, with the pattern compiled on its
+    /// own for renames
+    regex_rules: Vec<(usize, Regex)>,
+    /// Segments a rename's pattern takes before the `**` whose segments its
+    /// target keeps
+    rename_heads: BTreeMap<usize, usize>,
+    has_keep: bool,
+    separator: String,
+    escaped: bool,
+}
+
+impl KeyPlan {
+    /// Compiles `rules` for keys flattened with `options`
+    pub fn new(rules: impl IntoIterator<Item = PlanRule>, options: &FlattenOptions) -> Result<KeyPlan, PlanError> {
+        let rules: Vec<PlanRule> = rules.into_iter().collect();
+        let separator = &options.separator;
+        let mut trie = Node::default();
+        let mut regex_rules = Vec::new();
+        let mut rename_heads = BTreeMap::new();
+        for (index, rule) in rules.iter().enumerate() {
+            if let Some(regex) = rule.pattern.strip_prefix(REGEX_PREFIX) {
+                let regex = Regex::new(&format!("^(?:{})$", regex)).map_err(|error| PlanError::Regex {
+                    rule: rule.to_string(),
+                    error,
+                })?;
+                regex_rules.push((index, regex));
+                continue;
+            }
+            trie.insert(&rule.pattern, separator, index);
+            if let KeyAction::Rename(target) = &rule.action {
+                if split_key(target, separator, true).last().is_some_and(|last| last == "**") {
+                    let segments = split_key(&rule.pattern, separator, true);
+                    match segments.iter().position(|segment| segment == "**") {
+                        Some(head) if head + 1 == segments.len() => rename_heads.insert(index, head),
+                        _ => return Err(PlanError::RenameTail(rule.to_string())),
+                    };
+                }
+            }
+        }
+        // Each regex compiled on its own, so only the set's size limit can fail here
+        let regexes = RegexSet::new(regex_rules.iter().map(|(_, regex)| regex.as_str())).map_err(|error| {
+            PlanError::Regex {
+                rule: format!("{} regex rules", regex_rules.len()),
+                error,
+            }
+        })?;
+        Ok(KeyPlan {
+            has_keep: rules.iter().any(|rule| rule.action == KeyAction::Keep),
+            rules,
+            trie,
+            regexes,
+            regex_rules,
+            rename_heads,
+            separator: separator.clone(),
+            escaped: options.escape_keys,
+        })
+    }
+
+    pub fn rules(&self) -> &[PlanRule] {
+        &self.rules
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn stats(&self) -> PlanStats {
+        let (nodes, depth) = self.trie.size();
+        PlanStats {
+            rules: self.rules.len(),
+            glob_rules: self.rules.len() - self.regex_rules.len(),
+            regex_rules: self.regex_rules.len(),
+            trie_nodes: nodes - 1,
+            trie_depth: depth,
+        }
+    }
+
+    /// Indices of the rules matching a key, in rule order
+    fn matching(&self, key: &str, segments: &[String]) -> Vec<usize> {
+        let mut matched = Vec::new();
+        self.trie.collect(segments, &mut matched);
+        if !self.regex_rules.is_empty() {
+            matched.extend(self.regexes.matches(key).into_iter().map(|i| self.regex_rules[i].0));
+        }
+        matched.sort_unstable();
+        matched.dedup();
+        matched
+    }
+
+    /// The rules matching a key, in rule order
+    pub fn explain(&self, key: &str) -> Vec<&PlanRule> {
+        let segments = split_key(key, &self.separator, self.escaped);
+        self.matching(key, &segments).into_iter().map(|rule| &self.rules[rule]).collect()
+    }
+
+    /// Keeps, drops, renames and coerces the keys of a record
+    ///
+    /// A renamed key replaces any key already holding its new name.
+    pub fn apply(&self, record: &mut FlattenedJson) {
+        if self.is_empty() {
+            return;
+        }
+        let mut renamed = Vec::new();
+        record.retain(|key, value| {
+            let segments = split_key(key, &self.separator, self.escaped);
+            let matched = self.matching(key, &segments);
+            let find = |pick: fn(&KeyAction) -> bool| {
+                matched.iter().map(|&i| (i, &self.rules[i].action)).find(|(_, action)| pick(action))
+            };
+            if find(|action| *action == KeyAction::Drop).is_some()
+                || (self.has_keep && find(|action| *action == KeyAction::Keep).is_none())
+            {
+                return false;
+            }
+            if let Some((_, KeyAction::Coerce(column_type))) = find(|action| matches!(action, KeyAction::Coerce(_))) {
+                coerce(value, *column_type);
+            }
+            match find(|action| matches!(action, KeyAction::Rename(_))) {
+                Some((rule, KeyAction::Rename(target))) => {
+                    renamed.push((self.renamed(rule, target, key, &segments), std::mem::take(value)));
+                    false
+                }
+                _ => true,
+            }
+        });
+        record.extend(renamed);
+    }
+
+    fn renamed(&self, rule: usize, target: &str, key: &str, segments: &[String]) -> String {
+        if let Some((_, regex)) = self.regex_rules.iter().find(|(index, _)| *index == rule) {
+            return regex.replace(key, target).into_owned();
+        }
+        let Some(&head) = self.rename_heads.get(&rule) else {
+            return target.to_string();
+        };
+        let prefix = &target[..target.len() - "**".len()];
+        let tail = segments[head..].iter().map(|segment| match self.escaped {
+            true => escape_key(segment, &self.separator),
+            false => segment.into(),
+        });
+        let tail: Vec<_> = tail.collect();
+        match tail.is_empty() {
+            true => prefix.strip_suffix(self.separator.as_str()).unwrap_or(prefix).to_string(),
+            false => format!("{}{}", prefix, tail.join(&self.separator)),
+        }
+    }
+}
+
+/// Converts a value to `column_type`, or to `null` when it doesn't fit;
+/// integral floats become ints
+fn coerce(value: &mut String, column_type: ColumnType) {
+    if is_null(Some(value)) || column_type.fits(value) {
+        return;
+    }
+    let integral = value.parse::<f64>().ok().filter(|n| n.fract() == 0.0 && n.abs() < 2f64.powi(53));
+    *value = match (column_type, integral) {
+        (ColumnType::Int, Some(n)) => (n as i64).to_string(),
+        _ => "null".to_string(),
+    };
+}
+
+/// The trie, one line per segment with the rules ending there, then the regex set
+impl fmt::Display for KeyPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let PlanStats {
+            glob_rules,
+            regex_rules,
+            trie_nodes,
+            trie_depth,
+            ..
+        } = self.stats();
+        writeln!(f, "glob rules: {}, trie nodes: {}, trie depth: {}", glob_rules, trie_nodes, trie_depth)?;
+        self.trie.write_tree(f, &self.rules, 2)?;
+        writeln!(f, "regex rules: {}", regex_rules)?;
+        for (rule, regex) in &self.regex_rules {
+            writeln!(f, "  {} -> #{} {}", regex.as_str(), rule, self.rules[*rule])?;
+        }
+        Ok(())
+    }
+}
+
+/// Sink that applies a [`KeyPlan`] to every record before passing it on
+pub struct PlanSink<S: RecordSink> {
+    inner: S,
+    plan: Arc<KeyPlan>,
+}
+
+impl<S: RecordSink> PlanSink<S> {
+    pub fn new(inner: S, plan: Arc<KeyPlan>) -> Self {
+        PlanSink { inner, plan }
+    }
+
+    pub fn plan(&self) -> &Arc<KeyPlan> {
+        &self.plan
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: RecordSink> RecordSink for PlanSink<S> {
+    fn write_record(&mut self, mut record: FlattenedJson) -> io::Result<()> {
+        self.plan.apply(&mut record);
+        self.inner.write_record(record)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    fn skip_document(&mut self, skipped: SkippedDocument) -> io::Result<()> {
+        self.inner.skip_document(skipped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flatten_json;
+    use serde_json::json;
+
+    #[test]
+    fn test_plan_keeps_drops_renames_and_coerces() {
+        let rules = ["keep:user.**", "keep:re:price_(\\w+)", "drop:user.debug.*", "rename:user.**=u.**"]
+            .into_iter()
+            .chain(["rename:re:price_(\\w+)=${1}_price", "coerce:**.age=int", "coerce:user.name=bool"])
+            .map(|rule| rule.parse::<PlanRule>().unwrap());
+        let options = FlattenOptions::default();
+        let plan = KeyPlan::new(rules, &options).unwrap();
+        let document = json!({
+            "user": {"name": "a", "age": 41.0, "debug": {"trace": "t"}, "tags": ["x"]},
+            "price_usd": 3,
+            "other": 1,
+        });
+        let mut record = flatten_json(&document, &options);
+        plan.apply(&mut record);
+
+        let mut pairs: Vec<(&str, &str)> = record.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+        pairs.sort_unstable();
+        assert_eq!(pairs, [("u.age", "41"), ("u.name", "null"), ("u.tags.0", "x"), ("usd_price", "3")]);
+
+        let explained: Vec<String> = plan.explain("user.age").iter().map(ToString::to_string).collect();
+        assert_eq!(explained, ["keep:user.**", "rename:user.**=u.**", "coerce:**.age=int"]);
+        let stats = plan.stats();
+        assert_eq!((stats.rules, stats.glob_rules, stats.regex_rules), (7, 5, 2));
+        assert_eq!((stats.trie_nodes, stats.trie_depth), (7, 3));
+        assert!(plan.to_string().contains("  user\n    debug\n      * -> #2 drop:user.debug.*\n"));
+
+        let tail = KeyPlan::new([PlanRule::rename("**.a", "b.**")], &options);
+        assert!(matches!(tail, Err(PlanError::RenameTail(_))));
+        assert!("coerce:a=date".parse::<PlanRule>().is_err());
+    }
+}
@@ -0,0 +1,174 @@
+// src/pool.rs
+//! Result sets whose repeated keys and values share storage
+use crate::{FlattenedJson, FxBuildHasher, RecordSink};
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::sync::Arc;
+
+/// A flattened record whose keys and values point into a [`StringPool`]
+///
+/// Look values up by `&str` as with [`FlattenedJson`]; cloning a key or value
+/// only bumps a reference count.
+pub type PooledRecord = HashMap<Arc<str>, Arc<str>, FxBuildHasher>;
+
+/// How much a [`StringPool`] saved
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    /// Keys and values passed through the pool
+    pub strings: usize,
+    /// Distinct strings stored
+    pub unique_strings: usize,
+    /// Bytes of text stored once for the distinct strings
+    pub stored_bytes: usize,
+    /// Bytes of text not stored because an equal string was already pooled
+    pub saved_bytes: usize,
+}
+
+impl PoolStats {
+    /// Share of the text bytes that were duplicates, from 0.0 to 1.0
+    pub fn saved_ratio(&self) -> f64 {
+        match self.stored_bytes + self.saved_bytes {
+            0 => 0.0,
+            total => self.saved_bytes as f64 / total as f64,
+        }
+    }
+}
+
+/// Interns strings so equal keys and values are stored once
+#[derive(Debug)]
+pub struct StringPool {
+    strings: HashSet<Arc<str>, FxBuildHasher>,
+    max_len: usize,
+    stats: PoolStats,
+}
+
+impl Default for StringPool {
+    fn default() -> Self {
+        StringPool::new()
+    }
+}
+
+impl StringPool {
+    /// Creates a pool that interns strings of any length
+    pub fn new() -> Self {
+        StringPool::with_max_len(usize::MAX)
+    }
+
+    /// Creates a pool that leaves strings longer than `max_len` bytes unshared
+    ///
+    /// Long values such as descriptions or embedded JSON rarely repeat, and
+    /// skipping them saves hashing them and keeping them in the pool's table.
+    pub fn with_max_len(max_len: usize) -> Self {
+        StringPool {
+            strings: HashSet::default(),
+            max_len,
+            stats: PoolStats::default(),
+        }
+    }
+
+    /// Returns the pooled copy of `text`, adding it if it is new
+    pub fn intern(&mut self, text: String) -> Arc<str> {
+        self.stats.strings += 1;
+        if text.len() > self.max_len {
+            self.stats.stored_bytes += text.len();
+            return Arc::from(text);
+        }
+        if let Some(pooled) = self.strings.get(text.as_str()) {
+            self.stats.saved_bytes += text.len();
+            return Arc::clone(pooled);
+        }
+        let pooled: Arc<str> = Arc::from(text);
+        self.stats.unique_strings += 1;
+        self.stats.stored_bytes += pooled.len();
+        self.strings.insert(Arc::clone(&pooled));
+        pooled
+    }
+
+    /// Moves a record's keys and values into the pool
+    pub fn intern_record(&mut self, record: FlattenedJson) -> PooledRecord {
+        let mut pooled = PooledRecord::with_capacity_and_hasher(record.len(), FxBuildHasher::default());
+        for (key, value) in record {
+            let key = self.intern(key);
+            pooled.insert(key, self.intern(value));
+        }
+        pooled
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        self.stats
+    }
+}
+
+/// Collects records in memory with their repeated keys and values pooled
+///
+/// Useful for large result sets of enum-like values: status codes, country
+/// codes and booleans are stored once however many records hold them.
+#[derive(Debug, Default)]
+pub struct PooledSink {
+    pool: StringPool,
+    records: Vec<PooledRecord>,
+}
+
+impl PooledSink {
+    pub fn new() -> Self {
+        PooledSink::default()
+    }
+
+    /// Creates a sink whose pool leaves strings longer than `max_len` bytes unshared
+    pub fn with_max_len(max_len: usize) -> Self {
+        PooledSink {
+            pool: StringPool::with_max_len(max_len),
+            records: Vec::new(),
+        }
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        self.pool.stats()
+    }
+
+    /// Returns the records in input order with the pool's stats
+    ///
+    /// The pool's table is dropped; the strings live on in the records.
+    pub fn into_records(self) -> (Vec<PooledRecord>, PoolStats) {
+        (self.records, self.pool.stats())
+    }
+}
+
+impl RecordSink for PooledSink {
+    fn write_record(&mut self, record: FlattenedJson) -> io::Result<()> {
+        let record = self.pool.intern_record(record);
+        self.records.push(record);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{flatten_json_multi_str, FlattenOptions};
+
+    #[test]
+    fn test_repeated_values_share_storage() {
+        let json = r#"{"country": "DE", "active": true, "id": 1}
+            {"country": "DE", "active": true, "id": 2}
+            {"country": "FR", "active": false, "id": 3}"#;
+        let mut sink = PooledSink::new();
+        for record in flatten_json_multi_str(json, &FlattenOptions::default()).unwrap() {
+            sink.write_record(record).unwrap();
+        }
+        let (records, stats) = sink.into_records();
+
+        assert_eq!(&*records[2]["country"], "FR");
+        assert!(Arc::ptr_eq(&records[0]["country"], &records[1]["country"]));
+        assert_eq!(stats.strings, 18);
+        // country, active, id, DE, true, 1, 2, FR, false, 3
+        assert_eq!(stats.unique_strings, 10);
+        assert_eq!(stats.saved_bytes, 2 * "countryactiveid".len() + "DE".len() + "true".len());
+        assert!(stats.saved_ratio() > 0.5);
+
+        let mut pool = StringPool::with_max_len(2);
+        let first = pool.intern("long".to_string());
+        assert!(!Arc::ptr_eq(&first, &pool.intern("long".to_string())));
+        assert_eq!(pool.stats().unique_strings, 0);
+    }
+}
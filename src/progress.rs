@@ -0,0 +1,192 @@
+// src/progress.rs
+//! Per-chunk hook and time budget of the file and reader pipelines
+use crate::FlattenOptions;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// What a [`ChunkHook`] is told after a chunk's records reach the sink
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChunkProgress {
+    /// Index of the chunk, counting from 0
+    pub index: usize,
+    /// Records in this chunk
+    pub records: usize,
+    /// Records written so far, this chunk included
+    pub total_records: usize,
+    /// Records of this chunk left out by `filters`
+    pub filtered: usize,
+    /// Documents of this chunk left out by a `Skip` policy
+    pub skipped: usize,
+    /// Time since the pipeline started
+    pub elapsed: Duration,
+}
+
+/// Callback run after each chunk in `flatten_json_file` and the other
+/// pipelines that read documents in chunks
+///
+/// The hook runs on the thread writing to the sink, between chunks, so a slow
+/// hook slows the whole pipeline down: useful for throttling, and a place to
+/// record a checkpoint once a chunk is safely written.
+#[derive(Clone)]
+pub struct ChunkHook(Arc<dyn Fn(&ChunkProgress) + Send + Sync>);
+
+impl ChunkHook {
+    pub fn new(hook: impl Fn(&ChunkProgress) + Send + Sync + 'static) -> Self {
+        ChunkHook(Arc::new(hook))
+    }
+}
+
+impl fmt::Debug for ChunkHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ChunkHook")
+    }
+}
+
+/// Where a run stopped by `time_budget` left off
+///
+/// Every document flattens to one record, so this is also the number of
+/// records the runs so far have written, left out by `filters` or skipped. Carry on by running again with
+/// `skip_documents` set to `documents`; the skipped documents are still read,
+/// but not parsed or flattened. A run whose last chunk ends past the budget
+/// may return a checkpoint at the end of the input, and the next run then
+/// returns no records and no checkpoint.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// Documents from the start of the input, skipped ones included, whose
+    /// records reached the sink
+    pub documents: u64,
+}
+
+/// Counts chunks for the hook and time budget of one pipeline run
+pub(crate) struct ChunkTracker<'a> {
+    hook: Option<&'a ChunkHook>,
+    started: Instant,
+    deadline: Option<Instant>,
+    skipped: u64,
+    index: usize,
+    total_records: usize,
+    total_filtered: u64,
+    total_skipped: u64,
+}
+
+/// How a pipeline run ended
+#[derive(Debug)]
+pub(crate) struct PipelineEnd {
+    pub(crate) written: usize,
+    /// Records left out by `filters`
+    pub(crate) filtered: u64,
+    pub(crate) checkpoint: Option<Checkpoint>,
+}
+
+impl<'a> ChunkTracker<'a> {
+    pub(crate) fn new(options: &'a FlattenOptions) -> Self {
+        let started = Instant::now();
+        ChunkTracker {
+            hook: options.chunk_hook.as_ref(),
+            started,
+            deadline: options.time_budget.map(|budget| started + budget),
+            skipped: options.skip_documents,
+            index: 0,
+            total_records: 0,
+            total_filtered: 0,
+            total_skipped: 0,
+        }
+    }
+
+    /// Reports a chunk of `records` records as written, `filtered` as left
+    /// out and `skipped` documents as set aside, returning a checkpoint if the
+    /// run is out of time and should stop before the next
+    pub(crate) fn chunk_done(&mut self, records: usize, filtered: usize, skipped: usize) -> Option<Checkpoint> {
+        self.total_records += records;
+        self.total_filtered += filtered as u64;
+        self.total_skipped += skipped as u64;
+        if let Some(hook) = self.hook {
+            (hook.0)(&ChunkProgress {
+                index: self.index,
+                records,
+                total_records: self.total_records,
+                filtered,
+                skipped,
+                elapsed: self.started.elapsed(),
+            });
+        }
+        self.index += 1;
+        match self.deadline {
+            Some(deadline) if Instant::now() >= deadline => Some(Checkpoint {
+                documents: self.skipped + self.total_records as u64 + self.total_filtered + self.total_skipped,
+            }),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn finish(&self, checkpoint: Option<Checkpoint>) -> PipelineEnd {
+        PipelineEnd {
+            written: self.total_records,
+            filtered: self.total_filtered,
+            checkpoint,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{flatten_json_file, flatten_json_file_timed, flatten_reader_to_sink_timed, RecordIdSource};
+    use std::io::Write;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_hook_sees_every_chunk() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        for i in 0..10 {
+            writeln!(file, "{{\"i\": {}}}", i).unwrap();
+        }
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let hook_seen = Arc::clone(&seen);
+        let options = FlattenOptions {
+            chunk_size: 4,
+            chunk_hook: Some(ChunkHook::new(move |progress| {
+                hook_seen.lock().unwrap().push((progress.index, progress.records, progress.total_records))
+            })),
+            ..Default::default()
+        };
+
+        let records = flatten_json_file(file.path().to_str().unwrap(), &options).unwrap();
+        assert_eq!(records.len(), 10);
+        assert_eq!(*seen.lock().unwrap(), vec![(0, 4, 4), (1, 4, 8), (2, 2, 10)]);
+    }
+
+    #[test]
+    fn test_time_budget_stops_between_chunks_with_a_checkpoint() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        for i in 0..10 {
+            writeln!(file, "{{\"i\": {}}}", i).unwrap();
+        }
+        let mut options = FlattenOptions {
+            chunk_size: 4,
+            time_budget: Some(Duration::ZERO),
+            record_id: RecordIdSource::Sequence,
+            ..Default::default()
+        };
+        let (records, checkpoint) = flatten_json_file_timed(file.path(), &options).unwrap();
+        assert_eq!(records.len(), 4);
+        assert_eq!(checkpoint, Some(Checkpoint { documents: 4 }));
+
+        // Carrying on skips the documents already written, and ids keep counting
+        options.skip_documents = checkpoint.unwrap().documents;
+        options.time_budget = None;
+        let (records, checkpoint) = flatten_json_file_timed(file.path(), &options).unwrap();
+        let ids: Vec<(&str, &str)> = records.iter().map(|r| (r["_id"].as_str(), r["i"].as_str())).collect();
+        assert_eq!(ids, [("4", "4"), ("5", "5"), ("6", "6"), ("7", "7"), ("8", "8"), ("9", "9")]);
+        assert_eq!(checkpoint, None);
+
+        let mut sink = Vec::new();
+        let input = std::fs::File::open(file.path()).unwrap();
+        options.skip_documents = 8;
+        options.time_budget = Some(Duration::ZERO);
+        let run = flatten_reader_to_sink_timed(input, &mut sink, &options).unwrap();
+        assert_eq!(run, (2, Some(Checkpoint { documents: 10 })));
+    }
+}
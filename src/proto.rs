@@ -0,0 +1,197 @@
+// src/proto.rs
+use crate::schema::{is_null, ColumnType, FlatSchema};
+use crate::FlattenedJson;
+use alloc::collections::BTreeSet;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+/// Field numbers 19000 to 19999 are reserved by protobuf
+const RESERVED_FIELD_NUMBERS: core::ops::Range<u32> = 19_000..20_000;
+
+/// One field of a [`ProtoMessage`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProtoField {
+    /// Protobuf identifier derived from the flattened key
+    pub name: String,
+    pub number: u32,
+    pub column_type: ColumnType,
+    /// Flattened key the field is read from, kept as its `json_name`
+    pub key: String,
+}
+
+/// A protobuf message definition for flattened records
+///
+/// Built from an inferred schema (see [`infer_schema`](crate::schema::infer_schema)).
+/// Every field is a proto3 `optional` so nulls and missing keys stay
+/// distinguishable from zero values. Flattened keys become identifiers by
+/// replacing every character that is not a letter, digit or underscore with
+/// `_`; the original key is kept as the field's `json_name`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProtoMessage {
+    pub name: String,
+    pub fields: Vec<ProtoField>,
+}
+
+impl ProtoMessage {
+    /// Numbers the columns of a schema in name order, starting at 1
+    pub fn from_schema(name: &str, schema: &FlatSchema) -> Self {
+        let mut used = BTreeSet::new();
+        let mut number = 0;
+        let fields = schema
+            .iter()
+            .map(|(key, column_type)| {
+                number += 1;
+                if RESERVED_FIELD_NUMBERS.contains(&number) {
+                    number = RESERVED_FIELD_NUMBERS.end;
+                }
+                let mut name = field_name(key);
+                if !used.insert(name.clone()) {
+                    name = format!("{}_{}", name, number);
+                    used.insert(name.clone());
+                }
+                ProtoField {
+                    name,
+                    number,
+                    column_type: *column_type,
+                    key: key.clone(),
+                }
+            })
+            .collect();
+        ProtoMessage {
+            name: name.to_string(),
+            fields,
+        }
+    }
+
+    /// Renders a `.proto` file holding this message
+    pub fn to_proto(&self) -> String {
+        let mut proto = format!("syntax = \"proto3\";\n\nmessage {} {{\n", self.name);
+        for field in &self.fields {
+            let proto_type = match field.column_type {
+                ColumnType::Bool => "bool",
+                ColumnType::Int => "int64",
+                ColumnType::Float => "double",
+                ColumnType::Decimal | ColumnType::Text => "string",
+            };
+            let _ = writeln!(
+                proto,
+                "  optional {} {} = {} [json_name = \"{}\"];",
+                proto_type,
+                field.name,
+                field.number,
+                field.key.replace('\\', "\\\\").replace('"', "\\\"")
+            );
+        }
+        proto.push_str("}\n");
+        proto
+    }
+
+    /// Serializes a record as this message in the protobuf wire format
+    ///
+    /// Nulls, missing keys, keys that are not fields and values that don't
+    /// fit their field's type are left out.
+    pub fn encode(&self, record: &FlattenedJson) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for field in &self.fields {
+            let value = record.get(&field.key).map(String::as_str);
+            let value = match value {
+                Some(value) if !is_null(Some(value)) => value,
+                _ => continue,
+            };
+            match field.column_type {
+                ColumnType::Bool if value == "true" || value == "false" => {
+                    write_varint(&mut buf, u64::from(field.number) << 3);
+                    write_varint(&mut buf, u64::from(value == "true"));
+                }
+                ColumnType::Int => {
+                    if let Ok(n) = value.parse::<i64>() {
+                        write_varint(&mut buf, u64::from(field.number) << 3);
+                        write_varint(&mut buf, n as u64);
+                    }
+                }
+                ColumnType::Float => {
+                    if let Ok(n) = value.parse::<f64>() {
+                        write_varint(&mut buf, u64::from(field.number) << 3 | 1);
+                        buf.extend_from_slice(&n.to_le_bytes());
+                    }
+                }
+                ColumnType::Decimal | ColumnType::Text => {
+                    write_varint(&mut buf, u64::from(field.number) << 3 | 2);
+                    write_varint(&mut buf, value.len() as u64);
+                    buf.extend_from_slice(value.as_bytes());
+                }
+                ColumnType::Bool => {}
+            }
+        }
+        buf
+    }
+}
+
+/// Turns a flattened key into a protobuf identifier
+fn field_name(key: &str) -> String {
+    let mut name: String = key
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' })
+        .collect();
+    if !name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        name.insert_str(0, "field_");
+    }
+    name
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        buf.push((n as u8 & 0x7f) | 0x80);
+        n >>= 7;
+    }
+    buf.push(n as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    fn schema() -> FlatSchema {
+        [
+            ("active".to_string(), ColumnType::Bool),
+            ("id".to_string(), ColumnType::Int),
+            ("items.0".to_string(), ColumnType::Float),
+            ("items_0".to_string(), ColumnType::Text),
+            ("9lives".to_string(), ColumnType::Text),
+        ]
+        .into_iter()
+        .collect()
+    }
+
+    #[test]
+    fn test_proto_definition_from_schema() {
+        let message = ProtoMessage::from_schema("Event", &schema());
+        assert_eq!(
+            message.to_proto(),
+            "syntax = \"proto3\";\n\nmessage Event {\n\
+             \x20 optional string field_9lives = 1 [json_name = \"9lives\"];\n\
+             \x20 optional bool active = 2 [json_name = \"active\"];\n\
+             \x20 optional int64 id = 3 [json_name = \"id\"];\n\
+             \x20 optional double items_0 = 4 [json_name = \"items.0\"];\n\
+             \x20 optional string items_0_5 = 5 [json_name = \"items_0\"];\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn test_encode_skips_nulls_and_misfits() {
+        let message = ProtoMessage::from_schema("Event", &schema());
+        let record: FlattenedJson = [("active", "true"), ("id", "-1"), ("items.0", "null"), ("items_0", "ab")]
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+
+        let mut expected = vec![0x10, 0x01, 0x18];
+        expected.extend_from_slice(&[0xff; 9]);
+        expected.extend_from_slice(&[0x01, 0x2a, 0x02, b'a', b'b']);
+        assert_eq!(message.encode(&record), expected);
+    }
+}
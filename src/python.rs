@@ -1,19 +1,47 @@
 // src/python.rs
-use crate::{flatten_json, flatten_json_file, process_large_json_object, FlattenOptions, FlattenedJson};
+// pyo3 0.20 macros expand to impls that newer compilers flag as non-local, and
+// the options constructor mirrors every FlattenOptions field as a keyword argument
+#![allow(non_local_definitions, clippy::too_many_arguments)]
+use crate::duplicates::note_duplicates;
+use crate::raw::RawJson;
+use crate::keys::{split_key, KeyPattern};
+use crate::schema::{is_null, ColumnType};
+use crate::{
+    flatten_changes, flatten_document, flatten_json_file, flatten_json_file_run, flatten_json_file_timed, flatten_json_multi_str, into_sorted_vec, json_patch_from_diff,
+    process_large_json_object, try_flatten_json,
+    ArrayAggregates, ColumnarTable, DerivedColumn, Expr, FlattenBatches, ArrayLimitPolicy, ArrayPathMode, FlattenOptions, FlattenOverrides, FlattenedJson, FlattenerEngine,
+    AmbiguousKeys, BinaryPolicy, DuplicateKeys, EmptyKeys, SingleKeyChains, GeoJsonMode, InputEncoding, InvalidDocuments, InvalidUtf8, LeadingJunk, NonFiniteNumbers,
+    IndexKeys, NonObjectRecords, OverBudget, OversizedRecords, RecordIdSource, SparseIndices, parse_document, scrub_non_finite, skip_leading_junk, unflatten, unflatten_file,
+};
 use pyo3::exceptions::{PyIOError, PyRuntimeError, PyValueError};
 use pyo3::prelude::*;
 use pyo3::types::{PyDict, PyList};
-use serde_json::Value;
 use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+use std::time::Duration;
 
 /// A high-performance JSON flattener
 #[pymodule]
 fn json_flattener_rust(_py: Python, m: &PyModule) -> PyResult<()> {
     m.add_class::<PyFlattenOptions>()?;
+    m.add_class::<PyFlattenerEngine>()?;
     m.add_function(wrap_pyfunction!(flatten_json_str, m)?)?;
+    m.add_function(wrap_pyfunction!(flatten_json_multi_str_py, m)?)?;
+    m.add_function(wrap_pyfunction!(flatten_many_json_str, m)?)?;
+    m.add_function(wrap_pyfunction!(flatten_changes_str, m)?)?;
+    m.add_function(wrap_pyfunction!(json_patch_str, m)?)?;
+    m.add_function(wrap_pyfunction!(unflatten_dict, m)?)?;
+    m.add_function(wrap_pyfunction!(unflatten_file_py, m)?)?;
     m.add_function(wrap_pyfunction!(flatten_json_file_py, m)?)?;
+    m.add_function(wrap_pyfunction!(flatten_json_file_timed_py, m)?)?;
+    m.add_function(wrap_pyfunction!(flatten_json_file_run_py, m)?)?;
     m.add_function(wrap_pyfunction!(process_large_json_file, m)?)?;
     m.add_function(wrap_pyfunction!(flatten_pandas_ready, m)?)?;
+    #[cfg(feature = "bench")]
+    m.add_function(wrap_pyfunction!(flatten_pandas_ready_appended, m)?)?;
+    m.add_function(wrap_pyfunction!(flatten_to_pandas, m)?)?;
     m.add_function(wrap_pyfunction!(flatten_polaris_ready, m)?)?;
     Ok(())
 }
@@ -25,8 +53,16 @@ struct PyFlattenOptions {
     #[pyo3(get, set)]
     separator: String,
     #[pyo3(get, set)]
+    escape_keys: bool,
+    #[pyo3(get, set)]
     max_concurrency: usize,
     #[pyo3(get, set)]
+    parse_threads: usize,
+    #[pyo3(get, set)]
+    flatten_threads: usize,
+    #[pyo3(get, set)]
+    local_pools: bool,
+    #[pyo3(get, set)]
     max_depth: usize,
     #[pyo3(get, set)]
     include_array_indices: bool,
@@ -34,6 +70,67 @@ struct PyFlattenOptions {
     expand_arrays: bool,
     #[pyo3(get, set)]
     chunk_size: usize,
+    #[pyo3(get, set)]
+    chunk_bytes: usize,
+    #[pyo3(get, set)]
+    memory_budget: usize,
+    #[pyo3(get, set)]
+    max_input_bytes: usize,
+    #[pyo3(get, set)]
+    max_document_bytes: usize,
+    time_budget: Option<Duration>,
+    #[pyo3(get, set)]
+    skip_documents: u64,
+    #[pyo3(get, set)]
+    split_root_array: bool,
+    #[pyo3(get, set)]
+    ndjson: bool,
+    #[pyo3(get, set)]
+    canonical: bool,
+    #[pyo3(get, set)]
+    array_metadata: bool,
+    #[pyo3(get, set)]
+    max_array_elements: Option<usize>,
+    array_limit_policy: ArrayLimitPolicy,
+    #[pyo3(get, set)]
+    max_record_keys: usize,
+    record_time_limit: Option<Duration>,
+    oversized_records: OversizedRecords,
+    non_object_records: NonObjectRecords,
+    array_aggregates: ArrayAggregates,
+    array_modes: Vec<ArrayPathMode>,
+    record_id: RecordIdSource,
+    #[pyo3(get, set)]
+    record_id_key: String,
+    geojson: GeoJsonMode,
+    binary_values: BinaryPolicy,
+    #[pyo3(get, set)]
+    binary_min_length: usize,
+    #[pyo3(get, set)]
+    normalize_keys: bool,
+    #[pyo3(get, set)]
+    normalize_values: bool,
+    #[pyo3(get, set)]
+    raw_values: bool,
+    invalid_utf8: InvalidUtf8,
+    invalid_documents: InvalidDocuments,
+    encoding: InputEncoding,
+    leading_junk: LeadingJunk,
+    non_finite: NonFiniteNumbers,
+    duplicate_keys: DuplicateKeys,
+    empty_keys: EmptyKeys,
+    ambiguous_keys: AmbiguousKeys,
+    single_key_chains: SingleKeyChains,
+    #[pyo3(get, set)]
+    strip_prefix: Option<String>,
+    index_keys: IndexKeys,
+    sparse_indices: SparseIndices,
+    #[pyo3(get, set)]
+    structure_metrics: bool,
+    derived_columns: Vec<DerivedColumn>,
+    filters: Vec<Expr>,
+    #[pyo3(get, set)]
+    presize_records: bool,
 }
 
 #[pymethods]
@@ -46,149 +143,1224 @@ impl PyFlattenOptions {
         include_array_indices: Option<bool>,
         expand_arrays: Option<bool>,
         chunk_size: Option<usize>,
-    ) -> Self {
+        parse_threads: Option<usize>,
+        flatten_threads: Option<usize>,
+        chunk_bytes: Option<usize>,
+        memory_budget: Option<usize>,
+        canonical: Option<bool>,
+        split_root_array: Option<bool>,
+        escape_keys: Option<bool>,
+        array_metadata: Option<bool>,
+        max_array_elements: Option<usize>,
+        array_limit_policy: Option<&str>,
+        array_aggregates: Option<&str>,
+        array_modes: Option<Vec<(String, String)>>,
+        record_id: Option<&str>,
+        record_id_key: Option<String>,
+        geojson: Option<&str>,
+        binary_values: Option<&str>,
+        binary_min_length: Option<usize>,
+        normalize_keys: Option<bool>,
+        normalize_values: Option<bool>,
+        invalid_utf8: Option<&str>,
+        encoding: Option<&str>,
+        presize_records: Option<bool>,
+        non_finite: Option<&str>,
+        duplicate_keys: Option<&str>,
+        raw_values: Option<bool>,
+        empty_keys: Option<&str>,
+        structure_metrics: Option<bool>,
+        leading_junk: Option<&str>,
+        ambiguous_keys: Option<&str>,
+        single_key_chains: Option<&str>,
+        strip_prefix: Option<String>,
+        local_pools: Option<bool>,
+        index_keys: Option<&str>,
+        sparse_indices: Option<&str>,
+        max_input_bytes: Option<usize>,
+        max_document_bytes: Option<usize>,
+        time_budget: Option<f64>,
+        skip_documents: Option<u64>,
+        max_record_keys: Option<usize>,
+        record_time_limit: Option<f64>,
+        oversized_records: Option<&str>,
+        non_object_records: Option<&str>,
+        invalid_documents: Option<&str>,
+        ndjson: Option<bool>,
+        derived_columns: Option<Vec<String>>,
+        filters: Option<Vec<String>>,
+    ) -> PyResult<Self> {
         let default_options = FlattenOptions::default();
-        PyFlattenOptions {
+        let array_limit_policy = match array_limit_policy {
+            Some(policy) => policy.parse().map_err(PyValueError::new_err)?,
+            None => default_options.array_limit_policy,
+        };
+        let oversized_records = match oversized_records {
+            Some(policy) => policy.parse().map_err(PyValueError::new_err)?,
+            None => default_options.oversized_records,
+        };
+        let non_object_records = match non_object_records {
+            Some(policy) => policy.parse().map_err(PyValueError::new_err)?,
+            None => default_options.non_object_records,
+        };
+        let derived_columns = match derived_columns {
+            Some(columns) => parse_all(columns)?,
+            None => default_options.derived_columns,
+        };
+        let filters = match filters {
+            Some(filters) => parse_all(filters)?,
+            None => default_options.filters,
+        };
+        let array_aggregates = match array_aggregates {
+            Some(mode) => mode.parse().map_err(PyValueError::new_err)?,
+            None => default_options.array_aggregates,
+        };
+        let array_modes = match array_modes {
+            Some(modes) => parse_array_modes(modes)?,
+            None => default_options.array_modes,
+        };
+        let record_id = match record_id {
+            Some(source) => source.parse().map_err(PyValueError::new_err)?,
+            None => default_options.record_id,
+        };
+        let geojson = match geojson {
+            Some(mode) => mode.parse().map_err(PyValueError::new_err)?,
+            None => default_options.geojson,
+        };
+        let binary_values = match binary_values {
+            Some(policy) => policy.parse().map_err(PyValueError::new_err)?,
+            None => default_options.binary_values,
+        };
+        let invalid_utf8 = match invalid_utf8 {
+            Some(policy) => policy.parse().map_err(PyValueError::new_err)?,
+            None => default_options.invalid_utf8,
+        };
+        let invalid_documents = match invalid_documents {
+            Some(policy) => policy.parse().map_err(PyValueError::new_err)?,
+            None => default_options.invalid_documents,
+        };
+        let encoding = match encoding {
+            Some(label) => label.parse().map_err(PyValueError::new_err)?,
+            None => default_options.encoding,
+        };
+        let non_finite = match non_finite {
+            Some(policy) => policy.parse().map_err(PyValueError::new_err)?,
+            None => default_options.non_finite,
+        };
+        let duplicate_keys = match duplicate_keys {
+            Some(policy) => policy.parse().map_err(PyValueError::new_err)?,
+            None => default_options.duplicate_keys,
+        };
+        let leading_junk = match leading_junk {
+            Some(policy) => policy.parse().map_err(PyValueError::new_err)?,
+            None => default_options.leading_junk,
+        };
+        let empty_keys = match empty_keys {
+            Some(policy) => policy.parse().map_err(PyValueError::new_err)?,
+            None => default_options.empty_keys,
+        };
+        let ambiguous_keys = match ambiguous_keys {
+            Some(policy) => policy.parse().map_err(PyValueError::new_err)?,
+            None => default_options.ambiguous_keys,
+        };
+        let index_keys = match index_keys {
+            Some(policy) => policy.parse().map_err(PyValueError::new_err)?,
+            None => default_options.index_keys,
+        };
+        let sparse_indices = match sparse_indices {
+            Some(policy) => policy.parse().map_err(PyValueError::new_err)?,
+            None => default_options.sparse_indices,
+        };
+        let single_key_chains = match single_key_chains {
+            Some(policy) => policy.parse().map_err(PyValueError::new_err)?,
+            None => default_options.single_key_chains,
+        };
+        let options = PyFlattenOptions {
             separator: separator.unwrap_or(default_options.separator),
+            escape_keys: escape_keys.unwrap_or(default_options.escape_keys),
             max_concurrency: max_concurrency.unwrap_or(default_options.max_concurrency),
+            parse_threads: parse_threads.unwrap_or(default_options.parse_threads),
+            flatten_threads: flatten_threads.unwrap_or(default_options.flatten_threads),
+            local_pools: local_pools.unwrap_or(default_options.local_pools),
             max_depth: max_depth.unwrap_or(default_options.max_depth),
             include_array_indices: include_array_indices.unwrap_or(default_options.include_array_indices),
             expand_arrays: expand_arrays.unwrap_or(default_options.expand_arrays),
             chunk_size: chunk_size.unwrap_or(default_options.chunk_size),
+            chunk_bytes: chunk_bytes.unwrap_or(default_options.chunk_bytes),
+            memory_budget: memory_budget.unwrap_or(default_options.memory_budget),
+            max_input_bytes: max_input_bytes.unwrap_or(default_options.max_input_bytes),
+            max_document_bytes: max_document_bytes.unwrap_or(default_options.max_document_bytes),
+            time_budget: time_budget.map(seconds).transpose()?,
+            skip_documents: skip_documents.unwrap_or(default_options.skip_documents),
+            canonical: canonical.unwrap_or(default_options.canonical),
+            split_root_array: split_root_array.unwrap_or(default_options.split_root_array),
+            ndjson: ndjson.unwrap_or(default_options.ndjson),
+            array_metadata: array_metadata.unwrap_or(default_options.array_metadata),
+            max_array_elements: max_array_elements.or(default_options.max_array_elements),
+            array_limit_policy,
+            max_record_keys: max_record_keys.unwrap_or(default_options.max_record_keys),
+            record_time_limit: record_time_limit.map(seconds).transpose()?,
+            oversized_records,
+            non_object_records,
+            array_aggregates,
+            array_modes,
+            record_id,
+            record_id_key: record_id_key.unwrap_or(default_options.record_id_key),
+            geojson,
+            binary_values,
+            binary_min_length: binary_min_length.unwrap_or(default_options.binary_min_length),
+            normalize_keys: normalize_keys.unwrap_or(default_options.normalize_keys),
+            normalize_values: normalize_values.unwrap_or(default_options.normalize_values),
+            raw_values: raw_values.unwrap_or(default_options.raw_values),
+            invalid_utf8,
+            invalid_documents,
+            encoding,
+            leading_junk,
+            non_finite,
+            duplicate_keys,
+            empty_keys,
+            ambiguous_keys,
+            single_key_chains,
+            strip_prefix: strip_prefix.or(default_options.strip_prefix),
+            index_keys,
+            sparse_indices,
+            structure_metrics: structure_metrics.unwrap_or(default_options.structure_metrics),
+            derived_columns,
+            filters,
+            presize_records: presize_records.unwrap_or(default_options.presize_records),
+        };
+        rust_options(Some(options.clone()))?;
+        Ok(options)
+    }
+
+    /// Seconds the file functions may run before returning the records read so
+    /// far, or None for no limit
+    #[getter]
+    fn get_time_budget(&self) -> Option<f64> {
+        self.time_budget.map(|budget| budget.as_secs_f64())
+    }
+
+    #[setter]
+    fn set_time_budget(&mut self, secs: Option<f64>) -> PyResult<()> {
+        self.time_budget = secs.map(seconds).transpose()?;
+        Ok(())
+    }
+
+    /// Policy for arrays over `max_array_elements`: "truncate", "head_tail" or "error"
+    #[getter]
+    fn get_array_limit_policy(&self) -> String {
+        self.array_limit_policy.to_string()
+    }
+
+    #[setter]
+    fn set_array_limit_policy(&mut self, policy: &str) -> PyResult<()> {
+        self.array_limit_policy = policy.parse().map_err(PyValueError::new_err)?;
+        Ok(())
+    }
+
+    /// Seconds a single record may take to flatten, or None for no limit
+    #[getter]
+    fn get_record_time_limit(&self) -> Option<f64> {
+        self.record_time_limit.map(|limit| limit.as_secs_f64())
+    }
+
+    #[setter]
+    fn set_record_time_limit(&mut self, secs: Option<f64>) -> PyResult<()> {
+        self.record_time_limit = secs.map(seconds).transpose()?;
+        Ok(())
+    }
+
+    /// Policy for records over `max_record_keys` or `record_time_limit`: "error" or "skip"
+    #[getter]
+    fn get_oversized_records(&self) -> String {
+        self.oversized_records.to_string()
+    }
+
+    #[setter]
+    fn set_oversized_records(&mut self, policy: &str) -> PyResult<()> {
+        self.oversized_records = policy.parse().map_err(PyValueError::new_err)?;
+        Ok(())
+    }
+
+    /// Policy for documents that aren't objects: "keep", "wrap", "wrap:KEY", "skip" or "error"
+    #[getter]
+    fn get_non_object_records(&self) -> String {
+        self.non_object_records.to_string()
+    }
+
+    #[setter]
+    fn set_non_object_records(&mut self, policy: &str) -> PyResult<()> {
+        self.non_object_records = policy.parse().map_err(PyValueError::new_err)?;
+        Ok(())
+    }
+
+    /// Aggregates for arrays of numbers: "off", "replace" or "alongside"
+    #[getter]
+    fn get_array_aggregates(&self) -> String {
+        self.array_aggregates.to_string()
+    }
+
+    #[setter]
+    fn set_array_aggregates(&mut self, mode: &str) -> PyResult<()> {
+        self.array_aggregates = mode.parse().map_err(PyValueError::new_err)?;
+        Ok(())
+    }
+
+    /// Per-path array modes as (pattern, mode) pairs, e.g. ("tags", "join:,")
+    #[getter]
+    fn get_array_modes(&self) -> Vec<(String, String)> {
+        self.array_modes
+            .iter()
+            .map(|m| (m.pattern.to_string(), m.mode.to_string()))
+            .collect()
+    }
+
+    #[setter]
+    fn set_array_modes(&mut self, modes: Vec<(String, String)>) -> PyResult<()> {
+        self.array_modes = parse_array_modes(modes)?;
+        Ok(())
+    }
+
+    /// Derived columns as "key = expression" strings, e.g. "price_usd = price_cents / 100"
+    #[getter]
+    fn get_derived_columns(&self) -> Vec<String> {
+        self.derived_columns.iter().map(|c| c.to_string()).collect()
+    }
+
+    #[setter]
+    fn set_derived_columns(&mut self, columns: Vec<String>) -> PyResult<()> {
+        self.derived_columns = parse_all(columns)?;
+        Ok(())
+    }
+
+    /// Expressions every record must satisfy to be kept, e.g. 'event.type == "purchase"'
+    #[getter]
+    fn get_filters(&self) -> Vec<String> {
+        self.filters.iter().map(|f| f.to_string()).collect()
+    }
+
+    #[setter]
+    fn set_filters(&mut self, filters: Vec<String>) -> PyResult<()> {
+        self.filters = parse_all(filters)?;
+        Ok(())
+    }
+
+    /// Record id source: "none", "sequence", "uuid", "hash" or a path such as "$.id"
+    #[getter]
+    fn get_record_id(&self) -> String {
+        self.record_id.to_string()
+    }
+
+    #[setter]
+    fn set_record_id(&mut self, source: &str) -> PyResult<()> {
+        self.record_id = source.parse().map_err(PyValueError::new_err)?;
+        Ok(())
+    }
+
+    /// GeoJSON geometry handling: "off", "wkt", "wkb" or "point_columns"
+    #[getter]
+    fn get_geojson(&self) -> String {
+        self.geojson.to_string()
+    }
+
+    #[setter]
+    fn set_geojson(&mut self, mode: &str) -> PyResult<()> {
+        self.geojson = mode.parse().map_err(PyValueError::new_err)?;
+        Ok(())
+    }
+
+    /// Binary value handling: "keep", "drop", "truncate:<chars>" or "side-files:<dir>"
+    #[getter]
+    fn get_binary_values(&self) -> String {
+        self.binary_values.to_string()
+    }
+
+    #[setter]
+    fn set_binary_values(&mut self, policy: &str) -> PyResult<()> {
+        self.binary_values = policy.parse().map_err(PyValueError::new_err)?;
+        Ok(())
+    }
+
+    /// Invalid UTF-8 in file input: "error", "replace" or "skip"
+    #[getter]
+    fn get_invalid_utf8(&self) -> String {
+        self.invalid_utf8.to_string()
+    }
+
+    #[setter]
+    fn set_invalid_utf8(&mut self, policy: &str) -> PyResult<()> {
+        self.invalid_utf8 = policy.parse().map_err(PyValueError::new_err)?;
+        Ok(())
+    }
+
+    /// Documents that are not valid JSON: "error" or "skip" (leave them out;
+    /// flatten_json_file_run lists them with the error's position)
+    #[getter]
+    fn get_invalid_documents(&self) -> String {
+        self.invalid_documents.to_string()
+    }
+
+    #[setter]
+    fn set_invalid_documents(&mut self, policy: &str) -> PyResult<()> {
+        self.invalid_documents = policy.parse().map_err(PyValueError::new_err)?;
+        Ok(())
+    }
+
+    /// Input encoding: "auto" or an encoding label such as "utf-16le" or "latin1"
+    #[getter]
+    fn get_encoding(&self) -> String {
+        self.encoding.to_string()
+    }
+
+    #[setter]
+    fn set_encoding(&mut self, label: &str) -> PyResult<()> {
+        self.encoding = label.parse().map_err(PyValueError::new_err)?;
+        Ok(())
+    }
+
+    /// NaN/Infinity tokens in the input: "error", "null" or "string"
+    #[getter]
+    fn get_non_finite(&self) -> String {
+        self.non_finite.to_string()
+    }
+
+    #[setter]
+    fn set_non_finite(&mut self, policy: &str) -> PyResult<()> {
+        self.non_finite = policy.parse().map_err(PyValueError::new_err)?;
+        Ok(())
+    }
+
+    /// Keys repeated within a source object: "last", "error" or "report"
+    #[getter]
+    fn get_duplicate_keys(&self) -> String {
+        self.duplicate_keys.to_string()
+    }
+
+    #[setter]
+    fn set_duplicate_keys(&mut self, policy: &str) -> PyResult<()> {
+        self.duplicate_keys = policy.parse().map_err(PyValueError::new_err)?;
+        Ok(())
+    }
+
+    /// Byte order marks and non-JSON whitespace before the first document: "skip" or "error"
+    #[getter]
+    fn get_leading_junk(&self) -> String {
+        self.leading_junk.to_string()
+    }
+
+    #[setter]
+    fn set_leading_junk(&mut self, policy: &str) -> PyResult<()> {
+        self.leading_junk = policy.parse().map_err(PyValueError::new_err)?;
+        Ok(())
+    }
+
+    /// Object keys that are the empty string: "keep", "placeholder" or "error"
+    #[getter]
+    fn get_empty_keys(&self) -> String {
+        self.empty_keys.to_string()
+    }
+
+    #[setter]
+    fn set_empty_keys(&mut self, policy: &str) -> PyResult<()> {
+        self.empty_keys = policy.parse().map_err(PyValueError::new_err)?;
+        Ok(())
+    }
+
+    /// Keys holding the separator or looking like array indices: "allow", "report" or "error"
+    #[getter]
+    fn get_ambiguous_keys(&self) -> String {
+        self.ambiguous_keys.to_string()
+    }
+
+    #[setter]
+    fn set_ambiguous_keys(&mut self, policy: &str) -> PyResult<()> {
+        self.ambiguous_keys = policy.parse().map_err(PyValueError::new_err)?;
+        Ok(())
+    }
+
+    /// Chains of single-key objects: "keep", "head" (first key only) or "tail" (last key only)
+    #[getter]
+    fn get_single_key_chains(&self) -> String {
+        self.single_key_chains.to_string()
+    }
+
+    #[setter]
+    fn set_single_key_chains(&mut self, policy: &str) -> PyResult<()> {
+        self.single_key_chains = policy.parse().map_err(PyValueError::new_err)?;
+        Ok(())
+    }
+
+    /// Objects keyed by indices when unflattening: "arrays" or "objects"
+    #[getter]
+    fn get_index_keys(&self) -> String {
+        self.index_keys.to_string()
+    }
+
+    #[setter]
+    fn set_index_keys(&mut self, policy: &str) -> PyResult<()> {
+        self.index_keys = policy.parse().map_err(PyValueError::new_err)?;
+        Ok(())
+    }
+
+    /// Index keys that skip numbers when unflattening: "null" (fill the gaps) or "object"
+    #[getter]
+    fn get_sparse_indices(&self) -> String {
+        self.sparse_indices.to_string()
+    }
+
+    #[setter]
+    fn set_sparse_indices(&mut self, policy: &str) -> PyResult<()> {
+        self.sparse_indices = policy.parse().map_err(PyValueError::new_err)?;
+        Ok(())
+    }
+}
+
+/// Parse a string override into one of the option enums
+fn parse_override<T: std::str::FromStr<Err = String>>(value: &PyAny) -> PyResult<T> {
+    value.extract::<&str>()?.parse().map_err(PyValueError::new_err)
+}
+
+/// Parses (pattern, mode) pairs into per-path array modes
+fn parse_array_modes(modes: Vec<(String, String)>) -> PyResult<Vec<ArrayPathMode>> {
+    modes
+        .into_iter()
+        .map(|(pattern, mode)| {
+            let mode = mode.parse().map_err(PyValueError::new_err)?;
+            Ok(ArrayPathMode::new(&pattern, mode))
+        })
+        .collect()
+}
+
+fn parse_all<T: std::str::FromStr<Err = String>>(texts: Vec<String>) -> PyResult<Vec<T>> {
+    texts.iter().map(|text| text.parse().map_err(PyValueError::new_err)).collect()
+}
+
+impl Default for PyFlattenOptions {
+    fn default() -> Self {
+        FlattenOptions::default().into()
+    }
+}
+
+impl From<FlattenOptions> for PyFlattenOptions {
+    fn from(options: FlattenOptions) -> Self {
+        PyFlattenOptions {
+            separator: options.separator,
+            escape_keys: options.escape_keys,
+            max_concurrency: options.max_concurrency,
+            parse_threads: options.parse_threads,
+            flatten_threads: options.flatten_threads,
+            local_pools: options.local_pools,
+            max_depth: options.max_depth,
+            include_array_indices: options.include_array_indices,
+            expand_arrays: options.expand_arrays,
+            chunk_size: options.chunk_size,
+            chunk_bytes: options.chunk_bytes,
+            memory_budget: options.memory_budget,
+            max_input_bytes: options.max_input_bytes,
+            max_document_bytes: options.max_document_bytes,
+            time_budget: options.time_budget,
+            skip_documents: options.skip_documents,
+            split_root_array: options.split_root_array,
+            ndjson: options.ndjson,
+            canonical: options.canonical,
+            array_metadata: options.array_metadata,
+            max_array_elements: options.max_array_elements,
+            array_limit_policy: options.array_limit_policy,
+            max_record_keys: options.max_record_keys,
+            record_time_limit: options.record_time_limit,
+            oversized_records: options.oversized_records,
+            non_object_records: options.non_object_records,
+            array_aggregates: options.array_aggregates,
+            array_modes: options.array_modes,
+            record_id: options.record_id,
+            record_id_key: options.record_id_key,
+            geojson: options.geojson,
+            binary_values: options.binary_values,
+            binary_min_length: options.binary_min_length,
+            normalize_keys: options.normalize_keys,
+            normalize_values: options.normalize_values,
+            raw_values: options.raw_values,
+            invalid_utf8: options.invalid_utf8,
+            invalid_documents: options.invalid_documents,
+            encoding: options.encoding,
+            leading_junk: options.leading_junk,
+            non_finite: options.non_finite,
+            duplicate_keys: options.duplicate_keys,
+            empty_keys: options.empty_keys,
+            ambiguous_keys: options.ambiguous_keys,
+            single_key_chains: options.single_key_chains,
+            strip_prefix: options.strip_prefix,
+            index_keys: options.index_keys,
+            sparse_indices: options.sparse_indices,
+            structure_metrics: options.structure_metrics,
+            derived_columns: options.derived_columns,
+            filters: options.filters,
+            presize_records: options.presize_records,
         }
     }
 }
 
+/// A time limit in seconds, raising `ValueError` for negative or non-finite values
+fn seconds(secs: f64) -> PyResult<Duration> {
+    Duration::try_from_secs_f64(secs).map_err(|e| PyValueError::new_err(format!("invalid time limit {}: {}", secs, e)))
+}
+
+/// Converts binding options, or the defaults, to Rust options, raising
+/// `ValueError` for settings [`FlattenOptions::validate`] rejects
+fn rust_options(options: Option<PyFlattenOptions>) -> PyResult<FlattenOptions> {
+    let options: FlattenOptions = options.unwrap_or_default().into();
+    options.validate().map_err(|e| PyValueError::new_err(format!("invalid options: {}", e)))?;
+    Ok(options)
+}
+
 impl From<PyFlattenOptions> for FlattenOptions {
     fn from(options: PyFlattenOptions) -> Self {
         FlattenOptions {
             separator: options.separator,
+            escape_keys: options.escape_keys,
             max_concurrency: options.max_concurrency,
+            parse_threads: options.parse_threads,
+            flatten_threads: options.flatten_threads,
+            local_pools: options.local_pools,
             max_depth: options.max_depth,
             include_array_indices: options.include_array_indices,
             expand_arrays: options.expand_arrays,
             chunk_size: options.chunk_size,
+            chunk_bytes: options.chunk_bytes,
+            memory_budget: options.memory_budget,
+            over_budget: OverBudget::default(),
+            max_input_bytes: options.max_input_bytes,
+            max_document_bytes: options.max_document_bytes,
+            time_budget: options.time_budget,
+            skip_documents: options.skip_documents,
+            split_root_array: options.split_root_array,
+            ndjson: options.ndjson,
+            canonical: options.canonical,
+            array_metadata: options.array_metadata,
+            max_array_elements: options.max_array_elements,
+            array_limit_policy: options.array_limit_policy,
+            max_record_keys: options.max_record_keys,
+            record_time_limit: options.record_time_limit,
+            oversized_records: options.oversized_records,
+            non_object_records: options.non_object_records,
+            array_aggregates: options.array_aggregates,
+            array_modes: options.array_modes,
+            record_id: options.record_id,
+            record_id_key: options.record_id_key,
+            geojson: options.geojson,
+            binary_values: options.binary_values,
+            binary_min_length: options.binary_min_length,
+            normalize_keys: options.normalize_keys,
+            normalize_values: options.normalize_values,
+            raw_values: options.raw_values,
+            invalid_utf8: options.invalid_utf8,
+            invalid_documents: options.invalid_documents,
+            encoding: options.encoding,
+            leading_junk: options.leading_junk,
+            non_finite: options.non_finite,
+            duplicate_keys: options.duplicate_keys,
+            empty_keys: options.empty_keys,
+            ambiguous_keys: options.ambiguous_keys,
+            single_key_chains: options.single_key_chains,
+            strip_prefix: options.strip_prefix,
+            index_keys: options.index_keys,
+            sparse_indices: options.sparse_indices,
+            structure_metrics: options.structure_metrics,
+            derived_columns: options.derived_columns,
+            filters: options.filters,
+            presize_records: options.presize_records,
+            chunk_hook: None,
+        }
+    }
+}
+
+/// A reusable flattener that keeps its options and thread pools between calls
+#[pyclass(name = "FlattenerEngine")]
+struct PyFlattenerEngine {
+    engine: FlattenerEngine,
+}
+
+#[pymethods]
+impl PyFlattenerEngine {
+    #[new]
+    fn new(options: Option<PyFlattenOptions>) -> PyResult<Self> {
+        let options = rust_options(options)?;
+        let engine = FlattenerEngine::new(options)
+            .map_err(|e| PyRuntimeError::new_err(format!("Error building thread pools: {}", e)))?;
+        Ok(PyFlattenerEngine { engine })
+    }
+
+    /// Return an engine that shares this engine's thread pools, with the given
+    /// keyword arguments overriding its options, e.g. with_overrides(separator="__")
+    #[pyo3(signature = (**overrides))]
+    fn with_overrides(&self, overrides: Option<&PyDict>) -> PyResult<Self> {
+        let mut parsed = FlattenOverrides::default();
+        for (key, value) in overrides.into_iter().flatten() {
+            match key.extract::<&str>()? {
+                "separator" => parsed.separator = Some(value.extract()?),
+                "escape_keys" => parsed.escape_keys = Some(value.extract()?),
+                "max_depth" => parsed.max_depth = Some(value.extract()?),
+                "include_array_indices" => parsed.include_array_indices = Some(value.extract()?),
+                "expand_arrays" => parsed.expand_arrays = Some(value.extract()?),
+                "canonical" => parsed.canonical = Some(value.extract()?),
+                "array_metadata" => parsed.array_metadata = Some(value.extract()?),
+                "max_array_elements" => parsed.max_array_elements = Some(value.extract()?),
+                "array_limit_policy" => parsed.array_limit_policy = Some(parse_override(value)?),
+                "array_aggregates" => parsed.array_aggregates = Some(parse_override(value)?),
+                "array_modes" => parsed.array_modes = Some(parse_array_modes(value.extract()?)?),
+                "record_id" => parsed.record_id = Some(parse_override(value)?),
+                "record_id_key" => parsed.record_id_key = Some(value.extract()?),
+                "geojson" => parsed.geojson = Some(parse_override(value)?),
+                "binary_values" => parsed.binary_values = Some(parse_override(value)?),
+                "normalize_keys" => parsed.normalize_keys = Some(value.extract()?),
+                "normalize_values" => parsed.normalize_values = Some(value.extract()?),
+                "raw_values" => parsed.raw_values = Some(value.extract()?),
+                "strip_prefix" => parsed.strip_prefix = Some(value.extract()?),
+                "derived_columns" => parsed.derived_columns = Some(parse_all(value.extract()?)?),
+                "filters" => parsed.filters = Some(parse_all(value.extract()?)?),
+                other => return Err(PyValueError::new_err(format!("Unknown override: {}", other))),
+            }
+        }
+        Ok(PyFlattenerEngine {
+            engine: self.engine.with_overrides(&parsed),
+        })
+    }
+
+    /// Flatten a JSON string holding a single document to a dictionary
+    fn flatten_str(&self, py: Python, json_str: &str) -> PyResult<PyObject> {
+        let options = self.engine.options();
+        let json_str =
+            skip_leading_junk(json_str, options.leading_junk).map_err(|e| PyValueError::new_err(e.to_string()))?;
+        let json_str = scrub_non_finite(&json_str, options.non_finite);
+        let invalid = |e: serde_json::Error| PyValueError::new_err(format!("Invalid JSON: {}", e));
+        let flattened = if options.raw_values {
+            let document = RawJson::parse(&json_str).map_err(invalid)?;
+            py.allow_threads(|| flatten_document(&document, options, 0))
+                .map_err(|e| PyValueError::new_err(e.to_string()))?
+        } else {
+            let (json_value, duplicates) = parse_document(&json_str, options.duplicate_keys).map_err(invalid)?;
+            let mut flattened = py
+                .allow_threads(|| self.engine.flatten(&json_value))
+                .map_err(|e| PyValueError::new_err(e.to_string()))?;
+            note_duplicates(&mut flattened, &duplicates);
+            flattened
+        };
+        Ok(record_to_dict(py, flattened, self.engine.options().canonical)?.into())
+    }
+
+    /// Flatten a string holding a JSON array, concatenated documents or NDJSON to a list of dictionaries
+    fn flatten_multi_str(&self, py: Python, json_str: &str) -> PyResult<PyObject> {
+        let result = py
+            .allow_threads(|| self.engine.flatten_str(json_str).map_err(|e| e.to_string()))
+            .map_err(|e| PyValueError::new_err(format!("Invalid JSON: {}", e)))?;
+        records_to_list(py, result, self.engine.options().canonical)
+    }
+
+    /// Flatten a JSON file to a list of dictionaries
+    fn flatten_file(&self, py: Python, filepath: PathBuf) -> PyResult<PyObject> {
+        let result = py
+            .allow_threads(|| self.engine.flatten_file(&filepath).map_err(|e| e.to_string()))
+            .map_err(|e| PyIOError::new_err(format!("Error reading file: {}", e)))?;
+        records_to_list(py, result, self.engine.options().canonical)
+    }
+}
+
+/// Converts flattened records to a Python list of dicts
+fn records_to_list(py: Python, records: Vec<FlattenedJson>, canonical: bool) -> PyResult<PyObject> {
+    let py_list = PyList::empty(py);
+    for item in records {
+        py_list.append(record_to_dict(py, item, canonical)?)?;
+    }
+    Ok(py_list.into())
+}
+
+/// Converts a flattened record to a Python dict, inserting keys in sorted order in canonical mode
+fn record_to_dict<'py>(py: Python<'py>, record: FlattenedJson, canonical: bool) -> PyResult<&'py PyDict> {
+    let py_dict = PyDict::new(py);
+    if canonical {
+        for (key, value) in into_sorted_vec(record) {
+            py_dict.set_item(key, value)?;
+        }
+    } else {
+        for (key, value) in record {
+            py_dict.set_item(key, value)?;
         }
     }
+    Ok(py_dict)
 }
 
 /// Flatten a JSON string to a dictionary with dot-notation keys
 #[pyfunction]
 fn flatten_json_str(py: Python, json_str: &str, options: Option<PyFlattenOptions>) -> PyResult<PyObject> {
-    let options = options.unwrap_or_else(|| PyFlattenOptions::new(None, None, None, None, None, None));
-    let rust_options: FlattenOptions = options.into();
+    let rust_options = rust_options(options)?;
 
     // Parse the JSON string
-    let json_value: Value = serde_json::from_str(json_str)
-        .map_err(|e| PyValueError::new_err(format!("Invalid JSON: {}", e)))?;
+    let json_str =
+        skip_leading_junk(json_str, rust_options.leading_junk).map_err(|e| PyValueError::new_err(e.to_string()))?;
+    let json_str = scrub_non_finite(&json_str, rust_options.non_finite);
+    let invalid = |e: serde_json::Error| {
+        if e.to_string().starts_with("trailing characters") {
+            PyValueError::new_err(format!(
+                "Invalid JSON: {} (use flatten_json_multi_str for multiple documents)",
+                e
+            ))
+        } else {
+            PyValueError::new_err(format!("Invalid JSON: {}", e))
+        }
+    };
 
     // Flatten the JSON
-    let flattened = flatten_json(&json_value, &rust_options);
+    let flattened = if rust_options.raw_values {
+        let document = RawJson::parse(&json_str).map_err(invalid)?;
+        flatten_document(&document, &rust_options, 0).map_err(|e| PyValueError::new_err(e.to_string()))?
+    } else {
+        let (json_value, duplicates) = parse_document(&json_str, rust_options.duplicate_keys).map_err(invalid)?;
+        let mut flattened = try_flatten_json(&json_value, &rust_options)
+            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+        note_duplicates(&mut flattened, &duplicates);
+        flattened
+    };
 
     // Convert the HashMap to a Python dict
-    let py_dict = PyDict::new(py);
-    for (key, value) in flattened {
-        py_dict.set_item(key, value)?;
-    }
+    let py_dict = record_to_dict(py, flattened, rust_options.canonical)?;
 
     Ok(py_dict.into())
 }
 
+/// Flatten a string holding a JSON array, concatenated documents or NDJSON to a list of dictionaries
+#[pyfunction]
+#[pyo3(name = "flatten_json_multi_str")]
+fn flatten_json_multi_str_py(py: Python, json_str: &str, options: Option<PyFlattenOptions>) -> PyResult<PyObject> {
+    let rust_options = rust_options(options)?;
+
+    let result = flatten_json_multi_str(json_str, &rust_options)
+        .map_err(|e| PyValueError::new_err(format!("Invalid JSON: {}", e)))?;
+
+    let py_list = PyList::empty(py);
+    for item in result {
+        py_list.append(record_to_dict(py, item, rust_options.canonical)?)?;
+    }
+
+    Ok(py_list.into())
+}
+
+/// Flatten a list of JSON strings, each holding a single document, to a list of dictionaries
+///
+/// The strings are parsed and flattened on Rust threads with the GIL released,
+/// which avoids the per-call overhead of `flatten_json_str` in a Python loop.
+#[pyfunction]
+fn flatten_many_json_str(py: Python, json_strs: Vec<String>, options: Option<PyFlattenOptions>) -> PyResult<PyObject> {
+    let rust_options = rust_options(options)?;
+
+    let result = py
+        .allow_threads(|| {
+            let mut records = Vec::with_capacity(json_strs.len());
+            let documents = json_strs.into_iter().map(Ok::<_, std::io::Error>);
+            for batch in FlattenBatches::new(documents, rust_options.chunk_size, rust_options.clone()) {
+                records.extend(batch.map_err(|e| e.to_string())?);
+            }
+            Ok::<_, String>(records)
+        })
+        .map_err(|e| PyValueError::new_err(format!("Invalid JSON: {}", e)))?;
+    records_to_list(py, result, rust_options.canonical)
+}
+
+/// Flatten only what changed between two versions of a JSON document
+///
+/// Returns a dict of the new or changed keys with their new values and a
+/// sorted list of the removed keys; unchanged subtrees are not flattened.
+#[pyfunction]
+fn flatten_changes_str(
+    py: Python,
+    old_json: &str,
+    new_json: &str,
+    options: Option<PyFlattenOptions>,
+) -> PyResult<(PyObject, Vec<String>)> {
+    let rust_options = rust_options(options)?;
+    let parse = |json: &str| {
+        serde_json::from_str::<serde_json::Value>(json).map_err(|e| PyValueError::new_err(format!("Invalid JSON: {}", e)))
+    };
+    let (old, new) = (parse(old_json)?, parse(new_json)?);
+
+    let diff = py
+        .allow_threads(|| flatten_changes(&old, &new, &rust_options))
+        .map_err(|e| PyValueError::new_err(e.to_string()))?;
+    let changed = PyDict::new(py);
+    for (key, value) in diff.changed {
+        changed.set_item(key, value)?;
+    }
+    Ok((changed.into(), diff.removed))
+}
+
+/// The RFC 6902 JSON Patch that turns one JSON document into another, as JSON text
+#[pyfunction]
+fn json_patch_str(old_json: &str, new_json: &str) -> PyResult<String> {
+    let parse = |json: &str| {
+        serde_json::from_str::<serde_json::Value>(json).map_err(|e| PyValueError::new_err(format!("Invalid JSON: {}", e)))
+    };
+    let patch = json_patch_from_diff(&parse(old_json)?, &parse(new_json)?);
+    Ok(serde_json::to_string(&patch).expect("patches always serialize"))
+}
+
+/// Rebuild the JSON document a flattened dictionary came from, as JSON text
+#[pyfunction]
+fn unflatten_dict(record: HashMap<String, String>, options: Option<PyFlattenOptions>) -> PyResult<String> {
+    let options = rust_options(options)?;
+    let record: FlattenedJson = record.into_iter().collect();
+    Ok(unflatten(&record, &options).to_string())
+}
+
+/// Rebuild the nested documents of a file of flattened records (one JSON
+/// object per line) into an NDJSON file, in parallel with the GIL released
+///
+/// Returns the number of documents written.
+#[pyfunction]
+#[pyo3(name = "unflatten_file")]
+fn unflatten_file_py(
+    py: Python,
+    filepath: PathBuf,
+    output: PathBuf,
+    options: Option<PyFlattenOptions>,
+) -> PyResult<usize> {
+    let rust_options = rust_options(options)?;
+    py.allow_threads(|| {
+        let output = BufWriter::new(File::create(&output).map_err(|e| e.to_string())?);
+        unflatten_file(&filepath, output, &rust_options).map_err(|e| e.to_string())
+    })
+    .map_err(|e| PyIOError::new_err(format!("Error unflattening file: {}", e)))
+}
+
 /// Flatten a JSON file to a list of dictionaries
 #[pyfunction]
-fn flatten_json_file_py(py: Python, filepath: &str, options: Option<PyFlattenOptions>) -> PyResult<PyObject> {
-    let options = options.unwrap_or_else(|| PyFlattenOptions::new(None, None, None, None, None, None));
-    let rust_options: FlattenOptions = options.into();
+fn flatten_json_file_py(py: Python, filepath: PathBuf, options: Option<PyFlattenOptions>) -> PyResult<PyObject> {
+    let rust_options = rust_options(options)?;
 
     // Flatten the JSON file
-    let result = flatten_json_file(filepath, &rust_options)
+    let result = flatten_json_file(&filepath, &rust_options)
         .map_err(|e| PyIOError::new_err(format!("Error reading file: {}", e)))?;
 
     // Convert the result to a Python list of dicts
     let py_list = PyList::empty(py);
     for item in result {
-        let py_dict = PyDict::new(py);
-        for (key, value) in item {
-            py_dict.set_item(key, value)?;
-        }
-        py_list.append(py_dict)?;
+        py_list.append(record_to_dict(py, item, rust_options.canonical)?)?;
     }
 
     Ok(py_list.into())
 }
 
+/// Flatten as much of a JSON file to a list of dictionaries as `time_budget` allows
+///
+/// Returns `(records, skip)`, where `skip` is the `skip_documents` value that
+/// carries on where the budget ran out, or None once the whole file is read.
+#[pyfunction]
+#[pyo3(name = "flatten_json_file_timed")]
+fn flatten_json_file_timed_py(
+    py: Python,
+    filepath: PathBuf,
+    options: Option<PyFlattenOptions>,
+) -> PyResult<(PyObject, Option<u64>)> {
+    let rust_options = rust_options(options)?;
+
+    let (result, checkpoint) = flatten_json_file_timed(&filepath, &rust_options)
+        .map_err(|e| PyIOError::new_err(format!("Error reading file: {}", e)))?;
+
+    let records = records_to_list(py, result, rust_options.canonical)?;
+    Ok((records, checkpoint.map(|checkpoint| checkpoint.documents)))
+}
+
+/// Flatten a JSON file to a dict describing the whole run
+///
+/// Holds "records" (list of dicts), "stats" (documents, records, keys,
+/// non_objects, invalid_documents, filtered and elapsed seconds), "errors" (a list of
+/// dicts with the document number, message, and for parse errors the byte
+/// offset, line and excerpt, for documents skipped by `oversized_records`,
+/// `non_object_records` or `invalid_documents="skip"`), "schema" (column to "bool", "int",
+/// "float", "decimal" or "text") and "skip" (as for `flatten_json_file_timed`).
+#[pyfunction]
+#[pyo3(name = "flatten_json_file_run")]
+fn flatten_json_file_run_py(py: Python, filepath: PathBuf, options: Option<PyFlattenOptions>) -> PyResult<PyObject> {
+    let rust_options = rust_options(options)?;
+
+    let run = flatten_json_file_run(&filepath, &rust_options)
+        .map_err(|e| PyIOError::new_err(format!("Error reading file: {}", e)))?;
+
+    let stats = PyDict::new(py);
+    stats.set_item("documents", run.stats.documents)?;
+    stats.set_item("records", run.stats.records)?;
+    stats.set_item("keys", run.stats.keys)?;
+    stats.set_item("non_objects", run.stats.non_objects)?;
+    stats.set_item("invalid_documents", run.stats.invalid_documents)?;
+    stats.set_item("filtered", run.stats.filtered)?;
+    stats.set_item("elapsed", run.stats.elapsed.as_secs_f64())?;
+    let errors = PyList::empty(py);
+    for error in run.errors {
+        let entry = PyDict::new(py);
+        entry.set_item("document", error.document)?;
+        entry.set_item("message", error.message)?;
+        entry.set_item("offset", error.offset)?;
+        entry.set_item("line", error.line)?;
+        entry.set_item("excerpt", error.excerpt)?;
+        errors.append(entry)?;
+    }
+    let schema = PyDict::new(py);
+    for (column, column_type) in run.schema.iter() {
+        schema.set_item(column, column_type.to_string())?;
+    }
+
+    let result = PyDict::new(py);
+    result.set_item("records", records_to_list(py, run.records, rust_options.canonical)?)?;
+    result.set_item("stats", stats)?;
+    result.set_item("errors", errors)?;
+    result.set_item("schema", schema)?;
+    result.set_item("skip", run.checkpoint.map(|checkpoint| checkpoint.documents))?;
+    Ok(result.into())
+}
+
 /// Process a large JSON file optimized for memory usage
 #[pyfunction]
-fn process_large_json_file(py: Python, filepath: &str, options: Option<PyFlattenOptions>) -> PyResult<PyObject> {
-    let options = options.unwrap_or_else(|| PyFlattenOptions::new(None, None, None, None, None, None));
-    let rust_options: FlattenOptions = options.into();
+fn process_large_json_file(py: Python, filepath: PathBuf, options: Option<PyFlattenOptions>) -> PyResult<PyObject> {
+    let rust_options = rust_options(options)?;
 
     // Process the large JSON file
-    let result = process_large_json_object(filepath, &rust_options)
+    let result = process_large_json_object(&filepath, &rust_options)
         .map_err(|e| PyIOError::new_err(format!("Error processing file: {}", e)))?;
 
     // Convert the result to a Python dict
-    let py_dict = PyDict::new(py);
-    for (key, value) in result {
-        py_dict.set_item(key, value)?;
-    }
+    let py_dict = record_to_dict(py, result, rust_options.canonical)?;
 
     Ok(py_dict.into())
 }
 
 /// Flatten a JSON file and prepare it for pandas DataFrame conversion
 /// Returns a dict with column names as keys and lists of values as values
+///
+/// With `with_dtypes=True` returns a `(data, dtypes)` tuple instead, where
+/// dtypes maps every column to an inferred pandas dtype string. Values of
+/// numeric and boolean columns are then Python numbers and booleans, with
+/// `None` for nulls and missing keys, so `pd.DataFrame(data).astype(dtypes)`
+/// needs no further conversion.
+///
+/// `select` keeps only the columns matching one of its key patterns (e.g.
+/// `"user.**"`) and `drop` removes those matching one of its patterns, before
+/// any values are converted to Python.
 #[pyfunction]
-fn flatten_pandas_ready(py: Python, filepath: &str, options: Option<PyFlattenOptions>) -> PyResult<PyObject> {
-    let options = options.unwrap_or_else(|| PyFlattenOptions::new(None, None, None, None, None, None));
-    let rust_options: FlattenOptions = options.into();
+#[pyo3(signature = (filepath, options=None, with_dtypes=false, select=None, drop=None))]
+fn flatten_pandas_ready(
+    py: Python,
+    filepath: PathBuf,
+    options: Option<PyFlattenOptions>,
+    with_dtypes: bool,
+    select: Option<Vec<String>>,
+    drop: Option<Vec<String>>,
+) -> PyResult<PyObject> {
+    let rust_options = rust_options(options)?;
+    let keep = column_filter(select, drop, &rust_options);
 
     // Flatten the JSON file
-    let flattened_data = flatten_json_file(filepath, &rust_options)
+    let flattened_data = py
+        .allow_threads(|| flatten_json_file(&filepath, &rust_options).map_err(|e| e.to_string()))
         .map_err(|e| PyIOError::new_err(format!("Error reading file: {}", e)))?;
 
-    // If there's no data, return an empty dict
-    if flattened_data.is_empty() {
-        return Ok(PyDict::new(py).into());
+    let (data, dtypes) = pandas_columns(py, &flattened_data, rust_options.canonical, with_dtypes, keep)?;
+    if with_dtypes {
+        return Ok((data, dtypes).into_py(py));
     }
+    Ok(data.into())
+}
+
+/// Builds the column dict of `flatten_pandas_ready` without dtypes the way
+/// it was built before each column went to Python in one call: a
+/// `PyList.append` per value
+///
+/// Kept as the baseline `python/benchmark.py` measures `flatten_pandas_ready`
+/// against; only built with the `bench` feature.
+#[cfg(feature = "bench")]
+#[pyfunction]
+#[pyo3(signature = (filepath, options=None))]
+fn flatten_pandas_ready_appended(
+    py: Python,
+    filepath: PathBuf,
+    options: Option<PyFlattenOptions>,
+) -> PyResult<PyObject> {
+    let rust_options = rust_options(options)?;
+    let flattened_data = py
+        .allow_threads(|| flatten_json_file(&filepath, &rust_options).map_err(|e| e.to_string()))
+        .map_err(|e| PyIOError::new_err(format!("Error reading file: {}", e)))?;
 
-    // Collect all column names
     let mut all_columns = std::collections::HashSet::new();
     for item in &flattened_data {
         for key in item.keys() {
             all_columns.insert(key.clone());
         }
     }
+    let mut all_columns: Vec<String> = all_columns.into_iter().collect();
+    if rust_options.canonical {
+        all_columns.sort();
+    }
 
-    // Create dict with column names as keys and empty lists as values
-    let py_dict = PyDict::new(py);
+    let data = PyDict::new(py);
     for column in &all_columns {
         let py_list = PyList::empty(py);
-        py_dict.set_item(column, py_list)?;
+        for item in &flattened_data {
+            py_list.append(item.get(column).map_or("", String::as_str))?;
+        }
+        data.set_item(column, py_list)?;
     }
+    Ok(data.into())
+}
+
+/// Flatten a JSON file straight into a pandas DataFrame
+///
+/// Column dtypes are inferred as for `flatten_pandas_ready(..., with_dtypes=True)`,
+/// and `select` and `drop` prune columns as there.
+#[pyfunction]
+#[pyo3(signature = (filepath, options=None, select=None, drop=None))]
+fn flatten_to_pandas(
+    py: Python,
+    filepath: PathBuf,
+    options: Option<PyFlattenOptions>,
+    select: Option<Vec<String>>,
+    drop: Option<Vec<String>>,
+) -> PyResult<PyObject> {
+    let rust_options = rust_options(options)?;
+    let keep = column_filter(select, drop, &rust_options);
+
+    let flattened_data = py
+        .allow_threads(|| flatten_json_file(&filepath, &rust_options).map_err(|e| e.to_string()))
+        .map_err(|e| PyIOError::new_err(format!("Error reading file: {}", e)))?;
+
+    let (data, dtypes) = pandas_columns(py, &flattened_data, rust_options.canonical, true, keep)?;
+    let frame = py.import("pandas")?.getattr("DataFrame")?.call1((data,))?;
+    Ok(frame.call_method1("astype", (dtypes,))?.into())
+}
 
-    // Fill in the lists with values
-    for item in flattened_data {
-        for column in &all_columns {
-            let value = item.get(column).cloned().unwrap_or_else(|| "".to_string());
-            let py_list = py_dict.get_item(column).unwrap().downcast::<PyList>().unwrap();
-            py_list.append(value)?;
+/// Builds the column dict of a pandas-ready result, and the dtype of every
+/// column when `with_dtypes` is set, leaving out columns `keep` rejects
+///
+/// Without dtypes every column holds the flattened strings, with "" for
+/// missing keys. Records are split into one Rust `Vec` per column first, and
+/// each column becomes a Python list in a single call rather than one
+/// `append` per value.
+fn pandas_columns<'py>(
+    py: Python<'py>,
+    records: &[FlattenedJson],
+    canonical: bool,
+    with_dtypes: bool,
+    keep: impl Fn(&str) -> bool,
+) -> PyResult<(&'py PyDict, &'py PyDict)> {
+    // Column names in first-seen order, and the values of each column by row;
+    // pruned columns are remembered as None so `keep` runs once per key
+    let mut positions: HashMap<&str, Option<usize>> = HashMap::new();
+    let mut columns: Vec<(&str, Vec<Option<&str>>)> = Vec::new();
+    for (row, item) in records.iter().enumerate() {
+        for (key, value) in item {
+            let position = *positions.entry(key).or_insert_with(|| {
+                keep(key).then(|| {
+                    columns.push((key, vec![None; records.len()]));
+                    columns.len() - 1
+                })
+            });
+            if let Some(position) = position {
+                columns[position].1[row] = Some(value);
+            }
         }
     }
+    if canonical {
+        columns.sort_unstable_by(|a, b| a.0.cmp(b.0));
+    }
 
-    Ok(py_dict.into())
+    let data = PyDict::new(py);
+    let dtypes = PyDict::new(py);
+    for (column, values) in columns {
+        let column_type = match with_dtypes {
+            true => ColumnType::infer(values.iter().copied()),
+            false => ColumnType::Text,
+        };
+        let py_list = match column_type {
+            ColumnType::Text => {
+                let strings: Vec<&str> = values.iter().map(|value| value.unwrap_or("")).collect();
+                PyList::new(py, strings)
+            }
+            _ => PyList::new(py, values.iter().map(|value| column_type.to_py(py, *value))),
+        };
+        data.set_item(column, py_list)?;
+        if with_dtypes {
+            dtypes.set_item(column, column_type.dtype(values.iter().copied().any(is_null)))?;
+        }
+    }
+    Ok((data, dtypes))
+}
+
+/// Column filter of the dataframe-ready functions: columns matching a
+/// `select` pattern (any column without `select`) and no `drop` pattern
+///
+/// Patterns and keys are split on the options' separator.
+fn column_filter(
+    select: Option<Vec<String>>,
+    drop: Option<Vec<String>>,
+    options: &FlattenOptions,
+) -> impl Fn(&str) -> bool {
+    let separator = options.separator.clone();
+    let escaped = options.escape_keys;
+    let patterns = |patterns: Vec<String>| -> Vec<KeyPattern> {
+        patterns.iter().map(|pattern| KeyPattern::with_separator(pattern, &separator)).collect()
+    };
+    let select = select.map(patterns);
+    let drop = drop.map(patterns).unwrap_or_default();
+    move |key| {
+        let segments = split_key(key, &separator, escaped);
+        let matches = |patterns: &[KeyPattern]| patterns.iter().any(|pattern| pattern.matches(&segments));
+        select.as_deref().is_none_or(matches) && !matches(&drop)
+    }
+}
+
+impl ColumnType {
+    /// pandas dtype string; `nullable` picks the extension types that hold missing values
+    fn dtype(self, nullable: bool) -> &'static str {
+        match (self, nullable) {
+            (ColumnType::Bool, false) => "bool",
+            (ColumnType::Bool, true) => "boolean",
+            (ColumnType::Int, false) => "int64",
+            (ColumnType::Int, true) => "Int64",
+            (ColumnType::Float, _) => "float64",
+            (ColumnType::Decimal | ColumnType::Text, _) => "object",
+        }
+    }
+
+    /// Converts a value to Python; text columns keep the flattened string, and
+    /// decimal columns the exact number text
+    fn to_py(self, py: Python, value: Option<&str>) -> PyObject {
+        match (self, value) {
+            (ColumnType::Text, value) => value.unwrap_or("").into_py(py),
+            (_, value) if is_null(value) => py.None(),
+            (ColumnType::Decimal, Some(value)) => value.into_py(py),
+            (ColumnType::Bool, Some(value)) => (value == "true").into_py(py),
+            (ColumnType::Int, Some(value)) => value.parse::<i64>().unwrap_or_default().into_py(py),
+            (_, value) => value.unwrap_or_default().parse::<f64>().unwrap_or(f64::NAN).into_py(py),
+        }
+    }
 }
 
 /// Flatten a JSON file and prepare it for polaris DataFrame conversion
 /// Returns a dict with column names as keys and lists of values as values
 /// This is optimized for the polaris DataFrame API
+///
+/// `select` and `drop` prune columns as in `flatten_pandas_ready`.
 #[pyfunction]
-fn flatten_polaris_ready(py: Python, filepath: &str, options: Option<PyFlattenOptions>) -> PyResult<PyObject> {
-    let options = options.unwrap_or_else(|| PyFlattenOptions::new(None, None, None, None, None, None));
-    let rust_options: FlattenOptions = options.into();
+#[pyo3(signature = (filepath, options=None, select=None, drop=None))]
+fn flatten_polaris_ready(
+    py: Python,
+    filepath: PathBuf,
+    options: Option<PyFlattenOptions>,
+    select: Option<Vec<String>>,
+    drop: Option<Vec<String>>,
+) -> PyResult<PyObject> {
+    let rust_options = rust_options(options)?;
+    let keep = column_filter(select, drop, &rust_options);
 
     // Flatten the JSON file
-    let flattened_data = flatten_json_file(filepath, &rust_options)
+    let flattened_data = py
+        .allow_threads(|| flatten_json_file(&filepath, &rust_options).map_err(|e| e.to_string()))
         .map_err(|e| PyIOError::new_err(format!("Error reading file: {}", e)))?;
 
     // If there's no data, return an empty dict
@@ -197,30 +1369,13 @@ fn flatten_polaris_ready(py: Python, filepath: &str, options: Option<PyFlattenOp
     }
 
     // Convert to column-oriented format for Polaris
-    let mut columns: HashMap<String, Vec<String>> = HashMap::new();
-    
-    // First pass: collect all column names
-    for item in &flattened_data {
-        for key in item.keys() {
-            if !columns.contains_key(key) {
-                columns.insert(key.clone(), Vec::with_capacity(flattened_data.len()));
-            }
-        }
-    }
-    
-    // Second pass: fill columns with values
-    for item in flattened_data {
-        for (key, column) in columns.iter_mut() {
-            let value = item.get(key).cloned().unwrap_or_else(|| "null".to_string());
-            column.push(value);
-        }
-    }
-    
-    // Convert to Python dict
+    let mut table = ColumnarTable::from_records(flattened_data);
+    table.retain_columns(keep);
+    table.fill_missing("null");
     let py_dict = PyDict::new(py);
-    for (key, values) in columns {
-        let py_list = PyList::new(py, &values);
-        py_dict.set_item(key, py_list)?;
+    for (key, values) in table {
+        let values: Vec<String> = values.into_iter().flatten().collect();
+        py_dict.set_item(key, PyList::new(py, &values))?;
     }
 
     Ok(py_dict.into())
@@ -0,0 +1,258 @@
+// src/raw.rs
+use crate::value::{JsonKind, JsonLike};
+use alloc::borrow::Cow;
+use alloc::string::String;
+use alloc::vec::Vec;
+use serde::de::{Error as _, IgnoredAny};
+use serde_json::Number;
+
+/// A parsed document that keeps the source text of every node
+///
+/// Flattened with `FlattenOptions::raw_values` set, its leaves are written
+/// exactly as they appear in the source instead of being re-serialized.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RawJson<'a> {
+    text: &'a str,
+    node: RawNode<'a>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum RawNode<'a> {
+    Null,
+    Bool(bool),
+    Number(Number),
+    String(Cow<'a, str>),
+    Array(Vec<RawJson<'a>>),
+    /// Entries in source order; a repeated key is kept twice and the last one
+    /// wins when flattened
+    Object(Vec<(Cow<'a, str>, RawJson<'a>)>),
+}
+
+impl<'a> RawJson<'a> {
+    /// Parses one document, failing on invalid JSON like `serde_json::from_str`
+    pub fn parse(json: &'a str) -> Result<Self, serde_json::Error> {
+        // serde_json reports errors with their position; after it accepts the
+        // text, the scanner below only has to find the node boundaries
+        serde_json::from_str::<IgnoredAny>(json)?;
+        let mut scanner = Scanner { text: json, pos: 0 };
+        scanner.skip_whitespace();
+        scanner.value()
+    }
+
+    /// The node's text exactly as in the source, with the quotes of a string
+    pub fn source(&self) -> &'a str {
+        self.text
+    }
+}
+
+/// Walks text that serde_json has already accepted
+struct Scanner<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Scanner<'a> {
+    fn peek(&self) -> u8 {
+        self.text.as_bytes().get(self.pos).copied().unwrap_or(0)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), b' ' | b'\t' | b'\n' | b'\r') {
+            self.pos += 1;
+        }
+    }
+
+    /// Consumes `byte` and any whitespace after it
+    fn expect(&mut self, byte: u8) -> Result<(), serde_json::Error> {
+        if self.peek() != byte {
+            return Err(serde_json::Error::custom(format_args!(
+                "expected {:?} at byte {}",
+                byte as char, self.pos
+            )));
+        }
+        self.pos += 1;
+        self.skip_whitespace();
+        Ok(())
+    }
+
+    fn value(&mut self) -> Result<RawJson<'a>, serde_json::Error> {
+        let start = self.pos;
+        let node = match self.peek() {
+            b'{' => {
+                self.expect(b'{')?;
+                let mut entries = Vec::new();
+                while self.peek() != b'}' {
+                    if !entries.is_empty() {
+                        self.expect(b',')?;
+                    }
+                    let key = self.string()?;
+                    self.skip_whitespace();
+                    self.expect(b':')?;
+                    entries.push((key, self.value()?));
+                }
+                self.pos += 1;
+                RawNode::Object(entries)
+            }
+            b'[' => {
+                self.expect(b'[')?;
+                let mut items = Vec::new();
+                while self.peek() != b']' {
+                    if !items.is_empty() {
+                        self.expect(b',')?;
+                    }
+                    items.push(self.value()?);
+                }
+                self.pos += 1;
+                RawNode::Array(items)
+            }
+            b'"' => RawNode::String(self.string()?),
+            b't' | b'f' | b'n' => {
+                let (node, len) = match self.peek() {
+                    b't' => (RawNode::Bool(true), 4),
+                    b'f' => (RawNode::Bool(false), 5),
+                    _ => (RawNode::Null, 4),
+                };
+                self.pos += len;
+                node
+            }
+            _ => {
+                let len = self.text[start..]
+                    .find(|c: char| !matches!(c, '0'..='9' | '-' | '+' | '.' | 'e' | 'E'))
+                    .unwrap_or(self.text.len() - start);
+                self.pos += len;
+                RawNode::Number(self.text[start..self.pos].parse()?)
+            }
+        };
+        let text = &self.text[start..self.pos];
+        self.skip_whitespace();
+        Ok(RawJson { text, node })
+    }
+
+    /// Reads a string, borrowing it from the source unless it has escapes
+    fn string(&mut self) -> Result<Cow<'a, str>, serde_json::Error> {
+        let start = self.pos;
+        let bytes = self.text.as_bytes();
+        let mut escaped = false;
+        self.pos += 1;
+        while self.pos < bytes.len() && bytes[self.pos] != b'"' {
+            escaped |= bytes[self.pos] == b'\\';
+            self.pos += if bytes[self.pos] == b'\\' { 2 } else { 1 };
+        }
+        self.pos += 1;
+        let quoted = &self.text[start..self.pos];
+        if escaped {
+            Ok(Cow::Owned(serde_json::from_str::<String>(quoted)?))
+        } else {
+            Ok(Cow::Borrowed(&quoted[1..quoted.len() - 1]))
+        }
+    }
+}
+
+impl JsonLike for RawJson<'_> {
+    fn kind(&self) -> JsonKind {
+        match self.node {
+            RawNode::Null => JsonKind::Null,
+            RawNode::Bool(_) => JsonKind::Bool,
+            RawNode::Number(_) => JsonKind::Number,
+            RawNode::String(_) => JsonKind::String,
+            RawNode::Array(_) => JsonKind::Array,
+            RawNode::Object(_) => JsonKind::Object,
+        }
+    }
+
+    fn as_bool(&self) -> Option<bool> {
+        match self.node {
+            RawNode::Bool(b) => Some(b),
+            _ => None,
+        }
+    }
+
+    fn as_number(&self) -> Option<Number> {
+        match &self.node {
+            RawNode::Number(n) => Some(n.clone()),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match &self.node {
+            RawNode::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    fn array_iter(&self) -> Option<impl ExactSizeIterator<Item = &Self>> {
+        match &self.node {
+            RawNode::Array(items) => Some(items.iter()),
+            _ => None,
+        }
+    }
+
+    fn object_iter(&self) -> Option<impl Iterator<Item = (&str, &Self)>> {
+        match &self.node {
+            RawNode::Object(entries) => Some(entries.iter().map(|(key, value)| (key.as_ref(), value))),
+            _ => None,
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<&Self> {
+        // The last entry wins, like in a parsed `Value`
+        self.object_iter()?.filter(|(k, _)| *k == key).last().map(|(_, value)| value)
+    }
+
+    fn source_text(&self) -> Option<&str> {
+        Some(self.text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{flatten_into, FlattenOptions};
+    use alloc::collections::BTreeMap;
+
+    const DOCUMENT: &str = r#" {"price": 1.50, "big": 1E+3, "name": "café \"x\"", "ok": true,
+        "tags": ["a" , 10.0], "nested": {"n": -0.0} } "#;
+
+    #[test]
+    fn test_parse_keeps_source_text_and_values() {
+        let document = RawJson::parse(DOCUMENT).unwrap();
+        assert_eq!(document.source(), DOCUMENT.trim());
+        assert_eq!(document.to_value(), serde_json::from_str::<serde_json::Value>(DOCUMENT).unwrap());
+        #[cfg(not(feature = "arbitrary_precision"))]
+        assert_eq!(
+            document.to_value(),
+            serde_json::json!({
+                "price": 1.5, "big": 1000.0, "name": "café \"x\"", "ok": true, "tags": ["a", 10.0], "nested": {"n": -0.0}
+            })
+        );
+        assert_eq!(document.get("tags").unwrap().source(), r#"["a" , 10.0]"#);
+        assert!(RawJson::parse(r#"{"a": 01}"#).is_err());
+        assert!(RawJson::parse("[1] [2]").is_err());
+    }
+
+    #[test]
+    fn test_raw_values_flatten_to_source_text() {
+        let document = RawJson::parse(DOCUMENT).unwrap();
+        let options = FlattenOptions {
+            raw_values: true,
+            ..Default::default()
+        };
+        let mut record = BTreeMap::new();
+        flatten_into(&document, &options, &mut record).unwrap();
+        let expected = [
+            ("big", "1E+3"),
+            ("name", r#"café \"x\""#),
+            ("nested.n", "-0.0"),
+            ("ok", "true"),
+            ("price", "1.50"),
+            ("tags.0", "a"),
+            ("tags.1", "10.0"),
+        ];
+        assert_eq!(record, expected.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect());
+
+        let mut record = BTreeMap::new();
+        flatten_into(&document, &FlattenOptions { max_depth: 1, ..options }, &mut record).unwrap();
+        assert_eq!(record["nested"], r#"{"n": -0.0}"#);
+    }
+}
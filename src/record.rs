@@ -0,0 +1,142 @@
+// src/record.rs
+//! A flattened record as its own type, for storing and logging
+use crate::FlattenedJson;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::{Deref, DerefMut, Index};
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Serialize, Serializer};
+
+/// A [`FlattenedJson`] record that serializes, prints and compares on its own
+///
+/// Serializes as a JSON object with keys in sorted order, so the same record
+/// always gives the same text whatever the hash order, and deserializes from
+/// any object of strings. It derefs to the map, so lookups and iteration work
+/// as on [`FlattenedJson`]; convert with `From` in either direction.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(transparent)]
+pub struct FlattenedRecord(FlattenedJson);
+
+impl FlattenedRecord {
+    pub fn new(record: FlattenedJson) -> Self {
+        FlattenedRecord(record)
+    }
+
+    pub fn into_inner(self) -> FlattenedJson {
+        self.0
+    }
+
+    /// Pairs sorted by key
+    pub fn sorted(&self) -> Vec<(&str, &str)> {
+        let mut pairs: Vec<(&str, &str)> = self.0.iter().map(|(key, value)| (key.as_str(), value.as_str())).collect();
+        pairs.sort_unstable_by(|a, b| a.0.cmp(b.0));
+        pairs
+    }
+}
+
+impl Serialize for FlattenedRecord {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (key, value) in self.sorted() {
+            map.serialize_entry(key, value)?;
+        }
+        map.end()
+    }
+}
+
+/// The record as a single-line JSON object, keys sorted
+impl fmt::Display for FlattenedRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&serde_json::to_string(self).map_err(|_| fmt::Error)?)
+    }
+}
+
+impl Deref for FlattenedRecord {
+    type Target = FlattenedJson;
+
+    fn deref(&self) -> &FlattenedJson {
+        &self.0
+    }
+}
+
+impl DerefMut for FlattenedRecord {
+    fn deref_mut(&mut self) -> &mut FlattenedJson {
+        &mut self.0
+    }
+}
+
+/// Panics when the key is missing, like indexing the map
+impl Index<&str> for FlattenedRecord {
+    type Output = String;
+
+    fn index(&self, key: &str) -> &String {
+        &self.0[key]
+    }
+}
+
+impl From<FlattenedJson> for FlattenedRecord {
+    fn from(record: FlattenedJson) -> Self {
+        FlattenedRecord(record)
+    }
+}
+
+impl From<FlattenedRecord> for FlattenedJson {
+    fn from(record: FlattenedRecord) -> Self {
+        record.0
+    }
+}
+
+impl FromIterator<(String, String)> for FlattenedRecord {
+    fn from_iter<I: IntoIterator<Item = (String, String)>>(pairs: I) -> Self {
+        FlattenedRecord(pairs.into_iter().collect())
+    }
+}
+
+impl IntoIterator for FlattenedRecord {
+    type Item = (String, String);
+    type IntoIter = <FlattenedJson as IntoIterator>::IntoIter;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a FlattenedRecord {
+    type Item = (&'a String, &'a String);
+    type IntoIter = <&'a FlattenedJson as IntoIterator>::IntoIter;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{flatten_json, FlattenOptions};
+    use serde_json::json;
+
+    #[test]
+    fn test_record_round_trips_through_serde_with_sorted_keys() {
+        let flattened = flatten_json(&json!({"z": 1, "a": {"b": [true, null]}}), &FlattenOptions::default());
+        let record = FlattenedRecord::from(flattened.clone());
+        let text = serde_json::to_string(&record).unwrap();
+        assert_eq!(text, r#"{"a.b.0":"true","a.b.1":"null","z":"1"}"#);
+        assert_eq!(record.to_string(), text);
+        assert_eq!(&record["z"], "1");
+        assert_eq!(record.get("a.b.0").map(String::as_str), Some("true"));
+
+        let parsed: FlattenedRecord = serde_json::from_str(&text).unwrap();
+        assert_eq!(parsed, record);
+        assert_eq!(FlattenedJson::from(parsed), flattened);
+        assert_eq!((&record).into_iter().count(), 3);
+        assert!(serde_json::from_str::<FlattenedRecord>(r#"{"a": 1}"#).is_err());
+
+        #[cfg(feature = "std")]
+        {
+            let map: std::collections::HashMap<String, String> = record.into();
+            assert_eq!(map["a.b.1"], "null");
+        }
+    }
+}
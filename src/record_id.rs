@@ -0,0 +1,189 @@
+// src/record_id.rs
+use crate::canonical::to_json;
+use crate::error::FlattenError;
+use crate::value::{JsonKind, JsonLike};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+/// Where the identifier attached to each flattened record comes from
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum RecordIdSource {
+    /// No identifier is attached
+    #[default]
+    None,
+    /// Position of the document in the input, starting at 0
+    Sequence,
+    /// A random UUIDv4; needs the `std` feature, which supplies the randomness
+    #[cfg(feature = "std")]
+    Uuid,
+    /// 64-bit FNV-1a hash of the document's canonical JSON, as 16 hex digits;
+    /// identical documents get identical ids
+    ContentHash,
+    /// A value extracted from the source document, e.g. `$.id`
+    Path(JsonPath),
+}
+
+impl core::str::FromStr for RecordIdSource {
+    type Err = String;
+
+    /// Parses `none`, `sequence`, `uuid`, `hash` or a path starting with `$`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(RecordIdSource::None),
+            "sequence" => Ok(RecordIdSource::Sequence),
+            #[cfg(feature = "std")]
+            "uuid" => Ok(RecordIdSource::Uuid),
+            #[cfg(not(feature = "std"))]
+            "uuid" => Err("record id source \"uuid\" needs the std feature".to_string()),
+            "hash" => Ok(RecordIdSource::ContentHash),
+            _ if s.starts_with('$') => Ok(RecordIdSource::Path(s.parse()?)),
+            _ => Err(format!(
+                "unknown record id source {:?} (expected none, sequence, uuid, hash or a $.path)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for RecordIdSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordIdSource::None => f.write_str("none"),
+            RecordIdSource::Sequence => f.write_str("sequence"),
+            #[cfg(feature = "std")]
+            RecordIdSource::Uuid => f.write_str("uuid"),
+            RecordIdSource::ContentHash => f.write_str("hash"),
+            RecordIdSource::Path(path) => write!(f, "{}", path),
+        }
+    }
+}
+
+impl RecordIdSource {
+    /// Computes the id of a document at position `sequence` in the input
+    pub fn record_id<J: JsonLike + ?Sized>(&self, document: &J, sequence: u64) -> Result<Option<String>, FlattenError> {
+        match self {
+            RecordIdSource::None => Ok(None),
+            RecordIdSource::Sequence => Ok(Some(sequence.to_string())),
+            #[cfg(feature = "std")]
+            RecordIdSource::Uuid => Ok(Some(uuid::Uuid::new_v4().to_string())),
+            RecordIdSource::ContentHash => Ok(Some(format!("{:016x}", fnv1a(to_json(document, true).as_bytes())))),
+            RecordIdSource::Path(path) => match path.lookup(document) {
+                Some(value) if value.kind() == JsonKind::String => Ok(value.as_str().map(String::from)),
+                Some(value) if value.kind() == JsonKind::Null => Err(FlattenError::MissingRecordId(path.to_string())),
+                None => Err(FlattenError::MissingRecordId(path.to_string())),
+                Some(other) => Ok(Some(to_json(other, true))),
+            },
+        }
+    }
+}
+
+/// 64-bit FNV-1a, chosen because its output is stable across platforms and releases
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// A simple JSONPath: `$` followed by `.key`, `['key']` and `[index]` steps
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JsonPath {
+    source: String,
+    steps: Vec<PathStep>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum PathStep {
+    Key(String),
+    Index(usize),
+}
+
+impl JsonPath {
+    /// Returns the value at this path, if present
+    pub fn lookup<'a, J: JsonLike + ?Sized>(&self, document: &'a J) -> Option<&'a J> {
+        self.steps.iter().try_fold(document, |value, step| match step {
+            PathStep::Key(key) => value.get(key),
+            PathStep::Index(index) => value.array_iter()?.nth(*index),
+        })
+    }
+}
+
+impl core::str::FromStr for JsonPath {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid JSON path {:?}", s);
+        let mut rest = s.strip_prefix('$').ok_or_else(invalid)?;
+        let mut steps = Vec::new();
+        while !rest.is_empty() {
+            if let Some(after_dot) = rest.strip_prefix('.') {
+                let end = after_dot.find(['.', '[']).unwrap_or(after_dot.len());
+                if end == 0 {
+                    return Err(invalid());
+                }
+                steps.push(PathStep::Key(after_dot[..end].to_string()));
+                rest = &after_dot[end..];
+            } else if let Some(after_bracket) = rest.strip_prefix('[') {
+                let end = after_bracket.find(']').ok_or_else(invalid)?;
+                let inner = &after_bracket[..end];
+                let quoted = inner
+                    .strip_prefix('\'')
+                    .and_then(|i| i.strip_suffix('\''))
+                    .or_else(|| inner.strip_prefix('"').and_then(|i| i.strip_suffix('"')));
+                steps.push(match quoted {
+                    Some(key) => PathStep::Key(key.to_string()),
+                    None => PathStep::Index(inner.parse().map_err(|_| invalid())?),
+                });
+                rest = &after_bracket[end + 1..];
+            } else {
+                return Err(invalid());
+            }
+        }
+        Ok(JsonPath {
+            source: s.to_string(),
+            steps,
+        })
+    }
+}
+
+impl fmt::Display for JsonPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_json_path_lookup() {
+        let document = json!({"meta": {"ids": [{"id": 7}]}, "a b": "x"});
+        let path: JsonPath = "$.meta.ids[0].id".parse().unwrap();
+        assert_eq!(path.lookup(&document), Some(&json!(7)));
+        let quoted: JsonPath = "$['a b']".parse().unwrap();
+        assert_eq!(quoted.lookup(&document), Some(&json!("x")));
+        assert!("$.".parse::<JsonPath>().is_err());
+        assert!("meta".parse::<JsonPath>().is_err());
+    }
+
+    #[test]
+    fn test_record_id_sources() {
+        let document = json!({"id": "abc", "n": 1});
+        let same = json!({"n": 1, "id": "abc"});
+        let hash: RecordIdSource = "hash".parse().unwrap();
+        assert_eq!(hash.record_id(&document, 0), hash.record_id(&same, 5));
+        assert_eq!(RecordIdSource::Sequence.record_id(&document, 5), Ok(Some("5".to_string())));
+
+        let path: RecordIdSource = "$.id".parse().unwrap();
+        assert_eq!(path.record_id(&document, 0), Ok(Some("abc".to_string())));
+        assert!("$.missing".parse::<RecordIdSource>().unwrap().record_id(&document, 0).is_err());
+
+        #[cfg(feature = "std")]
+        assert_eq!(RecordIdSource::Uuid.record_id(&document, 0).unwrap().unwrap().len(), 36);
+        #[cfg(not(feature = "std"))]
+        assert!("uuid".parse::<RecordIdSource>().is_err());
+    }
+}
@@ -0,0 +1,192 @@
+// src/redis_sink.rs
+use crate::{into_sorted_vec, FlattenedJson, RecordSink};
+use redis::{ConnectionLike, Pipeline, RedisResult};
+use std::io;
+
+/// Records sent per pipeline round trip by default
+pub const DEFAULT_REDIS_BATCH_RECORDS: usize = 1_000;
+
+/// How a [`RedisSink`] lays out each record's pairs
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RedisLayout {
+    /// One hash per record at `<prefix><id>`, replacing any previous hash
+    #[default]
+    Hash,
+    /// One string key per pair at `<prefix><id>:<key>`
+    Keys,
+}
+
+/// Sink that writes flattened records to Redis, batched in pipelines
+///
+/// Records are numbered from 0 in input order unless an id key is set, like
+/// [`EavSink`](crate::EavSink). Commands are queued and sent every
+/// `batch_records` records and on flush, so a failed batch surfaces on the
+/// write that triggered it.
+pub struct RedisSink<C: ConnectionLike> {
+    connection: C,
+    layout: RedisLayout,
+    prefix: String,
+    id_key: Option<String>,
+    batch_records: usize,
+    pipeline: Pipeline,
+    queued: usize,
+    next_id: u64,
+}
+
+impl RedisSink<redis::Connection> {
+    /// Connects to a server URL such as `redis://127.0.0.1/`
+    pub fn connect(url: &str, layout: RedisLayout) -> RedisResult<Self> {
+        let connection = redis::Client::open(url)?.get_connection()?;
+        Ok(Self::new(connection, layout))
+    }
+}
+
+impl<C: ConnectionLike> RedisSink<C> {
+    /// Creates a sink on an existing connection
+    pub fn new(connection: C, layout: RedisLayout) -> Self {
+        RedisSink {
+            connection,
+            layout,
+            prefix: String::new(),
+            id_key: None,
+            batch_records: DEFAULT_REDIS_BATCH_RECORDS,
+            pipeline: Pipeline::new(),
+            queued: 0,
+            next_id: 0,
+        }
+    }
+
+    /// Prepends a prefix, e.g. `config:`, to every key written
+    pub fn with_prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.to_string();
+        self
+    }
+
+    /// Takes the record id from this key (see `FlattenOptions::record_id_key`)
+    /// instead of numbering records; the key itself is not written
+    pub fn with_id_key(mut self, key: &str) -> Self {
+        self.id_key = Some(key.to_string());
+        self
+    }
+
+    /// Sets how many records are queued before the pipeline is sent
+    pub fn with_batch_records(mut self, batch_records: usize) -> Self {
+        self.batch_records = batch_records.max(1);
+        self
+    }
+
+    /// Sends queued commands and returns the connection
+    pub fn into_inner(mut self) -> io::Result<C> {
+        RecordSink::flush(&mut self)?;
+        Ok(self.connection)
+    }
+
+    /// Queues the commands that store one record
+    fn queue(&mut self, mut record: FlattenedJson) {
+        let id = match self.id_key.as_ref().and_then(|key| record.remove(key)) {
+            Some(id) => id,
+            None => self.next_id.to_string(),
+        };
+        self.next_id += 1;
+        if record.is_empty() {
+            return;
+        }
+
+        let entries = into_sorted_vec(record);
+        let key = format!("{}{}", self.prefix, id);
+        match self.layout {
+            RedisLayout::Hash => {
+                self.pipeline.cmd("DEL").arg(&key).ignore();
+                self.pipeline.cmd("HSET").arg(&key).arg(entries).ignore();
+            }
+            RedisLayout::Keys => {
+                let pairs: Vec<(String, String)> = entries
+                    .into_iter()
+                    .map(|(field, value)| (format!("{}:{}", key, field), value))
+                    .collect();
+                self.pipeline.cmd("MSET").arg(pairs).ignore();
+            }
+        }
+        self.queued += 1;
+    }
+
+    fn send(&mut self) -> io::Result<()> {
+        if self.pipeline.is_empty() {
+            return Ok(());
+        }
+        let result = self.pipeline.exec(&mut self.connection);
+        self.pipeline.clear();
+        self.queued = 0;
+        result.map_err(io::Error::other)
+    }
+}
+
+impl<C: ConnectionLike> RecordSink for RedisSink<C> {
+    fn write_record(&mut self, record: FlattenedJson) -> io::Result<()> {
+        self.queue(record);
+        if self.queued >= self.batch_records {
+            self.send()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Splits packed RESP commands into their arguments
+    fn commands(packed: &[u8]) -> Vec<Vec<String>> {
+        let text = String::from_utf8(packed.to_vec()).unwrap();
+        let mut lines = text.split("\r\n");
+        let mut commands = Vec::new();
+        while let Some(header) = lines.next().filter(|line| !line.is_empty()) {
+            let count: usize = header[1..].parse().unwrap();
+            let args = (0..count).map(|_| lines.nth(1).unwrap().to_string()).collect();
+            commands.push(args);
+        }
+        commands
+    }
+
+    fn record(pairs: &[(&str, &str)]) -> FlattenedJson {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn test_hash_layout_replaces_one_hash_per_record() {
+        let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+        let mut sink = RedisSink::new(client, RedisLayout::Hash)
+            .with_prefix("cfg:")
+            .with_id_key("_id");
+        sink.queue(record(&[("_id", "db"), ("port", "5432"), ("host", "a")]));
+        sink.queue(record(&[("x", "1")]));
+
+        assert_eq!(
+            commands(&sink.pipeline.get_packed_pipeline()),
+            vec![
+                vec!["DEL", "cfg:db"],
+                vec!["HSET", "cfg:db", "host", "a", "port", "5432"],
+                vec!["DEL", "cfg:1"],
+                vec!["HSET", "cfg:1", "x", "1"],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_keys_layout_sets_one_key_per_pair() {
+        let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+        let mut sink = RedisSink::new(client, RedisLayout::Keys).with_prefix("doc:");
+        sink.queue(record(&[("a.b", "1"), ("c", "x")]));
+        sink.queue(FlattenedJson::default());
+
+        assert_eq!(
+            commands(&sink.pipeline.get_packed_pipeline()),
+            vec![vec!["MSET", "doc:0:a.b", "1", "doc:0:c", "x"]]
+        );
+        assert_eq!(sink.queued, 1);
+    }
+}
@@ -0,0 +1,204 @@
+// src/retry.rs
+use std::io::{self, ErrorKind, Read};
+use std::thread;
+use std::time::Duration;
+
+/// How often and how patiently a [`ResumableReader`] retries failed reads
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Consecutive failures tolerated before the error is returned
+    pub max_retries: u32,
+    /// Wait before the first retry; doubled after every further failure
+    pub initial_backoff: Duration,
+    /// Upper bound on the wait between retries
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 5,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Wait before retry number `attempt` (starting at 0)
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        self.initial_backoff
+            .checked_mul(1u32.checked_shl(attempt).unwrap_or(u32::MAX))
+            .map_or(self.max_backoff, |backoff| backoff.min(self.max_backoff))
+    }
+}
+
+/// Whether a read error is worth retrying: dropped or timed out connections,
+/// as opposed to missing objects or malformed data
+pub fn is_transient(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::BrokenPipe
+            | ErrorKind::TimedOut
+            | ErrorKind::UnexpectedEof
+            | ErrorKind::Interrupted
+    )
+}
+
+/// A reader that reopens its source at the current byte offset when a read
+/// fails with a transient error
+///
+/// `open` is called with the offset to resume from: 0 for the initial
+/// connection, and the number of bytes already read after a failure. Sources
+/// that support ranged reads (an HTTP `Range` header, an S3 `GetObject` range)
+/// can resume mid-stream this way; the documents downstream never see the
+/// interruption. The retry count resets after every successful read.
+pub struct ResumableReader<R, F> {
+    open: F,
+    reader: Option<R>,
+    offset: u64,
+    policy: RetryPolicy,
+    failures: u32,
+}
+
+impl<R: Read, F: FnMut(u64) -> io::Result<R>> ResumableReader<R, F> {
+    /// Creates a reader that connects lazily on the first read
+    pub fn new(open: F, policy: RetryPolicy) -> Self {
+        ResumableReader {
+            open,
+            reader: None,
+            offset: 0,
+            policy,
+            failures: 0,
+        }
+    }
+
+    /// Number of bytes read so far
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    fn try_read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let reader = match &mut self.reader {
+            Some(reader) => reader,
+            None => self.reader.insert((self.open)(self.offset)?),
+        };
+        reader.read(buf)
+    }
+}
+
+impl<R: Read, F: FnMut(u64) -> io::Result<R>> Read for ResumableReader<R, F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.try_read(buf) {
+                Ok(n) => {
+                    self.offset += n as u64;
+                    self.failures = 0;
+                    return Ok(n);
+                }
+                Err(e) if is_transient(&e) && self.failures < self.policy.max_retries => {
+                    self.reader = None;
+                    thread::sleep(self.policy.backoff(self.failures));
+                    self.failures += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Serves `data` from an offset, dropping the connection after `left` bytes
+    struct Flaky {
+        data: &'static [u8],
+        offset: usize,
+        left: usize,
+    }
+
+    impl Read for Flaky {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.left == 0 && self.offset < self.data.len() {
+                return Err(io::Error::new(ErrorKind::ConnectionReset, "connection reset"));
+            }
+            let n = buf.len().min(self.left).min(self.data.len() - self.offset);
+            buf[..n].copy_from_slice(&self.data[self.offset..self.offset + n]);
+            self.offset += n;
+            self.left -= n;
+            Ok(n)
+        }
+    }
+
+    fn no_wait(max_retries: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_retries,
+            initial_backoff: Duration::ZERO,
+            max_backoff: Duration::ZERO,
+        }
+    }
+
+    #[test]
+    fn test_reader_resumes_from_offset_after_dropped_connections() {
+        let data: &[u8] = b"{\"a\": 1}\n{\"b\": 2}\n{\"c\": 3}\n";
+        let mut offsets = Vec::new();
+        let mut reader = ResumableReader::new(
+            |offset| {
+                offsets.push(offset);
+                Ok(Flaky {
+                    data,
+                    offset: offset as usize,
+                    left: 10,
+                })
+            },
+            no_wait(1),
+        );
+        let mut text = String::new();
+        reader.read_to_string(&mut text).unwrap();
+
+        assert_eq!(text.as_bytes(), data);
+        assert_eq!(offsets, vec![0, 10, 20]);
+    }
+
+    #[test]
+    fn test_reader_gives_up_after_max_retries_and_on_permanent_errors() {
+        let mut reader = ResumableReader::new(
+            |_| {
+                Ok(Flaky {
+                    data: b"abc",
+                    offset: 0,
+                    left: 0,
+                })
+            },
+            no_wait(2),
+        );
+        let err = reader.read(&mut [0; 4]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ConnectionReset);
+
+        let mut opens = 0;
+        let mut reader = ResumableReader::new(
+            |_| -> io::Result<&[u8]> {
+                opens += 1;
+                Err(ErrorKind::NotFound.into())
+            },
+            no_wait(2),
+        );
+        assert_eq!(reader.read(&mut [0; 4]).unwrap_err().kind(), ErrorKind::NotFound);
+        assert_eq!(opens, 1);
+    }
+
+    #[test]
+    fn test_backoff_doubles_up_to_the_cap() {
+        let policy = RetryPolicy {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(350),
+            ..Default::default()
+        };
+        let waits: Vec<u128> = (0..4).map(|attempt| policy.backoff(attempt).as_millis()).collect();
+        assert_eq!(waits, vec![100, 200, 350, 350]);
+        assert_eq!(policy.backoff(40), Duration::from_millis(350));
+    }
+}
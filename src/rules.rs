@@ -0,0 +1,298 @@
+// src/rules.rs
+//! Dropping and masking keys by pattern, with rules that can change while running
+use crate::keys::{split_key, KeyPattern};
+use crate::{FlattenOptions, FlattenedJson, RecordSink, SkippedDocument};
+use serde::Deserialize;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Value written over redacted values when the rules file sets no `mask`
+pub const DEFAULT_MASK: &str = "[REDACTED]";
+
+/// How often a [`SharedRules`] made from a file checks whether the file changed
+pub const RELOAD_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Keys to drop and keys whose values to mask
+///
+/// Rules files are JSON objects such as
+/// `{"drop": ["debug.**"], "redact": ["**.ssn", "user.email"], "mask": "***"}`,
+/// every field optional. Patterns are [`KeyPattern`]s, split on the options'
+/// separator; a key matching both lists is dropped.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyRules {
+    drop: Vec<KeyPattern>,
+    redact: Vec<KeyPattern>,
+    mask: String,
+    separator: String,
+    escaped: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RulesFile {
+    #[serde(default)]
+    drop: Vec<String>,
+    #[serde(default)]
+    redact: Vec<String>,
+    mask: Option<String>,
+}
+
+impl KeyRules {
+    /// Parses the text of a rules file, for keys flattened with `options`
+    pub fn parse(text: &str, options: &FlattenOptions) -> Result<KeyRules, serde_json::Error> {
+        Self::parse_for(text, &options.separator, options.escape_keys)
+    }
+
+    fn parse_for(text: &str, separator: &str, escaped: bool) -> Result<KeyRules, serde_json::Error> {
+        let file: RulesFile = serde_json::from_str(text)?;
+        let compile = |patterns: Vec<String>| -> Vec<KeyPattern> {
+            patterns.iter().map(|pattern| KeyPattern::with_separator(pattern, separator)).collect()
+        };
+        Ok(KeyRules {
+            drop: compile(file.drop),
+            redact: compile(file.redact),
+            mask: file.mask.unwrap_or_else(|| DEFAULT_MASK.to_string()),
+            separator: separator.to_string(),
+            escaped,
+        })
+    }
+
+    /// Reads and parses a rules file
+    pub fn load(path: impl AsRef<Path>, options: &FlattenOptions) -> io::Result<KeyRules> {
+        Self::load_for(path.as_ref(), &options.separator, options.escape_keys)
+    }
+
+    fn load_for(path: &Path, separator: &str, escaped: bool) -> io::Result<KeyRules> {
+        let text = std::fs::read_to_string(path)?;
+        Self::parse_for(&text, separator, escaped)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))
+    }
+
+    pub fn drop_patterns(&self) -> &[KeyPattern] {
+        &self.drop
+    }
+
+    pub fn redact_patterns(&self) -> &[KeyPattern] {
+        &self.redact
+    }
+
+    pub fn mask(&self) -> &str {
+        &self.mask
+    }
+
+    /// True when the rules leave every record as it is
+    pub fn is_empty(&self) -> bool {
+        self.drop.is_empty() && self.redact.is_empty()
+    }
+
+    /// Drops and masks the matching keys of a record
+    pub fn apply(&self, record: &mut FlattenedJson) {
+        if self.is_empty() {
+            return;
+        }
+        record.retain(|key, value| {
+            let segments = split_key(key, &self.separator, self.escaped);
+            if self.drop.iter().any(|pattern| pattern.matches(&segments)) {
+                return false;
+            }
+            if self.redact.iter().any(|pattern| pattern.matches(&segments)) {
+                value.clone_from(&self.mask);
+            }
+            true
+        });
+    }
+}
+
+/// [`KeyRules`] that can be swapped while records are flowing
+///
+/// Clones share the rules: a service keeps one clone to [`replace`](SharedRules::replace)
+/// or [`reload`](SharedRules::reload) them, and every sink or engine holding
+/// another uses the new rules from its next record on, without a restart.
+///
+/// Made with [`from_file`](SharedRules::from_file), the handle also rereads
+/// the file when its modification time changes, checking at most once per
+/// [`RELOAD_INTERVAL`]. A changed file that can't be read or parsed leaves
+/// the previous rules in place; the error is kept in [`last_error`](SharedRules::last_error).
+#[derive(Clone, Debug)]
+pub struct SharedRules(Arc<Shared>);
+
+#[derive(Debug)]
+struct Shared {
+    rules: RwLock<Arc<KeyRules>>,
+    file: Option<Mutex<WatchedFile>>,
+}
+
+#[derive(Debug)]
+struct WatchedFile {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    checked: Instant,
+    error: Option<String>,
+}
+
+impl SharedRules {
+    pub fn new(rules: KeyRules) -> Self {
+        SharedRules(Arc::new(Shared {
+            rules: RwLock::new(Arc::new(rules)),
+            file: None,
+        }))
+    }
+
+    /// Loads the rules in `path`, following later changes to the file
+    pub fn from_file(path: impl Into<PathBuf>, options: &FlattenOptions) -> io::Result<Self> {
+        let path = path.into();
+        let modified = modified(&path);
+        let rules = KeyRules::load(&path, options)?;
+        let file = WatchedFile {
+            path,
+            modified,
+            checked: Instant::now(),
+            error: None,
+        };
+        Ok(SharedRules(Arc::new(Shared {
+            rules: RwLock::new(Arc::new(rules)),
+            file: Some(Mutex::new(file)),
+        })))
+    }
+
+    /// The rules in force, after rereading the rules file if it is due a check and changed
+    pub fn current(&self) -> Arc<KeyRules> {
+        // A thread finding the file locked leaves the check to the one holding it
+        if let Some(mut file) = self.0.file.as_ref().and_then(|file| file.try_lock().ok()) {
+            if file.checked.elapsed() >= RELOAD_INTERVAL {
+                file.checked = Instant::now();
+                if modified(&file.path) != file.modified {
+                    let _ = self.reload_locked(&mut file);
+                }
+            }
+        }
+        Arc::clone(&self.0.rules.read().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    /// Puts `rules` in force for every clone of this handle
+    pub fn replace(&self, rules: KeyRules) {
+        *self.0.rules.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(rules);
+    }
+
+    /// Rereads the rules file now, whether or not it changed
+    ///
+    /// Returns false for a handle without a file. On error the previous
+    /// rules stay in force.
+    pub fn reload(&self) -> io::Result<bool> {
+        match &self.0.file {
+            Some(file) => {
+                let mut file = file.lock().unwrap_or_else(|e| e.into_inner());
+                self.reload_locked(&mut file).map(|_| true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn reload_locked(&self, file: &mut WatchedFile) -> io::Result<()> {
+        file.modified = modified(&file.path);
+        let current = self.0.rules.read().unwrap_or_else(|e| e.into_inner()).clone();
+        match KeyRules::load_for(&file.path, &current.separator, current.escaped) {
+            Ok(rules) => {
+                file.error = None;
+                self.replace(rules);
+                Ok(())
+            }
+            Err(e) => {
+                file.error = Some(e.to_string());
+                Err(e)
+            }
+        }
+    }
+
+    /// Why the last reload of the rules file failed, if it did
+    pub fn last_error(&self) -> Option<String> {
+        let file = self.0.file.as_ref()?;
+        file.lock().unwrap_or_else(|e| e.into_inner()).error.clone()
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+/// Sink that applies the current [`SharedRules`] to every record before
+/// passing it on
+///
+/// Wrap the sink of a watched directory or a long stream in one to pick up
+/// rule changes as they are made.
+pub struct RulesSink<S: RecordSink> {
+    inner: S,
+    rules: SharedRules,
+}
+
+impl<S: RecordSink> RulesSink<S> {
+    pub fn new(inner: S, rules: SharedRules) -> Self {
+        RulesSink { inner, rules }
+    }
+
+    pub fn rules(&self) -> &SharedRules {
+        &self.rules
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: RecordSink> RecordSink for RulesSink<S> {
+    fn write_record(&mut self, mut record: FlattenedJson) -> io::Result<()> {
+        self.rules.current().apply(&mut record);
+        self.inner.write_record(record)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    fn skip_document(&mut self, skipped: SkippedDocument) -> io::Result<()> {
+        self.inner.skip_document(skipped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flatten_json;
+    use serde_json::json;
+
+    #[test]
+    fn test_rules_drop_and_mask_and_reload_without_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rules.json");
+        std::fs::write(&path, r#"{"drop": ["debug.**"], "redact": ["**.ssn"]}"#).unwrap();
+        let options = FlattenOptions::default();
+        let rules = SharedRules::from_file(&path, &options).unwrap();
+        let document = json!({"user": {"ssn": "123", "email": "a@b.c"}, "debug": {"trace": "t"}});
+
+        let mut sink = RulesSink::new(Vec::new(), rules.clone());
+        sink.write_record(flatten_json(&document, &options)).unwrap();
+        std::fs::write(&path, r#"{"redact": ["**.ssn", "user.email"], "mask": "***"}"#).unwrap();
+        assert!(rules.reload().unwrap());
+        sink.write_record(flatten_json(&document, &options)).unwrap();
+
+        // A broken file keeps the rules in force
+        std::fs::write(&path, r#"{"redact": "#).unwrap();
+        assert!(rules.reload().is_err());
+        assert!(rules.last_error().unwrap().contains("rules.json"));
+        sink.write_record(flatten_json(&document, &options)).unwrap();
+
+        let records = sink.into_inner();
+        assert_eq!(records[0]["user.ssn"], DEFAULT_MASK);
+        assert_eq!(records[0]["user.email"], "a@b.c");
+        assert!(!records[0].contains_key("debug.trace"));
+        for record in &records[1..] {
+            assert_eq!((record["user.ssn"].as_str(), record["user.email"].as_str()), ("***", "***"));
+            assert_eq!(record["debug.trace"], "t");
+        }
+
+        rules.replace(KeyRules::parse("{}", &options).unwrap());
+        assert!(rules.current().is_empty());
+    }
+}
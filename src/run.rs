@@ -0,0 +1,257 @@
+// src/run.rs
+//! Flattening a whole input with everything learned along the way
+use crate::input::open_input_checked;
+use crate::progress::Checkpoint;
+use crate::schema::{FlatSchema, SchemaBuilder};
+use crate::stream::flatten_pipelined_to_sink;
+use crate::{BudgetedSink, FlattenError, FlattenOptions, FlattenedJson, RecordSink, SkipReason, SkippedDocument};
+use serde::Serialize;
+use std::io::{self, Read};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Counts of a [`FlattenRun`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct RunStats {
+    /// Documents read, not counting `skip_documents`
+    pub documents: u64,
+    /// Records in [`FlattenRun::records`]
+    pub records: usize,
+    /// Keys across those records
+    pub keys: u64,
+    /// Documents that were scalars or arrays, left out under
+    /// [`NonObjectRecords::Skip`](crate::NonObjectRecords::Skip) and listed in [`FlattenRun::errors`]
+    pub non_objects: u64,
+    /// Documents that were not valid JSON, left out under
+    /// [`InvalidDocuments::Skip`](crate::InvalidDocuments::Skip) and listed in [`FlattenRun::errors`]
+    pub invalid_documents: u64,
+    /// Records left out by `filters`
+    pub filtered: u64,
+    /// Time the run took
+    pub elapsed: Duration,
+}
+
+/// A document that produced no record
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct RecordError {
+    /// Position of the document in the input, counting `skip_documents`
+    pub document: u64,
+    pub message: String,
+    /// Byte offset of a parse error in the input, when known
+    pub offset: Option<u64>,
+    /// Line of a parse error in the input, when known
+    pub line: Option<u64>,
+    /// The text around a parse error
+    pub excerpt: Option<String>,
+}
+
+/// Everything a run over an input produced: the records, their counts and
+/// schema, the documents left out, and where to carry on
+///
+/// New fields are added in minor releases, so callers can rely on this type
+/// where a bare `Vec` of records would have to change shape.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct FlattenRun {
+    pub records: Vec<FlattenedJson>,
+    pub stats: RunStats,
+    /// Documents a `Skip` policy left out, in input order
+    pub errors: Vec<RecordError>,
+    /// Schema inferred from `records`
+    pub schema: FlatSchema,
+    /// Where to carry on when `time_budget` ran out first
+    pub checkpoint: Option<Checkpoint>,
+}
+
+impl From<SkippedDocument> for RecordError {
+    fn from(skipped: SkippedDocument) -> Self {
+        let message = skipped.reason.to_string();
+        match skipped.reason {
+            SkipReason::Parse(error) => RecordError {
+                document: skipped.document,
+                message,
+                offset: error.position.map(|position| position.offset),
+                line: error.position.map(|position| position.line),
+                excerpt: Some(error.excerpt),
+            },
+            SkipReason::Flatten(_) => RecordError {
+                document: skipped.document,
+                message,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// Sink collecting a [`FlattenRun`]
+struct RunSink {
+    records: BudgetedSink,
+    stats: RunStats,
+    errors: Vec<RecordError>,
+    schema: SchemaBuilder,
+}
+
+impl RecordSink for RunSink {
+    fn write_record(&mut self, record: FlattenedJson) -> io::Result<()> {
+        self.stats.documents += 1;
+        self.stats.records += 1;
+        self.stats.keys += record.len() as u64;
+        self.schema.add(&record);
+        self.records.write_record(record)
+    }
+
+    fn skip_document(&mut self, skipped: SkippedDocument) -> io::Result<()> {
+        self.stats.documents += 1;
+        match &skipped.reason {
+            SkipReason::Flatten(FlattenError::NotAnObject(_)) => self.stats.non_objects += 1,
+            SkipReason::Parse(_) => self.stats.invalid_documents += 1,
+            SkipReason::Flatten(_) => {}
+        }
+        self.errors.push(skipped.into());
+        Ok(())
+    }
+}
+
+/// Flattens a JSON file like [`flatten_json_file`](crate::flatten_json_file),
+/// returning a [`FlattenRun`] instead of just the records
+///
+/// `memory_budget` and `time_budget` apply as in the file functions. The
+/// documents a `Skip` policy (of `oversized_records`, `non_object_records` or
+/// `invalid_documents`) leaves out are listed in [`FlattenRun::errors`], parse
+/// errors with their position and excerpt.
+pub fn flatten_json_file_run(
+    filepath: impl AsRef<Path>,
+    options: &FlattenOptions,
+) -> Result<FlattenRun, Box<dyn std::error::Error>> {
+    flatten_reader_run(open_input_checked(filepath, options)?, options)
+}
+
+/// Flattens every document of a reader into a [`FlattenRun`], like
+/// [`flatten_json_file_run`]
+pub fn flatten_reader_run<R: Read + Send>(
+    reader: R,
+    options: &FlattenOptions,
+) -> Result<FlattenRun, Box<dyn std::error::Error>> {
+    let started = Instant::now();
+    let mut sink = RunSink {
+        records: BudgetedSink::for_options(options),
+        stats: RunStats::default(),
+        errors: Vec::new(),
+        schema: SchemaBuilder::default(),
+    };
+    let end = flatten_pipelined_to_sink(reader, &mut sink, options)?;
+    Ok(FlattenRun {
+        records: sink.records.into_records(),
+        stats: RunStats {
+            documents: sink.stats.documents + end.filtered,
+            filtered: end.filtered,
+            elapsed: started.elapsed(),
+            ..sink.stats
+        },
+        errors: sink.errors,
+        schema: sink.schema.schema(),
+        checkpoint: end.checkpoint,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::ColumnType;
+    use crate::{InvalidDocuments, NonObjectRecords, OversizedRecords};
+
+    #[test]
+    fn test_run_reports_records_schema_and_skipped_documents() {
+        let input = "{\"id\": 1, \"tags\": [\"a\"]}\n{\"id\": 2, \"tags\": [1, 2, 3, 4]}\n{\"id\": 3.5}\n\"ping\"\n";
+        let options = FlattenOptions {
+            max_record_keys: 3,
+            oversized_records: OversizedRecords::Skip,
+            non_object_records: NonObjectRecords::Skip,
+            ..Default::default()
+        };
+        let run = flatten_reader_run(input.as_bytes(), &options).unwrap();
+
+        let ids: Vec<&str> = run.records.iter().map(|record| record["id"].as_str()).collect();
+        assert_eq!(ids, ["1", "3.5"]);
+        let oversized = RecordError {
+            document: 1,
+            message: "record has more than 3 keys".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(run.errors[0], oversized);
+        assert_eq!(run.errors[1].document, 3);
+        assert_eq!((run.stats.documents, run.stats.records, run.stats.keys), (4, 2, 3));
+        assert_eq!(run.stats.non_objects, 1);
+        assert_eq!(run.schema.get("id"), Some(ColumnType::Float));
+        assert_eq!(run.schema.get("tags.0"), Some(ColumnType::Text));
+        assert_eq!(run.checkpoint, None);
+    }
+
+    #[test]
+    fn test_invalid_documents_are_located_and_skipped() {
+        let input = "{\"id\": 1}\n{\"id\": 2, \"name\": \"a\" \"b\"}\n{\"id\": 3}\n{\"id\": 4, \"cut\": ";
+        let options = FlattenOptions {
+            invalid_documents: InvalidDocuments::Skip,
+            ..Default::default()
+        };
+        let run = flatten_reader_run(input.as_bytes(), &options).unwrap();
+
+        let ids: Vec<&str> = run.records.iter().map(|record| record["id"].as_str()).collect();
+        assert_eq!(ids, ["1", "3"]);
+        assert_eq!(run.stats.invalid_documents, 2);
+        let error = &run.errors[0];
+        assert_eq!((error.document, error.offset, error.line), (1, Some(32), Some(2)));
+        assert_eq!(error.excerpt.as_deref(), Some("{\"id\": 2, \"name\": \"a\" \"b\"}"));
+        assert!(error.message.starts_with("invalid JSON at line 2, byte 32: expected `,` or `}`"));
+        assert_eq!((run.errors[1].document, run.errors[1].line), (3, Some(4)));
+
+        let strict = flatten_reader_run(input.as_bytes(), &FlattenOptions::default());
+        assert!(strict.is_err());
+    }
+
+    #[test]
+    fn test_skipped_documents_never_reach_the_records() {
+        let input = "{\"id\": 1, \"_skipped\": \"no\"}\n42\n{\"id\": 3}\n";
+        let options = FlattenOptions {
+            non_object_records: NonObjectRecords::Skip,
+            filters: vec!["id > 2".parse().unwrap()],
+            ..Default::default()
+        };
+        let mut records = Vec::new();
+        crate::flatten_reader_to_sink(input.as_bytes(), &mut records, &options).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0]["id"], "3");
+
+        let run = flatten_reader_run("{\"_skipped\": \"no\"}\n42\n".as_bytes(), &options).unwrap();
+        assert_eq!((run.stats.records, run.stats.filtered, run.errors.len()), (0, 1, 1));
+        let run = flatten_reader_run(input.as_bytes(), &FlattenOptions { filters: Vec::new(), ..options }).unwrap();
+        assert_eq!(run.records[0]["_skipped"], "no");
+        assert_eq!((run.errors[0].document, run.stats.non_objects), (1, 1));
+    }
+
+    #[test]
+    fn test_filtered_records_are_counted_and_checkpointed() {
+        let input: String = (0..10).map(|i| format!("{{\"n\": {}, \"kind\": \"{}\"}}\n", i, i % 3)).collect();
+        let options = FlattenOptions {
+            filters: vec!["kind == \"0\" || n > 7".parse().unwrap()],
+            chunk_size: 4,
+            time_budget: Some(Duration::ZERO),
+            ..Default::default()
+        };
+        let run = flatten_reader_run(input.as_bytes(), &options).unwrap();
+        let ns: Vec<&str> = run.records.iter().map(|record| record["n"].as_str()).collect();
+        assert_eq!(ns, ["0", "3"]);
+        assert_eq!((run.stats.documents, run.stats.records, run.stats.filtered), (4, 2, 2));
+        assert_eq!(run.checkpoint, Some(Checkpoint { documents: 4 }));
+
+        let rest = FlattenOptions {
+            skip_documents: 4,
+            time_budget: None,
+            ..options
+        };
+        let run = flatten_reader_run(input.as_bytes(), &rest).unwrap();
+        let ns: Vec<&str> = run.records.iter().map(|record| record["n"].as_str()).collect();
+        assert_eq!(ns, ["6", "8", "9"]);
+        assert_eq!(run.stats.filtered, 3);
+    }
+}
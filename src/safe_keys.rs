@@ -0,0 +1,211 @@
+// src/safe_keys.rs
+//! Rewriting keys as column names any database accepts, keeping the mapping
+use crate::record_id::fnv1a;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::format;
+use alloc::string::{String, ToString};
+
+/// Byte limit of [`SafeKeys::default`]: Postgres' identifier length
+pub const DEFAULT_MAX_KEY_BYTES: usize = 63;
+
+/// Shortest byte limit [`SafeKeys`] takes, leaving room for a hash suffix
+const MIN_KEY_BYTES: usize = 16;
+
+/// Rewrites flattened keys as safe identifiers of at most `max_bytes` bytes,
+/// remembering each key's column
+///
+/// Every character that is not an ASCII letter, digit or `_` becomes `_`, and
+/// names starting with a digit (or empty ones) get a leading `_`. Names over
+/// the limit are cut short and end in `_` plus 8 hex digits of a hash of the
+/// original key, as do names that would collide with an earlier key's column,
+/// so distinct keys always get distinct columns and a key gets the same
+/// column in every run that doesn't collide.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SafeKeys {
+    max_bytes: usize,
+    /// Column of every key seen, by key
+    columns: BTreeMap<String, String>,
+    taken: BTreeSet<String>,
+}
+
+impl Default for SafeKeys {
+    fn default() -> Self {
+        SafeKeys::new(DEFAULT_MAX_KEY_BYTES)
+    }
+}
+
+impl SafeKeys {
+    /// Limits columns to `max_bytes` bytes (at least 16); 0 leaves them unlimited
+    pub fn new(max_bytes: usize) -> Self {
+        SafeKeys {
+            max_bytes: match max_bytes {
+                0 => usize::MAX,
+                limit => limit.max(MIN_KEY_BYTES),
+            },
+            columns: BTreeMap::new(),
+            taken: BTreeSet::new(),
+        }
+    }
+
+    /// The column a key is written as, assigning one the first time it is seen
+    pub fn column(&mut self, key: &str) -> &str {
+        if !self.columns.contains_key(key) {
+            let column = self.new_column(key);
+            self.taken.insert(column.clone());
+            self.columns.insert(key.to_string(), column);
+        }
+        &self.columns[key]
+    }
+
+    fn new_column(&self, key: &str) -> String {
+        let mut base: String =
+            key.chars().map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' }).collect();
+        if !base.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+            base.insert(0, '_');
+        }
+        if base.len() <= self.max_bytes && !self.taken.contains(&base) {
+            return base;
+        }
+        let hashed = fit(&base, &format!("_{:08x}", fnv1a(key.as_bytes()) as u32), self.max_bytes);
+        if !self.taken.contains(&hashed) {
+            return hashed;
+        }
+        (2..)
+            .map(|n| fit(&hashed, &format!("_{}", n), self.max_bytes))
+            .find(|column| !self.taken.contains(column))
+            .expect("there are more suffixes than columns")
+    }
+
+    /// Every key seen so far with its column, sorted by key
+    pub fn mapping(&self) -> &BTreeMap<String, String> {
+        &self.columns
+    }
+
+    /// Keys whose column differs from the key
+    pub fn renamed(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.columns.iter().filter(|(key, column)| key != column).map(|(key, column)| (key.as_str(), column.as_str()))
+    }
+
+    /// The mapping as a JSON object of keys to columns, for a sidecar file
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(&self.columns).unwrap_or_default()
+    }
+}
+
+/// `name` cut short to leave room for `suffix` within `max_bytes`, then `suffix`;
+/// names are ASCII, so any byte is a character boundary
+fn fit(name: &str, suffix: &str, max_bytes: usize) -> String {
+    let keep = name.len().min(max_bytes.saturating_sub(suffix.len()));
+    format!("{}{}", &name[..keep], suffix)
+}
+
+#[cfg(feature = "std")]
+pub use sink::SafeKeySink;
+
+#[cfg(feature = "std")]
+mod sink {
+    use super::SafeKeys;
+    use crate::{FlattenedJson, RecordSink, SkippedDocument};
+    use std::io;
+    use std::path::PathBuf;
+
+    /// Sink that renames every key to its [`SafeKeys`] column before passing
+    /// the record on
+    ///
+    /// With [`with_mapping_file`](SafeKeySink::with_mapping_file), each flush
+    /// also (re)writes the key to column mapping as a JSON sidecar, so
+    /// warehouse columns can be traced back to their JSON paths.
+    pub struct SafeKeySink<S: RecordSink> {
+        inner: S,
+        keys: SafeKeys,
+        mapping_file: Option<PathBuf>,
+    }
+
+    impl<S: RecordSink> SafeKeySink<S> {
+        pub fn new(inner: S, keys: SafeKeys) -> Self {
+            SafeKeySink {
+                inner,
+                keys,
+                mapping_file: None,
+            }
+        }
+
+        pub fn with_mapping_file(mut self, path: impl Into<PathBuf>) -> Self {
+            self.mapping_file = Some(path.into());
+            self
+        }
+
+        pub fn keys(&self) -> &SafeKeys {
+            &self.keys
+        }
+
+        pub fn into_parts(self) -> (S, SafeKeys) {
+            (self.inner, self.keys)
+        }
+    }
+
+    impl<S: RecordSink> RecordSink for SafeKeySink<S> {
+        fn write_record(&mut self, record: FlattenedJson) -> io::Result<()> {
+            let mut renamed = FlattenedJson::with_capacity_and_hasher(record.len(), Default::default());
+            for (key, value) in record {
+                renamed.insert(self.keys.column(&key).to_string(), value);
+            }
+            self.inner.write_record(renamed)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.inner.flush()?;
+            match &self.mapping_file {
+                Some(path) => std::fs::write(path, self.keys.to_json() + "\n"),
+                None => Ok(()),
+            }
+        }
+
+        fn skip_document(&mut self, skipped: SkippedDocument) -> io::Result<()> {
+            self.inner.skip_document(skipped)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn test_keys_become_distinct_bounded_identifiers() {
+        let mut keys = SafeKeys::new(20);
+        assert_eq!(keys.column("user.name"), "user_name");
+        assert_eq!(keys.column("user_name"), "user_name_88d171f4");
+        assert_eq!(keys.column("9lives"), "_9lives");
+        assert_eq!(keys.column("id"), "id");
+        let long = keys.column("payload.attributes.description").to_string();
+        assert_eq!(long.len(), 20);
+        assert!(long.starts_with("payload_att_"));
+
+        let columns: Vec<&str> = keys.mapping().values().map(String::as_str).collect();
+        let distinct: BTreeSet<&str> = columns.iter().copied().collect();
+        assert_eq!(distinct.len(), columns.len());
+        assert_eq!(keys.renamed().count(), 4);
+        assert_eq!(keys.column("user.name"), "user_name");
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_sink_renames_keys_and_writes_the_mapping_on_flush() {
+        use crate::{FlattenedJson, RecordSink};
+
+        let dir = tempfile::tempdir().unwrap();
+        let mapping = dir.path().join("out.csv.columns.json");
+        let mut sink = SafeKeySink::new(Vec::new(), SafeKeys::default()).with_mapping_file(&mapping);
+        let record: FlattenedJson = [("a b".to_string(), "1".to_string())].into_iter().collect();
+        sink.write_record(record).unwrap();
+        sink.flush().unwrap();
+
+        let (records, keys) = sink.into_parts();
+        assert_eq!(records[0]["a_b"], "1");
+        let written: BTreeMap<String, String> =
+            serde_json::from_str(&std::fs::read_to_string(&mapping).unwrap()).unwrap();
+        assert_eq!(&written, keys.mapping());
+    }
+}
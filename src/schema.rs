@@ -0,0 +1,691 @@
+// src/schema.rs
+use crate::canonical::exact_in_f64;
+use crate::FlattenedJson;
+use alloc::collections::{btree_map, BTreeMap};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "std")]
+use std::path::Path;
+
+/// Type of a column of flattened values, for typed outputs like DataFrames
+/// and Arrow files
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColumnType {
+    Bool,
+    Int,
+    Float,
+    /// Numbers an `Int` or `Float` column would round, like 128-bit amounts
+    /// kept exact by the `arbitrary_precision` feature
+    Decimal,
+    Text,
+}
+
+impl ColumnType {
+    /// Infers the narrowest type holding every value of a column
+    ///
+    /// Nulls and missing values (`None`) fit any type; integers mixed with
+    /// floats make a float column, any number mixed with a decimal a decimal
+    /// column, and a column of only nulls is `Text`.
+    pub fn infer<'a>(values: impl IntoIterator<Item = Option<&'a str>>) -> Self {
+        values
+            .into_iter()
+            .filter(|value| !is_null(*value))
+            .flatten()
+            .map(Self::of)
+            .reduce(Self::widen)
+            .unwrap_or(ColumnType::Text)
+    }
+
+    /// The narrowest type holding the values of both types
+    pub fn widen(self, other: Self) -> Self {
+        match (self, other) {
+            (a, b) if a == b => a,
+            (ColumnType::Decimal, b) | (b, ColumnType::Decimal) if b.is_numeric() => ColumnType::Decimal,
+            (ColumnType::Int | ColumnType::Float, ColumnType::Int | ColumnType::Float) => ColumnType::Float,
+            _ => ColumnType::Text,
+        }
+    }
+
+    /// Whether a non-null value can be stored in a column of this type
+    pub fn fits(self, value: &str) -> bool {
+        match (self, Self::of(value)) {
+            (ColumnType::Text, _) | (ColumnType::Float, ColumnType::Int) => true,
+            (ColumnType::Decimal, value_type) => value_type.is_numeric(),
+            (column_type, value_type) => column_type == value_type,
+        }
+    }
+
+    /// Type of a single non-null value
+    pub fn of(value: &str) -> Self {
+        let numeric = |value: &str| value.bytes().all(|b| b.is_ascii_digit() || b"+-.eE".contains(&b));
+        match value {
+            "true" | "false" => ColumnType::Bool,
+            _ if value.parse::<i64>().is_ok() => ColumnType::Int,
+            _ if numeric(value) && exact_in_f64(value) => ColumnType::Float,
+            _ if numeric(value) && value.parse::<f64>().is_ok() => ColumnType::Decimal,
+            _ => ColumnType::Text,
+        }
+    }
+
+    /// Whether the type holds numbers
+    pub fn is_numeric(self) -> bool {
+        matches!(self, ColumnType::Int | ColumnType::Float | ColumnType::Decimal)
+    }
+}
+
+impl fmt::Display for ColumnType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ColumnType::Bool => "bool",
+            ColumnType::Int => "int",
+            ColumnType::Float => "float",
+            ColumnType::Decimal => "decimal",
+            ColumnType::Text => "text",
+        })
+    }
+}
+
+impl core::str::FromStr for ColumnType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bool" => Ok(ColumnType::Bool),
+            "int" => Ok(ColumnType::Int),
+            "float" => Ok(ColumnType::Float),
+            "decimal" => Ok(ColumnType::Decimal),
+            "text" => Ok(ColumnType::Text),
+            other => Err(format!("unknown column type {:?} (expected bool, int, float, decimal or text)", other)),
+        }
+    }
+}
+
+/// Whether a flattened value is a JSON null or a missing key
+pub fn is_null(value: Option<&str>) -> bool {
+    value.is_none_or(|value| value == "null")
+}
+
+/// Typed columns of flattened records, sorted by name
+///
+/// Serializes as an object of column names to `bool`, `int`, `float`,
+/// `decimal` or `text`, so a schema saved by one run can be loaded by the next.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct FlatSchema {
+    columns: BTreeMap<String, ColumnType>,
+}
+
+impl FlatSchema {
+    /// Infers the schema of a set of records, like [`infer_schema`]
+    pub fn infer(records: &[FlattenedJson]) -> Self {
+        let mut builder = SchemaBuilder::default();
+        for record in records {
+            builder.add(record);
+        }
+        builder.schema()
+    }
+
+    /// Type of a column, if the schema has it
+    pub fn get(&self, column: &str) -> Option<ColumnType> {
+        self.columns.get(column).copied()
+    }
+
+    /// Adds a column or changes its type
+    pub fn insert(&mut self, column: String, column_type: ColumnType) -> Option<ColumnType> {
+        self.columns.insert(column, column_type)
+    }
+
+    /// Columns and their types, sorted by name
+    pub fn iter(&self) -> btree_map::Iter<'_, String, ColumnType> {
+        self.columns.iter()
+    }
+
+    /// Number of columns
+    pub fn len(&self) -> usize {
+        self.columns.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.columns.is_empty()
+    }
+
+    /// Pretty-printed JSON, one column per line
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("a schema always serializes")
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    #[cfg(feature = "yaml")]
+    pub fn to_yaml(&self) -> Result<String, serde_yaml::Error> {
+        serde_yaml::to_string(self)
+    }
+
+    #[cfg(feature = "yaml")]
+    pub fn from_yaml(yaml: &str) -> Result<Self, serde_yaml::Error> {
+        serde_yaml::from_str(yaml)
+    }
+
+    /// Writes the schema to a file, as YAML when the path ends in `.yaml` or
+    /// `.yml` and as JSON otherwise
+    #[cfg(feature = "std")]
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn std::error::Error>> {
+        let path = path.as_ref();
+        let text = match is_yaml_path(path) {
+            true => yaml_schema(self)?,
+            false => self.to_json() + "\n",
+        };
+        std::fs::write(path, text)?;
+        Ok(())
+    }
+
+    /// Reads a schema written by [`save`](FlatSchema::save)
+    #[cfg(feature = "std")]
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)?;
+        match is_yaml_path(path) {
+            true => parse_yaml_schema(&text),
+            false => Ok(Self::from_json(&text)?),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+fn is_yaml_path(path: &Path) -> bool {
+    matches!(path.extension().and_then(|ext| ext.to_str()), Some("yaml" | "yml"))
+}
+
+#[cfg(all(feature = "std", feature = "yaml"))]
+fn yaml_schema(schema: &FlatSchema) -> Result<String, Box<dyn std::error::Error>> {
+    Ok(schema.to_yaml()?)
+}
+
+#[cfg(all(feature = "std", feature = "yaml"))]
+fn parse_yaml_schema(yaml: &str) -> Result<FlatSchema, Box<dyn std::error::Error>> {
+    Ok(FlatSchema::from_yaml(yaml)?)
+}
+
+#[cfg(all(feature = "std", not(feature = "yaml")))]
+fn yaml_schema(_schema: &FlatSchema) -> Result<String, Box<dyn std::error::Error>> {
+    Err("YAML schemas need the yaml feature".into())
+}
+
+#[cfg(all(feature = "std", not(feature = "yaml")))]
+fn parse_yaml_schema(_yaml: &str) -> Result<FlatSchema, Box<dyn std::error::Error>> {
+    Err("YAML schemas need the yaml feature".into())
+}
+
+impl<'a> IntoIterator for &'a FlatSchema {
+    type Item = (&'a String, &'a ColumnType);
+    type IntoIter = btree_map::Iter<'a, String, ColumnType>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.columns.iter()
+    }
+}
+
+impl FromIterator<(String, ColumnType)> for FlatSchema {
+    fn from_iter<I: IntoIterator<Item = (String, ColumnType)>>(iter: I) -> Self {
+        FlatSchema {
+            columns: iter.into_iter().collect(),
+        }
+    }
+}
+
+/// Infers a schema one record at a time, for sinks that can't hold every record
+#[derive(Clone, Debug, Default)]
+pub struct SchemaBuilder {
+    /// `None` while a column has only held nulls
+    columns: BTreeMap<String, Option<ColumnType>>,
+}
+
+impl SchemaBuilder {
+    /// Widens the schema to hold a record
+    pub fn add(&mut self, record: &FlattenedJson) {
+        for (column, value) in record {
+            let value_type = Some(value.as_str()).filter(|_| !is_null(Some(value))).map(ColumnType::of);
+            match self.columns.get_mut(column) {
+                Some(column_type) => {
+                    if let Some(value_type) = value_type {
+                        *column_type = Some(column_type.map_or(value_type, |t| t.widen(value_type)));
+                    }
+                }
+                None => {
+                    self.columns.insert(column.clone(), value_type);
+                }
+            }
+        }
+    }
+
+    /// Type inferred so far for a column; `Some(None)` while it has only held nulls
+    pub fn get(&self, column: &str) -> Option<Option<ColumnType>> {
+        self.columns.get(column).copied()
+    }
+
+    /// The schema inferred so far; columns of only nulls are `Text`
+    pub fn schema(&self) -> FlatSchema {
+        self.columns
+            .iter()
+            .map(|(column, column_type)| (column.clone(), column_type.unwrap_or(ColumnType::Text)))
+            .collect()
+    }
+}
+
+/// Every column of a set of records, sorted by name, with its inferred type
+pub fn infer_schema(records: &[FlattenedJson]) -> FlatSchema {
+    FlatSchema::infer(records)
+}
+
+/// A column whose type differs between two schemas
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TypeChange {
+    pub column: String,
+    pub old: ColumnType,
+    pub new: ColumnType,
+}
+
+impl TypeChange {
+    /// Whether the change is int to float, which keeps every old value a number
+    ///
+    /// Changes to text are not widening: they hold the old values but break
+    /// typed consumers.
+    pub fn is_widening(&self) -> bool {
+        self.old == ColumnType::Int && self.new == ColumnType::Float
+    }
+}
+
+/// Differences between two schemas, each list sorted by column name
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SchemaDiff {
+    pub added: Vec<(String, ColumnType)>,
+    pub removed: Vec<(String, ColumnType)>,
+    pub changed: Vec<TypeChange>,
+}
+
+impl SchemaDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    /// The part of the diff a policy doesn't allow
+    pub fn violations(&self, policy: &DriftPolicy) -> SchemaDiff {
+        SchemaDiff {
+            added: if policy.allow_added { Vec::new() } else { self.added.clone() },
+            removed: if policy.allow_removed { Vec::new() } else { self.removed.clone() },
+            changed: self
+                .changed
+                .iter()
+                .filter(|change| !policy.allows(change))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// Fails with the violations of `policy`, if there are any
+    pub fn check(&self, policy: &DriftPolicy) -> Result<(), SchemaDrift> {
+        let violations = self.violations(policy);
+        match violations.is_empty() {
+            true => Ok(()),
+            false => Err(SchemaDrift(violations)),
+        }
+    }
+}
+
+/// Reports the columns added to, removed from and retyped in `new` relative to `old`
+pub fn compare_schemas(old: &FlatSchema, new: &FlatSchema) -> SchemaDiff {
+    let mut diff = SchemaDiff::default();
+    for (column, &new_type) in new {
+        match old.get(column) {
+            None => diff.added.push((column.clone(), new_type)),
+            Some(old_type) if old_type != new_type => diff.changed.push(TypeChange {
+                column: column.clone(),
+                old: old_type,
+                new: new_type,
+            }),
+            Some(_) => {}
+        }
+    }
+    for (column, &old_type) in old {
+        if new.get(column).is_none() {
+            diff.removed.push((column.clone(), old_type));
+        }
+    }
+    diff
+}
+
+/// Which schema changes a run tolerates; the default tolerates none
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DriftPolicy {
+    pub allow_added: bool,
+    pub allow_removed: bool,
+    /// Allow int columns to become float columns
+    pub allow_widening: bool,
+    /// Allow any type change
+    pub allow_type_changes: bool,
+}
+
+impl DriftPolicy {
+    fn allows(&self, change: &TypeChange) -> bool {
+        self.allow_type_changes || (self.allow_widening && change.is_widening())
+    }
+}
+
+/// Schema changes that a [`DriftPolicy`] doesn't allow
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SchemaDrift(pub SchemaDiff);
+
+impl fmt::Display for SchemaDrift {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let diff = &self.0;
+        let mut parts = Vec::new();
+        if !diff.added.is_empty() {
+            let columns: Vec<&str> = diff.added.iter().map(|(column, _)| column.as_str()).collect();
+            parts.push(format!("added columns {}", columns.join(", ")));
+        }
+        if !diff.removed.is_empty() {
+            let columns: Vec<&str> = diff.removed.iter().map(|(column, _)| column.as_str()).collect();
+            parts.push(format!("removed columns {}", columns.join(", ")));
+        }
+        for change in &diff.changed {
+            parts.push(format!("{} changed from {} to {}", change.column, change.old, change.new));
+        }
+        write!(f, "schema drift: {}", parts.join("; "))
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for SchemaDrift {}
+
+/// What to do with values that don't fit their column in an expected schema
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SchemaMode {
+    /// Fail on the first value that doesn't fit
+    #[default]
+    Enforce,
+    /// Replace values that don't fit with nulls
+    Coerce,
+}
+
+impl core::str::FromStr for SchemaMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "enforce" => Ok(SchemaMode::Enforce),
+            "coerce" => Ok(SchemaMode::Coerce),
+            other => Err(format!("unknown schema mode {:?} (expected enforce or coerce)", other)),
+        }
+    }
+}
+
+impl fmt::Display for SchemaMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SchemaMode::Enforce => "enforce",
+            SchemaMode::Coerce => "coerce",
+        })
+    }
+}
+
+/// Where keys that are not columns of an expected schema go
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum UnexpectedColumns {
+    /// Leave them out of the record
+    #[default]
+    Drop,
+    /// Fail on the first one
+    Fail,
+    /// Pass them through unchanged
+    Keep,
+    /// Gather them into one column holding a JSON object of their values,
+    /// or null when a record has none
+    Collect(String),
+}
+
+impl core::str::FromStr for UnexpectedColumns {
+    type Err = String;
+
+    /// Parses `drop`, `fail`, `keep` or `collect:<column>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop" => Ok(UnexpectedColumns::Drop),
+            "fail" => Ok(UnexpectedColumns::Fail),
+            "keep" => Ok(UnexpectedColumns::Keep),
+            _ => match s.strip_prefix("collect:") {
+                Some(column) if !column.is_empty() => Ok(UnexpectedColumns::Collect(column.to_string())),
+                _ => Err(format!(
+                    "unknown unexpected column policy {:?} (expected drop, fail, keep or collect:<column>)",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+impl fmt::Display for UnexpectedColumns {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnexpectedColumns::Drop => f.write_str("drop"),
+            UnexpectedColumns::Fail => f.write_str("fail"),
+            UnexpectedColumns::Keep => f.write_str("keep"),
+            UnexpectedColumns::Collect(column) => write!(f, "collect:{}", column),
+        }
+    }
+}
+
+/// A record that doesn't match an expected schema
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SchemaMismatch {
+    /// A key that is not a column, under [`UnexpectedColumns::Fail`]
+    Unexpected(String),
+    /// A value that doesn't fit its column, under [`SchemaMode::Enforce`]
+    Type { column: String, expected: ColumnType, value: String },
+}
+
+impl fmt::Display for SchemaMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SchemaMismatch::Unexpected(column) => write!(f, "unexpected column {:?}", column),
+            SchemaMismatch::Type { column, expected, value } => {
+                write!(f, "column {:?} expects {}, got {:?}", column, expected, value)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for SchemaMismatch {}
+
+impl FlatSchema {
+    /// Reshapes a record to this schema
+    ///
+    /// Every column is present in the result; missing keys and values coerced
+    /// away become `null`.
+    pub fn conform(
+        &self,
+        mut record: FlattenedJson,
+        mode: SchemaMode,
+        unexpected: &UnexpectedColumns,
+    ) -> Result<FlattenedJson, SchemaMismatch> {
+        let mut conformed = FlattenedJson::default();
+        for (column, &column_type) in self {
+            let value = match record.remove(column) {
+                Some(value) if is_null(Some(&value)) || column_type.fits(&value) => value,
+                Some(value) if mode == SchemaMode::Enforce => {
+                    return Err(SchemaMismatch::Type {
+                        column: column.clone(),
+                        expected: column_type,
+                        value,
+                    })
+                }
+                _ => "null".to_string(),
+            };
+            conformed.insert(column.clone(), value);
+        }
+
+        match unexpected {
+            UnexpectedColumns::Drop => {}
+            UnexpectedColumns::Fail => {
+                if let Some(column) = record.into_keys().min() {
+                    return Err(SchemaMismatch::Unexpected(column));
+                }
+            }
+            UnexpectedColumns::Keep => conformed.extend(record),
+            UnexpectedColumns::Collect(column) => {
+                let extra = match record.is_empty() {
+                    true => "null".to_string(),
+                    false => {
+                        let sorted: BTreeMap<String, String> = record.into_iter().collect();
+                        serde_json::to_string(&sorted).expect("string maps always serialize")
+                    }
+                };
+                conformed.insert(column.clone(), extra);
+            }
+        }
+        Ok(conformed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{flatten_json, FlattenOptions};
+    use serde_json::json;
+
+    #[test]
+    fn test_infer_schema_widens_and_skips_nulls() {
+        let options = FlattenOptions::default();
+        let records: Vec<FlattenedJson> = [
+            json!({"id": 1, "score": 2, "ok": true, "name": "a", "gone": null}),
+            json!({"id": 2, "score": 2.5, "ok": null, "name": 3}),
+        ]
+        .iter()
+        .map(|value| flatten_json(value, &options))
+        .collect();
+
+        let schema = infer_schema(&records);
+        let types: Vec<(&str, ColumnType)> = schema.iter().map(|(name, t)| (name.as_str(), *t)).collect();
+        assert_eq!(
+            types,
+            vec![
+                ("gone", ColumnType::Text),
+                ("id", ColumnType::Int),
+                ("name", ColumnType::Text),
+                ("ok", ColumnType::Bool),
+                ("score", ColumnType::Float),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_words_that_parse_as_floats_are_text() {
+        assert_eq!(ColumnType::of("NaN"), ColumnType::Text);
+        assert_eq!(ColumnType::of("inf"), ColumnType::Text);
+        assert_eq!(ColumnType::of("-1.5e3"), ColumnType::Float);
+        assert_eq!(ColumnType::of("18446744073709551615"), ColumnType::Decimal);
+    }
+
+    #[test]
+    fn test_numbers_a_float_would_round_are_decimal() {
+        assert_eq!(ColumnType::of("0.30000000000000004"), ColumnType::Float);
+        assert_eq!(ColumnType::of("1.50"), ColumnType::Float);
+        assert_eq!(ColumnType::of("9007199254740993"), ColumnType::Int);
+        assert_eq!(ColumnType::of("12345678901234567890.123456789"), ColumnType::Decimal);
+        assert_eq!(ColumnType::of("0.1000000000000000000001"), ColumnType::Decimal);
+
+        let amounts = ["1", "2.5", "170141183460469231731687303715884105727"].map(Some);
+        assert_eq!(ColumnType::infer(amounts), ColumnType::Decimal);
+        assert!(ColumnType::Decimal.fits("-3.25"));
+        assert!(!ColumnType::Decimal.fits("abc"));
+        assert!(!ColumnType::Float.fits("0.1000000000000000000001"));
+    }
+
+    fn schema(columns: &[(&str, ColumnType)]) -> FlatSchema {
+        columns.iter().map(|(name, t)| (name.to_string(), *t)).collect()
+    }
+
+    #[test]
+    fn test_compare_schemas_reports_added_removed_and_changed() {
+        let old = schema(&[("id", ColumnType::Int), ("score", ColumnType::Int), ("tag", ColumnType::Text)]);
+        let new = schema(&[("id", ColumnType::Text), ("note", ColumnType::Text), ("score", ColumnType::Float)]);
+
+        let diff = compare_schemas(&old, &new);
+        assert_eq!(diff.added, vec![("note".to_string(), ColumnType::Text)]);
+        assert_eq!(diff.removed, vec![("tag".to_string(), ColumnType::Text)]);
+        assert_eq!(
+            diff.changed.iter().map(|c| (c.column.as_str(), c.is_widening())).collect::<Vec<_>>(),
+            vec![("id", false), ("score", true)]
+        );
+        assert!(compare_schemas(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn test_drift_policy_only_reports_disallowed_changes() {
+        let old = schema(&[("flag", ColumnType::Bool), ("n", ColumnType::Int)]);
+        let new = schema(&[("extra", ColumnType::Int), ("flag", ColumnType::Int), ("n", ColumnType::Float)]);
+        let diff = compare_schemas(&old, &new);
+
+        let policy = DriftPolicy {
+            allow_added: true,
+            allow_widening: true,
+            ..DriftPolicy::default()
+        };
+        let err = diff.check(&policy).unwrap_err();
+        assert_eq!(err.to_string(), "schema drift: flag changed from bool to int");
+        assert!(!DriftPolicy::default().allows(&diff.changed[1]));
+        assert!(diff
+            .check(&DriftPolicy {
+                allow_type_changes: true,
+                ..policy
+            })
+            .is_ok());
+    }
+
+    #[test]
+    fn test_schema_round_trips_through_json() {
+        let saved = schema(&[("id", ColumnType::Int), ("user.name", ColumnType::Text)]);
+        let json = saved.to_json();
+        assert_eq!(json, "{\n  \"id\": \"int\",\n  \"user.name\": \"text\"\n}");
+        assert_eq!(FlatSchema::from_json(&json).unwrap(), saved);
+        assert!(FlatSchema::from_json("{\"id\": \"integer\"}").is_err());
+        #[cfg(feature = "yaml")]
+        assert_eq!(FlatSchema::from_yaml(&saved.to_yaml().unwrap()).unwrap(), saved);
+    }
+
+    #[test]
+    fn test_conform_fills_coerces_and_routes_unexpected_columns() {
+        let expected = schema(&[("id", ColumnType::Int), ("score", ColumnType::Float), ("tag", ColumnType::Text)]);
+        let record: FlattenedJson = [("id", "x"), ("score", "2"), ("b", "1"), ("a", "true")]
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let sorted = |record: FlattenedJson| record.into_iter().collect::<BTreeMap<_, _>>();
+
+        let err = expected.conform(record.clone(), SchemaMode::Enforce, &UnexpectedColumns::Drop);
+        assert_eq!(err.unwrap_err().to_string(), "column \"id\" expects int, got \"x\"");
+
+        let collect = UnexpectedColumns::Collect("_extra".to_string());
+        let conformed = expected.conform(record.clone(), SchemaMode::Coerce, &collect).unwrap();
+        let expected_pairs = [
+            ("_extra", "{\"a\":\"true\",\"b\":\"1\"}"),
+            ("id", "null"),
+            ("score", "2"),
+            ("tag", "null"),
+        ];
+        assert_eq!(
+            sorted(conformed),
+            expected_pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+        );
+
+        let err = expected.conform(record, SchemaMode::Coerce, &UnexpectedColumns::Fail);
+        assert_eq!(err.unwrap_err(), SchemaMismatch::Unexpected("a".to_string()));
+        assert_eq!("collect:_x".parse(), Ok(UnexpectedColumns::Collect("_x".to_string())));
+    }
+}
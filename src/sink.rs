@@ -0,0 +1,1115 @@
+// src/sink.rs
+use crate::schema::{
+    infer_schema, is_null, ColumnType, DriftPolicy, FlatSchema, SchemaBuilder, SchemaDiff, SchemaMode, TypeChange,
+    UnexpectedColumns,
+};
+use crate::{FlattenedJson, SkippedDocument, RECORD_SEPARATOR};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// Path that selects standard output instead of a file
+pub const STDOUT_PATH: &str = "-";
+
+/// Opens a buffered writer for a file, or standard output when the path is `"-"`
+pub fn open_output(filepath: impl AsRef<Path>) -> io::Result<Box<dyn Write + Send>> {
+    let filepath = filepath.as_ref();
+    if filepath == Path::new(STDOUT_PATH) {
+        Ok(Box::new(BufWriter::with_capacity(1 << 16, io::stdout())))
+    } else {
+        Ok(Box::new(BufWriter::with_capacity(1 << 16, File::create(filepath)?)))
+    }
+}
+
+/// A destination for flattened records
+///
+/// Sinks receive records one at a time in input order, so streaming APIs
+/// never have to hold the whole result set in memory.
+pub trait RecordSink {
+    /// Writes a single flattened record
+    fn write_record(&mut self, record: FlattenedJson) -> io::Result<()>;
+
+    /// Flushes any buffered output
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Hears of a document a `Skip` policy left out, which has no record;
+    /// ignored by default, and passed on by the sinks that wrap another
+    fn skip_document(&mut self, skipped: SkippedDocument) -> io::Result<()> {
+        let _ = skipped;
+        Ok(())
+    }
+}
+
+impl RecordSink for Vec<FlattenedJson> {
+    fn write_record(&mut self, record: FlattenedJson) -> io::Result<()> {
+        self.push(record);
+        Ok(())
+    }
+}
+
+impl<S: RecordSink + ?Sized> RecordSink for &mut S {
+    fn write_record(&mut self, record: FlattenedJson) -> io::Result<()> {
+        (**self).write_record(record)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (**self).flush()
+    }
+
+    fn skip_document(&mut self, skipped: SkippedDocument) -> io::Result<()> {
+        (**self).skip_document(skipped)
+    }
+}
+
+impl<S: RecordSink + ?Sized> RecordSink for Box<S> {
+    fn write_record(&mut self, record: FlattenedJson) -> io::Result<()> {
+        (**self).write_record(record)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (**self).flush()
+    }
+
+    fn skip_document(&mut self, skipped: SkippedDocument) -> io::Result<()> {
+        (**self).skip_document(skipped)
+    }
+}
+
+/// Sink that hands every record to a closure
+pub struct CallbackSink<F>(pub F);
+
+impl<F: FnMut(FlattenedJson)> RecordSink for CallbackSink<F> {
+    fn write_record(&mut self, record: FlattenedJson) -> io::Result<()> {
+        (self.0)(record);
+        Ok(())
+    }
+}
+
+/// Sink that sends records through a crossbeam channel
+///
+/// With a bounded channel, `write_record` blocks while the channel is full, so a
+/// slow consumer throttles parsing instead of records piling up in memory.
+pub struct ChannelSink {
+    tx: crossbeam_channel::Sender<FlattenedJson>,
+}
+
+impl ChannelSink {
+    pub fn new(tx: crossbeam_channel::Sender<FlattenedJson>) -> Self {
+        ChannelSink { tx }
+    }
+}
+
+impl RecordSink for ChannelSink {
+    fn write_record(&mut self, record: FlattenedJson) -> io::Result<()> {
+        self.tx
+            .send(record)
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "record receiver was dropped"))
+    }
+}
+
+/// Sink that writes each record as one JSON object per line (NDJSON)
+pub struct NdjsonSink<W: Write> {
+    writer: W,
+    sort_keys: bool,
+    json_seq: bool,
+}
+
+impl<W: Write> NdjsonSink<W> {
+    pub fn new(writer: W) -> Self {
+        NdjsonSink {
+            writer,
+            sort_keys: false,
+            json_seq: false,
+        }
+    }
+
+    /// Creates a sink that writes every object's keys in sorted order,
+    /// as required for canonical output
+    pub fn with_sorted_keys(writer: W) -> Self {
+        Self::new(writer).write_sorted(true)
+    }
+
+    /// Writes every object's keys in sorted order or, by default, in the
+    /// record's own order; pass `options.canonical` to follow canonical mode
+    pub fn write_sorted(mut self, sorted: bool) -> Self {
+        self.sort_keys = sorted;
+        self
+    }
+
+    /// Starts every record with a record separator, writing a JSON text
+    /// sequence (RFC 7464, `application/json-seq`) instead of NDJSON
+    pub fn write_json_seq(mut self, json_seq: bool) -> Self {
+        self.json_seq = json_seq;
+        self
+    }
+
+    /// Consumes the sink and returns the underlying writer
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> RecordSink for NdjsonSink<W> {
+    fn write_record(&mut self, record: FlattenedJson) -> io::Result<()> {
+        if self.json_seq {
+            self.writer.write_all(&[RECORD_SEPARATOR])?;
+        }
+        if self.sort_keys {
+            let sorted: BTreeMap<&String, &String> = record.iter().collect();
+            serde_json::to_writer(&mut self.writer, &sorted)?;
+        } else {
+            serde_json::to_writer(&mut self.writer, &record)?;
+        }
+        self.writer.write_all(b"\n")
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// How [`CsvSink`] and [`EavSink`] write fields that hold the delimiter or a line break
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum FieldEscape {
+    /// Quote the field, doubling quotes inside it (RFC 4180)
+    #[default]
+    Quote,
+    /// Never quote; write `\`, line breaks and the delimiter as `\\`, `\n`,
+    /// `\r` and `\` followed by the delimiter (`\t` for tabs), as MySQL's
+    /// `LOAD DATA` and many legacy loaders read them
+    Backslash,
+    /// Never quote; replace the delimiter and line breaks (`\r\n` counting
+    /// as one) with this text, losing them for loaders that can't take them
+    Replace(String),
+}
+
+impl std::str::FromStr for FieldEscape {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "quote" => Ok(FieldEscape::Quote),
+            "backslash" => Ok(FieldEscape::Backslash),
+            "replace" => Ok(FieldEscape::Replace(" ".to_string())),
+            _ => match s.strip_prefix("replace:") {
+                Some(text) => Ok(FieldEscape::Replace(text.to_string())),
+                None => Err(format!("unknown field escape {:?} (expected quote, backslash or replace[:TEXT])", s)),
+            },
+        }
+    }
+}
+
+impl fmt::Display for FieldEscape {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FieldEscape::Quote => f.write_str("quote"),
+            FieldEscape::Backslash => f.write_str("backslash"),
+            FieldEscape::Replace(text) if text == " " => f.write_str("replace"),
+            FieldEscape::Replace(text) => write!(f, "replace:{}", text),
+        }
+    }
+}
+
+/// Rows [`CsvSink`] holds by default to build its header
+pub const CSV_HEADER_ROWS: usize = 1024;
+
+/// Sink that writes records as delimited rows with a header line
+///
+/// The header covers every column (sorted by name) of the first
+/// [`CSV_HEADER_ROWS`] records, or of those before the first flush, unless
+/// [`with_schema`](CsvSink::with_schema) fixed it; only those rows are held,
+/// and the rest are streamed. Missing keys are written as empty fields, and
+/// keys that are not in the header follow [`UnexpectedColumns`], failing with
+/// `InvalidData` by default. `Keep` fails too, since a written header can't grow.
+pub struct CsvSink<W: Write> {
+    writer: W,
+    delimiter: char,
+    escape: FieldEscape,
+    unexpected: UnexpectedColumns,
+    header_rows: usize,
+    /// Header to write instead of one from the first rows
+    schema: Option<Vec<String>>,
+    header: Option<Vec<String>>,
+    columns: BTreeSet<String>,
+    rows: Vec<FlattenedJson>,
+}
+
+impl<W: Write> CsvSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self::with_delimiter(writer, ',')
+    }
+
+    /// Creates a sink using a custom field delimiter, e.g. `'\t'` for TSV
+    pub fn with_delimiter(writer: W, delimiter: char) -> Self {
+        CsvSink {
+            writer,
+            delimiter,
+            escape: FieldEscape::default(),
+            unexpected: UnexpectedColumns::Fail,
+            header_rows: CSV_HEADER_ROWS,
+            schema: None,
+            header: None,
+            columns: BTreeSet::new(),
+            rows: Vec::new(),
+        }
+    }
+
+    /// Sets how fields holding the delimiter or line breaks are written
+    pub fn with_escape(mut self, escape: FieldEscape) -> Self {
+        self.escape = escape;
+        self
+    }
+
+    /// Writes the columns of a schema saved from an earlier run as the header,
+    /// so the column set is the same every run, even when no record has some
+    /// column, and the first rows need not be scanned for it
+    pub fn with_schema(mut self, schema: FlatSchema) -> Self {
+        self.schema = Some(schema.iter().map(|(column, _)| column.clone()).collect());
+        self
+    }
+
+    /// Sets what happens to keys that are not in the header
+    pub fn with_unexpected(mut self, unexpected: UnexpectedColumns) -> Self {
+        self.unexpected = unexpected;
+        self
+    }
+
+    /// Builds the header from the first `rows` records instead of
+    /// [`CSV_HEADER_ROWS`], holding that many in memory
+    pub fn with_header_rows(mut self, rows: usize) -> Self {
+        self.header_rows = rows.max(1);
+        self
+    }
+
+    /// Flushes buffered rows and returns the underlying writer
+    pub fn into_inner(mut self) -> io::Result<W> {
+        RecordSink::flush(&mut self)?;
+        Ok(self.writer)
+    }
+
+    fn write_row<'a>(&mut self, fields: impl Iterator<Item = &'a str>) -> io::Result<()> {
+        write_delimited_row(&mut self.writer, self.delimiter, &self.escape, fields)
+    }
+
+    /// Writes the header, then the rows held while waiting for it
+    fn start(&mut self, mut header: Vec<String>) -> io::Result<()> {
+        if let UnexpectedColumns::Collect(column) = &self.unexpected {
+            if !header.contains(column) {
+                header.push(column.clone());
+            }
+        }
+        self.write_row(header.iter().map(String::as_str))?;
+        self.columns = header.iter().cloned().collect();
+        self.header = Some(header);
+        for row in std::mem::take(&mut self.rows) {
+            self.write_fields(row)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the header from the columns of the rows held so far
+    fn start_from_rows(&mut self) -> io::Result<()> {
+        let columns: BTreeSet<&String> = self.rows.iter().flat_map(|row| row.keys()).collect();
+        let header = columns.into_iter().cloned().collect();
+        self.start(header)
+    }
+
+    fn write_fields(&mut self, mut record: FlattenedJson) -> io::Result<()> {
+        let unexpected: Vec<String> = record.keys().filter(|key| !self.columns.contains(*key)).cloned().collect();
+        if !unexpected.is_empty() {
+            match &self.unexpected {
+                UnexpectedColumns::Drop => {}
+                UnexpectedColumns::Fail | UnexpectedColumns::Keep => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("column {:?} is not in the CSV header", unexpected[0]),
+                    ));
+                }
+                UnexpectedColumns::Collect(column) => {
+                    let extra: serde_json::Map<String, serde_json::Value> = unexpected
+                        .into_iter()
+                        .filter(|key| key != column)
+                        .filter_map(|key| record.remove(&key).map(|value| (key, serde_json::Value::String(value))))
+                        .collect();
+                    record.insert(column.clone(), serde_json::Value::Object(extra).to_string());
+                }
+            }
+        }
+        let header = self.header.as_deref().unwrap_or_default();
+        let fields = header.iter().map(|column| record.get(column).map_or("", String::as_str));
+        write_delimited_row(&mut self.writer, self.delimiter, &self.escape, fields)
+    }
+}
+
+/// Writes one delimited row, escaping fields that contain the delimiter or
+/// line breaks (and quotes or backslashes, for the escapes that use them)
+fn write_delimited_row<'a, W: Write>(
+    writer: &mut W,
+    delimiter: char,
+    escape: &FieldEscape,
+    fields: impl Iterator<Item = &'a str>,
+) -> io::Result<()> {
+    let special = |c: char| c == delimiter || c == '\n' || c == '\r';
+    for (i, field) in fields.enumerate() {
+        if i > 0 {
+            write!(writer, "{}", delimiter)?;
+        }
+        match escape {
+            FieldEscape::Quote if field.chars().any(|c| special(c) || c == '"') => {
+                write!(writer, "\"{}\"", field.replace('"', "\"\""))?;
+            }
+            FieldEscape::Backslash if field.chars().any(|c| special(c) || c == '\\') => {
+                let mut escaped = String::with_capacity(field.len() + 8);
+                for c in field.chars() {
+                    match c {
+                        '\\' => escaped.push_str("\\\\"),
+                        '\n' => escaped.push_str("\\n"),
+                        '\r' => escaped.push_str("\\r"),
+                        '\t' if c == delimiter => escaped.push_str("\\t"),
+                        c if c == delimiter => {
+                            escaped.push('\\');
+                            escaped.push(c);
+                        }
+                        c => escaped.push(c),
+                    }
+                }
+                writer.write_all(escaped.as_bytes())?;
+            }
+            FieldEscape::Replace(text) if field.chars().any(special) => {
+                let replaced = field.replace("\r\n", "\n").split(special).collect::<Vec<_>>().join(text);
+                writer.write_all(replaced.as_bytes())?;
+            }
+            _ => writer.write_all(field.as_bytes())?,
+        }
+    }
+    writer.write_all(b"\n")
+}
+
+impl<W: Write> RecordSink for CsvSink<W> {
+    fn write_record(&mut self, record: FlattenedJson) -> io::Result<()> {
+        if self.header.is_some() {
+            return self.write_fields(record);
+        }
+        if let Some(schema) = self.schema.take() {
+            self.start(schema)?;
+            return self.write_fields(record);
+        }
+        self.rows.push(record);
+        if self.rows.len() >= self.header_rows {
+            self.start_from_rows()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.header.is_none() {
+            match self.schema.take() {
+                Some(schema) => self.start(schema)?,
+                None if !self.rows.is_empty() => self.start_from_rows()?,
+                None => {}
+            }
+        }
+        self.writer.flush()
+    }
+}
+
+/// Sink that writes records in long (entity-attribute-value) format
+///
+/// Every key/value pair becomes a `record_id,key,value` row, so the column
+/// count stays fixed no matter how many distinct keys the documents have.
+/// Records are numbered from 0 in input order unless an id key is set, and
+/// their keys are written in sorted order. Rows are streamed; nothing is
+/// buffered besides the writer.
+pub struct EavSink<W: Write> {
+    writer: W,
+    delimiter: char,
+    escape: FieldEscape,
+    with_types: bool,
+    id_key: Option<String>,
+    next_id: u64,
+    header_written: bool,
+}
+
+impl<W: Write> EavSink<W> {
+    pub fn new(writer: W) -> Self {
+        EavSink {
+            writer,
+            delimiter: ',',
+            escape: FieldEscape::default(),
+            with_types: false,
+            id_key: None,
+            next_id: 0,
+            header_written: false,
+        }
+    }
+
+    /// Adds a `type` column (`number`, `boolean`, `null` or `string`)
+    ///
+    /// The type is inferred from the flattened text, so a JSON string such as
+    /// `"42"` is reported as a number.
+    pub fn with_types(mut self) -> Self {
+        self.with_types = true;
+        self
+    }
+
+    /// Uses a custom field delimiter, e.g. `'\t'` for TSV
+    pub fn with_delimiter(mut self, delimiter: char) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// Sets how fields holding the delimiter or line breaks are written
+    pub fn with_escape(mut self, escape: FieldEscape) -> Self {
+        self.escape = escape;
+        self
+    }
+
+    /// Takes the record id from this key (see `FlattenOptions::record_id_key`)
+    /// instead of numbering records; the key itself is not written as a row
+    pub fn with_id_key(mut self, key: &str) -> Self {
+        self.id_key = Some(key.to_string());
+        self
+    }
+
+    /// Consumes the sink and returns the underlying writer
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Infers the JSON type of a flattened value from its text
+fn inferred_type(value: &str) -> &'static str {
+    match value {
+        "true" | "false" => "boolean",
+        "null" => "null",
+        _ if serde_json::from_str::<serde_json::Number>(value).is_ok() => "number",
+        _ => "string",
+    }
+}
+
+impl<W: Write> RecordSink for EavSink<W> {
+    fn write_record(&mut self, record: FlattenedJson) -> io::Result<()> {
+        if !self.header_written {
+            let header: &[&str] = if self.with_types {
+                &["record_id", "key", "value", "type"]
+            } else {
+                &["record_id", "key", "value"]
+            };
+            write_delimited_row(&mut self.writer, self.delimiter, &self.escape, header.iter().copied())?;
+            self.header_written = true;
+        }
+
+        let mut record = record;
+        let id = match self.id_key.as_ref().and_then(|key| record.remove(key)) {
+            Some(id) => id,
+            None => self.next_id.to_string(),
+        };
+        self.next_id += 1;
+        let entries: BTreeMap<&String, &String> = record.iter().collect();
+        for (key, value) in entries {
+            let mut fields = vec![id.as_str(), key.as_str(), value.as_str()];
+            if self.with_types {
+                fields.push(inferred_type(value));
+            }
+            write_delimited_row(&mut self.writer, self.delimiter, &self.escape, fields.into_iter())?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Sink that writes records in ClickHouse's `RowBinaryWithNamesAndTypes` format
+///
+/// The header is every column of the first [`CSV_HEADER_ROWS`] records, or of
+/// those before the first flush, sorted by name and typed by [`infer_schema`]
+/// as a `Nullable` of `Bool`, `Int64`, `Float64` or `String`, unless
+/// [`with_schema`](RowBinarySink::with_schema) fixed it; only those rows are
+/// held, and the rest are streamed. JSON nulls and missing keys are written as
+/// NULL. A key that is not in the header, or a value that doesn't fit its
+/// column's type, fails with `InvalidData`, since the header can't change once
+/// written. Load the output with `INSERT INTO t FORMAT RowBinaryWithNamesAndTypes`.
+pub struct RowBinarySink<W: Write> {
+    writer: W,
+    header_rows: usize,
+    /// Columns to write instead of those inferred from the first rows
+    schema: Option<FlatSchema>,
+    columns: Option<FlatSchema>,
+    rows: Vec<FlattenedJson>,
+    buf: Vec<u8>,
+}
+
+impl<W: Write> RowBinarySink<W> {
+    pub fn new(writer: W) -> Self {
+        RowBinarySink {
+            writer,
+            header_rows: CSV_HEADER_ROWS,
+            schema: None,
+            columns: None,
+            rows: Vec::new(),
+            buf: Vec::new(),
+        }
+    }
+
+    /// Writes the columns and types of a known schema, e.g. one saved by an
+    /// earlier run of a stable feed, instead of inferring them, so the table
+    /// layout can't change with the data
+    pub fn with_schema(mut self, schema: FlatSchema) -> Self {
+        self.schema = Some(schema);
+        self
+    }
+
+    /// Infers the header from the first `rows` records instead of
+    /// [`CSV_HEADER_ROWS`], holding that many in memory
+    pub fn with_header_rows(mut self, rows: usize) -> Self {
+        self.header_rows = rows.max(1);
+        self
+    }
+
+    /// Flushes buffered rows and returns the underlying writer
+    pub fn into_inner(mut self) -> io::Result<W> {
+        RecordSink::flush(&mut self)?;
+        Ok(self.writer)
+    }
+
+    /// Writes the header, then the rows held while waiting for it
+    fn start(&mut self, columns: FlatSchema) -> io::Result<()> {
+        let mut header = Vec::new();
+        write_leb128(&mut header, columns.len() as u64);
+        for (name, _) in &columns {
+            write_row_binary_string(&mut header, name);
+        }
+        for (_, column_type) in &columns {
+            write_row_binary_string(&mut header, clickhouse_type(*column_type));
+        }
+        self.writer.write_all(&header)?;
+        self.columns = Some(columns);
+        for row in std::mem::take(&mut self.rows) {
+            self.write_row(row)?;
+        }
+        Ok(())
+    }
+
+    fn write_row(&mut self, record: FlattenedJson) -> io::Result<()> {
+        let columns = self.columns.as_ref().expect("header is written before rows");
+        if let Some(key) = record.keys().find(|key| columns.get(key.as_str()).is_none()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("column {:?} is not in the RowBinary header", key),
+            ));
+        }
+        self.buf.clear();
+        for (name, column_type) in columns {
+            write_row_binary_value(&mut self.buf, name, record.get(name).map(String::as_str), *column_type)?;
+        }
+        self.writer.write_all(&self.buf)
+    }
+}
+
+impl<W: Write> RecordSink for RowBinarySink<W> {
+    fn write_record(&mut self, record: FlattenedJson) -> io::Result<()> {
+        if self.columns.is_some() {
+            return self.write_row(record);
+        }
+        if let Some(schema) = self.schema.take() {
+            self.start(schema)?;
+            return self.write_row(record);
+        }
+        self.rows.push(record);
+        if self.rows.len() >= self.header_rows {
+            let columns = infer_schema(&self.rows);
+            self.start(columns)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.columns.is_none() {
+            match self.schema.take() {
+                Some(schema) => self.start(schema)?,
+                None if !self.rows.is_empty() => {
+                    let columns = infer_schema(&self.rows);
+                    self.start(columns)?;
+                }
+                None => {}
+            }
+        }
+        self.writer.flush()
+    }
+}
+
+fn clickhouse_type(column_type: ColumnType) -> &'static str {
+    match column_type {
+        ColumnType::Bool => "Nullable(Bool)",
+        ColumnType::Int => "Nullable(Int64)",
+        ColumnType::Float => "Nullable(Float64)",
+        // Written as exact text, which ClickHouse can cast to any Decimal width
+        ColumnType::Decimal | ColumnType::Text => "Nullable(String)",
+    }
+}
+
+/// Writes one `Nullable` value: a null flag byte, then the value unless it is
+/// null, failing on a value the column's type can't hold
+fn write_row_binary_value(buf: &mut Vec<u8>, column: &str, value: Option<&str>, column_type: ColumnType) -> io::Result<()> {
+    let value = match value.filter(|_| !is_null(value)) {
+        Some(value) if column_type.fits(value) => value,
+        Some(value) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("value {:?} of column {:?} does not fit {}", value, column, clickhouse_type(column_type)),
+            ))
+        }
+        None => {
+            buf.push(1);
+            return Ok(());
+        }
+    };
+    buf.push(0);
+    match column_type {
+        ColumnType::Bool => buf.push(u8::from(value == "true")),
+        ColumnType::Int => buf.extend_from_slice(&value.parse::<i64>().unwrap_or_default().to_le_bytes()),
+        ColumnType::Float => buf.extend_from_slice(&value.parse::<f64>().unwrap_or_default().to_le_bytes()),
+        ColumnType::Decimal | ColumnType::Text => write_row_binary_string(buf, value),
+    }
+    Ok(())
+}
+
+/// Writes a string as its LEB128 byte length followed by the bytes
+fn write_row_binary_string(buf: &mut Vec<u8>, value: &str) {
+    write_leb128(buf, value.len() as u64);
+    buf.extend_from_slice(value.as_bytes());
+}
+
+fn write_leb128(buf: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        buf.push((n as u8 & 0x7f) | 0x80);
+        n >>= 7;
+    }
+    buf.push(n as u8);
+}
+
+/// Sink that fails a run whose records drift from an expected schema more
+/// than a [`DriftPolicy`] allows, passing records on to another sink
+///
+/// New columns and type changes are checked as records arrive, so the record
+/// that breaks the policy is never written. Columns holding only nulls so far
+/// match any type. Removed columns are only known once every record is seen
+/// and are checked on flush. Errors are `InvalidData` wrapping a
+/// [`SchemaDrift`](crate::schema::SchemaDrift).
+pub struct SchemaCheckSink<S: RecordSink> {
+    inner: S,
+    expected: FlatSchema,
+    policy: DriftPolicy,
+    observed: SchemaBuilder,
+}
+
+impl<S: RecordSink> SchemaCheckSink<S> {
+    pub fn new(inner: S, expected: FlatSchema, policy: DriftPolicy) -> Self {
+        SchemaCheckSink {
+            inner,
+            expected,
+            policy,
+            observed: SchemaBuilder::default(),
+        }
+    }
+
+    /// Schema of the records written so far
+    pub fn schema(&self) -> FlatSchema {
+        self.observed.schema()
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    /// Drift introduced by the columns of one record
+    fn record_drift(&self, record: &FlattenedJson) -> SchemaDiff {
+        let mut diff = SchemaDiff::default();
+        let mut columns: Vec<&String> = record.keys().collect();
+        columns.sort_unstable();
+        for column in columns {
+            match (self.expected.get(column), self.observed.get(column).flatten()) {
+                (None, column_type) => diff.added.push((column.clone(), column_type.unwrap_or(ColumnType::Text))),
+                (Some(old), Some(new)) if old != new => diff.changed.push(TypeChange {
+                    column: column.clone(),
+                    old,
+                    new,
+                }),
+                _ => {}
+            }
+        }
+        diff
+    }
+}
+
+impl<S: RecordSink> RecordSink for SchemaCheckSink<S> {
+    fn write_record(&mut self, record: FlattenedJson) -> io::Result<()> {
+        self.observed.add(&record);
+        self.record_drift(&record)
+            .check(&self.policy)
+            .map_err(|drift| io::Error::new(io::ErrorKind::InvalidData, drift))?;
+        self.inner.write_record(record)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let removed = self
+            .expected
+            .iter()
+            .filter(|(column, _)| self.observed.get(column).is_none())
+            .map(|(column, column_type)| (column.clone(), *column_type))
+            .collect();
+        SchemaDiff {
+            removed,
+            ..SchemaDiff::default()
+        }
+        .check(&self.policy)
+        .map_err(|drift| io::Error::new(io::ErrorKind::InvalidData, drift))?;
+        self.inner.flush()
+    }
+
+    fn skip_document(&mut self, skipped: SkippedDocument) -> io::Result<()> {
+        self.inner.skip_document(skipped)
+    }
+}
+
+/// Sink that reshapes every record to an expected schema before passing it on
+///
+/// Records always carry every column of the schema, so outputs keep the same
+/// shape from run to run; see [`FlatSchema::conform`]. Values that don't fit
+/// and unexpected keys are handled per [`SchemaMode`] and
+/// [`UnexpectedColumns`]; mismatches fail with `InvalidData`.
+pub struct ExpectedSchemaSink<S: RecordSink> {
+    inner: S,
+    schema: FlatSchema,
+    mode: SchemaMode,
+    unexpected: UnexpectedColumns,
+}
+
+impl<S: RecordSink> ExpectedSchemaSink<S> {
+    pub fn new(inner: S, schema: FlatSchema) -> Self {
+        ExpectedSchemaSink {
+            inner,
+            schema,
+            mode: SchemaMode::default(),
+            unexpected: UnexpectedColumns::default(),
+        }
+    }
+
+    pub fn with_mode(mut self, mode: SchemaMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn with_unexpected(mut self, unexpected: UnexpectedColumns) -> Self {
+        self.unexpected = unexpected;
+        self
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: RecordSink> RecordSink for ExpectedSchemaSink<S> {
+    fn write_record(&mut self, record: FlattenedJson) -> io::Result<()> {
+        let record = self
+            .schema
+            .conform(record, self.mode, &self.unexpected)
+            .map_err(|mismatch| io::Error::new(io::ErrorKind::InvalidData, mismatch))?;
+        self.inner.write_record(record)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    fn skip_document(&mut self, skipped: SkippedDocument) -> io::Result<()> {
+        self.inner.skip_document(skipped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ndjson_sink_writes_one_line_per_record() {
+        let mut sink = NdjsonSink::new(Vec::new());
+        let mut record = FlattenedJson::default();
+        record.insert("a.b".to_string(), "1".to_string());
+        sink.write_record(record.clone()).unwrap();
+        sink.write_record(record).unwrap();
+
+        let output = String::from_utf8(sink.into_inner()).unwrap();
+        assert_eq!(output, "{\"a.b\":\"1\"}\n{\"a.b\":\"1\"}\n");
+    }
+
+    #[test]
+    fn test_json_seq_round_trips_through_the_reader() {
+        let mut sink = NdjsonSink::new(Vec::new()).write_json_seq(true);
+        for id in ["1", "2"] {
+            sink.write_record([("id".to_string(), id.to_string())].into_iter().collect()).unwrap();
+        }
+        let output = sink.into_inner();
+        assert_eq!(output, b"\x1e{\"id\":\"1\"}\n\x1e{\"id\":\"2\"}\n");
+
+        let records = crate::flatten_json_multi_str(core::str::from_utf8(&output).unwrap(), &Default::default());
+        let ids: Vec<String> = records.unwrap().into_iter().map(|record| record["id"].clone()).collect();
+        assert_eq!(ids, ["1", "2"]);
+    }
+
+    #[test]
+    fn test_ndjson_sink_sorted_keys() {
+        let mut sink = NdjsonSink::with_sorted_keys(Vec::new());
+        let record: FlattenedJson = ["c", "a", "b"]
+            .iter()
+            .map(|k| (k.to_string(), k.to_string()))
+            .collect();
+        sink.write_record(record).unwrap();
+
+        let output = String::from_utf8(sink.into_inner()).unwrap();
+        assert_eq!(output, "{\"a\":\"a\",\"b\":\"b\",\"c\":\"c\"}\n");
+    }
+
+    #[test]
+    fn test_ndjson_sink_write_sorted_follows_flag() {
+        let record: FlattenedJson = [("b.1", "x"), ("a", "y"), ("b.0", "z")]
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let pairs = crate::to_sorted_vec(&record);
+        assert_eq!(pairs.iter().map(|(k, _)| k.as_str()).collect::<Vec<_>>(), ["a", "b.0", "b.1"]);
+        assert_eq!(crate::into_sorted_vec(record.clone()), pairs);
+
+        let mut sink = NdjsonSink::new(Vec::new()).write_sorted(true);
+        sink.write_record(record).unwrap();
+        let output = String::from_utf8(sink.into_inner()).unwrap();
+        assert_eq!(output, "{\"a\":\"y\",\"b.0\":\"z\",\"b.1\":\"x\"}\n");
+    }
+
+    #[test]
+    fn test_csv_sink_unions_columns_and_quotes_fields() {
+        let mut sink = CsvSink::new(Vec::new());
+        let mut first = FlattenedJson::default();
+        first.insert("name".to_string(), "Smith, John".to_string());
+        let mut second = FlattenedJson::default();
+        second.insert("age".to_string(), "30".to_string());
+        sink.write_record(first).unwrap();
+        sink.write_record(second).unwrap();
+
+        let output = String::from_utf8(sink.into_inner().unwrap()).unwrap();
+        assert_eq!(output, "age,name\n,\"Smith, John\"\n30,\n");
+    }
+
+    #[test]
+    fn test_csv_sink_streams_after_header_rows() {
+        let record = |key: &str| -> FlattenedJson { [(key.to_string(), "1".to_string())].into_iter().collect() };
+        let mut sink = CsvSink::new(Vec::new()).with_header_rows(2);
+        sink.write_record(record("b")).unwrap();
+        assert!(sink.writer.is_empty());
+        sink.write_record(record("a")).unwrap();
+        assert_eq!(String::from_utf8_lossy(&sink.writer), "a,b\n,1\n1,\n");
+        let err = sink.write_record(record("c")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let mut collect = CsvSink::new(Vec::new()).with_unexpected("collect:_extra".parse().unwrap());
+        collect.write_record(record("a")).unwrap();
+        collect.flush().unwrap();
+        collect.write_record(record("c")).unwrap();
+        let output = String::from_utf8(collect.into_inner().unwrap()).unwrap();
+        assert_eq!(output, "a,_extra\n1,\n,\"{\"\"c\"\":\"\"1\"\"}\"\n");
+    }
+
+    #[test]
+    fn test_field_escapes_for_loaders_without_quoting() {
+        let record: FlattenedJson = [("note".to_string(), "a,\"b\"\r\nc\\d\te".to_string())].into_iter().collect();
+        let write = |sink: CsvSink<Vec<u8>>| {
+            let mut sink = sink;
+            sink.write_record(record.clone()).unwrap();
+            String::from_utf8(sink.into_inner().unwrap()).unwrap()
+        };
+
+        assert_eq!(write(CsvSink::new(Vec::new())), "note\n\"a,\"\"b\"\"\r\nc\\d\te\"\n");
+        let backslash = CsvSink::new(Vec::new()).with_escape(FieldEscape::Backslash);
+        assert_eq!(write(backslash), "note\na\\,\"b\"\\r\\nc\\\\d\te\n");
+        let tsv = CsvSink::with_delimiter(Vec::new(), '\t').with_escape(FieldEscape::Backslash);
+        assert_eq!(write(tsv), "note\na,\"b\"\\r\\nc\\\\d\\te\n");
+        let replace = CsvSink::new(Vec::new()).with_escape("replace:_".parse().unwrap());
+        assert_eq!(write(replace), "note\na_\"b\"_c\\d\te\n");
+
+        assert_eq!("replace".parse::<FieldEscape>(), Ok(FieldEscape::Replace(" ".to_string())));
+        assert_eq!(FieldEscape::Replace("|".to_string()).to_string(), "replace:|");
+        assert!("escape".parse::<FieldEscape>().is_err());
+    }
+
+    #[test]
+    fn test_schema_fixes_csv_and_row_binary_columns() {
+        let schema: FlatSchema =
+            [("id".to_string(), ColumnType::Text), ("score".to_string(), ColumnType::Float)].into_iter().collect();
+        let record: FlattenedJson =
+            [("id", "7"), ("extra", "x")].iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+
+        let mut csv = CsvSink::new(Vec::new()).with_schema(schema.clone()).with_unexpected(UnexpectedColumns::Drop);
+        csv.write_record(record.clone()).unwrap();
+        assert_eq!(String::from_utf8(csv.into_inner().unwrap()).unwrap(), "id,score\n7,\n");
+        let empty = CsvSink::new(Vec::new()).with_schema(schema.clone());
+        assert_eq!(String::from_utf8(empty.into_inner().unwrap()).unwrap(), "id,score\n");
+
+        let mut row_binary = RowBinarySink::new(Vec::new()).with_schema(schema);
+        let err = row_binary.write_record(record.clone()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let mut record = record;
+        record.remove("extra");
+        row_binary.write_record(record).unwrap();
+        let mut expected = vec![2, 2, b'i', b'd', 5, b's', b'c', b'o', b'r', b'e', 16];
+        expected.extend_from_slice(b"Nullable(String)");
+        expected.push(17);
+        expected.extend_from_slice(b"Nullable(Float64)");
+        expected.extend_from_slice(&[0, 1, b'7', 1]);
+        assert_eq!(row_binary.into_inner().unwrap(), expected);
+    }
+
+    #[test]
+    fn test_eav_sink_writes_one_row_per_key() {
+        let mut sink = EavSink::new(Vec::new()).with_types();
+        let mut first = FlattenedJson::default();
+        first.insert("b".to_string(), "x, y".to_string());
+        first.insert("a".to_string(), "1.5".to_string());
+        let mut second = FlattenedJson::default();
+        second.insert("c".to_string(), "null".to_string());
+        sink.write_record(first).unwrap();
+        sink.write_record(second).unwrap();
+
+        let output = String::from_utf8(sink.into_inner()).unwrap();
+        assert_eq!(
+            output,
+            "record_id,key,value,type\n0,a,1.5,number\n0,b,\"x, y\",string\n1,c,null,null\n"
+        );
+    }
+
+    #[test]
+    fn test_eav_sink_uses_record_id_key() {
+        let mut sink = EavSink::new(Vec::new()).with_id_key("_id");
+        let mut record = FlattenedJson::default();
+        record.insert("_id".to_string(), "abc".to_string());
+        record.insert("a".to_string(), "1".to_string());
+        sink.write_record(record).unwrap();
+
+        let output = String::from_utf8(sink.into_inner()).unwrap();
+        assert_eq!(output, "record_id,key,value\nabc,a,1\n");
+    }
+
+    #[test]
+    fn test_row_binary_sink_writes_header_and_nullable_values() {
+        let mut sink = RowBinarySink::new(Vec::new());
+        let mut first = FlattenedJson::default();
+        first.insert("id".to_string(), "1".to_string());
+        first.insert("name".to_string(), "é".to_string());
+        let mut second = FlattenedJson::default();
+        second.insert("id".to_string(), "null".to_string());
+        sink.write_record(first).unwrap();
+        sink.write_record(second).unwrap();
+
+        let mut expected = vec![2, 2, b'i', b'd', 4, b'n', b'a', b'm', b'e', 15];
+        expected.extend_from_slice(b"Nullable(Int64)");
+        expected.push(16);
+        expected.extend_from_slice(b"Nullable(String)");
+        expected.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0xc3, 0xa9]);
+        expected.extend_from_slice(&[1, 1]);
+        assert_eq!(sink.into_inner().unwrap(), expected);
+    }
+
+    #[test]
+    fn test_row_binary_sink_streams_after_header_rows() {
+        let record = |key: &str, value: &str| -> FlattenedJson { [(key.to_string(), value.to_string())].into_iter().collect() };
+        let mut sink = RowBinarySink::new(Vec::new()).with_header_rows(2);
+        sink.write_record(record("n", "1")).unwrap();
+        assert!(sink.writer.is_empty());
+        sink.write_record(record("n", "2")).unwrap();
+        sink.write_record(record("n", "null")).unwrap();
+
+        let mut expected = vec![1, 1, b'n', 15];
+        expected.extend_from_slice(b"Nullable(Int64)");
+        expected.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0, 0]);
+        expected.extend_from_slice(&[0, 2, 0, 0, 0, 0, 0, 0, 0]);
+        expected.push(1);
+        assert_eq!(sink.writer, expected);
+        assert!(sink.rows.is_empty());
+
+        let err = sink.write_record(record("n", "x")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let err = sink.write_record(record("m", "1")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(sink.into_inner().unwrap(), expected);
+    }
+
+    #[test]
+    fn test_leb128_lengths() {
+        let mut buf = Vec::new();
+        write_leb128(&mut buf, 300);
+        assert_eq!(buf, [0xac, 0x02]);
+    }
+
+    #[test]
+    fn test_schema_check_sink_stops_before_drifting_record() {
+        let expected: FlatSchema = [("id".to_string(), ColumnType::Int), ("score".to_string(), ColumnType::Int)]
+            .into_iter()
+            .collect();
+        let policy = DriftPolicy {
+            allow_widening: true,
+            ..DriftPolicy::default()
+        };
+        let record = |pairs: &[(&str, &str)]| -> FlattenedJson {
+            pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+        };
+        let mut sink = SchemaCheckSink::new(Vec::new(), expected, policy);
+        sink.write_record(record(&[("id", "1"), ("score", "null")])).unwrap();
+        sink.write_record(record(&[("id", "2"), ("score", "2.5")])).unwrap();
+        let err = sink.write_record(record(&[("id", "x")])).unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "schema drift: id changed from int to text");
+        assert_eq!(sink.into_inner().len(), 2);
+    }
+
+    #[test]
+    fn test_schema_check_sink_reports_removed_columns_on_flush() {
+        let expected: FlatSchema = [("a".to_string(), ColumnType::Int), ("b".to_string(), ColumnType::Text)]
+            .into_iter()
+            .collect();
+        let mut sink = SchemaCheckSink::new(Vec::new(), expected, DriftPolicy::default());
+        let mut record = FlattenedJson::default();
+        record.insert("a".to_string(), "1".to_string());
+        sink.write_record(record).unwrap();
+
+        let err = sink.flush().unwrap_err();
+        assert_eq!(err.to_string(), "schema drift: removed columns b");
+    }
+
+    #[test]
+    fn test_expected_schema_sink_keeps_every_column() {
+        let schema: FlatSchema = [("a".to_string(), ColumnType::Int), ("b".to_string(), ColumnType::Bool)]
+            .into_iter()
+            .collect();
+        let mut sink = ExpectedSchemaSink::new(Vec::new(), schema).with_unexpected(UnexpectedColumns::Keep);
+        let mut record = FlattenedJson::default();
+        record.insert("a".to_string(), "1".to_string());
+        record.insert("c".to_string(), "x".to_string());
+        sink.write_record(record).unwrap();
+        let mut mismatched = FlattenedJson::default();
+        mismatched.insert("b".to_string(), "2".to_string());
+        let err = sink.write_record(mismatched).unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let records = sink.into_inner();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].get("b").map(String::as_str), Some("null"));
+        assert_eq!(records[0].get("c").map(String::as_str), Some("x"));
+    }
+}
@@ -0,0 +1,213 @@
+// src/spill.rs
+use crate::estimate::ENTRY_OVERHEAD;
+use crate::{FlattenOptions, FlattenedJson, RecordSink};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Seek, SeekFrom, Write};
+
+/// Rough number of bytes a record occupies in memory
+///
+/// Counts key and value bytes plus the `String` headers and hash table slot of
+/// every entry. It is an estimate for budgeting, not an exact measurement.
+pub fn estimated_record_bytes(record: &FlattenedJson) -> usize {
+    record
+        .iter()
+        .map(|(key, value)| key.len() + value.len() + ENTRY_OVERHEAD)
+        .sum::<usize>()
+        + std::mem::size_of::<FlattenedJson>()
+}
+
+/// Collects records in memory and fails once they exceed a memory budget
+///
+/// Used by `flatten_json_file` when `memory_budget` is set, so an unexpectedly
+/// large input produces an `OutOfMemory` error instead of exhausting the host.
+pub struct BudgetedSink {
+    budget: usize,
+    bytes: usize,
+    records: Vec<FlattenedJson>,
+}
+
+impl BudgetedSink {
+    pub fn new(budget: usize) -> Self {
+        BudgetedSink {
+            budget,
+            bytes: 0,
+            records: Vec::new(),
+        }
+    }
+
+    /// A sink for `memory_budget`, collecting without limit when it is 0
+    pub(crate) fn for_options(options: &FlattenOptions) -> Self {
+        match options.memory_budget {
+            0 => BudgetedSink::new(usize::MAX),
+            budget => BudgetedSink::new(budget),
+        }
+    }
+
+    pub fn into_records(self) -> Vec<FlattenedJson> {
+        self.records
+    }
+}
+
+impl RecordSink for BudgetedSink {
+    fn write_record(&mut self, record: FlattenedJson) -> io::Result<()> {
+        self.bytes += estimated_record_bytes(&record);
+        if self.bytes > self.budget {
+            return Err(io::Error::new(
+                io::ErrorKind::OutOfMemory,
+                format!(
+                    "memory budget of {} bytes exceeded after {} records; \
+                     use flatten_json_file_spilled or flatten_json_file_to_sink",
+                    self.budget,
+                    self.records.len()
+                ),
+            ));
+        }
+        self.records.push(record);
+        Ok(())
+    }
+}
+
+/// Collects records in memory until a budget is exceeded, then moves them to
+/// an anonymous temp file and writes every further record there as NDJSON
+///
+/// The temp file is removed by the OS once the sink or its records are dropped.
+pub struct SpillSink {
+    budget: usize,
+    bytes: usize,
+    records: Vec<FlattenedJson>,
+    spill: Option<BufWriter<File>>,
+    len: usize,
+}
+
+impl SpillSink {
+    pub fn new(budget: usize) -> Self {
+        SpillSink {
+            budget,
+            bytes: 0,
+            records: Vec::new(),
+            spill: None,
+            len: 0,
+        }
+    }
+
+    /// Number of records written so far
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Whether the budget was exceeded and records moved to disk
+    pub fn is_spilled(&self) -> bool {
+        self.spill.is_some()
+    }
+
+    /// Returns an iterator over all records, reading them back from disk if spilled
+    pub fn into_records(self) -> io::Result<SpilledRecords> {
+        match self.spill {
+            None => Ok(SpilledRecords::Memory(self.records.into_iter())),
+            Some(writer) => {
+                let mut file = writer.into_inner().map_err(|e| e.into_error())?;
+                file.seek(SeekFrom::Start(0))?;
+                Ok(SpilledRecords::Disk(BufReader::new(file).lines()))
+            }
+        }
+    }
+
+    fn spill_to_disk(&mut self) -> io::Result<()> {
+        let mut writer = BufWriter::new(tempfile::tempfile()?);
+        for record in self.records.drain(..) {
+            write_ndjson(&mut writer, &record)?;
+        }
+        self.records.shrink_to_fit();
+        self.spill = Some(writer);
+        Ok(())
+    }
+}
+
+impl RecordSink for SpillSink {
+    fn write_record(&mut self, record: FlattenedJson) -> io::Result<()> {
+        self.len += 1;
+        if let Some(writer) = &mut self.spill {
+            return write_ndjson(writer, &record);
+        }
+
+        self.bytes += estimated_record_bytes(&record);
+        self.records.push(record);
+        if self.bytes > self.budget {
+            self.spill_to_disk()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.spill {
+            Some(writer) => writer.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+fn write_ndjson<W: Write>(writer: &mut W, record: &FlattenedJson) -> io::Result<()> {
+    serde_json::to_writer(&mut *writer, record)?;
+    writer.write_all(b"\n")
+}
+
+/// Records collected by a [`SpillSink`], in input order
+pub enum SpilledRecords {
+    Memory(std::vec::IntoIter<FlattenedJson>),
+    Disk(io::Lines<BufReader<File>>),
+}
+
+impl Iterator for SpilledRecords {
+    type Item = io::Result<FlattenedJson>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            SpilledRecords::Memory(records) => records.next().map(Ok),
+            SpilledRecords::Disk(lines) => lines
+                .next()
+                .map(|line| Ok(serde_json::from_str(&line?)?)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(i: usize) -> FlattenedJson {
+        let mut record = FlattenedJson::default();
+        record.insert("id".to_string(), i.to_string());
+        record
+    }
+
+    #[test]
+    fn test_spill_sink_moves_records_to_disk_in_order() {
+        let mut sink = SpillSink::new(estimated_record_bytes(&record(0)) * 2);
+        for i in 0..5 {
+            sink.write_record(record(i)).unwrap();
+        }
+        sink.flush().unwrap();
+
+        assert!(sink.is_spilled());
+        assert_eq!(sink.len(), 5);
+        let ids: Vec<String> = sink
+            .into_records()
+            .unwrap()
+            .map(|r| r.unwrap()["id"].clone())
+            .collect();
+        assert_eq!(ids, vec!["0", "1", "2", "3", "4"]);
+    }
+
+    #[test]
+    fn test_budgeted_sink_fails_with_out_of_memory() {
+        let mut sink = BudgetedSink::new(estimated_record_bytes(&record(0)));
+        sink.write_record(record(0)).unwrap();
+        let err = sink.write_record(record(1)).unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::OutOfMemory);
+    }
+}
@@ -0,0 +1,512 @@
+// src/stats.rs
+use crate::hash::FxBuildHasher;
+use crate::keys::{split_key, KeyPattern};
+use crate::schema::{is_null, ColumnType};
+use crate::{flatten_reader_to_sink, CallbackSink, FlattenOptions, FlattenedJson, RecordSink, SkippedDocument};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::hash::BuildHasher;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// Statistics of one column, as written in a [`QualityReport`]
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct ColumnStats {
+    /// Inferred type of the non-null values; `None` for a column of only nulls
+    #[serde(rename = "type")]
+    pub column_type: Option<ColumnType>,
+    pub non_null: u64,
+    /// Share of records where the column is null or missing
+    pub null_rate: f64,
+    /// Distinct non-null values, counted by 64-bit hash
+    pub distinct: u64,
+    /// Numeric distribution, for int and float columns
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub numeric: Option<NumericStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub length: Option<LengthStats>,
+}
+
+/// Shortest and longest value of a column, in characters
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct LengthStats {
+    pub min: usize,
+    pub max: usize,
+}
+
+/// Min, max, mean and standard deviation of a numeric column
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct NumericStats {
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub stddev: f64,
+}
+
+/// A record kept by [`ColumnStatsSink::with_top_widest`]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct WideRecord {
+    /// Index of the record among those written to the sink, which is the
+    /// index of its document in the input unless root arrays were split
+    pub position: u64,
+    /// Number of keys in the record
+    pub keys: usize,
+    /// The record, keys sorted
+    pub record: BTreeMap<String, String>,
+}
+
+/// How often each value of a key occurs, kept by [`ColumnStatsSink::with_value_counts`]
+///
+/// Counts from separate runs over parts of the input add up with
+/// [`merge`](ValueCounts::merge), so a long run can save them next to its
+/// checkpoint and carry on from there.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ValueCounts {
+    /// Records per non-null value, sorted by value
+    pub values: BTreeMap<String, u64>,
+    /// Records where the key is null or missing
+    pub missing: u64,
+}
+
+impl ValueCounts {
+    pub fn merge(&mut self, other: &ValueCounts) {
+        for (value, count) in &other.values {
+            *self.values.entry(value.clone()).or_default() += count;
+        }
+        self.missing += other.missing;
+    }
+}
+
+/// Distinct values of a column, kept by [`ColumnStatsSink::with_distinct_values`]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct DistinctValues {
+    /// Non-null values, sorted
+    pub values: BTreeSet<String>,
+    /// Whether the column had more distinct values than the limit; `values`
+    /// then holds the first ones seen
+    pub overflow: bool,
+}
+
+impl DistinctValues {
+    fn add(&mut self, value: &str, limit: usize) {
+        if self.values.contains(value) {
+            return;
+        }
+        if self.values.len() < limit {
+            self.values.insert(value.to_string());
+        } else {
+            self.overflow = true;
+        }
+    }
+}
+
+/// Per-column statistics of every record written to a [`ColumnStatsSink`]
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct QualityReport {
+    pub records: u64,
+    /// Columns sorted by name
+    pub columns: BTreeMap<String, ColumnStats>,
+    /// Records with the most keys, widest first; the first one wins a tie
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub widest: Vec<WideRecord>,
+    /// Value counts of the keys passed to [`ColumnStatsSink::with_value_counts`]
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub value_counts: BTreeMap<String, ValueCounts>,
+    /// Distinct values of the columns matching the patterns passed to
+    /// [`ColumnStatsSink::with_distinct_values`]
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub distinct_values: BTreeMap<String, DistinctValues>,
+}
+
+impl QualityReport {
+    /// Pretty-printed JSON
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("a report always serializes")
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        std::fs::write(path, self.to_json() + "\n")
+    }
+}
+
+/// Running statistics of one column
+#[derive(Default)]
+struct ColumnAccumulator {
+    column_type: Option<ColumnType>,
+    non_null: u64,
+    hashes: HashSet<u64, FxBuildHasher>,
+    /// Count, mean and sum of squared deviations (Welford)
+    numbers: (u64, f64, f64),
+    min: f64,
+    max: f64,
+    length: Option<LengthStats>,
+    /// Set for columns matching a distinct values pattern
+    distinct_values: Option<DistinctValues>,
+}
+
+impl ColumnAccumulator {
+    fn add(&mut self, value: &str) {
+        let value_type = ColumnType::of(value);
+        self.column_type = Some(self.column_type.map_or(value_type, |t| t.widen(value_type)));
+        self.non_null += 1;
+        self.hashes.insert(FxBuildHasher::default().hash_one(value));
+
+        let chars = value.chars().count();
+        self.length = Some(match self.length {
+            Some(LengthStats { min, max }) => LengthStats {
+                min: min.min(chars),
+                max: max.max(chars),
+            },
+            None => LengthStats { min: chars, max: chars },
+        });
+
+        if value_type.is_numeric() {
+            if let Ok(n) = value.parse::<f64>() {
+                let (count, mean, m2) = &mut self.numbers;
+                if *count == 0 {
+                    self.min = n;
+                    self.max = n;
+                }
+                self.min = self.min.min(n);
+                self.max = self.max.max(n);
+                *count += 1;
+                let delta = n - *mean;
+                *mean += delta / *count as f64;
+                *m2 += delta * (n - *mean);
+            }
+        }
+    }
+
+    fn stats(&self, records: u64) -> ColumnStats {
+        let (count, mean, m2) = self.numbers;
+        let numeric = self.column_type.is_some_and(ColumnType::is_numeric) && count > 0;
+        ColumnStats {
+            column_type: self.column_type,
+            non_null: self.non_null,
+            null_rate: match records {
+                0 => 0.0,
+                _ => (records - self.non_null) as f64 / records as f64,
+            },
+            distinct: self.hashes.len() as u64,
+            numeric: numeric.then(|| NumericStats {
+                min: self.min,
+                max: self.max,
+                mean,
+                stddev: (m2 / count as f64).sqrt(),
+            }),
+            length: self.length,
+        }
+    }
+}
+
+/// Sink that computes per-column statistics of the records passing through
+/// it, so a data quality report needs no second scan of the output
+///
+/// Every value's hash is kept to count distinct values, so memory grows with
+/// the number of distinct values per column.
+pub struct ColumnStatsSink<S: RecordSink> {
+    inner: S,
+    records: u64,
+    columns: BTreeMap<String, ColumnAccumulator>,
+    top_widest: usize,
+    /// Sorted by descending key count, then by position
+    widest: Vec<WideRecord>,
+    value_counts: BTreeMap<String, ValueCounts>,
+    distinct_patterns: Vec<KeyPattern>,
+    max_distinct_values: usize,
+    separator: String,
+    escaped: bool,
+}
+
+impl<S: RecordSink> ColumnStatsSink<S> {
+    pub fn new(inner: S) -> Self {
+        ColumnStatsSink {
+            inner,
+            records: 0,
+            columns: BTreeMap::new(),
+            top_widest: 0,
+            widest: Vec::new(),
+            value_counts: BTreeMap::new(),
+            distinct_patterns: Vec::new(),
+            max_distinct_values: 0,
+            separator: ".".to_string(),
+            escaped: false,
+        }
+    }
+
+    /// Keeps a copy of the `n` records with the most keys for the report, so
+    /// pathological documents can be inspected without scanning the input again
+    pub fn with_top_widest(mut self, n: usize) -> Self {
+        self.top_widest = n;
+        self
+    }
+
+    /// Counts the records per value of each of `keys`, e.g. the distribution
+    /// of `status`, in the same pass as the other statistics
+    ///
+    /// Every distinct value is kept, so this is meant for keys with few values.
+    pub fn with_value_counts<K: Into<String>>(mut self, keys: impl IntoIterator<Item = K>) -> Self {
+        for key in keys {
+            self.value_counts.entry(key.into()).or_default();
+        }
+        self
+    }
+
+    /// Adds counts saved from an earlier run, e.g. with the report at a
+    /// checkpoint, so the next report covers both runs
+    ///
+    /// Counts of keys not passed to [`with_value_counts`](Self::with_value_counts)
+    /// are ignored.
+    pub fn resume_value_counts(mut self, saved: &BTreeMap<String, ValueCounts>) -> Self {
+        for (key, counts) in &mut self.value_counts {
+            if let Some(saved) = saved.get(key) {
+                counts.merge(saved);
+            }
+        }
+        self
+    }
+
+    /// Collects up to `limit` distinct values of each column matching one of
+    /// `patterns` (see [`KeyPattern`]), e.g. candidates for an enum type, in
+    /// the same pass as the other statistics
+    ///
+    /// Columns with more distinct values are marked as overflowing instead
+    /// of growing further.
+    pub fn with_distinct_values<P: AsRef<str>>(mut self, patterns: impl IntoIterator<Item = P>, limit: usize) -> Self {
+        self.distinct_patterns.extend(patterns.into_iter().map(|pattern| KeyPattern::new(pattern.as_ref())));
+        self.max_distinct_values = limit;
+        self
+    }
+
+    /// Splits keys on the separator they were flattened with (see
+    /// `FlattenOptions::separator` and `escape_keys`) to match distinct
+    /// values patterns; patterns keep `.` between segments
+    pub fn with_separator(mut self, separator: &str, escaped: bool) -> Self {
+        self.separator = separator.to_string();
+        self.escaped = escaped;
+        self
+    }
+
+    /// Statistics of the records written so far
+    pub fn report(&self) -> QualityReport {
+        QualityReport {
+            records: self.records,
+            columns: self
+                .columns
+                .iter()
+                .map(|(column, accumulator)| (column.clone(), accumulator.stats(self.records)))
+                .collect(),
+            widest: self.widest.clone(),
+            value_counts: self.value_counts.clone(),
+            distinct_values: self
+                .columns
+                .iter()
+                .filter_map(|(column, accumulator)| Some((column.clone(), accumulator.distinct_values.clone()?)))
+                .collect(),
+        }
+    }
+
+    fn keep_if_widest(&mut self, record: &FlattenedJson) {
+        let keys = record.len();
+        let full = self.widest.len() == self.top_widest;
+        if self.top_widest == 0 || full && self.widest.last().is_some_and(|narrowest| narrowest.keys >= keys) {
+            return;
+        }
+        if full {
+            self.widest.pop();
+        }
+        let at = self.widest.partition_point(|wide| wide.keys >= keys);
+        let record = record.iter().map(|(key, value)| (key.clone(), value.clone())).collect();
+        self.widest.insert(
+            at,
+            WideRecord {
+                position: self.records,
+                keys,
+                record,
+            },
+        );
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: RecordSink> RecordSink for ColumnStatsSink<S> {
+    fn write_record(&mut self, record: FlattenedJson) -> io::Result<()> {
+        self.keep_if_widest(&record);
+        self.records += 1;
+        for (key, counts) in &mut self.value_counts {
+            match record.get(key).filter(|value| !is_null(Some(value))) {
+                Some(value) => match counts.values.get_mut(value) {
+                    Some(count) => *count += 1,
+                    None => {
+                        counts.values.insert(value.clone(), 1);
+                    }
+                },
+                None => counts.missing += 1,
+            }
+        }
+        for (column, value) in &record {
+            let accumulator = match self.columns.get_mut(column) {
+                Some(accumulator) => accumulator,
+                None => {
+                    let segments = split_key(column, &self.separator, self.escaped);
+                    let distinct = self.distinct_patterns.iter().any(|pattern| pattern.matches(&segments));
+                    let accumulator = self.columns.entry(column.clone()).or_default();
+                    accumulator.distinct_values = distinct.then(DistinctValues::default);
+                    accumulator
+                }
+            };
+            if !is_null(Some(value)) {
+                accumulator.add(value);
+                if let Some(distinct) = &mut accumulator.distinct_values {
+                    distinct.add(value, self.max_distinct_values);
+                }
+            }
+        }
+        self.inner.write_record(record)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    fn skip_document(&mut self, skipped: SkippedDocument) -> io::Result<()> {
+        self.inner.skip_document(skipped)
+    }
+}
+
+/// Streams a dataset and counts the records holding each flattened key
+///
+/// Keys with null values count, since they appear in the records. Cheaper
+/// than a [`QualityReport`] when all you need is which keys exist and how
+/// common they are, e.g. to pick a projection or spot misspelled fields.
+pub fn index_keys<R: Read>(
+    reader: R,
+    options: &FlattenOptions,
+) -> Result<BTreeMap<String, u64>, Box<dyn std::error::Error>> {
+    let mut counts: BTreeMap<String, u64> = BTreeMap::new();
+    let mut sink = CallbackSink(|record: FlattenedJson| {
+        for key in record.into_keys() {
+            *counts.entry(key).or_default() += 1;
+        }
+    });
+    flatten_reader_to_sink(reader, &mut sink, options)?;
+    Ok(counts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flatten_json;
+    use serde_json::json;
+
+    #[test]
+    fn test_column_stats_cover_nulls_distinct_and_distribution() {
+        let options = FlattenOptions::default();
+        let mut sink = ColumnStatsSink::new(Vec::new());
+        for value in [
+            json!({"n": 1, "s": "ab", "z": null}),
+            json!({"n": 3, "s": "ab"}),
+            json!({"n": 2.0, "s": "xyz"}),
+            json!({"s": null}),
+        ] {
+            sink.write_record(flatten_json(&value, &options)).unwrap();
+        }
+        let report = sink.report();
+        assert_eq!(report.records, 4);
+
+        let n = &report.columns["n"];
+        assert_eq!((n.column_type, n.non_null, n.null_rate, n.distinct), (Some(ColumnType::Float), 3, 0.25, 3));
+        let numeric = n.numeric.unwrap();
+        assert_eq!((numeric.min, numeric.max, numeric.mean), (1.0, 3.0, 2.0));
+        assert!((numeric.stddev - (2.0f64 / 3.0).sqrt()).abs() < 1e-12);
+
+        let s = &report.columns["s"];
+        assert_eq!((s.distinct, s.length, s.numeric), (2, Some(LengthStats { min: 2, max: 3 }), None));
+        assert_eq!(report.columns["z"].column_type, None);
+        assert_eq!(report.columns["z"].null_rate, 1.0);
+        assert_eq!(sink.into_inner().len(), 4);
+    }
+
+    #[test]
+    fn test_top_widest_records_are_kept_with_positions() {
+        let options = FlattenOptions::default();
+        let mut sink = ColumnStatsSink::new(Vec::new()).with_top_widest(2);
+        for value in [
+            json!({"a": 1}),
+            json!({"a": 1, "b": 2, "c": 3}),
+            json!({"a": 1, "b": 2}),
+            json!({"x": [1, 2, 3]}),
+            json!({"a": 1, "b": 2}),
+        ] {
+            sink.write_record(flatten_json(&value, &options)).unwrap();
+        }
+        let widest = sink.report().widest;
+        let found: Vec<_> = widest.iter().map(|wide| (wide.position, wide.keys)).collect();
+        assert_eq!(found, vec![(1, 3), (3, 3)]);
+        assert_eq!(widest[1].record["x.2"], "3");
+        assert!(!ColumnStatsSink::new(Vec::new()).report().to_json().contains("widest"));
+    }
+
+    #[test]
+    fn test_value_counts_add_up_across_resumed_runs() {
+        let options = FlattenOptions::default();
+        let documents = [
+            json!({"status": "ok", "http": {"code": 200}}),
+            json!({"status": "error", "http": {"code": 500}}),
+            json!({"status": "ok"}),
+            json!({"status": null, "http": {"code": 200}}),
+        ];
+        let mut first = ColumnStatsSink::new(Vec::new()).with_value_counts(["status", "http.code"]);
+        for value in &documents[..2] {
+            first.write_record(flatten_json(value, &options)).unwrap();
+        }
+        let saved: BTreeMap<String, ValueCounts> =
+            serde_json::from_str(&serde_json::to_string(&first.report().value_counts).unwrap()).unwrap();
+
+        let mut resumed = ColumnStatsSink::new(Vec::new())
+            .with_value_counts(["status", "http.code"])
+            .resume_value_counts(&saved);
+        for value in &documents[2..] {
+            resumed.write_record(flatten_json(value, &options)).unwrap();
+        }
+        let counts = resumed.report().value_counts;
+        let status: Vec<(&str, u64)> = counts["status"].values.iter().map(|(v, n)| (v.as_str(), *n)).collect();
+        assert_eq!((status, counts["status"].missing), (vec![("error", 1), ("ok", 2)], 1));
+        assert_eq!(counts["http.code"].values["200"], 2);
+        assert_eq!(counts["http.code"].missing, 1);
+        assert!(!ColumnStatsSink::new(Vec::new()).report().to_json().contains("value_counts"));
+    }
+
+    #[test]
+    fn test_distinct_values_are_bounded_per_matching_column() {
+        let options = FlattenOptions {
+            separator: "/".to_string(),
+            ..Default::default()
+        };
+        let mut sink = ColumnStatsSink::new(Vec::new())
+            .with_distinct_values(["items.*.unit", "status"], 2)
+            .with_separator(&options.separator, options.escape_keys);
+        for (status, unit) in [("ok", "kg"), ("error", "g"), ("ok", "kg"), ("retry", "kg")] {
+            let document = json!({"status": status, "items": [{"unit": unit, "qty": 1}, {"unit": null}], "id": 1});
+            sink.write_record(flatten_json(&document, &options)).unwrap();
+        }
+        let distinct = sink.report().distinct_values;
+        assert_eq!(distinct.keys().collect::<Vec<_>>(), ["items/0/unit", "items/1/unit", "status"]);
+        assert_eq!(distinct["items/0/unit"].values.iter().collect::<Vec<_>>(), ["g", "kg"]);
+        assert!(!distinct["items/0/unit"].overflow);
+        assert!(distinct["items/1/unit"].values.is_empty());
+        assert_eq!(distinct["status"].values.iter().collect::<Vec<_>>(), ["error", "ok"]);
+        assert!(distinct["status"].overflow);
+    }
+
+    #[test]
+    fn test_index_keys_counts_records_per_key() {
+        let input = "{\"id\": 1, \"user\": {\"name\": \"a\"}}\n{\"id\": 2, \"usr\": {\"name\": null}}\n{\"id\": 3}\n";
+        let counts = index_keys(input.as_bytes(), &FlattenOptions::default()).unwrap();
+        let counts: Vec<(&str, u64)> = counts.iter().map(|(key, count)| (key.as_str(), *count)).collect();
+        assert_eq!(counts, [("id", 3), ("user.name", 1), ("usr.name", 1)]);
+    }
+}
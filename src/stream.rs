@@ -0,0 +1,556 @@
+// src/stream.rs
+//! File, reader and channel entry points
+use crate::duplicates::{deserialize_document, note_duplicates, DuplicateKeys};
+use crate::estimate::structure_metrics;
+use crate::flatten::{flatten_subtree, follow_chain, note_ambiguous_keys, source_key};
+use crate::input::{limit_document, open_input_checked, prepare_input, read_documents};
+use crate::par::*;
+use crate::progress::{Checkpoint, ChunkTracker, PipelineEnd};
+use crate::raw::RawJson;
+use crate::{
+    batch, flatten_document, flatten_input_document, try_flatten_json, BudgetedSink, CallbackSink, ChannelSink,
+    FlattenError, FlattenOptions, FlattenedJson, Outcome, OverBudget, PoolStats, PooledRecord, PooledSink, RecordSink,
+    SpillSink, SpilledRecords,
+};
+use serde_json::Value;
+use std::io::{BufReader, Read, Write};
+use std::path::Path;
+
+/// Flattens a JSON file in a streaming fashion
+/// This is optimized for memory usage with very large files
+/// A path of `"-"` reads from standard input
+/// With `memory_budget` set, fails with an `OutOfMemory` error once the
+/// estimated size of the results exceeds the budget
+pub fn flatten_json_file(
+    filepath: impl AsRef<Path>,
+    options: &FlattenOptions,
+) -> Result<Vec<FlattenedJson>, Box<dyn std::error::Error>> {
+    flatten_reader(open_input_checked(filepath, options)?, options)
+}
+
+/// Flattens every document of an already open file, or any other reader,
+/// like [`flatten_json_file`]
+pub fn flatten_reader<R: Read + Send>(
+    reader: R,
+    options: &FlattenOptions,
+) -> Result<Vec<FlattenedJson>, Box<dyn std::error::Error>> {
+    Ok(collect_records(reader, options)?.0)
+}
+
+/// Flattens a JSON file like [`flatten_json_file`] within `time_budget`,
+/// returning the records of the chunks finished in time and, when the budget
+/// ran out first, the [`Checkpoint`] to carry on from
+pub fn flatten_json_file_timed(
+    filepath: impl AsRef<Path>,
+    options: &FlattenOptions,
+) -> Result<(Vec<FlattenedJson>, Option<Checkpoint>), Box<dyn std::error::Error>> {
+    collect_records(open_input_checked(filepath, options)?, options)
+}
+
+fn collect_records<R: Read + Send>(
+    reader: R,
+    options: &FlattenOptions,
+) -> Result<(Vec<FlattenedJson>, Option<Checkpoint>), Box<dyn std::error::Error>> {
+    let mut sink = BudgetedSink::for_options(options);
+    let end = flatten_pipelined_to_sink(reader, &mut sink, options)?;
+    Ok((sink.into_records(), end.checkpoint))
+}
+
+/// Flattens a JSON file, spilling results to a temp file past `memory_budget`
+/// Without a budget every record stays in memory, as with `flatten_json_file`;
+/// with [`OverBudget::Error`] it fails past the budget instead of spilling
+pub fn flatten_json_file_spilled(
+    filepath: impl AsRef<Path>,
+    options: &FlattenOptions,
+) -> Result<SpilledRecords, Box<dyn std::error::Error>> {
+    if options.over_budget == OverBudget::Error {
+        let mut sink = BudgetedSink::for_options(options);
+        flatten_json_file_to_sink(filepath, &mut sink, options)?;
+        return Ok(SpilledRecords::Memory(sink.into_records().into_iter()));
+    }
+    let budget = match options.memory_budget {
+        0 => usize::MAX,
+        budget => budget,
+    };
+    let mut sink = SpillSink::new(budget);
+    flatten_json_file_to_sink(filepath, &mut sink, options)?;
+    Ok(sink.into_records()?)
+}
+
+/// Flattens a JSON file into records whose repeated keys and values share
+/// storage, returned with the pool's stats
+pub fn flatten_json_file_pooled(
+    filepath: impl AsRef<Path>,
+    options: &FlattenOptions,
+) -> Result<(Vec<PooledRecord>, PoolStats), Box<dyn std::error::Error>> {
+    let mut sink = PooledSink::new();
+    flatten_json_file_to_sink(filepath, &mut sink, options)?;
+    Ok(sink.into_records())
+}
+
+/// Flattens a JSON file and writes the records to a sink in input order
+/// Only one chunk of parsed values is held in memory at a time
+/// A path of `"-"` reads from standard input
+pub fn flatten_json_file_to_sink<S: RecordSink>(
+    filepath: impl AsRef<Path>,
+    sink: &mut S,
+    options: &FlattenOptions,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    Ok(flatten_pipelined_to_sink(open_input_checked(filepath, options)?, sink, options)?.written)
+}
+
+/// Reads, flattens and writes on separate threads with the `parallel` feature
+pub(crate) fn flatten_pipelined_to_sink<R: Read + Send, S: RecordSink>(
+    reader: R,
+    sink: &mut S,
+    options: &FlattenOptions,
+) -> Result<PipelineEnd, Box<dyn std::error::Error>> {
+    #[cfg(feature = "parallel")]
+    {
+        let pools = batch::StagePools::new(options)?;
+        batch::flatten_pipelined(reader, sink, options, &pools)
+    }
+    #[cfg(not(feature = "parallel"))]
+    {
+        reader_to_sink(reader, sink, options)
+    }
+}
+
+/// Flattens every document in a string: a top-level array, concatenated
+/// documents or NDJSON text, detected the same way as for files
+///
+/// The elements of a top-level array are always separate records, whatever
+/// `split_root_array` says.
+pub fn flatten_json_multi_str(
+    json_str: &str,
+    options: &FlattenOptions,
+) -> Result<Vec<FlattenedJson>, Box<dyn std::error::Error>> {
+    let options = FlattenOptions {
+        split_root_array: true,
+        ..options.clone()
+    };
+    let mut results = Vec::new();
+    flatten_reader_to_sink(json_str.as_bytes(), &mut results, &options)?;
+    Ok(results)
+}
+
+/// Flattens already parsed documents in parallel, preserving their order
+///
+/// Runs on the same flatten pool as the file functions, so callers holding
+/// `Value`s (from a web framework, say) don't have to serialize them again.
+/// Sequence record ids count positions in `values`.
+///
+/// Never panics with the default policies, and like
+/// [`flatten_json`](crate::flatten_json) ignores the chunking and concurrency
+/// checks of [`FlattenOptions::validate`].
+///
+/// # Panics
+///
+/// Panics for any document [`crate::flatten_json`] would panic on; see there
+/// for the list.
+pub fn flatten_values(values: &[Value], options: &FlattenOptions) -> Vec<FlattenedJson> {
+    try_flatten_values(values, options).unwrap_or_else(|e| panic!("{}", e))
+}
+
+/// Flattens already parsed documents in parallel, returning an error instead
+/// of panicking when a limit with an error policy is exceeded
+///
+/// Records `filters` reject, and documents a `Skip` policy sets aside, are left out.
+pub fn try_flatten_values(
+    values: &[Value],
+    options: &FlattenOptions,
+) -> Result<Vec<FlattenedJson>, Box<dyn std::error::Error>> {
+    options.validate_document()?;
+    let pools = batch::StagePools::new(options)?;
+    let records = pools.flatten(|| {
+        values
+            .par_iter()
+            .enumerate()
+            .map(|(i, value)| match flatten_input_document(value, options, i as u64)?.filter(options) {
+                Outcome::Record(record) => Ok::<_, FlattenError>(Some(record)),
+                Outcome::Filtered | Outcome::Skipped(_) => Ok(None),
+            })
+            .collect::<Result<Vec<_>, _>>()
+    })?;
+    Ok(records.into_iter().flatten().collect())
+}
+
+/// Flattens JSON documents from any reader and writes the records to a sink
+/// Accepts a single document, a top-level array, concatenated documents or NDJSON
+pub fn flatten_reader_to_sink<'a, R: Read + 'a, S: RecordSink>(
+    reader: R,
+    sink: &mut S,
+    options: &FlattenOptions,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    Ok(flatten_reader_to_sink_timed(reader, sink, options)?.0)
+}
+
+/// Flattens a reader into a sink like [`flatten_reader_to_sink`] within
+/// `time_budget`, also returning the [`Checkpoint`] to carry on from when the
+/// budget ran out first
+pub fn flatten_reader_to_sink_timed<'a, R: Read + 'a, S: RecordSink>(
+    reader: R,
+    sink: &mut S,
+    options: &FlattenOptions,
+) -> Result<(usize, Option<Checkpoint>), Box<dyn std::error::Error>> {
+    let end = reader_to_sink(reader, sink, options)?;
+    Ok((end.written, end.checkpoint))
+}
+
+fn reader_to_sink<'a, R: Read + 'a, S: RecordSink>(
+    reader: R,
+    sink: &mut S,
+    options: &FlattenOptions,
+) -> Result<PipelineEnd, Box<dyn std::error::Error>> {
+    options.validate()?;
+    let mut checkpoint = None;
+    let mut chunks = ChunkTracker::new(options);
+    let mut batches = batch::chunk_reader(reader, options);
+    let mut filtered = 0;
+    while let Some(batch) = batches.next() {
+        let batch = batch?;
+        let records = batch.len();
+        for record in batch {
+            sink.write_record(record)?;
+        }
+        let skipped = batches.take_skipped();
+        let chunk_skipped = skipped.len();
+        for document in skipped {
+            sink.skip_document(document)?;
+        }
+        let chunk_filtered = batches.filtered() - filtered;
+        filtered = batches.filtered();
+        checkpoint = chunks.chunk_done(records, chunk_filtered as usize, chunk_skipped);
+        if checkpoint.is_some() {
+            break;
+        }
+    }
+
+    sink.flush()?;
+    Ok(chunks.finish(checkpoint))
+}
+
+/// Flattens a JSON file into a channel, returning the number of records sent
+///
+/// Use a bounded channel to get backpressure: parsing pauses while the channel
+/// is full, so at most one chunk plus the channel capacity is held in memory.
+/// Returns an error if the receiver is dropped before the input is exhausted.
+pub fn flatten_to_channel(
+    filepath: impl AsRef<Path>,
+    tx: crossbeam_channel::Sender<FlattenedJson>,
+    options: &FlattenOptions,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let mut sink = ChannelSink::new(tx);
+    flatten_json_file_to_sink(filepath, &mut sink, options)
+}
+
+/// Rebuilds nested documents from a file of flattened records, like
+/// [`unflatten_reader`]; a path of `"-"` reads from standard input
+pub fn unflatten_file<W: Write>(
+    filepath: impl AsRef<Path>,
+    output: W,
+    options: &FlattenOptions,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    unflatten_reader(open_input_checked(filepath, options)?, output, options)
+}
+
+/// Rebuilds the nested document of every flattened record in a reader with
+/// [`unflatten`](crate::unflatten), writing one JSON document per line in
+/// input order and returning the number written
+///
+/// Records are read like documents, usually one JSON object per line as
+/// written by the NDJSON sink. Values that are not strings, as left by an
+/// editor that retyped them, are read as their JSON text. Each chunk of
+/// `chunk_size` records is rebuilt in parallel on the flatten pool.
+pub fn unflatten_reader<R: Read, W: Write>(
+    reader: R,
+    mut output: W,
+    options: &FlattenOptions,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    options.validate()?;
+    let pools = batch::StagePools::new(options)?;
+    let mut documents = read_documents(reader, options).peekable();
+    let mut written = 0;
+    while documents.peek().is_some() {
+        let chunk = documents.by_ref().take(options.chunk_size.max(1)).collect::<Result<Vec<_>, _>>()?;
+        let rebuilt = pools.flatten(|| {
+            chunk
+                .par_iter()
+                .map(|text| Ok(crate::unflatten(&flat_record(text)?, options).to_string()))
+                .collect::<Result<Vec<String>, serde_json::Error>>()
+        })?;
+        for document in &rebuilt {
+            writeln!(output, "{}", document)?;
+        }
+        written += rebuilt.len();
+    }
+    output.flush()?;
+    Ok(written)
+}
+
+/// Reads a flattened record, keeping the JSON text of values that are not strings
+fn flat_record(text: &str) -> Result<FlattenedJson, serde_json::Error> {
+    let object: serde_json::Map<String, Value> = serde_json::from_str(text)?;
+    Ok(object
+        .into_iter()
+        .map(|(key, value)| match value {
+            Value::String(value) => (key, value),
+            value => (key, value.to_string()),
+        })
+        .collect())
+}
+
+/// Processes a single large JSON object by iterating through its top-level keys
+/// This is useful for very large objects that might not fit in memory
+// Process a large JSON object by iterating through its top-level keys
+pub fn process_large_json_object(
+    filepath: impl AsRef<Path>,
+    options: &FlattenOptions,
+) -> Result<FlattenedJson, Box<dyn std::error::Error>> {
+    options.validate()?;
+    let input = prepare_input(open_input_checked(filepath, options)?, options);
+    let mut reader = BufReader::new(limit_document(input, options));
+    if options.raw_values {
+        let mut text = String::new();
+        reader.read_to_string(&mut text)?;
+        return Ok(flatten_document(&RawJson::parse(&text)?, options, 0)?);
+    }
+    
+    // Parse the outer structure of the JSON to get top-level keys
+    let (json, duplicates) = match options.duplicate_keys {
+        DuplicateKeys::Last => (serde_json::from_reader(reader)?, Vec::new()),
+        policy => {
+            let mut deserializer = serde_json::Deserializer::from_reader(reader);
+            let parsed = deserialize_document(&mut deserializer, policy)?;
+            deserializer.end()?;
+            parsed
+        }
+    };
+    let record_id = options.record_id.record_id(&json, 0)?;
+    let metrics = options.structure_metrics.then(|| structure_metrics(&json));
+    let mut ambiguous = FlattenedJson::default();
+    note_ambiguous_keys(&json, options, &mut ambiguous);
+    
+    if let Value::Object(map) = json {
+        // Convert map entries to a Vec which can be processed in parallel
+        let entries: Vec<_> = map.into_iter().collect();
+        
+        // Flatten each top-level key in parallel on the flatten pool, then merge the partial results
+        let pools = batch::StagePools::new(options)?;
+        let partials = pools.flatten(|| {
+            entries
+                .par_iter()
+                .map(|(key, value)| {
+                    let mut partial_result = FlattenedJson::default();
+                    let (key, value, depth) = follow_chain(key, value, options, 0);
+                    flatten_subtree(&source_key(key, "", options)?, value, &mut partial_result, options, depth)?;
+                    Ok(partial_result)
+                })
+                .collect::<Result<Vec<FlattenedJson>, FlattenError>>()
+        })?;
+        
+        let mut result = FlattenedJson::default();
+        for partial_result in partials {
+            result.extend(partial_result);
+        }
+        if let Some(id) = record_id {
+            result.insert(options.record_id_key.clone(), id);
+        }
+        if let Some(metrics) = metrics {
+            metrics.insert_into(&mut result);
+        }
+        result.extend(ambiguous);
+        note_duplicates(&mut result, &duplicates);
+        
+        Ok(result)
+    } else {
+        // If the top-level is not an object, just flatten it directly
+        let mut result = try_flatten_json(&json, options)?;
+        note_duplicates(&mut result, &duplicates);
+        Ok(result)
+    }
+}
+
+/// A more memory efficient version for extremely large files
+/// Each record goes to the callback, in input order on the calling thread,
+/// instead of being collected; set `ndjson` to have NDJSON lines split,
+/// parsed and flattened on the worker threads
+/// A path of `"-"` reads from standard input
+/// Stops early when `time_budget` runs out; use
+/// [`flatten_json_streaming_resumable`] to learn where to carry on from
+pub fn flatten_json_streaming(
+    filepath: impl AsRef<Path>,
+    callback: impl Fn(FlattenedJson) + Send + Sync,
+    options: &FlattenOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    flatten_json_streaming_resumable(filepath, callback, options)?;
+    Ok(())
+}
+
+/// Streams a JSON file into a callback like [`flatten_json_streaming`],
+/// returning the [`Checkpoint`] to carry on from when `time_budget` runs out
+/// first; `skip_documents` skips the documents already done
+pub fn flatten_json_streaming_resumable(
+    filepath: impl AsRef<Path>,
+    callback: impl Fn(FlattenedJson) + Send + Sync,
+    options: &FlattenOptions,
+) -> Result<Option<Checkpoint>, Box<dyn std::error::Error>> {
+    let reader = open_input_checked(filepath, options)?;
+    Ok(flatten_pipelined_to_sink(reader, &mut CallbackSink(callback), options)?.checkpoint)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flatten_reader_to_sink_ndjson() {
+        let input = "{\"a\": {\"b\": 1}}\n{\"a\": {\"b\": 2}}\n";
+        let mut records: Vec<FlattenedJson> = Vec::new();
+        let written = flatten_reader_to_sink(input.as_bytes(), &mut records, &FlattenOptions::default()).unwrap();
+
+        assert_eq!(written, 2);
+        assert_eq!(records[0].get("a.b"), Some(&"1".to_string()));
+        assert_eq!(records[1].get("a.b"), Some(&"2".to_string()));
+    }
+
+    #[test]
+    fn test_files_open_from_paths_and_file_handles() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("input.ndjson");
+        std::fs::write(&path, "{\"a\": 1}\n{\"a\": 2}\n").unwrap();
+        let options = FlattenOptions::default();
+
+        assert_eq!(flatten_json_file(&path, &options).unwrap().len(), 2);
+        assert_eq!(flatten_json_file(path.as_path(), &options).unwrap().len(), 2);
+        let records = flatten_reader(std::fs::File::open(&path).unwrap(), &options).unwrap();
+        assert_eq!(records[1].get("a"), Some(&"2".to_string()));
+    }
+
+    #[test]
+    fn test_oversized_files_fail_before_they_are_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("input.json");
+        std::fs::write(&path, "{\"a\": [1, 2, 3, 4, 5, 6, 7, 8]}").unwrap();
+
+        let small_input = FlattenOptions {
+            max_input_bytes: 16,
+            ..Default::default()
+        };
+        let error = flatten_json_file(&path, &small_input).unwrap_err();
+        assert_eq!(error.to_string(), "input is 31 bytes, more than the limit of 16 (max_input_bytes)");
+
+        let small_document = FlattenOptions {
+            max_document_bytes: 16,
+            ..Default::default()
+        };
+        let error = process_large_json_object(&path, &small_document).unwrap_err();
+        assert!(error.to_string().contains("document 0 is larger than the limit of 16 bytes"));
+        assert!(process_large_json_object(&path, &FlattenOptions::default()).is_ok());
+    }
+
+    #[test]
+    fn test_streaming_follows_ndjson_and_resumes_from_a_checkpoint() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("input.json");
+        std::fs::write(&path, "{\"a\": 1} {\"a\": 2}\n{\"a\": 3}\n").unwrap();
+        let seen = std::sync::Mutex::new(Vec::new());
+        let collect = |record: FlattenedJson| seen.lock().unwrap().push(record["a"].clone());
+
+        // Concatenated documents on one line are only read when `ndjson` is off
+        flatten_json_streaming(&path, collect, &FlattenOptions::default()).unwrap();
+        assert_eq!(*seen.lock().unwrap(), ["1", "2", "3"]);
+        let ndjson = FlattenOptions {
+            ndjson: true,
+            ..Default::default()
+        };
+        assert!(flatten_json_streaming(&path, collect, &ndjson).is_err());
+
+        seen.lock().unwrap().clear();
+        let mut options = FlattenOptions {
+            chunk_size: 2,
+            time_budget: Some(std::time::Duration::ZERO),
+            ..Default::default()
+        };
+        let checkpoint = flatten_json_streaming_resumable(&path, collect, &options).unwrap();
+        assert_eq!(checkpoint, Some(Checkpoint { documents: 2 }));
+        options.skip_documents = checkpoint.unwrap().documents;
+        options.time_budget = None;
+        assert_eq!(flatten_json_streaming_resumable(&path, collect, &options).unwrap(), None);
+        assert_eq!(*seen.lock().unwrap(), ["1", "2", "3"]);
+    }
+
+    #[test]
+    fn test_flatten_json_multi_str_detects_layout() {
+        let options = FlattenOptions::default();
+        let from_array = flatten_json_multi_str(r#"[{"a": 1}, {"a": 2}]"#, &options).unwrap();
+        let from_ndjson = flatten_json_multi_str("{\"a\": 1}\n{\"a\": 2}\n", &options).unwrap();
+        let from_concatenated = flatten_json_multi_str(r#"{"a": 1} {"a": 2}"#, &options).unwrap();
+
+        for records in [from_array, from_ndjson, from_concatenated] {
+            assert_eq!(records.len(), 2);
+            assert_eq!(records[1].get("a"), Some(&"2".to_string()));
+        }
+
+        // The reader keeps a root array as one record unless asked to split it
+        let array = r#"[{"a": 1}, {"a": 2}]"#;
+        let records = flatten_reader(array.as_bytes(), &options).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].len(), 2);
+    }
+
+    #[test]
+    fn test_flatten_values_preserves_order_and_sequence() {
+        let values: Vec<Value> = (0..100).map(|i| serde_json::json!({"n": {"i": i}})).collect();
+        let options = FlattenOptions {
+            record_id: crate::RecordIdSource::Sequence,
+            ..Default::default()
+        };
+        let records = flatten_values(&values, &options);
+
+        assert_eq!(records.len(), 100);
+        for (i, record) in records.iter().enumerate() {
+            assert_eq!(record["n.i"], i.to_string());
+            assert_eq!(record["_id"], i.to_string());
+        }
+        assert!(flatten_values(&[], &options).is_empty());
+    }
+
+    #[test]
+    fn test_try_flatten_values_reports_limit_errors() {
+        let values = vec![serde_json::json!({"a": [1]}), serde_json::json!({"a": [1, 2, 3]})];
+        let options = FlattenOptions {
+            max_array_elements: Some(2),
+            array_limit_policy: crate::ArrayLimitPolicy::Error,
+            ..Default::default()
+        };
+        assert!(try_flatten_values(&values, &options).is_err());
+    }
+
+    #[test]
+    fn test_unflatten_reader_round_trips_ndjson_records() {
+        let input = "{\"id\": 1, \"user\": {\"tags\": [\"a\", \"b\"]}}\n{\"id\": 2, \"user\": {\"name\": \"x\"}}\n";
+        let options = FlattenOptions {
+            chunk_size: 1,
+            ..Default::default()
+        };
+        let mut flat = Vec::new();
+        flatten_reader_to_sink(input.as_bytes(), &mut crate::NdjsonSink::new(&mut flat), &options).unwrap();
+
+        let mut rebuilt = Vec::new();
+        assert_eq!(unflatten_reader(flat.as_slice(), &mut rebuilt, &options).unwrap(), 2);
+        let documents: Vec<Value> = String::from_utf8(rebuilt)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let expected: Vec<Value> = input.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(documents, expected);
+
+        // Values an editor retyped are read as their JSON text
+        let mut edited = Vec::new();
+        unflatten_reader(&b"{\"a.b\": 5, \"a.c\": null}"[..], &mut edited, &options).unwrap();
+        assert_eq!(String::from_utf8(edited).unwrap(), "{\"a\":{\"b\":5,\"c\":null}}\n");
+        assert!(unflatten_reader(&b"[1]\n"[..], &mut Vec::new(), &options).is_err());
+    }
+}
@@ -0,0 +1,234 @@
+// src/testing.rs
+//! Fixtures and golden-file assertions for testing integrations
+use crate::value::JsonKind;
+use crate::{flatten_json_file, to_sorted_vec, FlattenOptions, FlattenedJson};
+use serde_json::{Map, Value};
+use std::fmt::Write as _;
+use std::path::Path;
+
+/// Environment variable that makes [`assert_golden`] rewrite golden files
+/// instead of comparing against them
+pub const UPDATE_GOLDEN_VAR: &str = "UPDATE_GOLDEN";
+
+/// Shape of the documents made by [`generate_fixture`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FixtureConfig {
+    /// Nesting levels below the root object
+    pub depth: usize,
+    /// Keys per object
+    pub width: usize,
+    /// Elements per array; 0 leaves arrays out
+    pub array_len: usize,
+    /// Kinds of scalar leaves to draw from: `Null`, `Bool`, `Number` and
+    /// `String`; container kinds are ignored
+    pub scalars: Vec<JsonKind>,
+    /// Add keys holding `.`, spaces and non-ASCII text, and strings with escapes
+    pub awkward_text: bool,
+    /// Same seed, same documents
+    pub seed: u64,
+}
+
+impl Default for FixtureConfig {
+    fn default() -> Self {
+        FixtureConfig {
+            depth: 3,
+            width: 4,
+            array_len: 3,
+            scalars: vec![JsonKind::Null, JsonKind::Bool, JsonKind::Number, JsonKind::String],
+            awkward_text: false,
+            seed: 0,
+        }
+    }
+}
+
+/// SplitMix64, so fixtures are identical on every platform and release
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+const WORDS: [&str; 8] = ["alpha", "beta", "gamma", "delta", "epsilon", "zeta", "eta", "theta"];
+const AWKWARD_KEYS: [&str; 3] = ["dotted.key", "with space", "clé"];
+const AWKWARD_STRINGS: [&str; 3] = ["quote \" and \\ backslash", "line\nbreak", "ünïcödé ✓"];
+
+/// Generates one document: an object of `width` keys, nesting objects and
+/// arrays down to `depth` levels
+pub fn generate_fixture(config: &FixtureConfig) -> Value {
+    generate_object(config, &mut Rng(config.seed), config.depth)
+}
+
+/// Generates `count` different documents, document `i` using seed `seed + i`
+pub fn generate_fixtures(config: &FixtureConfig, count: usize) -> Vec<Value> {
+    (0..count as u64)
+        .map(|i| {
+            generate_fixture(&FixtureConfig {
+                seed: config.seed.wrapping_add(i),
+                ..config.clone()
+            })
+        })
+        .collect()
+}
+
+fn generate_object(config: &FixtureConfig, rng: &mut Rng, depth: usize) -> Value {
+    let mut map = Map::new();
+    for i in 0..config.width {
+        let key = match config.awkward_text && i < AWKWARD_KEYS.len() && rng.below(2) == 0 {
+            true => AWKWARD_KEYS[i].to_string(),
+            false => format!("{}_{}", WORDS[i % WORDS.len()], i),
+        };
+        map.insert(key, generate_value(config, rng, depth));
+    }
+    Value::Object(map)
+}
+
+fn generate_value(config: &FixtureConfig, rng: &mut Rng, depth: usize) -> Value {
+    if depth > 0 {
+        match rng.below(4) {
+            0 | 1 => return generate_object(config, rng, depth - 1),
+            2 if config.array_len > 0 => {
+                let items = (0..config.array_len).map(|_| generate_value(config, rng, depth - 1));
+                return Value::Array(items.collect());
+            }
+            _ => {}
+        }
+    }
+    generate_scalar(config, rng)
+}
+
+fn generate_scalar(config: &FixtureConfig, rng: &mut Rng) -> Value {
+    let kinds: Vec<JsonKind> = config
+        .scalars
+        .iter()
+        .copied()
+        .filter(|kind| !matches!(kind, JsonKind::Array | JsonKind::Object))
+        .collect();
+    if kinds.is_empty() {
+        return Value::Null;
+    }
+    match kinds[rng.below(kinds.len())] {
+        JsonKind::Bool => Value::Bool(rng.below(2) == 0),
+        JsonKind::Number if rng.below(2) == 0 => Value::from(rng.below(2001) as i64 - 1000),
+        JsonKind::Number => Value::from(rng.below(100_000) as f64 / 100.0),
+        JsonKind::String if config.awkward_text && rng.below(3) == 0 => {
+            Value::from(AWKWARD_STRINGS[rng.below(AWKWARD_STRINGS.len())])
+        }
+        JsonKind::String => Value::from(WORDS[rng.below(WORDS.len())]),
+        _ => Value::Null,
+    }
+}
+
+/// Golden-file text for records: one JSON object per line, keys sorted
+pub fn golden_text(records: &[FlattenedJson]) -> String {
+    let mut text = String::new();
+    for record in records {
+        let pairs: Map<String, Value> = to_sorted_vec(record)
+            .into_iter()
+            .map(|(key, value)| (key, Value::String(value)))
+            .collect();
+        let _ = writeln!(text, "{}", Value::Object(pairs));
+    }
+    text
+}
+
+/// Asserts that records match a golden file, comparing keys in sorted order
+///
+/// A missing golden file is written instead, as is every golden file when
+/// [`UPDATE_GOLDEN_VAR`] is set, so new and changed outputs can be reviewed
+/// in version control. On a mismatch the first differing line is reported.
+pub fn assert_golden(golden: impl AsRef<Path>, records: &[FlattenedJson]) {
+    let golden = golden.as_ref();
+    let actual = golden_text(records);
+    if std::env::var_os(UPDATE_GOLDEN_VAR).is_some() || !golden.exists() {
+        if let Some(dir) = golden.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir).unwrap_or_else(|e| panic!("cannot create {}: {}", dir.display(), e));
+        }
+        std::fs::write(golden, &actual).unwrap_or_else(|e| panic!("cannot write {}: {}", golden.display(), e));
+        return;
+    }
+
+    let expected =
+        std::fs::read_to_string(golden).unwrap_or_else(|e| panic!("cannot read {}: {}", golden.display(), e));
+    if expected == actual {
+        return;
+    }
+    let (expected_lines, actual_lines): (Vec<_>, Vec<_>) = (expected.lines().collect(), actual.lines().collect());
+    let line = (0..expected_lines.len().max(actual_lines.len()))
+        .find(|&i| expected_lines.get(i) != actual_lines.get(i))
+        .unwrap_or(0);
+    panic!(
+        "records differ from {} at line {}\n  expected: {}\n    actual: {}\n(set {}=1 to update)",
+        golden.display(),
+        line + 1,
+        expected_lines.get(line).unwrap_or(&"<end of file>"),
+        actual_lines.get(line).unwrap_or(&"<end of file>"),
+        UPDATE_GOLDEN_VAR
+    );
+}
+
+/// Flattens an input file and asserts the records match a golden file, see
+/// [`assert_golden`]
+pub fn assert_golden_file(input: impl AsRef<Path>, golden: impl AsRef<Path>, options: &FlattenOptions) {
+    let input = input.as_ref();
+    let records =
+        flatten_json_file(input, options).unwrap_or_else(|e| panic!("cannot flatten {}: {}", input.display(), e));
+    assert_golden(golden, &records);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{estimate_flattened_size, flatten_json};
+
+    #[test]
+    fn test_fixtures_are_deterministic_and_bounded() {
+        let config = FixtureConfig {
+            awkward_text: true,
+            seed: 7,
+            ..Default::default()
+        };
+        let fixtures = generate_fixtures(&config, 5);
+        assert_eq!(fixtures, generate_fixtures(&config, 5));
+        assert_ne!(fixtures[0], fixtures[1]);
+        for fixture in &fixtures {
+            assert_eq!(fixture.as_object().unwrap().len(), 4);
+            assert!(estimate_flattened_size(fixture).max_depth <= config.depth + 1);
+        }
+
+        let numbers_only = FixtureConfig {
+            depth: 0,
+            scalars: vec![JsonKind::Number],
+            ..Default::default()
+        };
+        assert!(generate_fixture(&numbers_only).as_object().unwrap().values().all(Value::is_number));
+    }
+
+    #[test]
+    fn test_golden_file_is_written_then_compared() {
+        let dir = tempfile::tempdir().unwrap();
+        let golden = dir.path().join("nested/out.ndjson");
+        let options = FlattenOptions::default();
+        let records: Vec<_> = generate_fixtures(&FixtureConfig::default(), 3)
+            .iter()
+            .map(|fixture| flatten_json(fixture, &options))
+            .collect();
+
+        assert_golden(&golden, &records);
+        assert_eq!(std::fs::read_to_string(&golden).unwrap(), golden_text(&records));
+        assert_golden(&golden, &records);
+
+        let changed = std::panic::catch_unwind(|| assert_golden(&golden, &records[1..]));
+        let message = *changed.unwrap_err().downcast::<String>().unwrap();
+        assert!(message.contains("at line 1"), "{}", message);
+    }
+}
@@ -0,0 +1,181 @@
+// src/throttle.rs
+//! Limiting how fast records reach a downstream system
+use crate::{FlattenedJson, RecordSink, SkippedDocument};
+use std::io;
+use std::time::{Duration, Instant};
+
+/// Most records and bytes per second a [`RateLimiter`] lets through
+///
+/// `None`, zero or a negative rate leaves that measure unlimited. After an
+/// idle period, up to `burst` worth of either rate goes through at once.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateLimit {
+    pub records_per_second: Option<f64>,
+    pub bytes_per_second: Option<f64>,
+    pub burst: Duration,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        RateLimit {
+            records_per_second: None,
+            bytes_per_second: None,
+            burst: Duration::from_secs(1),
+        }
+    }
+}
+
+impl RateLimit {
+    /// Whether any rate is limited
+    pub fn is_limited(&self) -> bool {
+        [self.records_per_second, self.bytes_per_second].iter().any(|rate| positive(*rate).is_some())
+    }
+}
+
+/// Paces work to a [`RateLimit`]
+///
+/// Use it directly where records leave the process, e.g. in the thread
+/// draining a [`ChannelSink`](crate::ChannelSink)'s channel into HTTP
+/// requests; while it waits the channel fills up and parsing pauses, so the
+/// whole pipeline slows to the limit.
+#[derive(Clone, Debug)]
+pub struct RateLimiter {
+    limit: RateLimit,
+    /// When the records and bytes booked so far are paid for
+    records_paid: Option<Instant>,
+    bytes_paid: Option<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: RateLimit) -> Self {
+        RateLimiter {
+            limit,
+            records_paid: None,
+            bytes_paid: None,
+        }
+    }
+
+    /// Books `records` records holding `bytes` bytes sent at `now`, returning
+    /// how long to wait before sending them
+    pub fn reserve(&mut self, now: Instant, records: u64, bytes: u64) -> Duration {
+        let burst = self.limit.burst;
+        let records = book(&mut self.records_paid, now, records, self.limit.records_per_second, burst);
+        let bytes = book(&mut self.bytes_paid, now, bytes, self.limit.bytes_per_second, burst);
+        records.max(bytes)
+    }
+
+    /// Blocks until `records` records holding `bytes` bytes may be sent
+    pub fn acquire(&mut self, records: u64, bytes: u64) {
+        let wait = self.reserve(Instant::now(), records, bytes);
+        if !wait.is_zero() {
+            std::thread::sleep(wait);
+        }
+    }
+}
+
+/// Adds the time `amount` takes at `rate` to `paid`, returning how far it now
+/// runs ahead of `now` beyond the burst
+fn book(paid: &mut Option<Instant>, now: Instant, amount: u64, rate: Option<f64>, burst: Duration) -> Duration {
+    let Some(rate) = positive(rate) else {
+        return Duration::ZERO;
+    };
+    let start = paid.map_or(now, |paid| paid.max(now));
+    let until = start + Duration::from_secs_f64(amount as f64 / rate);
+    *paid = Some(until);
+    until.saturating_duration_since(now + burst)
+}
+
+fn positive(rate: Option<f64>) -> Option<f64> {
+    rate.filter(|rate| *rate > 0.0 && rate.is_finite())
+}
+
+/// Bytes a record sends downstream: its key and value bytes
+pub fn record_payload_bytes(record: &FlattenedJson) -> usize {
+    record.iter().map(|(key, value)| key.len() + value.len()).sum()
+}
+
+/// Sink that holds records back to a [`RateLimit`] before passing them on
+///
+/// `write_record` blocks until the record may go, which stalls the pipeline
+/// the same way a full bounded channel does, so downstream systems (HTTP
+/// endpoints, Kafka, databases) see at most the configured rate. Bytes are
+/// counted with [`record_payload_bytes`].
+pub struct ThrottledSink<S: RecordSink> {
+    inner: S,
+    limiter: RateLimiter,
+}
+
+impl<S: RecordSink> ThrottledSink<S> {
+    pub fn new(inner: S, limit: RateLimit) -> Self {
+        ThrottledSink {
+            inner,
+            limiter: RateLimiter::new(limit),
+        }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: RecordSink> RecordSink for ThrottledSink<S> {
+    fn write_record(&mut self, record: FlattenedJson) -> io::Result<()> {
+        self.limiter.acquire(1, record_payload_bytes(&record) as u64);
+        self.inner.write_record(record)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    fn skip_document(&mut self, skipped: SkippedDocument) -> io::Result<()> {
+        self.inner.skip_document(skipped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limiter_allows_a_burst_then_paces() {
+        let mut limiter = RateLimiter::new(RateLimit {
+            records_per_second: Some(10.0),
+            bytes_per_second: Some(100.0),
+            burst: Duration::from_millis(200),
+        });
+        let start = Instant::now();
+        assert_eq!(limiter.reserve(start, 1, 10), Duration::ZERO);
+        assert_eq!(limiter.reserve(start, 1, 10), Duration::ZERO);
+        assert_eq!(limiter.reserve(start, 1, 10), Duration::from_millis(100));
+        // Bytes are the tighter limit for large records
+        assert_eq!(limiter.reserve(start, 1, 50), Duration::from_millis(600));
+
+        // Time spent idle is not saved up beyond the burst
+        let later = start + Duration::from_secs(60);
+        assert_eq!(limiter.reserve(later, 2, 20), Duration::ZERO);
+        assert_eq!(limiter.reserve(later, 1, 10), Duration::from_millis(100));
+
+        let mut unlimited = RateLimiter::new(RateLimit::default());
+        assert_eq!(unlimited.reserve(start, 1_000_000, u64::MAX), Duration::ZERO);
+        assert!(!RateLimit::default().is_limited());
+    }
+
+    #[test]
+    fn test_throttled_sink_passes_records_on_at_the_limit() {
+        let limit = RateLimit {
+            records_per_second: Some(500.0),
+            burst: Duration::ZERO,
+            ..Default::default()
+        };
+        let mut sink = ThrottledSink::new(Vec::new(), limit);
+        let started = Instant::now();
+        for i in 0..10 {
+            let record: FlattenedJson = [("id".to_string(), i.to_string())].into_iter().collect();
+            sink.write_record(record).unwrap();
+        }
+        sink.flush().unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(20));
+        assert_eq!(sink.into_inner().len(), 10);
+    }
+}
@@ -0,0 +1,242 @@
+// src/unflatten.rs
+//! Rebuilding documents from flattened records
+use alloc::format;
+use alloc::string::String;
+use core::fmt;
+#[cfg(feature = "std")]
+use crate::keys::split_source_key;
+#[cfg(feature = "std")]
+use crate::{FlattenOptions, FlattenedJson};
+#[cfg(feature = "std")]
+use alloc::collections::BTreeMap;
+#[cfg(feature = "std")]
+use alloc::vec;
+#[cfg(feature = "std")]
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use serde_json::{Map, Value};
+
+/// Whether [`unflatten`] turns objects whose keys are all indices (`"0"`,
+/// `"1"`, ...) back into arrays
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum IndexKeys {
+    /// Rebuild them as arrays, reversing how arrays are flattened
+    #[default]
+    Arrays,
+    /// Keep them as objects, for producers whose maps are keyed by numbers
+    Objects,
+}
+
+impl core::str::FromStr for IndexKeys {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "arrays" => Ok(IndexKeys::Arrays),
+            "objects" => Ok(IndexKeys::Objects),
+            other => Err(format!("unknown index key policy {:?} (expected arrays or objects)", other)),
+        }
+    }
+}
+
+impl fmt::Display for IndexKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            IndexKeys::Arrays => "arrays",
+            IndexKeys::Objects => "objects",
+        })
+    }
+}
+
+/// What [`unflatten`] does with index keys that skip numbers, e.g. only
+/// `tags.0` and `tags.3` left after nulls were dropped
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SparseIndices {
+    /// Rebuild an array, filling the missing elements with nulls, unless that
+    /// takes more than [`MAX_FILLED_NULLS`] nulls and more nulls than elements,
+    /// which keeps an object as with `Object`
+    #[default]
+    Null,
+    /// Keep an object keyed by the indices that are present
+    Object,
+}
+
+impl core::str::FromStr for SparseIndices {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "null" => Ok(SparseIndices::Null),
+            "object" => Ok(SparseIndices::Object),
+            other => Err(format!("unknown sparse index policy {:?} (expected null or object)", other)),
+        }
+    }
+}
+
+impl fmt::Display for SparseIndices {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SparseIndices::Null => "null",
+            SparseIndices::Object => "object",
+        })
+    }
+}
+
+/// Most nulls [`SparseIndices::Null`] fills into an array with fewer elements,
+/// so an index like `a.100000000000` can't allocate without bound
+pub const MAX_FILLED_NULLS: usize = 1 << 16;
+
+/// A document being rebuilt: a value, or an object not yet turned into a `Value`
+#[cfg(feature = "std")]
+enum Node {
+    Leaf(Value),
+    Branch(BTreeMap<String, Node>),
+}
+
+/// Rebuilds the document a record was flattened from
+///
+/// Keys are split with the options' separator, `escape_keys` and
+/// `empty_keys`, and objects keyed by indices become arrays according to
+/// `index_keys` and `sparse_indices`. Values that parse as JSON are read as
+/// JSON, the rest as strings, so the string `"1"` comes back as the number 1.
+/// A key that is also the prefix of other keys, like `a` next to `a.b`, is
+/// dropped in favour of the nested keys.
+#[cfg(feature = "std")]
+pub fn unflatten(record: &FlattenedJson, options: &FlattenOptions) -> Value {
+    let mut entries: Vec<(&String, &String)> = record.iter().collect();
+    entries.sort_unstable();
+    let mut root = BTreeMap::new();
+    for (key, value) in entries {
+        let segments = split_source_key(key, &options.separator, options.escape_keys, options.empty_keys);
+        let Some((last, parents)) = segments.split_last() else {
+            continue;
+        };
+        let mut branch = &mut root;
+        for segment in parents {
+            let node = branch.entry(segment.clone()).or_insert_with(|| Node::Branch(BTreeMap::new()));
+            if let Node::Leaf(_) = node {
+                *node = Node::Branch(BTreeMap::new());
+            }
+            let Node::Branch(children) = node else {
+                unreachable!("leaves were just replaced")
+            };
+            branch = children;
+        }
+        if !matches!(branch.get(last), Some(Node::Branch(_))) {
+            let value = serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.clone()));
+            branch.insert(last.clone(), Node::Leaf(value));
+        }
+    }
+    build(root, options)
+}
+
+#[cfg(feature = "std")]
+fn build(children: BTreeMap<String, Node>, options: &FlattenOptions) -> Value {
+    let indices: Option<Vec<usize>> = match options.index_keys {
+        IndexKeys::Arrays if !children.is_empty() => children.keys().map(|key| index(key)).collect(),
+        _ => None,
+    };
+    let Some(indices) = indices else {
+        let map: Map<String, Value> = children.into_iter().map(|(key, node)| (key, value(node, options))).collect();
+        return Value::Object(map);
+    };
+
+    // Indices are distinct, so `len` is at least the number of children
+    let len = indices.iter().max().map_or(Some(0), |max| max.checked_add(1));
+    let too_sparse = |len: usize| {
+        let filled = len - children.len();
+        match options.sparse_indices {
+            SparseIndices::Null => filled > MAX_FILLED_NULLS.max(children.len()),
+            SparseIndices::Object => filled > 0,
+        }
+    };
+    let Some(len) = len.filter(|&len| !too_sparse(len)) else {
+        let map: Map<String, Value> = children.into_iter().map(|(key, node)| (key, value(node, options))).collect();
+        return Value::Object(map);
+    };
+    let mut items = vec![Value::Null; len];
+    for (i, node) in indices.into_iter().zip(children.into_values()) {
+        items[i] = value(node, options);
+    }
+    Value::Array(items)
+}
+
+#[cfg(feature = "std")]
+fn value(node: Node, options: &FlattenOptions) -> Value {
+    match node {
+        Node::Leaf(value) => value,
+        Node::Branch(children) => build(children, options),
+    }
+}
+
+/// An array index as written by the flattener: digits without leading zeros
+#[cfg(feature = "std")]
+fn index(key: &str) -> Option<usize> {
+    let digits = !key.is_empty() && key.bytes().all(|b| b.is_ascii_digit());
+    match key {
+        "0" => Some(0),
+        _ if digits && !key.starts_with('0') => key.parse().ok(),
+        _ => None,
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::flatten_json;
+    use alloc::string::ToString;
+    use serde_json::json;
+
+    #[test]
+    fn test_unflatten_reverses_flatten() {
+        let options = FlattenOptions::default();
+        let document = json!({"id": 7, "user": {"name": "a", "tags": ["x", {"k": null}]}, "ok": true, "empty": "text"});
+        assert_eq!(unflatten(&flatten_json(&document, &options), &options), document);
+
+        let escaped = FlattenOptions {
+            escape_keys: true,
+            ..Default::default()
+        };
+        let document = json!({"a.b": {"c": [1, 2]}});
+        assert_eq!(unflatten(&flatten_json(&document, &escaped), &escaped), document);
+    }
+
+    #[test]
+    fn test_index_key_policies() {
+        let record: FlattenedJson = [("tags.0", "x"), ("tags.3", "y"), ("codes.01", "a"), ("codes.1", "b")]
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+
+        let arrays = FlattenOptions::default();
+        let rebuilt = unflatten(&record, &arrays);
+        assert_eq!(rebuilt["tags"], json!(["x", null, null, "y"]));
+        assert_eq!(rebuilt["codes"], json!({"01": "a", "1": "b"}));
+
+        let sparse_objects = FlattenOptions {
+            sparse_indices: SparseIndices::Object,
+            ..Default::default()
+        };
+        assert_eq!(unflatten(&record, &sparse_objects)["tags"], json!({"0": "x", "3": "y"}));
+
+        let objects = FlattenOptions {
+            index_keys: IndexKeys::Objects,
+            ..Default::default()
+        };
+        let dense: FlattenedJson = [("m.0".to_string(), "1".to_string())].into_iter().collect();
+        assert_eq!(unflatten(&dense, &objects), json!({"m": {"0": 1}}));
+        assert_eq!(unflatten(&dense, &arrays), json!({"m": [1]}));
+        assert_eq!("objects".parse::<IndexKeys>(), Ok(IndexKeys::Objects));
+        assert!("sometimes".parse::<SparseIndices>().is_err());
+    }
+
+    #[test]
+    fn test_huge_indices_keep_an_object() {
+        let options = FlattenOptions::default();
+        for key in ["a.18446744073709551615", "a.100000000000"] {
+            let record: FlattenedJson = [(key.to_string(), "1".to_string())].into_iter().collect();
+            let index = key.strip_prefix("a.").unwrap();
+            assert_eq!(unflatten(&record, &options)["a"][index], json!(1));
+        }
+    }
+}
@@ -0,0 +1,287 @@
+// src/validate.rs
+use crate::keys::{split_key, KeyPattern};
+use crate::schema::is_null;
+use crate::{FlattenedJson, RecordSink, SkippedDocument};
+use regex::Regex;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io;
+
+/// Key added to records sent to a dead-letter sink, holding a JSON array of
+/// their violations
+pub const VIOLATIONS_KEY: &str = "_violations";
+
+/// A condition every non-null value of a key must meet
+#[derive(Clone, Debug)]
+pub enum Check {
+    /// The regex matches the value; see [`ValueRule::regex`] to match whole values
+    Matches(Regex),
+    /// The value is a number within the bounds, both inclusive
+    Range { min: Option<f64>, max: Option<f64> },
+    /// The value is one of a fixed set
+    OneOf(Vec<String>),
+}
+
+impl Check {
+    fn accepts(&self, value: &str) -> bool {
+        match self {
+            Check::Matches(regex) => regex.is_match(value),
+            Check::Range { min, max } => value
+                .parse::<f64>()
+                .is_ok_and(|n| min.is_none_or(|min| n >= min) && max.is_none_or(|max| n <= max)),
+            Check::OneOf(values) => values.iter().any(|allowed| allowed == value),
+        }
+    }
+}
+
+/// A check applied to the keys matching a pattern
+///
+/// Parsed from `<pattern>=regex:<regex>`, `<pattern>=range:<min>..<max>`
+/// (either bound may be left out) or `<pattern>=enum:<a>,<b>,...`, e.g.
+/// `user.email=regex:[^@]+@[^@]+` or `items.*.qty=range:1..`.
+#[derive(Clone, Debug)]
+pub struct ValueRule {
+    pub pattern: KeyPattern,
+    pub check: Check,
+    source: String,
+}
+
+impl ValueRule {
+    pub fn new(pattern: &str, check: Check) -> Self {
+        let source = match &check {
+            Check::Matches(regex) => format!("{}=regex:{}", pattern, regex.as_str()),
+            Check::Range { min, max } => {
+                let bound = |bound: &Option<f64>| bound.map(|n| n.to_string()).unwrap_or_default();
+                format!("{}=range:{}..{}", pattern, bound(min), bound(max))
+            }
+            Check::OneOf(values) => format!("{}=enum:{}", pattern, values.join(",")),
+        };
+        ValueRule {
+            pattern: KeyPattern::new(pattern),
+            check,
+            source,
+        }
+    }
+
+    /// Builds a check that the regex matches the whole value
+    pub fn regex(pattern: &str, regex: &str) -> Result<Self, regex::Error> {
+        let check = Check::Matches(Regex::new(&format!("^(?:{})$", regex))?);
+        Ok(ValueRule {
+            source: format!("{}=regex:{}", pattern, regex),
+            ..Self::new(pattern, check)
+        })
+    }
+
+    /// The rule as it was written
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+}
+
+impl core::str::FromStr for ValueRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (pattern, check) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected <pattern>=<check>, got {:?}", s))?;
+        let (kind, argument) = check.split_once(':').unwrap_or((check, ""));
+        match kind {
+            "regex" => Self::regex(pattern, argument).map_err(|e| format!("invalid regex in {:?}: {}", s, e)),
+            "range" => {
+                let (min, max) = argument
+                    .split_once("..")
+                    .ok_or_else(|| format!("expected range:<min>..<max> in {:?}", s))?;
+                let bound = |bound: &str| match bound {
+                    "" => Ok(None),
+                    _ => bound.parse().map(Some).map_err(|_| format!("invalid range bound {:?} in {:?}", bound, s)),
+                };
+                Ok(Self::new(pattern, Check::Range { min: bound(min)?, max: bound(max)? }))
+            }
+            "enum" => Ok(Self::new(pattern, Check::OneOf(argument.split(',').map(str::to_string).collect()))),
+            _ => Err(format!("unknown check {:?} in {:?} (expected regex, range or enum)", kind, s)),
+        }
+    }
+}
+
+impl fmt::Display for ValueRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+/// Sink that checks values against [`ValueRule`]s as records pass through
+///
+/// Nulls and missing keys pass every rule. Violations are counted per rule;
+/// records with any are passed on like the others, or sent to a dead-letter
+/// sink instead when one is set, with their violations under
+/// [`VIOLATIONS_KEY`].
+pub struct ValidatingSink<S: RecordSink> {
+    inner: S,
+    rules: Vec<ValueRule>,
+    separator: String,
+    escaped: bool,
+    dead_letter: Option<Box<dyn RecordSink>>,
+    /// Indices of the rules that apply to each key seen so far
+    matching: HashMap<String, Vec<usize>>,
+    violations: Vec<u64>,
+    invalid_records: u64,
+}
+
+impl<S: RecordSink> ValidatingSink<S> {
+    pub fn new(inner: S, rules: Vec<ValueRule>) -> Self {
+        let violations = vec![0; rules.len()];
+        ValidatingSink {
+            inner,
+            rules,
+            separator: ".".to_string(),
+            escaped: false,
+            dead_letter: None,
+            matching: HashMap::new(),
+            violations,
+            invalid_records: 0,
+        }
+    }
+
+    /// Splits keys on the separator they were flattened with (see
+    /// `FlattenOptions::separator` and `escape_keys`); rule patterns keep
+    /// using `.` between segments, like array path modes
+    pub fn with_separator(mut self, separator: &str, escaped: bool) -> Self {
+        self.separator = separator.to_string();
+        self.escaped = escaped;
+        self.matching.clear();
+        self
+    }
+
+    /// Sends records with violations here instead of to the inner sink
+    pub fn with_dead_letter(mut self, sink: Box<dyn RecordSink>) -> Self {
+        self.dead_letter = Some(sink);
+        self
+    }
+
+    /// Violations of each rule so far, keyed by the rule as written
+    pub fn violations(&self) -> BTreeMap<&str, u64> {
+        self.rules
+            .iter()
+            .zip(&self.violations)
+            .map(|(rule, count)| (rule.as_str(), *count))
+            .collect()
+    }
+
+    /// Records with at least one violation so far
+    pub fn invalid_records(&self) -> u64 {
+        self.invalid_records
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    /// Messages for the values of a record that break a rule
+    fn check(&mut self, record: &FlattenedJson) -> Vec<String> {
+        let mut messages = Vec::new();
+        for (key, value) in record {
+            if is_null(Some(value)) {
+                continue;
+            }
+            if !self.matching.contains_key(key) {
+                let segments = split_key(key, &self.separator, self.escaped);
+                let rules = (0..self.rules.len())
+                    .filter(|&i| self.rules[i].pattern.matches(&segments))
+                    .collect();
+                self.matching.insert(key.clone(), rules);
+            }
+            for &i in &self.matching[key] {
+                if !self.rules[i].check.accepts(value) {
+                    self.violations[i] += 1;
+                    messages.push(format!("{}: {:?} fails {}", key, value, self.rules[i]));
+                }
+            }
+        }
+        messages.sort_unstable();
+        messages
+    }
+}
+
+impl<S: RecordSink> RecordSink for ValidatingSink<S> {
+    fn write_record(&mut self, mut record: FlattenedJson) -> io::Result<()> {
+        let messages = self.check(&record);
+        if messages.is_empty() {
+            return self.inner.write_record(record);
+        }
+        self.invalid_records += 1;
+        match &mut self.dead_letter {
+            Some(dead_letter) => {
+                let messages = serde_json::to_string(&messages).expect("strings always serialize");
+                record.insert(VIOLATIONS_KEY.to_string(), messages);
+                dead_letter.write_record(record)
+            }
+            None => self.inner.write_record(record),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if let Some(dead_letter) = &mut self.dead_letter {
+            dead_letter.flush()?;
+        }
+        self.inner.flush()
+    }
+
+    fn skip_document(&mut self, skipped: SkippedDocument) -> io::Result<()> {
+        self.inner.skip_document(skipped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ChannelSink;
+
+    fn record(pairs: &[(&str, &str)]) -> FlattenedJson {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn test_rules_parse_and_check_whole_values() {
+        let email: ValueRule = "user.email=regex:[^@]+@[^@]+".parse().unwrap();
+        assert!(email.check.accepts("a@b"));
+        assert!(!email.check.accepts("a@b@c"));
+        assert_eq!(email.to_string(), "user.email=regex:[^@]+@[^@]+");
+
+        let qty: ValueRule = "items.*.qty=range:1..".parse().unwrap();
+        assert!(qty.check.accepts("1") && qty.check.accepts("2.5"));
+        assert!(!qty.check.accepts("0") && !qty.check.accepts("x"));
+        assert_eq!(qty.to_string(), "items.*.qty=range:1..");
+
+        let status: ValueRule = "status=enum:open,closed".parse().unwrap();
+        assert!(status.check.accepts("open") && !status.check.accepts("Open"));
+        assert!("x=regex:(".parse::<ValueRule>().is_err());
+        assert!("x=between:1".parse::<ValueRule>().is_err());
+    }
+
+    #[test]
+    fn test_invalid_records_go_to_dead_letter_sink() {
+        let rules = vec![
+            "**.qty=range:1..10".parse().unwrap(),
+            "status=enum:open,closed".parse().unwrap(),
+        ];
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let mut sink = ValidatingSink::new(Vec::new(), rules).with_dead_letter(Box::new(ChannelSink::new(tx)));
+        sink.write_record(record(&[("status", "open"), ("items.0.qty", "3")])).unwrap();
+        sink.write_record(record(&[("status", "lost"), ("items.0.qty", "0"), ("items.1.qty", "null")]))
+            .unwrap();
+        sink.flush().unwrap();
+
+        assert_eq!(sink.invalid_records(), 1);
+        assert_eq!(
+            sink.violations().into_iter().collect::<Vec<_>>(),
+            vec![("**.qty=range:1..10", 1), ("status=enum:open,closed", 1)]
+        );
+        assert_eq!(sink.into_inner().len(), 1);
+        let dead = rx.try_recv().unwrap();
+        assert_eq!(
+            dead[VIOLATIONS_KEY],
+            r#"["items.0.qty: \"0\" fails **.qty=range:1..10","status: \"lost\" fails status=enum:open,closed"]"#
+        );
+    }
+}
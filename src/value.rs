@@ -0,0 +1,305 @@
+// src/value.rs
+use alloc::string::String;
+use serde_json::{Map, Number, Value};
+
+/// Type of a JSON node
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JsonKind {
+    Null,
+    Bool,
+    Number,
+    String,
+    Array,
+    Object,
+}
+
+/// The kind with its article, e.g. "a string" or "null"
+impl core::fmt::Display for JsonKind {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            JsonKind::Null => "null",
+            JsonKind::Bool => "a boolean",
+            JsonKind::Number => "a number",
+            JsonKind::String => "a string",
+            JsonKind::Array => "an array",
+            JsonKind::Object => "an object",
+        })
+    }
+}
+
+/// Read access to a parsed JSON document
+///
+/// The flattener only tells node kinds apart, reads scalars and walks arrays
+/// and objects, so any document representation that implements this trait can
+/// be flattened with [`flatten_into`](crate::flatten_into) without first being
+/// converted to a `serde_json::Value`. The `simd-json` feature implements it
+/// for simd_json's `OwnedValue` and `BorrowedValue`, so documents parsed by
+/// simd_json are flattened in place.
+pub trait JsonLike {
+    /// Type of this node
+    fn kind(&self) -> JsonKind;
+
+    /// The value of a boolean node
+    fn as_bool(&self) -> Option<bool>;
+
+    /// The value of a number node
+    ///
+    /// Numbers are exchanged as `serde_json::Number`, which fixes how they are
+    /// written to flattened records regardless of the representation.
+    fn as_number(&self) -> Option<Number>;
+
+    /// The value of a string node
+    fn as_str(&self) -> Option<&str>;
+
+    /// The elements of an array node, in order
+    fn array_iter(&self) -> Option<impl ExactSizeIterator<Item = &Self>>;
+
+    /// The entries of an object node, in the representation's own order
+    fn object_iter(&self) -> Option<impl Iterator<Item = (&str, &Self)>>;
+
+    /// The value under `key` of an object node
+    fn get(&self, key: &str) -> Option<&Self> {
+        self.object_iter()?.find(|(k, _)| *k == key).map(|(_, value)| value)
+    }
+
+    /// The node's text exactly as in the source document, for representations
+    /// that keep it; used for leaves when `FlattenOptions::raw_values` is set
+    fn source_text(&self) -> Option<&str> {
+        None
+    }
+
+    /// Copies the node into a `serde_json::Value`
+    fn to_value(&self) -> Value {
+        match self.kind() {
+            JsonKind::Null => Value::Null,
+            JsonKind::Bool => Value::Bool(self.as_bool().unwrap_or_default()),
+            JsonKind::Number => self.as_number().map_or(Value::Null, Value::Number),
+            JsonKind::String => Value::String(self.as_str().unwrap_or_default().into()),
+            JsonKind::Array => Value::Array(self.array_iter().into_iter().flatten().map(Self::to_value).collect()),
+            JsonKind::Object => Value::Object(
+                self.object_iter()
+                    .into_iter()
+                    .flatten()
+                    .map(|(key, value)| (String::from(key), value.to_value()))
+                    .collect::<Map<_, _>>(),
+            ),
+        }
+    }
+}
+
+impl JsonLike for Value {
+    fn kind(&self) -> JsonKind {
+        match self {
+            Value::Null => JsonKind::Null,
+            Value::Bool(_) => JsonKind::Bool,
+            Value::Number(_) => JsonKind::Number,
+            Value::String(_) => JsonKind::String,
+            Value::Array(_) => JsonKind::Array,
+            Value::Object(_) => JsonKind::Object,
+        }
+    }
+
+    fn as_bool(&self) -> Option<bool> {
+        Value::as_bool(self)
+    }
+
+    fn as_number(&self) -> Option<Number> {
+        Value::as_number(self).cloned()
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        Value::as_str(self)
+    }
+
+    fn array_iter(&self) -> Option<impl ExactSizeIterator<Item = &Self>> {
+        self.as_array().map(|array| array.iter())
+    }
+
+    fn object_iter(&self) -> Option<impl Iterator<Item = (&str, &Self)>> {
+        self.as_object().map(|map| map.iter().map(|(key, value)| (key.as_str(), value)))
+    }
+
+    fn get(&self, key: &str) -> Option<&Self> {
+        self.as_object()?.get(key)
+    }
+
+    fn to_value(&self) -> Value {
+        self.clone()
+    }
+}
+
+/// Implements [`JsonLike`] for simd_json's owned and borrowed values, which
+/// differ only in how strings and object keys are held
+#[cfg(feature = "simd-json")]
+macro_rules! simd_json_like {
+    ($value:ty) => {
+        impl JsonLike for $value {
+            fn kind(&self) -> JsonKind {
+                match self {
+                    Self::Static(simd_json::StaticNode::Null) => JsonKind::Null,
+                    Self::Static(simd_json::StaticNode::Bool(_)) => JsonKind::Bool,
+                    Self::Static(_) => JsonKind::Number,
+                    Self::String(_) => JsonKind::String,
+                    Self::Array(_) => JsonKind::Array,
+                    Self::Object(_) => JsonKind::Object,
+                }
+            }
+
+            fn as_bool(&self) -> Option<bool> {
+                match self {
+                    Self::Static(simd_json::StaticNode::Bool(b)) => Some(*b),
+                    _ => None,
+                }
+            }
+
+            fn as_number(&self) -> Option<Number> {
+                match self {
+                    Self::Static(simd_json::StaticNode::I64(n)) => Some((*n).into()),
+                    Self::Static(simd_json::StaticNode::U64(n)) => Some((*n).into()),
+                    Self::Static(simd_json::StaticNode::F64(n)) => Number::from_f64(*n),
+                    _ => None,
+                }
+            }
+
+            fn as_str(&self) -> Option<&str> {
+                match self {
+                    Self::String(s) => Some(s),
+                    _ => None,
+                }
+            }
+
+            fn array_iter(&self) -> Option<impl ExactSizeIterator<Item = &Self>> {
+                match self {
+                    Self::Array(array) => Some(array.iter()),
+                    _ => None,
+                }
+            }
+
+            fn object_iter(&self) -> Option<impl Iterator<Item = (&str, &Self)>> {
+                match self {
+                    Self::Object(map) => Some(map.iter().map(|(key, value)| (&**key, value))),
+                    _ => None,
+                }
+            }
+
+            fn get(&self, key: &str) -> Option<&Self> {
+                match self {
+                    Self::Object(map) => map.get(key),
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+#[cfg(feature = "simd-json")]
+simd_json_like!(simd_json::OwnedValue);
+#[cfg(feature = "simd-json")]
+simd_json_like!(simd_json::BorrowedValue<'_>);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{flatten_into, FlattenOptions};
+    use alloc::collections::BTreeMap;
+    use alloc::vec::Vec;
+    use serde_json::json;
+
+    /// A minimal document tree standing in for another JSON library
+    enum Node {
+        Int(i64),
+        Text(&'static str),
+        List(Vec<Node>),
+        Fields(Vec<(&'static str, Node)>),
+    }
+
+    impl JsonLike for Node {
+        fn kind(&self) -> JsonKind {
+            match self {
+                Node::Int(_) => JsonKind::Number,
+                Node::Text(_) => JsonKind::String,
+                Node::List(_) => JsonKind::Array,
+                Node::Fields(_) => JsonKind::Object,
+            }
+        }
+
+        fn as_bool(&self) -> Option<bool> {
+            None
+        }
+
+        fn as_number(&self) -> Option<Number> {
+            match self {
+                Node::Int(n) => Some((*n).into()),
+                _ => None,
+            }
+        }
+
+        fn as_str(&self) -> Option<&str> {
+            match self {
+                Node::Text(s) => Some(s),
+                _ => None,
+            }
+        }
+
+        fn array_iter(&self) -> Option<impl ExactSizeIterator<Item = &Self>> {
+            match self {
+                Node::List(items) => Some(items.iter()),
+                _ => None,
+            }
+        }
+
+        fn object_iter(&self) -> Option<impl Iterator<Item = (&str, &Self)>> {
+            match self {
+                Node::Fields(fields) => Some(fields.iter().map(|(key, value)| (*key, value))),
+                _ => None,
+            }
+        }
+    }
+
+    #[test]
+    fn test_custom_representation_flattens_like_value() {
+        let node = Node::Fields(vec![
+            ("name", Node::Text("a\"b")),
+            ("tags", Node::List(vec![Node::Int(1), Node::Fields(vec![("x", Node::Int(2))])])),
+        ]);
+        let value = json!({"name": "a\"b", "tags": [1, {"x": 2}]});
+        assert_eq!(node.to_value(), value);
+
+        for options in [
+            FlattenOptions::default(),
+            FlattenOptions {
+                expand_arrays: false,
+                ..Default::default()
+            },
+        ] {
+            let mut from_node = BTreeMap::new();
+            flatten_into(&node, &options, &mut from_node).unwrap();
+            let mut from_value = BTreeMap::new();
+            flatten_into(&value, &options, &mut from_value).unwrap();
+            assert_eq!(from_node, from_value);
+        }
+    }
+
+    #[cfg(feature = "simd-json")]
+    #[test]
+    fn test_simd_json_values_flatten_like_value() {
+        let text = r#"{"id": 18446744073709551615, "ok": true, "none": null, "tags": ["a", {"x": -1.5}], "k": {}}"#;
+        let value: serde_json::Value = serde_json::from_str(text).unwrap();
+        let mut owned_bytes = text.as_bytes().to_vec();
+        let owned = simd_json::to_owned_value(&mut owned_bytes).unwrap();
+        let mut borrowed_bytes = text.as_bytes().to_vec();
+        let borrowed = simd_json::to_borrowed_value(&mut borrowed_bytes).unwrap();
+        assert_eq!(owned.to_value(), value);
+        assert_eq!(borrowed.to_value(), value);
+
+        let options = FlattenOptions::default();
+        let mut from_value = BTreeMap::new();
+        flatten_into(&value, &options, &mut from_value).unwrap();
+        let mut from_owned = BTreeMap::new();
+        flatten_into(&owned, &options, &mut from_owned).unwrap();
+        let mut from_borrowed = BTreeMap::new();
+        flatten_into(&borrowed, &options, &mut from_borrowed).unwrap();
+        assert_eq!(from_owned, from_value);
+        assert_eq!(from_borrowed, from_value);
+    }
+}
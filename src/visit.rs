@@ -0,0 +1,173 @@
+// src/visit.rs
+//! Walking a document the way the flattener does, for custom extractions
+use crate::error::FlattenError;
+use crate::flatten::{child_key, element_key, follow_chain, kept_elements, source_key};
+use crate::geo::{GeoJsonMode, Geometry};
+use crate::value::{JsonKind, JsonLike};
+use crate::{ArrayMode, FlattenOptions};
+use serde_json::Value;
+
+/// Callbacks for [`walk`]
+///
+/// `path` is the key the flattener would write for the node, so escaping,
+/// `separator`, `strip_prefix` and the other key options apply; the root
+/// object's path is empty. Returning false from `enter_object` or
+/// `enter_array` skips the node's children.
+pub trait JsonVisitor<J: JsonLike + ?Sized = Value> {
+    /// Called before the members of an object are visited
+    fn enter_object(&mut self, _path: &str, _object: &J) -> bool {
+        true
+    }
+
+    /// Called before the elements of an expanded array are visited, with the
+    /// array's full length
+    fn enter_array(&mut self, _path: &str, _len: usize) -> bool {
+        true
+    }
+
+    /// Called for each value the flattener writes under one key: scalars, and
+    /// subtrees it stores whole (beyond `max_depth`, arrays that are not
+    /// expanded, GeoJSON geometries)
+    ///
+    /// The value is the source node, not the flattened string.
+    fn leaf(&mut self, path: &str, value: &J);
+}
+
+/// Walks a document in the order the flattener visits it, calling `visitor`
+/// for each object, array and leaf
+///
+/// Keys, `max_depth`, `single_key_chains`, array modes and
+/// `max_array_elements` are handled as in [`flatten_into`](crate::flatten_into),
+/// and the same [`FlattenError`]s are returned. Values are not rendered, so
+/// options that only change how a leaf is written (`canonical`,
+/// `binary_values`, array aggregates) have no effect, and a scalar document
+/// has no leaves, as it has no key.
+pub fn walk<J: JsonLike + ?Sized, V: JsonVisitor<J> + ?Sized>(
+    value: &J,
+    visitor: &mut V,
+    options: &FlattenOptions,
+) -> Result<(), FlattenError> {
+    options.validate()?;
+    walk_value("", value, visitor, options, 0)
+}
+
+fn walk_value<J: JsonLike + ?Sized, V: JsonVisitor<J> + ?Sized>(
+    prefix: &str,
+    value: &J,
+    visitor: &mut V,
+    options: &FlattenOptions,
+    depth: usize,
+) -> Result<(), FlattenError> {
+    if options.max_depth > 0 && depth >= options.max_depth {
+        visitor.leaf(visible(prefix, options), value);
+        return Ok(());
+    }
+
+    let geometry = || options.geojson != GeoJsonMode::Off && !prefix.is_empty() && Geometry::from_json(value).is_some();
+    match value.kind() {
+        JsonKind::Object if geometry() => visitor.leaf(visible(prefix, options), value),
+        JsonKind::Object => {
+            if !visitor.enter_object(visible(prefix, options), value) {
+                return Ok(());
+            }
+            for (key, value) in value.object_iter().into_iter().flatten() {
+                let (key, value, depth) = follow_chain(key, value, options, depth + 1);
+                let key = source_key(key, prefix, options)?;
+                walk_value(&child_key(prefix, &key, options), value, visitor, options, depth)?;
+            }
+        }
+        JsonKind::Array => {
+            let len = value.array_iter().map_or(0, |items| items.len());
+            if options.array_mode(prefix) != ArrayMode::Expand {
+                visitor.leaf(visible(prefix, options), value);
+                return Ok(());
+            }
+            if !visitor.enter_array(visible(prefix, options), len) {
+                return Ok(());
+            }
+            let limit = options.max_array_elements.filter(|&limit| len > limit);
+            for (i, element) in kept_elements(prefix, value, limit, options)? {
+                walk_value(&element_key(prefix, i, options), element, visitor, options, depth + 1)?;
+            }
+        }
+        _ if prefix.is_empty() => {}
+        _ => visitor.leaf(visible(prefix, options), value),
+    }
+    Ok(())
+}
+
+/// A path as it appears in records, after `strip_prefix`
+fn visible<'a>(path: &'a str, options: &FlattenOptions) -> &'a str {
+    match options.strip_prefix.as_deref() {
+        Some(strip) if !strip.is_empty() && path.len() > strip.len() => path.strip_prefix(strip).unwrap_or(path),
+        _ => path,
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::flatten_json;
+    use alloc::format;
+    use alloc::string::{String, ToString};
+    use alloc::vec::Vec;
+    use serde_json::json;
+
+    #[derive(Default)]
+    struct Events(Vec<String>);
+
+    impl JsonVisitor for Events {
+        fn enter_object(&mut self, path: &str, _object: &Value) -> bool {
+            self.0.push(format!("object {}", path));
+            path != "skip"
+        }
+
+        fn enter_array(&mut self, path: &str, len: usize) -> bool {
+            self.0.push(format!("array {} {}", path, len));
+            true
+        }
+
+        fn leaf(&mut self, path: &str, value: &Value) {
+            self.0.push(format!("leaf {} {}", path, value));
+        }
+    }
+
+    #[test]
+    fn test_walk_visits_what_flatten_writes() {
+        let document = json!({"user": {"name": "a", "tags": ["x", {"k": null}]}, "skip": {"b": 1}, "a.b": 2});
+        let mut events = Events::default();
+        walk(&document, &mut events, &FlattenOptions::default()).unwrap();
+        assert_eq!(
+            events.0,
+            [
+                "object ",
+                "leaf a.b 2",
+                "object skip",
+                "object user",
+                "leaf user.name \"a\"",
+                "array user.tags 2",
+                "leaf user.tags.0 \"x\"",
+                "object user.tags.1",
+                "leaf user.tags.1.k null"
+            ]
+        );
+
+        // Leaf paths are the flattened keys, under the same key options and depth limit
+        let options = FlattenOptions {
+            escape_keys: true,
+            max_depth: 2,
+            ..Default::default()
+        };
+        let document = json!({"user": {"name": "a", "tags": ["x", {"k": null}]}, "a.b": 2});
+        let mut events = Events::default();
+        walk(&document, &mut events, &options).unwrap();
+        let mut leaves: Vec<String> =
+            events.0.iter().filter_map(|event| event.strip_prefix("leaf ")).map(|leaf| leaf.to_string()).collect();
+        leaves.sort_unstable();
+        let mut keys: Vec<String> = flatten_json(&document, &options).into_keys().collect();
+        keys.sort_unstable();
+        let leaf_keys: Vec<&str> = leaves.iter().map(|leaf| leaf.split(' ').next().unwrap()).collect();
+        assert_eq!(leaf_keys, keys);
+        assert!(leaves.contains(&"user.tags [\"x\",{\"k\":null}]".to_string()));
+    }
+}
@@ -0,0 +1,274 @@
+// src/watch.rs
+use crate::{flatten_json_file_to_sink, FlattenOptions, FlattenedJson, RecordSink, SkippedDocument};
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+/// Name of the file inside the watched directory that lists processed inputs
+pub const PROCESSED_LOG: &str = ".jflat_processed";
+
+/// Name of the file inside the watched directory that lists inputs that failed
+/// to flatten, one `name<TAB>error` line each
+pub const FAILED_LOG: &str = ".jflat_failed";
+
+/// How long a file must go without change events before it is flattened
+const SETTLE_TIME: Duration = Duration::from_millis(300);
+
+/// How often the stop flag and pending files are checked
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Watches a directory and flattens new JSON/NDJSON files into the sink as they arrive
+///
+/// Runs until the sink or the watcher fails. See [`watch_and_flatten_until`]
+/// for the delivery semantics.
+pub fn watch_and_flatten<S: RecordSink>(
+    dir: impl AsRef<Path>,
+    sink: &mut S,
+    options: &FlattenOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let stop = AtomicBool::new(false);
+    watch_and_flatten_until(dir, sink, options, &stop)
+}
+
+/// Watches a directory until `stop` is set
+///
+/// Files already in the directory are picked up on start. A file is only added
+/// to the processed log after its records were written and the sink flushed,
+/// so a crash in between re-flattens it on restart (at-least-once delivery).
+/// Files are tracked by name: rewriting an already processed file is ignored.
+///
+/// A file that fails to flatten, or that the watcher reports an error for, is
+/// added to the failed log instead and skipped from then on, restarts
+/// included, so one bad file can't stop the watcher; remove its line to retry
+/// it. Records it wrote before failing stay in the sink. Errors of the sink
+/// itself, or of the watcher as a whole, still end the watch.
+pub fn watch_and_flatten_until<S: RecordSink>(
+    dir: impl AsRef<Path>,
+    sink: &mut S,
+    options: &FlattenOptions,
+    stop: &AtomicBool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let dir = dir.as_ref();
+    let log_path = dir.join(PROCESSED_LOG);
+    let failed_path = dir.join(FAILED_LOG);
+    let mut processed = load_processed(&log_path)?;
+    processed.extend(load_failed(&failed_path)?);
+
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    watcher.watch(dir, RecursiveMode::NonRecursive)?;
+
+    // Files written before the watcher started are ready immediately
+    let mut pending: HashMap<PathBuf, Instant> = HashMap::new();
+    let started = Instant::now();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if is_json_input(&path) {
+            pending.insert(path, started - SETTLE_TIME);
+        }
+    }
+
+    while !stop.load(Ordering::Relaxed) {
+        match rx.recv_timeout(POLL_INTERVAL) {
+            Ok(Ok(event)) => {
+                if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+                    for path in event.paths {
+                        if is_json_input(&path) {
+                            pending.insert(path, Instant::now());
+                        }
+                    }
+                }
+            }
+            Ok(Err(e)) if !e.paths.is_empty() => {
+                for path in &e.paths {
+                    if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
+                        pending.remove(path);
+                        record_failure(&failed_path, name, &e)?;
+                        processed.insert(name.to_string());
+                    }
+                }
+            }
+            Ok(Err(e)) => return Err(Box::new(e)),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+
+        let mut ready: Vec<PathBuf> = pending
+            .iter()
+            .filter(|(_, last_event)| last_event.elapsed() >= SETTLE_TIME)
+            .map(|(path, _)| path.clone())
+            .collect();
+        ready.sort();
+
+        for path in ready {
+            pending.remove(&path);
+            let name = match path.file_name().and_then(|n| n.to_str()) {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+            if processed.contains(&name) || !path.is_file() {
+                continue;
+            }
+
+            let mut checked = CheckedSink { sink: &mut *sink, failed: false };
+            match flatten_json_file_to_sink(&path, &mut checked, options) {
+                Ok(_) => {
+                    let mut log = OpenOptions::new().create(true).append(true).open(&log_path)?;
+                    writeln!(log, "{}", name)?;
+                }
+                Err(e) if checked.failed => return Err(format!("{}: {}", path.display(), e).into()),
+                Err(e) => record_failure(&failed_path, &name, &e)?,
+            }
+            processed.insert(name);
+        }
+    }
+
+    Ok(())
+}
+
+/// Sink noting whether the wrapped sink failed, to tell its errors from the input's
+struct CheckedSink<'s, S> {
+    sink: &'s mut S,
+    failed: bool,
+}
+
+impl<S: RecordSink> CheckedSink<'_, S> {
+    fn check<T>(&mut self, result: io::Result<T>) -> io::Result<T> {
+        self.failed |= result.is_err();
+        result
+    }
+}
+
+impl<S: RecordSink> RecordSink for CheckedSink<'_, S> {
+    fn write_record(&mut self, record: FlattenedJson) -> io::Result<()> {
+        let result = self.sink.write_record(record);
+        self.check(result)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let result = self.sink.flush();
+        self.check(result)
+    }
+
+    fn skip_document(&mut self, skipped: SkippedDocument) -> io::Result<()> {
+        let result = self.sink.skip_document(skipped);
+        self.check(result)
+    }
+}
+
+/// Appends a file that failed, with its error on one line, to the failed log
+fn record_failure(failed_path: &Path, name: &str, error: &dyn std::fmt::Display) -> io::Result<()> {
+    let mut log = OpenOptions::new().create(true).append(true).open(failed_path)?;
+    let error = error.to_string().replace(['\n', '\r'], " ");
+    writeln!(log, "{}\t{}", name, error)
+}
+
+/// Returns true for files the watcher should flatten
+fn is_json_input(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|ext| ext.to_str()),
+        Some("json") | Some("ndjson") | Some("jsonl")
+    )
+}
+
+/// Reads the processed log, treating a missing log as empty
+fn load_processed(log_path: &Path) -> std::io::Result<HashSet<String>> {
+    match File::open(log_path) {
+        Ok(file) => BufReader::new(file)
+            .lines()
+            .filter(|line| !matches!(line, Ok(l) if l.is_empty()))
+            .collect(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(HashSet::new()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Reads the names in the failed log, treating a missing log as empty
+fn load_failed(failed_path: &Path) -> io::Result<HashSet<String>> {
+    let lines = load_processed(failed_path)?;
+    Ok(lines
+        .into_iter()
+        .map(|line| line.split('\t').next().unwrap_or_default().to_string())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_watch_flattens_existing_and_new_files_once() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.json"), r#"{"a": {"b": 1}}"#).unwrap();
+        fs::write(dir.path().join(PROCESSED_LOG), "old.json\n").unwrap();
+        fs::write(dir.path().join("old.json"), r#"{"old": true}"#).unwrap();
+        fs::write(dir.path().join("notes.txt"), "ignored").unwrap();
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let dir_path = dir.path().to_str().unwrap().to_string();
+        let watcher_stop = Arc::clone(&stop);
+        let handle = thread::spawn(move || {
+            let mut records: Vec<FlattenedJson> = Vec::new();
+            let options = FlattenOptions::default();
+            watch_and_flatten_until(&dir_path, &mut records, &options, &watcher_stop).unwrap();
+            records
+        });
+
+        thread::sleep(Duration::from_millis(200));
+        fs::write(dir.path().join("b.ndjson"), "{\"x\": 1}\n{\"x\": 2}\n").unwrap();
+
+        let log_path = dir.path().join(PROCESSED_LOG);
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while load_processed(&log_path).unwrap().len() < 3 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(50));
+        }
+        stop.store(true, Ordering::Relaxed);
+        let records = handle.join().unwrap();
+
+        assert_eq!(records.len(), 3);
+        assert!(records.iter().any(|r| r.get("a.b") == Some(&"1".to_string())));
+        assert!(records.iter().all(|r| !r.contains_key("old")));
+        let processed = load_processed(&log_path).unwrap();
+        assert!(processed.contains("a.json") && processed.contains("b.ndjson"));
+    }
+
+    #[test]
+    fn test_watch_logs_bad_files_and_keeps_going() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a_bad.json"), "{\"a\": ").unwrap();
+        fs::write(dir.path().join("b_good.json"), r#"{"b": 1}"#).unwrap();
+        fs::write(dir.path().join(FAILED_LOG), "old_bad.json\tEOF\n").unwrap();
+        fs::write(dir.path().join("old_bad.json"), "[").unwrap();
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let dir_path = dir.path().to_path_buf();
+        let watcher_stop = Arc::clone(&stop);
+        let handle = thread::spawn(move || {
+            let mut records: Vec<FlattenedJson> = Vec::new();
+            watch_and_flatten_until(&dir_path, &mut records, &FlattenOptions::default(), &watcher_stop).unwrap();
+            records
+        });
+
+        let log_path = dir.path().join(PROCESSED_LOG);
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while load_processed(&log_path).unwrap().is_empty() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(50));
+        }
+        stop.store(true, Ordering::Relaxed);
+        let records = handle.join().unwrap();
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0]["b"], "1");
+        let failed = fs::read_to_string(dir.path().join(FAILED_LOG)).unwrap();
+        assert!(failed.lines().any(|line| line.starts_with("a_bad.json\t")));
+        assert_eq!(failed.matches("old_bad.json").count(), 1);
+        assert_eq!(load_failed(&dir.path().join(FAILED_LOG)).unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,175 @@
+// src/xlsx.rs
+use crate::canonical::exact_in_f64;
+use crate::schema::{infer_schema, is_null, ColumnType, FlatSchema};
+use crate::{flatten_json_file_to_sink, FlattenOptions, FlattenedJson, RecordSink};
+use rust_xlsxwriter::{Workbook, Worksheet, XlsxError};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// Sink that writes records to an Excel workbook with one worksheet
+///
+/// Rows are buffered until the first flush, which fixes the columns: every
+/// column seen so far, sorted by name and typed by [`infer_schema`], or the
+/// schema given to [`with_schema`](XlsxSink::with_schema). The first row
+/// holds the column names. Boolean columns get boolean cells and numeric
+/// columns number cells, except for numbers an Excel number (an f64) would
+/// round, which are written as text like every other value; nulls and
+/// missing keys leave the cell empty and later keys are dropped. The
+/// workbook is built in memory and written by [`finish`](XlsxSink::finish);
+/// writing fails past Excel's 1,048,576 rows or 16,384 columns.
+pub struct XlsxSink<W: Write> {
+    writer: W,
+    sheet: Worksheet,
+    /// Schema to write instead of one inferred from the first rows
+    schema: Option<FlatSchema>,
+    columns: Option<Vec<(String, ColumnType)>>,
+    rows: Vec<FlattenedJson>,
+    next_row: u32,
+}
+
+impl<W: Write> XlsxSink<W> {
+    pub fn new(writer: W) -> Self {
+        XlsxSink {
+            writer,
+            sheet: Worksheet::new(),
+            schema: None,
+            columns: None,
+            rows: Vec::new(),
+            next_row: 0,
+        }
+    }
+
+    /// Takes the columns and their types from a schema saved by an earlier
+    /// run instead of inferring them
+    pub fn with_schema(mut self, schema: FlatSchema) -> Self {
+        self.schema = Some(schema);
+        self
+    }
+
+    /// Flushes buffered rows, writes the workbook and returns the underlying writer
+    pub fn finish(mut self) -> io::Result<W> {
+        RecordSink::flush(&mut self)?;
+        let mut workbook = Workbook::new();
+        workbook.push_worksheet(self.sheet);
+        self.writer.write_all(&workbook.save_to_buffer().map_err(io::Error::other)?)?;
+        Ok(self.writer)
+    }
+
+    fn write_rows(&mut self) -> Result<(), XlsxError> {
+        let columns = match &self.columns {
+            Some(columns) => columns,
+            None => {
+                let schema = self.schema.take().unwrap_or_else(|| infer_schema(&self.rows));
+                let columns: Vec<(String, ColumnType)> =
+                    schema.iter().map(|(name, column_type)| (name.clone(), *column_type)).collect();
+                for (col, (name, _)) in columns.iter().enumerate() {
+                    self.sheet.write_string(0, column_index(col)?, name)?;
+                }
+                self.sheet.set_freeze_panes(1, 0)?;
+                self.next_row = 1;
+                self.columns.insert(columns)
+            }
+        };
+
+        for row in std::mem::take(&mut self.rows) {
+            for (col, (name, column_type)) in columns.iter().enumerate() {
+                let value = row.get(name).map(String::as_str);
+                if let Some(value) = value.filter(|_| !is_null(value)) {
+                    write_cell(&mut self.sheet, self.next_row, column_index(col)?, value, *column_type)?;
+                }
+            }
+            self.next_row += 1;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> RecordSink for XlsxSink<W> {
+    fn write_record(&mut self, record: FlattenedJson) -> io::Result<()> {
+        self.rows.push(record);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_rows().map_err(io::Error::other)
+    }
+}
+
+/// A column's position as a worksheet column number
+fn column_index(col: usize) -> Result<u16, XlsxError> {
+    u16::try_from(col).map_err(|_| XlsxError::RowColumnLimitError)
+}
+
+/// Writes one value as a cell of its column's type, or as text when it
+/// doesn't fit that type or an f64 would round it
+fn write_cell(
+    sheet: &mut Worksheet,
+    row: u32,
+    col: u16,
+    value: &str,
+    column_type: ColumnType,
+) -> Result<(), XlsxError> {
+    match column_type {
+        ColumnType::Bool => match value.parse::<bool>() {
+            Ok(flag) => sheet.write_boolean(row, col, flag).map(drop),
+            Err(_) => sheet.write_string(row, col, value).map(drop),
+        },
+        ColumnType::Int | ColumnType::Float | ColumnType::Decimal if exact_in_f64(value) => {
+            let number: f64 = value.parse().expect("exact_in_f64 only accepts numbers");
+            sheet.write_number(row, col, number).map(drop)
+        }
+        _ => sheet.write_string(row, col, value).map(drop),
+    }
+}
+
+/// Flattens a JSON file into an Excel workbook, returning the number of
+/// records written
+///
+/// A path of `"-"` reads from standard input. The whole workbook is held in
+/// memory until it is written.
+pub fn flatten_to_xlsx(
+    input: impl AsRef<Path>,
+    output: impl AsRef<Path>,
+    options: &FlattenOptions,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let mut sink = XlsxSink::new(BufWriter::new(File::create(output)?));
+    let written = flatten_json_file_to_sink(input, &mut sink, options)?;
+    sink.finish()?.flush()?;
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Read};
+
+    fn part(workbook: &[u8], name: &str) -> String {
+        let mut archive = zip::ZipArchive::new(Cursor::new(workbook)).unwrap();
+        let mut xml = String::new();
+        archive.by_name(name).unwrap().read_to_string(&mut xml).unwrap();
+        xml
+    }
+
+    #[test]
+    fn test_xlsx_sink_writes_typed_cells() {
+        let options = FlattenOptions::default();
+        let mut sink = XlsxSink::new(Vec::new());
+        for value in [
+            serde_json::json!({"id": 1, "user": {"name": "Ann"}, "ok": true, "big": 9007199254740993u64}),
+            serde_json::json!({"id": 2, "user": {"name": null}, "ok": false, "big": 3}),
+        ] {
+            sink.write_record(crate::flatten_json(&value, &options)).unwrap();
+        }
+        let workbook = sink.finish().unwrap();
+
+        let sheet = part(&workbook, "xl/worksheets/sheet1.xml");
+        let strings = part(&workbook, "xl/sharedStrings.xml");
+        for header in ["big", "id", "ok", "user.name", "Ann", "9007199254740993"] {
+            assert!(strings.contains(&format!("<t>{}</t>", header)), "{} missing from {}", header, strings);
+        }
+        assert!(sheet.contains(r#"<c r="B2"><v>1</v></c>"#), "{}", sheet);
+        assert!(sheet.contains(r#"<c r="C3" t="b"><v>0</v></c>"#), "{}", sheet);
+        assert!(!sheet.contains(r#"r="D3""#), "{}", sheet);
+    }
+}
@@ -1,5 +1,5 @@
 // tests/test_flattener.rs
-use json_flattener::{FlattenOptions, flatten_json, flatten_json_file};
+use json_flattener::{FlattenOptions, OverBudget, flatten_json, flatten_json_file, flatten_json_file_spilled, flatten_to_channel};
 use serde_json::{Value};
 use std::fs::File;
 use std::io::BufReader;
@@ -57,3 +57,38 @@ fn test_flatten_file() {
     println!("File flattening produced {} result objects", flattened.len());
     println!("First result contains {} key-value pairs", flattened[0].len());
 }
+
+#[test]
+fn test_flatten_to_bounded_channel() {
+    let file_path = "tests/fixtures/small_sample.json";
+    let options = FlattenOptions::default();
+    let (tx, rx) = crossbeam_channel::bounded(1);
+
+    let producer = std::thread::spawn(move || flatten_to_channel(file_path, tx, &options).unwrap());
+    let received: Vec<_> = rx.iter().collect();
+
+    assert_eq!(producer.join().unwrap(), 1);
+    assert_eq!(received.len(), 1);
+    assert_eq!(received[0].get("metadata.version"), Some(&"1.0".to_string()));
+}
+
+#[test]
+fn test_memory_budget_fails_instead_of_growing() {
+    let file_path = "tests/fixtures/small_sample.json";
+    let mut options = FlattenOptions::default();
+    options.memory_budget = 64;
+
+    let err = flatten_json_file(file_path, &options).unwrap_err();
+    assert!(err.to_string().contains("memory budget"));
+
+    let spilled: Vec<_> = flatten_json_file_spilled(file_path, &options)
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(spilled.len(), 1);
+    assert_eq!(spilled[0].get("metadata.version"), Some(&"1.0".to_string()));
+
+    options.over_budget = OverBudget::Error;
+    let err = flatten_json_file_spilled(file_path, &options).err().unwrap();
+    assert!(err.to_string().contains("memory budget"));
+}
@@ -0,0 +1,214 @@
+// tests/test_jflat.rs
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::process::{Command, Output};
+
+/// Two documents that every input format below holds
+const DOCUMENTS: [&str; 2] = [
+    r#"{"id": 1, "user": {"name": "Ann"}, "tags": ["a", "b"]}"#,
+    r#"{"id": 2, "user": {"name": "Bo"}}"#,
+];
+
+fn jflat(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_jflat")).args(args).output().expect("failed to run jflat")
+}
+
+fn succeeds(args: &[&str]) -> Output {
+    let output = jflat(args);
+    assert!(output.status.success(), "jflat {:?} failed: {}", args, String::from_utf8_lossy(&output.stderr));
+    output
+}
+
+/// Writes [`DOCUMENTS`] into `dir` as every input format, returning the
+/// formats and paths
+fn write_inputs(dir: &Path) -> Vec<(&'static str, String)> {
+    let csv_cells: Vec<String> = DOCUMENTS.iter().map(|doc| format!("\"{}\"", doc.replace('"', "\"\""))).collect();
+    let inputs = [
+        ("json", "events.json", format!("[{}]", DOCUMENTS.join(", "))),
+        ("ndjson", "events.ndjson", DOCUMENTS.join("\n")),
+        ("yaml", "events.yaml", "id: 1\nuser:\n  name: Ann\ntags: [a, b]\n---\nid: 2\nuser:\n  name: Bo\n".to_string()),
+        ("csv", "events.csv", format!("json\n{}\n", csv_cells.join("\n"))),
+    ];
+    inputs
+        .into_iter()
+        .map(|(format, name, content)| {
+            let path = dir.join(name);
+            fs::write(&path, content).unwrap();
+            (format, path.to_string_lossy().into_owned())
+        })
+        .collect()
+}
+
+#[test]
+fn test_convert_matrix() {
+    let dir = tempfile::tempdir().unwrap();
+    let inputs = write_inputs(dir.path());
+    let ndjson = concat!(
+        r#"{"id":"1","tags.0":"a","tags.1":"b","user.name":"Ann"}"#,
+        "\n",
+        r#"{"id":"2","user.name":"Bo"}"#,
+        "\n",
+    );
+    let json_seq = format!("\u{1e}{}", ndjson.trim_end().replace('\n', "\n\u{1e}")) + "\n";
+    let expected_text = [
+        ("ndjson", ndjson.to_string()),
+        ("json-seq", json_seq),
+        ("csv", "id,tags.0,tags.1,user.name\n1,a,b,Ann\n2,,,Bo\n".to_string()),
+        ("eav", "record_id,key,value\n0,id,1\n0,tags.0,a\n0,tags.1,b\n0,user.name,Ann\n1,id,2\n1,user.name,Bo\n".into()),
+    ];
+    let binary = ["rowbinary", "ipc", "parquet", "xlsx"];
+
+    for (from, input) in &inputs {
+        for (to, expected) in &expected_text {
+            let output = succeeds(&["convert", "--sort-keys", "--from", from, "--to", to, input]);
+            assert_eq!(String::from_utf8_lossy(&output.stdout), *expected, "{} to {}", from, to);
+        }
+        // Every input format gives the same bytes as JSON input; workbooks
+        // also hold the time they were written
+        for to in binary {
+            let path = dir.path().join(format!("{}.{}", from, to));
+            succeeds(&["convert", "--from", from, "--to", to, input, "-o", &path.to_string_lossy()]);
+            let json = dir.path().join(format!("json.{}", to));
+            if *from != "json" && to != "xlsx" {
+                assert_eq!(fs::read(&path).unwrap(), fs::read(&json).unwrap(), "{} to {}", from, to);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_convert_writes_readable_parquet_and_xlsx() {
+    let dir = tempfile::tempdir().unwrap();
+    let inputs = write_inputs(dir.path());
+    let (_, ndjson) = &inputs[1];
+
+    let parquet = dir.path().join("events.parquet");
+    succeeds(&["convert", "--from", "ndjson", "--to", "parquet", ndjson, "-o", &parquet.to_string_lossy()]);
+    let reader = ParquetRecordBatchReaderBuilder::try_new(fs::File::open(&parquet).unwrap()).unwrap().build().unwrap();
+    let batches: Vec<_> = reader.collect::<Result<_, _>>().unwrap();
+    let schema = batches[0].schema();
+    let names: Vec<&str> = schema.fields().iter().map(|field| field.name().as_str()).collect();
+    assert_eq!(names, ["id", "tags.0", "tags.1", "user.name"]);
+    assert_eq!(batches.iter().map(|batch| batch.num_rows()).sum::<usize>(), 2);
+
+    let xlsx = dir.path().join("events.xlsx");
+    succeeds(&["convert", "--from", "ndjson", "--to", "xlsx", ndjson, "-o", &xlsx.to_string_lossy()]);
+    let mut workbook = zip::ZipArchive::new(fs::File::open(&xlsx).unwrap()).unwrap();
+    let mut strings = String::new();
+    workbook.by_name("xl/sharedStrings.xml").unwrap().read_to_string(&mut strings).unwrap();
+    for cell in ["user.name", "Ann", "Bo"] {
+        assert!(strings.contains(&format!("<t>{}</t>", cell)), "{} missing from {}", cell, strings);
+    }
+}
+
+#[test]
+fn test_convert_rejects_unknown_output_format() {
+    let output = jflat(&["convert", "--to", "orc", "-"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("invalid value 'orc'"));
+}
+
+/// Runs `jflat dir` over `*` in `inputs`, writing into `inputs/out`
+fn dir(inputs: &Path, to: &str, extra: &[&str]) -> Output {
+    let pattern = inputs.join("*").to_string_lossy().into_owned();
+    let out_dir = inputs.join("out").to_string_lossy().into_owned();
+    let mut args = vec!["dir", &pattern, "--out-dir", &out_dir, "--to", to, "--no-progress"];
+    args.extend(extra);
+    jflat(&args)
+}
+
+fn out_files(inputs: &Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(inputs.join("out"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn test_dir_exit_code_follows_fail_on() {
+    let some_fail = tempfile::tempdir().unwrap();
+    fs::write(some_fail.path().join("good.json"), r#"{"a": 1}"#).unwrap();
+    fs::write(some_fail.path().join("bad.json"), r#"{"a":"#).unwrap();
+    let all_fail = tempfile::tempdir().unwrap();
+    fs::write(all_fail.path().join("bad.json"), r#"{"a":"#).unwrap();
+
+    for (inputs, fail_on, success) in [
+        (&some_fail, "any", false),
+        (&some_fail, "all", true),
+        (&some_fail, "never", true),
+        (&all_fail, "any", false),
+        (&all_fail, "all", false),
+        (&all_fail, "never", true),
+    ] {
+        let output = dir(inputs.path(), "csv", &["--fail-on", fail_on]);
+        assert_eq!(output.status.success(), success, "--fail-on {}", fail_on);
+        assert!(String::from_utf8_lossy(&output.stderr).contains("bad.json: EOF while parsing"));
+    }
+}
+
+#[test]
+fn test_dir_leaves_no_output_for_failed_files() {
+    let inputs = tempfile::tempdir().unwrap();
+    fs::write(inputs.path().join("good.json"), r#"{"a": 1}"#).unwrap();
+    // The first document is written before the second fails to parse
+    fs::write(inputs.path().join("bad.json"), "{\"a\": 1}\n{\"a\":").unwrap();
+
+    dir(inputs.path(), "csv", &["--fail-on", "never"]);
+    assert_eq!(out_files(inputs.path()), ["good.csv"]);
+    assert_eq!(fs::read_to_string(inputs.path().join("out/good.csv")).unwrap(), "a\n1\n");
+}
+
+#[test]
+fn test_dir_names_outputs_after_input_stems() {
+    let inputs = tempfile::tempdir().unwrap();
+    fs::write(inputs.path().join("a.json"), r#"{"a": 1}"#).unwrap();
+    fs::write(inputs.path().join("b.jsonl"), r#"{"b": 1}"#).unwrap();
+    fs::write(inputs.path().join("c.ndjson"), r#"{"c": 1}"#).unwrap();
+    // Only the extensions dir knows are taken off
+    fs::write(inputs.path().join("d.v2.json"), r#"{"d": 1}"#).unwrap();
+
+    let output = dir(inputs.path(), "csv", &[]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(out_files(inputs.path()), ["a.csv", "b.csv", "c.csv", "d.v2.csv"]);
+    assert_eq!(fs::read_to_string(inputs.path().join("out/c.csv")).unwrap(), "c\n1\n");
+
+    let output = dir(inputs.path(), "eav", &[]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(out_files(inputs.path()).contains(&"a.eav.csv".to_string()));
+}
+
+#[test]
+fn test_dir_refuses_inputs_with_the_same_output_name() {
+    let inputs = tempfile::tempdir().unwrap();
+    fs::write(inputs.path().join("a.json"), r#"{"a": 1}"#).unwrap();
+    fs::write(inputs.path().join("a.ndjson"), r#"{"a": 2}"#).unwrap();
+
+    let output = dir(inputs.path(), "csv", &[]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("several inputs would be written to"));
+    assert!(!inputs.path().join("out").exists());
+}
+
+/// Runs a report subcommand on the report fixture and parses its JSON
+fn report(command: &str) -> serde_json::Value {
+    let output = succeeds(&[command, "tests/fixtures/report_input.ndjson"]);
+    serde_json::from_slice(&output.stdout).unwrap()
+}
+
+fn fixture(name: &str) -> serde_json::Value {
+    serde_json::from_str(&fs::read_to_string(Path::new("tests/fixtures").join(name)).unwrap()).unwrap()
+}
+
+#[test]
+fn test_schema_prints_column_types() {
+    assert_eq!(report("schema"), fixture("report_schema.json"));
+}
+
+#[test]
+fn test_profile_prints_column_statistics() {
+    assert_eq!(report("profile"), fixture("report_profile.json"));
+}